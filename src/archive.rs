@@ -0,0 +1,528 @@
+use std::fs;
+use std::path::{Component, Path};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+// An entry of the central directory of a zip, enough to list and extract it
+struct ZipEntry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    offset: usize,
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    return !crc;
+}
+
+// The time and date of a file in the MS-DOS format of zip headers, in UTC
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64;
+    // the civil date of a number of days since 1970, from Howard Hinnant's algorithms
+    let days = secs.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let secs_of_day = secs.rem_euclid(86400);
+    let time = ((secs_of_day / 3600) << 11) | ((secs_of_day % 3600 / 60) << 5) | ((secs_of_day % 60) / 2);
+    let date = ((year.min(2107) - 1980) << 9) | (month << 5) | day;
+    return (time as u16, date as u16);
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    return data.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])).ok_or_else(truncated);
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    return data.get(offset..offset + 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).ok_or_else(truncated);
+}
+
+fn truncated() -> String {
+    return "the archive is truncated".to_owned();
+}
+
+// The files under a directory, with their path relative to it, sorted so the
+// same content always makes the same archive. The links are skipped, they could
+// point outside of the directory, and out of the allowed paths.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, fs::Metadata)>) -> Result<(), String> {
+    let mut paths: Vec<_> = fs::read_dir(dir).map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let metadata = fs::symlink_metadata(&path).map_err(|e| e.to_string())?;
+        if metadata.file_type().is_symlink() {
+            continue;
+        }
+        let name = path.strip_prefix(root).unwrap_or(&path).components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        if metadata.is_dir() {
+            files.push((format!("{}/", name), metadata));
+            collect_files(root, &path, files)?;
+        } else {
+            files.push((name, metadata));
+        }
+    }
+    return Ok(());
+}
+
+// Writes a zip of the content of a directory, or of a single file, with the
+// files stored without compression. Returns the number of entries.
+pub fn zip_create(path: &Path, source: &Path) -> Result<usize, String> {
+    let metadata = fs::metadata(source).map_err(|e| e.to_string())?;
+    let mut files = vec![];
+    if metadata.is_dir() {
+        collect_files(source, source, &mut files)?;
+    } else {
+        let name = source.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        files.push((name, metadata));
+    }
+    if files.len() > u16::MAX as usize {
+        return Err(format!("a zip can't have more than {} entries", u16::MAX));
+    }
+    let mut out = vec![];
+    let mut central = vec![];
+    for (name, metadata) in &files {
+        let data = if metadata.is_dir() {
+            vec![]
+        } else if source.is_dir() {
+            fs::read(source.join(name)).map_err(|e| e.to_string())?
+        } else {
+            fs::read(source).map_err(|e| e.to_string())?
+        };
+        if data.len() > u32::MAX as usize || out.len() > u32::MAX as usize {
+            return Err("the archive would be larger than 4GB".to_owned());
+        }
+        let (time, date) = dos_time(metadata.modified().unwrap_or(UNIX_EPOCH));
+        let crc = crc32(&data);
+        let offset = out.len() as u32;
+        // the names are utf-8
+        let flags = 1 << 11;
+        push_u32(&mut out, LOCAL_HEADER);
+        for value in [20, flags, METHOD_STORED, time, date] {
+            push_u16(&mut out, value);
+        }
+        for value in [crc, data.len() as u32, data.len() as u32] {
+            push_u32(&mut out, value);
+        }
+        push_u16(&mut out, name.len() as u16);
+        push_u16(&mut out, 0);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&data);
+
+        push_u32(&mut central, CENTRAL_HEADER);
+        for value in [20, 20, flags, METHOD_STORED, time, date] {
+            push_u16(&mut central, value);
+        }
+        for value in [crc, data.len() as u32, data.len() as u32] {
+            push_u32(&mut central, value);
+        }
+        for value in [name.len() as u16, 0, 0, 0, 0] {
+            push_u16(&mut central, value);
+        }
+        push_u32(&mut central, if metadata.is_dir() { 0x10 } else { 0 });
+        push_u32(&mut central, offset);
+        central.extend_from_slice(name.as_bytes());
+    }
+    let central_offset = out.len() as u32;
+    let central_size = central.len() as u32;
+    out.extend_from_slice(&central);
+    push_u32(&mut out, END_OF_CENTRAL_DIRECTORY);
+    for value in [0, 0, files.len() as u16, files.len() as u16] {
+        push_u16(&mut out, value);
+    }
+    push_u32(&mut out, central_size);
+    push_u32(&mut out, central_offset);
+    push_u16(&mut out, 0);
+    fs::write(path, out).map_err(|e| e.to_string())?;
+    return Ok(files.len());
+}
+
+fn read_entries(data: &[u8]) -> Result<Vec<ZipEntry>, String> {
+    // the end of central directory record is followed by a comment of at most 64kB
+    let min_start = data.len().saturating_sub(22 + u16::MAX as usize);
+    let end = (min_start..=data.len().saturating_sub(22)).rev()
+        .find(|offset| read_u32(data, *offset) == Ok(END_OF_CENTRAL_DIRECTORY))
+        .ok_or("this is not a zip archive")?;
+    let count = read_u16(data, end + 10)? as usize;
+    let mut offset = read_u32(data, end + 16)? as usize;
+    if count == u16::MAX as usize || offset == u32::MAX as usize {
+        return Err("zip64 archives are not supported".to_owned());
+    }
+    let mut entries = vec![];
+    for _ in 0..count {
+        if read_u32(data, offset)? != CENTRAL_HEADER {
+            return Err("the central directory of the archive is corrupted".to_owned());
+        }
+        let name_len = read_u16(data, offset + 28)? as usize;
+        let extra_len = read_u16(data, offset + 30)? as usize;
+        let comment_len = read_u16(data, offset + 32)? as usize;
+        let name = data.get(offset + 46..offset + 46 + name_len).ok_or_else(truncated)?;
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).to_string(),
+            method: read_u16(data, offset + 10)?,
+            crc: read_u32(data, offset + 16)?,
+            compressed_size: read_u32(data, offset + 20)? as usize,
+            size: read_u32(data, offset + 24)? as usize,
+            offset: read_u32(data, offset + 42)? as usize,
+        });
+        offset += 46 + name_len + extra_len + comment_len;
+    }
+    return Ok(entries);
+}
+
+// The names of the entries of a zip, the directories end with a /
+pub fn zip_list(path: &Path) -> Result<Vec<String>, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    return Ok(read_entries(&data)?.into_iter().map(|entry| entry.name).collect());
+}
+
+// Extracts all the entries of a zip in the destination directory, which is created
+// if needed. Returns the number of files extracted.
+pub fn zip_extract(path: &Path, dest: &Path) -> Result<usize, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let entries = read_entries(&data)?;
+    // checked before writing anything, so a malicious archive doesn't leave files behind
+    for entry in &entries {
+        let inside = Path::new(&entry.name).components().all(|component| matches!(component, Component::Normal(_)));
+        if !inside || entry.name.contains('\\') {
+            return Err(format!("the entry '{}' would be extracted outside of the destination", entry.name));
+        }
+    }
+    fs::create_dir_all(dest).map_err(|e| e.to_string())?;
+    let mut extracted = 0;
+    for entry in &entries {
+        let target = dest.join(&entry.name);
+        if entry.name.ends_with('/') {
+            fs::create_dir_all(&target).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if read_u32(&data, entry.offset)? != LOCAL_HEADER {
+            return Err(format!("the entry '{}' is corrupted", entry.name));
+        }
+        let name_len = read_u16(&data, entry.offset + 26)? as usize;
+        let extra_len = read_u16(&data, entry.offset + 28)? as usize;
+        let start = entry.offset + 30 + name_len + extra_len;
+        let compressed = data.get(start..start + entry.compressed_size).ok_or_else(truncated)?;
+        let content = match entry.method {
+            METHOD_STORED => compressed.to_vec(),
+            METHOD_DEFLATED => inflate(compressed, entry.size).map_err(|message| format!("the entry '{}' {}", entry.name, message))?,
+            method => return Err(format!("the entry '{}' uses the unsupported compression method {}", entry.name, method)),
+        };
+        if content.len() != entry.size || crc32(&content) != entry.crc {
+            return Err(format!("the entry '{}' is corrupted", entry.name));
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::write(&target, content).map_err(|e| e.to_string())?;
+        extracted += 1;
+    }
+    return Ok(extracted);
+}
+
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u32; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTHS_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Reads the bits of a deflate stream, the least significant first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, needed: u32) -> Result<u32, String> {
+        while self.count < needed {
+            let byte = *self.data.get(self.pos).ok_or("is truncated")?;
+            self.bits |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.bits & ((1u32 << needed) - 1);
+        self.bits = self.bits.checked_shr(needed).unwrap_or(0);
+        self.count -= needed;
+        return Ok(value);
+    }
+}
+
+// A canonical huffman code, as the number of codes of each length and the
+// symbols in the order of their codes
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0usize; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length] as usize;
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize]] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        return Huffman { counts, symbols };
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, String> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err("has an invalid huffman code".to_owned());
+    }
+}
+
+// Decompresses a raw deflate stream, the way the entries of most zips are compressed.
+// It stops as soon as the output is larger than the size the zip gave for the entry,
+// a small entry could otherwise fill the memory.
+fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader { data, pos: 0, bits: 0, count: 0 };
+    let mut out = vec![];
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.bits = 0;
+                reader.count = 0;
+                let len = read_u16(data, reader.pos).map_err(|_| "is truncated")? as usize;
+                let nlen = read_u16(data, reader.pos + 2).map_err(|_| "is truncated")? as usize;
+                if len != !nlen & 0xffff {
+                    return Err("has a corrupted stored block".to_owned());
+                }
+                let block = data.get(reader.pos + 4..reader.pos + 4 + len).ok_or("is truncated")?;
+                if out.len() + len > size {
+                    return Err(too_large());
+                }
+                out.extend_from_slice(block);
+                reader.pos += 4 + len;
+            },
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (symbol, length) in lengths.iter_mut().enumerate() {
+                    *length = match symbol {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                inflate_block(&mut reader, &mut out, size, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            },
+            2 => {
+                let literal_count = reader.bits(5)? as usize + 257;
+                let dist_count = reader.bits(5)? as usize + 1;
+                let code_count = reader.bits(4)? as usize + 4;
+                let mut code_lengths = [0u8; 19];
+                for idx in CODE_LENGTHS_ORDER.iter().take(code_count) {
+                    code_lengths[*idx] = reader.bits(3)? as u8;
+                }
+                let code_lengths = Huffman::new(&code_lengths);
+                let mut lengths = vec![];
+                while lengths.len() < literal_count + dist_count {
+                    let (length, repeat) = match code_lengths.decode(&mut reader)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => (*lengths.last().ok_or("repeats a missing code length")?, 3 + reader.bits(2)?),
+                        17 => (0, 3 + reader.bits(3)?),
+                        _ => (0, 11 + reader.bits(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(length, repeat as usize));
+                }
+                if lengths.len() > literal_count + dist_count {
+                    return Err("has too many code lengths".to_owned());
+                }
+                let literals = Huffman::new(&lengths[..literal_count]);
+                let distances = Huffman::new(&lengths[literal_count..]);
+                inflate_block(&mut reader, &mut out, size, &literals, &distances)?;
+            },
+            _ => return Err("has an invalid block type".to_owned()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn too_large() -> String {
+    return "is larger than its declared size".to_owned();
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, size: usize, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        if symbol < 256 {
+            if out.len() >= size {
+                return Err(too_large());
+            }
+            out.push(symbol as u8);
+            continue;
+        } else if symbol == 256 {
+            return Ok(());
+        }
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err("has an invalid length".to_owned());
+        }
+        let length = LENGTH_BASE[symbol] + reader.bits(LENGTH_EXTRA[symbol])? as usize;
+        let symbol = distances.decode(reader)?;
+        if symbol >= DIST_BASE.len() {
+            return Err("has an invalid distance".to_owned());
+        }
+        let dist = DIST_BASE[symbol] + reader.bits(DIST_EXTRA[symbol])? as usize;
+        if dist > out.len() {
+            return Err("refers to data before its start".to_owned());
+        }
+        if out.len() + length > size {
+            return Err(too_large());
+        }
+        for _ in 0..length {
+            out.push(out[out.len() - dist]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414fa339);
+    }
+
+    #[test]
+    fn test_dos_time() {
+        // 2024-02-29 13:45:30 UTC
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1709214330);
+        assert_eq!(dos_time(time), ((13 << 11 | 45 << 5 | 15) as u16, ((2024 - 1980) << 9 | 2 << 5 | 29) as u16));
+        assert_eq!(dos_time(UNIX_EPOCH), (0, (1 << 5) | 1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_zip_create_skips_links() {
+        let dir = std::env::temp_dir().join(format!("nope_zip_links_{}", std::process::id()));
+        fs::create_dir_all(dir.join("src").join("sub")).unwrap();
+        fs::write(dir.join("src").join("a.txt"), "a").unwrap();
+        fs::write(dir.join("outside.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(dir.join("outside.txt"), dir.join("src").join("link.txt")).unwrap();
+        std::os::unix::fs::symlink("..", dir.join("src").join("sub").join("up")).unwrap();
+        let path = dir.join("links.zip");
+        assert_eq!(zip_create(&path, &dir.join("src")).unwrap(), 2);
+        assert_eq!(zip_list(&path).unwrap(), vec!["a.txt".to_owned(), "sub/".to_owned()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_zip_extract_stays_in_destination() {
+        let dir = std::env::temp_dir().join(format!("nope_zip_slip_{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src").join("abcde"), "x").unwrap();
+        let path = dir.join("slip.zip");
+        zip_create(&path, &dir.join("src")).unwrap();
+        let data = fs::read(&path).unwrap();
+        let mut patched = vec![];
+        let mut idx = 0;
+        while idx < data.len() {
+            if data[idx..].starts_with(b"abcde") {
+                patched.extend_from_slice(b"../de");
+                idx += 5;
+            } else {
+                patched.push(data[idx]);
+                idx += 1;
+            }
+        }
+        fs::write(&path, patched).unwrap();
+        assert_eq!(zip_list(&path).unwrap(), vec!["../de".to_owned()]);
+        assert!(zip_extract(&path, &dir.join("dest")).is_err());
+        assert!(!dir.join("de").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_inflate() {
+        // raw deflate streams made by zlib, with a fixed, a dynamic and a stored block
+        let fixed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+        assert_eq!(inflate(&fixed, 17).unwrap(), b"hello hello hello");
+        let dynamic = [
+            0xb5, 0xcb, 0xc9, 0x11, 0x80, 0x20, 0x10, 0x44, 0xd1, 0x54, 0x3a, 0x00, 0x93, 0x02, 0x65, 0x53, 0x60, 0xd8,
+            0x11, 0xa3, 0x77, 0xca, 0x1c, 0x3c, 0x76, 0xfd, 0xd7, 0xcd, 0x2a, 0xe4, 0xee, 0xf6, 0x0b, 0xb2, 0xd0, 0x8c,
+            0xd0, 0x74, 0xe3, 0xec, 0x21, 0x55, 0xd0, 0x50, 0x05, 0x8d, 0xb3, 0x17, 0xcf, 0xc2, 0x41, 0x66, 0xfb, 0xd6,
+            0x3f, 0x38, 0x09, 0x76, 0x61, 0x41, 0x32, 0x9a, 0xae, 0x59, 0x68, 0x37, 0x14, 0xa7, 0x47, 0x45, 0x78, 0x97,
+            0x3b, 0x15, 0xfe, 0x9a, 0xfa, 0x02,
+        ];
+        let text = "the quick brown fox jumps over the lazy dog, ".repeat(3) + "pack my box with five dozen liquor jugs";
+        assert_eq!(inflate(&dynamic, text.len()).unwrap(), text.as_bytes());
+        let stored = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        assert_eq!(inflate(&stored, 3).unwrap(), b"abc");
+        assert!(inflate(&fixed[..4], 17).is_err());
+        assert!(inflate(&[0x07], 17).is_err());
+        for size in [0, 3, 16] {
+            assert_eq!(inflate(&fixed, size), Err(too_large()));
+        }
+        assert_eq!(inflate(&dynamic, 40), Err(too_large()));
+        assert_eq!(inflate(&stored, 2), Err(too_large()));
+    }
+}
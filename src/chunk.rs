@@ -2,6 +2,8 @@ use std::collections::HashMap;
 
 use crate::{
     gc::GcRef,
+    objects::{StringBuffer, RunningStats, Thunk, Generator, Task, Channel, Socket, LineReader, FileWatch, Process, HttpRequest, ProgressBar, Drawing},
+    parser::Parser,
 };
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -11,9 +13,52 @@ pub enum Value {
     Boolean(bool),
     Num(f64),
     String(GcRef<String>),
+    Buffer(GcRef<StringBuffer>),
+    Stats(GcRef<RunningStats>),
+    Lazy(GcRef<Thunk>),
+    Generator(GcRef<Generator>),
+    Task(GcRef<Task>),
+    Channel(GcRef<Channel>),
+    Socket(GcRef<Socket>),
+    Lines(GcRef<LineReader>),
+    Watch(GcRef<FileWatch>),
+    Process(GcRef<Process>),
+    Request(GcRef<HttpRequest>),
+    Progress(GcRef<ProgressBar>),
+    Drawing(GcRef<Drawing>),
+    Function(usize),
 }
 
+// The names returned by type_of, which can also follow the `:` of a type annotation
+pub const TYPE_NAMES: [&str; 19] = [
+    "null", "void", "bool", "num", "str", "fn", "buffer", "stats", "lazy", "generator", "task",
+    "channel", "socket", "lines", "watch", "process", "request", "progress", "svg",
+];
+
 impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Void => "void",
+            Value::Boolean(_) => "bool",
+            Value::Num(_) => "num",
+            Value::String(_) => "str",
+            Value::Buffer(_) => "buffer",
+            Value::Stats(_) => "stats",
+            Value::Lazy(_) => "lazy",
+            Value::Generator(_) => "generator",
+            Value::Task(_) => "task",
+            Value::Channel(_) => "channel",
+            Value::Socket(_) => "socket",
+            Value::Lines(_) => "lines",
+            Value::Watch(_) => "watch",
+            Value::Process(_) => "process",
+            Value::Request(_) => "request",
+            Value::Progress(_) => "progress",
+            Value::Drawing(_) => "svg",
+            Value::Function(_) => "fn",
+        }
+    }
     pub fn is_truthy(&self) -> bool {
         match self {
             Value::Null => false,
@@ -21,6 +66,20 @@ impl Value {
             Value::Boolean(value) => *value,
             Value::Num(num) => *num != 0.0,
             Value::String(_) => true,
+            Value::Buffer(_) => true,
+            Value::Stats(_) => true,
+            Value::Lazy(_) => true,
+            Value::Generator(_) => true,
+            Value::Task(_) => true,
+            Value::Channel(_) => true,
+            Value::Socket(_) => true,
+            Value::Lines(_) => true,
+            Value::Watch(_) => true,
+            Value::Process(_) => true,
+            Value::Request(_) => true,
+            Value::Progress(_) => true,
+            Value::Drawing(_) => true,
+            Value::Function(_) => true,
             // _ => true,
         }
     }
@@ -44,11 +103,79 @@ impl Value {
             Value::Boolean(value) => (*value as i32) as f64,
             Value::Num(num) => *num,
             Value::String(_) => f64::NAN,
+            Value::Buffer(_) => f64::NAN,
+            Value::Stats(_) => f64::NAN,
+            Value::Lazy(_) => f64::NAN,
+            Value::Generator(_) => f64::NAN,
+            Value::Task(_) => f64::NAN,
+            Value::Channel(_) => f64::NAN,
+            Value::Socket(_) => f64::NAN,
+            Value::Lines(_) => f64::NAN,
+            Value::Watch(_) => f64::NAN,
+            Value::Process(_) => f64::NAN,
+            Value::Request(_) => f64::NAN,
+            Value::Progress(_) => f64::NAN,
+            Value::Drawing(_) => f64::NAN,
+            Value::Function(_) => f64::NAN,
         }
     }
 }
 
-pub type GlobalsTable = HashMap<GcRef<String>, Value>;
+// Globals are resolved to a slot index at compile time. The slots
+// are kept between two REPL submissions so that a global can be redefined.
+#[derive(PartialEq, Debug, Clone)]
+pub struct GlobalsTable {
+    values: Vec<Option<Value>>,
+    names: Vec<String>,
+    slots: HashMap<String, usize>,
+}
+
+impl GlobalsTable {
+    pub fn new() -> GlobalsTable {
+        return GlobalsTable {
+            values: vec![],
+            names: vec![],
+            slots: HashMap::new(),
+        };
+    }
+    pub fn resolve(&mut self, name: &str) -> usize {
+        if let Some(&slot) = self.slots.get(name) {
+            return slot;
+        }
+        self.values.push(None);
+        self.names.push(name.to_owned());
+        self.slots.insert(name.to_owned(), self.values.len() - 1);
+        return self.values.len() - 1;
+    }
+    #[allow(dead_code)]
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+    pub fn get(&self, slot: usize) -> Option<Value> {
+        self.values[slot]
+    }
+    pub fn set(&mut self, slot: usize, value: Value) {
+        self.values[slot] = Some(value);
+    }
+    // the slot is kept, the code compiled for it then finds an undefined global
+    pub fn unset(&mut self, slot: usize) {
+        self.values[slot] = None;
+    }
+    // the name is forgotten too, so that it resolves to something else, like the
+    // stdlib function it replaced, while the code compiled for the slot finds an
+    // undefined global
+    pub fn remove(&mut self, name: &str) {
+        if let Some(slot) = self.slots.remove(name) {
+            self.values[slot] = None;
+        }
+    }
+    pub fn name(&self, slot: usize) -> &str {
+        &self.names[slot]
+    }
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter().flatten()
+    }
+}
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct Local {
@@ -80,21 +207,8 @@ impl LocalsTable {
         }
         self.locals.pop();
     }
-    pub fn get_local_depth(&self, name: &str) -> usize {
-        if self.locals.is_empty() {
-            panic!("empty locals stash (get)");
-        }
-        let mut i = self.locals.len() - 1;
-        loop {
-            if self.locals[i].name == name {
-                return self.locals[i].depth;
-            }
-            if i == 0 {
-                panic!("local not found: {}", name);
-            } else {
-                i = i - 1;
-            }
-        }
+    pub fn find_local_depth(&self, name: &str) -> Option<usize> {
+        return self.locals.iter().rev().find(|local| local.name == name).map(|local| local.depth);
     }
     pub fn get_locals_count(&self) -> usize {
         return self.locals.len();
@@ -145,6 +259,10 @@ pub enum Instruction {
     SetGlobal(usize),
     LoadFromStack(usize),
     SetInStack(usize),
+    PushFunction(usize),
+    TraceOn,
+    TraceOff,
+    Call(usize),
     Jump(i64),
     JumpIfFalse(i64),
     JumpIfTrue(i64),
@@ -155,10 +273,14 @@ pub enum Instruction {
     IsBool,
     IsNum,
     IsStr,
+    IsFn,
+    TypeOf,
     IsNaN,
     IsInt,
     Swap,
+    Over,
     Pop,
+    PopResult,
     Return,
     Negate,
     Add,
@@ -167,7 +289,34 @@ pub enum Instruction {
     Divide,
     Power,
     Modulo,
+    FloorModulo,
     Random,
+    GcStats,
+    CallNative(usize),
+    BufNew,
+    BufPush,
+    BufStr,
+    Lazy,
+    Force,
+    Yield,
+    Resume,
+    Spawn,
+    Await,
+    Deadline,
+    Sleep,
+    ChanNew,
+    Send,
+    Recv,
+    Connect,
+    Listen,
+    EachLine,
+    Watch,
+    Serve,
+    SockReadLine,
+    SockWrite,
+    SockClose,
+    ClipGet,
+    ClipSet,
     Print,
     Echo,
     Num,
@@ -175,6 +324,7 @@ pub enum Instruction {
     Not,
     Bool,
     Equal,
+    IsSame,
     Greater,
     Less,
     BitwiseNot,
@@ -246,6 +396,7 @@ pub enum Instruction {
 pub struct Chunk {
     pub code: Vec<Instruction>,
     pub constants: Vec<Value>,
+    pub positions: Vec<(usize, usize)>, // source line & col of each instruction
     pub ast_map: Vec<usize>,
 }
 
@@ -255,6 +406,7 @@ impl Chunk {
             code: vec![],
             constants: vec![],
             ast_map: vec![],
+            positions: vec![],
         };
     }
 
@@ -282,19 +434,19 @@ impl Chunk {
         self.constants[index]
     }
 
-    pub fn read_constant_string(&self, index: usize) -> GcRef<String>{
-        if let Value::String(s) = self.read_constant(index) {
-            s
-        } else {
-            panic!("Constant is not a String");
-        }
-    }
-
     pub fn write(&mut self, ast_node_idx: usize, op: Instruction) {
         self.code.push(op);
         self.ast_map.push(ast_node_idx);
     }
 
+    // Computes the source positions of the instructions written since
+    // the last call, the ast_map entries must refer to this parser's ast
+    pub fn resolve_positions(&mut self, parser: &Parser) {
+        for idx in self.positions.len()..self.ast_map.len() {
+            self.positions.push(parser.get_ast_node_line_col(self.ast_map[idx]));
+        }
+    }
+
     pub fn rewrite(&mut self, instr_idx: usize, op: Instruction) {
         self.code[instr_idx] = op;
     }
@@ -322,3 +474,25 @@ impl Chunk {
         }
     }
 }
+
+// A user defined function, compiled to its own chunk. Arguments are
+// the first locals of the function, relative to the base of its call frame.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub memo: Option<HashMap<Vec<MemoKey>, Value>>, // results cache of memo functions
+    pub generator: bool, // calling it creates a generator instead of running it
+}
+
+// The arguments of a memo function, compared by value
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum MemoKey {
+    Null,
+    Void,
+    Boolean(bool),
+    Num(u64),
+    String(String),
+    Function(usize),
+}
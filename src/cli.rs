@@ -0,0 +1,247 @@
+// The options of a script, described by a spec with one option on each line:
+//
+//   -v, --verbose        Print more
+//   -o, --out=result.txt Where to write
+//   <input>              The file to read
+//
+// A --name without a value is a flag, false unless it is given. A --name=value
+// takes a value, the text after the = is its default, nothing for a null default.
+// The help is the rest of the line.
+// <name> is a positional argument, only used for the help.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionSpec {
+    pub name: String,
+    pub short: Option<char>,
+    pub takes_value: bool,
+    pub default: Option<String>,
+    pub help: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionValue {
+    Flag(bool),
+    Text(Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedArgs {
+    pub options: Vec<(String, OptionValue)>,
+    pub positionals: Vec<String>,
+    pub help: bool,
+}
+
+impl ParsedArgs {
+    pub fn get(&self, name: &str) -> Option<&OptionValue> {
+        let name = name.trim_start_matches('-');
+        return self.options.iter().find(|(opt, _)| opt == name).map(|(_, value)| value);
+    }
+}
+
+// A positional argument, like <input>, and its help
+pub type Positional = (String, String);
+
+fn valid_name(name: &str) -> bool {
+    return !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+}
+
+fn split_word(line: &str) -> (&str, &str) {
+    return match line.find(char::is_whitespace) {
+        Some(idx) => (&line[..idx], line[idx..].trim()),
+        None => (line, ""),
+    };
+}
+
+// The options and the positional arguments of the spec, with their help
+pub fn parse_spec(spec: &str) -> Result<(Vec<OptionSpec>, Vec<Positional>), String> {
+    let mut options: Vec<OptionSpec> = vec![];
+    let mut positionals = vec![];
+    for line in spec.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (mut head, mut help) = split_word(line);
+        if head.starts_with('<') || head.starts_with('[') {
+            positionals.push((head.to_owned(), help.to_owned()));
+            continue;
+        }
+        let mut short = None;
+        if let Some(first) = head.strip_suffix(',') {
+            let letter = first.strip_prefix('-').filter(|letter| letter.chars().count() == 1);
+            short = match letter {
+                Some(letter) => letter.chars().next(),
+                None => return Err(format!("'{}' is not a short option like -v", first)),
+            };
+            (head, help) = split_word(help);
+        }
+        let long = match head.strip_prefix("--") {
+            Some(long) => long,
+            None => return Err(format!("'{}' is not an option like --name", head)),
+        };
+        let (name, default) = match long.split_once('=') {
+            Some((name, default)) => (name, Some(default)),
+            None => (long, None),
+        };
+        if !valid_name(name) || name == "help" {
+            return Err(format!("'{}' is not a valid option name", name));
+        }
+        if options.iter().any(|opt| opt.name == name || (short.is_some() && opt.short == short)) {
+            return Err(format!("the option '{}' is given twice", name));
+        }
+        options.push(OptionSpec {
+            name: name.to_owned(),
+            short,
+            takes_value: default.is_some(),
+            default: default.filter(|default| !default.is_empty()).map(str::to_owned),
+            help: help.to_owned(),
+        });
+    }
+    return Ok((options, positionals));
+}
+
+// --name value, --name=value and -n value set an option, -- ends the options
+pub fn parse_args(options: &[OptionSpec], args: &[String]) -> Result<ParsedArgs, String> {
+    let mut parsed = ParsedArgs {
+        options: options.iter().map(|opt| {
+            let value = match opt.takes_value {
+                true => OptionValue::Text(opt.default.clone()),
+                false => OptionValue::Flag(false),
+            };
+            (opt.name.clone(), value)
+        }).collect(),
+        positionals: vec![],
+        help: false,
+    };
+    let mut idx = 0;
+    while idx < args.len() {
+        let arg = &args[idx];
+        idx += 1;
+        if arg == "--" {
+            parsed.positionals.extend(args[idx..].iter().cloned());
+            break;
+        }
+        if arg == "--help" || arg == "-h" {
+            parsed.help = true;
+            continue;
+        }
+        let (found, inline) = if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (long, None),
+            };
+            (options.iter().position(|opt| opt.name == name), inline)
+        } else if arg.len() > 1 && arg.starts_with('-') && arg.parse::<f64>().is_err() {
+            let mut chars = arg[1..].chars();
+            let letter = chars.next();
+            let rest: String = chars.collect();
+            (options.iter().position(|opt| opt.short == letter), Some(rest).filter(|rest| !rest.is_empty()))
+        } else {
+            parsed.positionals.push(arg.clone());
+            continue;
+        };
+        let opt_idx = match found {
+            Some(opt_idx) => opt_idx,
+            None => return Err(format!("unknown option '{}'", arg)),
+        };
+        let opt = &options[opt_idx];
+        if !opt.takes_value {
+            if inline.is_some() {
+                return Err(format!("the option '--{}' doesn't take a value", opt.name));
+            }
+            parsed.options[opt_idx].1 = OptionValue::Flag(true);
+            continue;
+        }
+        let value = match inline {
+            Some(value) => value,
+            None if idx < args.len() => {
+                idx += 1;
+                args[idx - 1].clone()
+            },
+            None => return Err(format!("the option '--{}' expects a value", opt.name)),
+        };
+        parsed.options[opt_idx].1 = OptionValue::Text(Some(value));
+    }
+    return Ok(parsed);
+}
+
+// The usage line followed by the arguments and the options with their help, aligned
+pub fn help_text(script: &str, options: &[OptionSpec], positionals: &[Positional]) -> String {
+    let mut usage = format!("usage: {} [options]", script);
+    for (name, _) in positionals {
+        usage.push(' ');
+        usage.push_str(name);
+    }
+    let mut rows: Vec<(String, String)> = positionals.to_vec();
+    for opt in options {
+        let mut head = match opt.short {
+            Some(letter) => format!("-{}, --{}", letter, opt.name),
+            None => format!("--{}", opt.name),
+        };
+        if opt.takes_value {
+            head.push('=');
+            head.push_str(opt.default.as_deref().unwrap_or(""));
+        }
+        rows.push((head, opt.help.clone()));
+    }
+    rows.push(("-h, --help".to_owned(), "Show this help".to_owned()));
+    let width = rows.iter().map(|(head, _)| head.chars().count()).max().unwrap_or(0);
+    let mut text = usage;
+    text.push('\n');
+    for (head, help) in rows {
+        text.push_str(&format!("\n  {:width$}  {}", head, help, width = width));
+    }
+    return text.trim_end().to_owned();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = "
+        -v, --verbose        Print more
+        -o, --out=result.txt Where to write
+        --limit=             Stop after that many lines
+        <input>              The file to read
+    ";
+
+    fn args(list: &[&str]) -> Vec<String> {
+        return list.iter().map(|arg| arg.to_string()).collect();
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let (options, positionals) = parse_spec(SPEC).unwrap();
+        assert_eq!(options.len(), 3);
+        assert_eq!(positionals, vec![("<input>".to_owned(), "The file to read".to_owned())]);
+
+        let parsed = parse_args(&options, &args(&["data.txt"])).unwrap();
+        assert_eq!(parsed.get("verbose"), Some(&OptionValue::Flag(false)));
+        assert_eq!(parsed.get("out"), Some(&OptionValue::Text(Some("result.txt".to_owned()))));
+        assert_eq!(parsed.get("--limit"), Some(&OptionValue::Text(None)));
+        assert_eq!(parsed.positionals, args(&["data.txt"]));
+
+        let parsed = parse_args(&options, &args(&["-v", "--limit=3", "a", "-o", "x.txt", "-5", "--", "--out"])).unwrap();
+        assert_eq!(parsed.get("verbose"), Some(&OptionValue::Flag(true)));
+        assert_eq!(parsed.get("out"), Some(&OptionValue::Text(Some("x.txt".to_owned()))));
+        assert_eq!(parsed.get("limit"), Some(&OptionValue::Text(Some("3".to_owned()))));
+        assert_eq!(parsed.positionals, args(&["a", "-5", "--out"]));
+        assert!(!parsed.help);
+
+        assert!(parse_args(&options, &args(&["--help"])).unwrap().help);
+        for bad in [&["--nope"][..], &["--out"], &["--verbose=yes"], &["-x"]] {
+            assert!(parse_args(&options, &args(bad)).is_err(), "{:?}", bad);
+        }
+        for bad in ["verbose", "-vv, --verbose", "--verbose\n--verbose", "--help", "--a!"] {
+            assert!(parse_spec(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_help_text() {
+        let (options, positionals) = parse_spec(SPEC).unwrap();
+        assert_eq!(help_text("count.nope", &options, &positionals), "\
+usage: count.nope [options] <input>
+
+  <input>               The file to read
+  -v, --verbose         Print more
+  -o, --out=result.txt  Where to write
+  --limit=              Stop after that many lines
+  -h, --help            Show this help");
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use crate::{config::NopeConfig, sandbox::check_exec_access};
+
+// The clipboard is accessed through the tools that come with each desktop,
+// the first one that can be started is used. They are commands, so they are
+// denied like the ones of proc_spawn.
+#[cfg(target_os = "macos")]
+const PASTE_COMMANDS: &[&[&str]] = &[&["pbpaste"]];
+#[cfg(target_os = "macos")]
+const COPY_COMMANDS: &[&[&str]] = &[&["pbcopy"]];
+
+#[cfg(target_os = "windows")]
+const PASTE_COMMANDS: &[&[&str]] = &[&["powershell", "-NoProfile", "-Command", "Get-Clipboard"]];
+#[cfg(target_os = "windows")]
+const COPY_COMMANDS: &[&[&str]] = &[&["clip"]];
+
+#[cfg(all(unix, not(target_os = "macos")))]
+const PASTE_COMMANDS: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-out"],
+    &["xsel", "--clipboard", "--output"],
+];
+#[cfg(all(unix, not(target_os = "macos")))]
+const COPY_COMMANDS: &[&[&str]] = &[
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard", "-in"],
+    &["xsel", "--clipboard", "--input"],
+];
+
+#[cfg(not(any(unix, target_os = "windows")))]
+const PASTE_COMMANDS: &[&[&str]] = &[];
+#[cfg(not(any(unix, target_os = "windows")))]
+const COPY_COMMANDS: &[&[&str]] = &[];
+
+pub fn get_clipboard(config: &NopeConfig) -> Result<String, String> {
+    for command in PASTE_COMMANDS {
+        check_exec_access(config, command[0])?;
+        let output = match Command::new(command[0]).args(&command[1..]).stderr(Stdio::null()).output() {
+            Ok(output) => output,
+            Err(_) => continue,
+        };
+        if !output.status.success() {
+            return Err(format!("{} failed to read the clipboard", command[0]));
+        }
+        let text = String::from_utf8_lossy(&output.stdout).into_owned();
+        // powershell ends the text with a new line
+        if cfg!(target_os = "windows") {
+            return Ok(text.strip_suffix("\r\n").unwrap_or(&text).to_owned());
+        }
+        return Ok(text);
+    }
+    return Err("no clipboard available".to_owned());
+}
+
+pub fn set_clipboard(config: &NopeConfig, text: &str) -> Result<(), String> {
+    for command in COPY_COMMANDS {
+        check_exec_access(config, command[0])?;
+        let mut child = match Command::new(command[0]).args(&command[1..])
+            .stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::null()).spawn() {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes()).map_err(|e| e.to_string())?;
+        }
+        return match child.wait() {
+            Ok(status) if status.success() => Ok(()),
+            _ => Err(format!("{} failed to write the clipboard", command[0])),
+        };
+    }
+    return Err("no clipboard available".to_owned());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(any(unix, target_os = "windows"))]
+    fn test_clipboard_denied() {
+        let config = NopeConfig { no_exec: true, ..NopeConfig::new() };
+        assert!(get_clipboard(&config).unwrap_err().contains("running commands is disabled"));
+        assert!(set_clipboard(&config, "secret").unwrap_err().contains("running commands is disabled"));
+    }
+}
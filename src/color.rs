@@ -0,0 +1,105 @@
+// The red, green and blue of a color, from 0 to 255
+pub type Rgb = (f64, f64, f64);
+
+// '#ff8800', '#f80' or 'rgb(255, 136, 0)', the # and the commas can be left out
+pub fn parse_color(text: &str) -> Option<Rgb> {
+    let text = text.trim();
+    if let Some(inner) = text.strip_prefix("rgb(").and_then(|inner| inner.strip_suffix(')')) {
+        let parts: Vec<f64> = inner.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse::<f64>().ok().filter(|part| (0.0..=255.0).contains(part)))
+            .collect::<Option<Vec<f64>>>()?;
+        return match parts[..] {
+            [r, g, b] => Some((r, g, b)),
+            _ => None,
+        };
+    }
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits: Vec<u32> = hex.chars().filter_map(|c| c.to_digit(16)).collect();
+    return match digits[..] {
+        [r, g, b] => Some(((r * 17) as f64, (g * 17) as f64, (b * 17) as f64)),
+        [r1, r2, g1, g2, b1, b2] => Some(((r1 * 16 + r2) as f64, (g1 * 16 + g2) as f64, (b1 * 16 + b2) as f64)),
+        _ => None,
+    };
+}
+
+fn channel(value: f64) -> u8 {
+    return value.round().clamp(0.0, 255.0) as u8;
+}
+
+pub fn to_hex(color: Rgb) -> String {
+    return format!("#{:02x}{:02x}{:02x}", channel(color.0), channel(color.1), channel(color.2));
+}
+
+pub fn to_rgb_text(color: Rgb) -> String {
+    return format!("rgb({}, {}, {})", channel(color.0), channel(color.1), channel(color.2));
+}
+
+// The hue is in degrees, the saturation and the lightness from 0 to 1
+pub fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> Rgb {
+    let (s, l) = (saturation.clamp(0.0, 1.0), lightness.clamp(0.0, 1.0));
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as usize {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = l - chroma / 2.0;
+    return ((r + m) * 255.0, (g + m) * 255.0, (b + m) * 255.0);
+}
+
+pub fn rgb_to_hsl(color: Rgb) -> (f64, f64, f64) {
+    let (r, g, b) = (color.0 / 255.0, color.1 / 255.0, color.2 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, l);
+    }
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    return (h * 60.0, s, l);
+}
+
+// t goes from 0 for the first color to 1 for the second one
+pub fn mix(a: Rgb, b: Rgb, t: f64) -> Rgb {
+    let t = t.clamp(0.0, 1.0);
+    return (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t, a.2 + (b.2 - a.2) * t);
+}
+
+// Adds the amount to the lightness of the color, a negative amount darkens it
+pub fn lighten(color: Rgb, amount: f64) -> Rgb {
+    let (h, s, l) = rgb_to_hsl(color);
+    return hsl_to_rgb(h, s, l + amount);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsl_round_trip() {
+        for hex in ["#000000", "#ffffff", "#ff8800", "#336699", "#7f7f7f", "#c0ffee", "#ff00ff"] {
+            let (h, s, l) = rgb_to_hsl(parse_color(hex).unwrap());
+            assert_eq!(to_hex(hsl_to_rgb(h, s, l)), hex);
+        }
+        for text in ["", "#12", "#12345g", "rgb(1, 2)", "rgb(1, 2, 300)", "pink"] {
+            assert_eq!(parse_color(text), None, "{}", text);
+        }
+    }
+}
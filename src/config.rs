@@ -1,7 +1,407 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+use crate::units::{convert_si_to_unit, unknown_unit_message};
+
+#[derive(PartialEq, Debug, Clone)]
 pub struct NopeConfig {
     pub debug: bool,
     pub trace: bool,
+    pub trace_from: Option<usize>,
     pub echo_result: bool,
+    pub no_fs: bool,
+    pub no_net: bool,
+    pub no_exec: bool,
+    pub allowed_paths: Vec<String>,
+    pub memory_limit: Option<usize>,
+    pub gc_log: bool,
+    pub checked_int: bool,
+    pub prelude: Option<String>,
+    pub repl_echo: bool,
+    pub color: bool,
+    pub history_size: usize,
+    pub repl_step_limit: usize, // instructions, after that the REPL asks to keep running, 0 to never ask
+    pub max_call_depth: usize,
+    pub log_level: usize,
+    pub log_timestamps: bool,
+    pub typecheck: bool,
+    pub run_tests: bool,
+    pub snapshot_dir: Option<String>, // where assert_snapshot keeps its files, __snapshots__ by default
+    pub script_name: Option<String>, // shown in the usage printed by parse_args
+    pub script_args: Vec<String>, // the command line arguments after the script
+    pub display_unit: Option<String>, // the unit the REPL also shows the numbers in
+    pub float_digits: Option<usize>, // significant digits of the printed numbers, all of them by default
+    pub decimal_comma: bool,
+}
+
+// The log levels from the most to the least verbose, messages below
+// the configured level are not shown
+pub const LOG_LEVELS: [&str; 5] = ["debug", "info", "warn", "error", "off"];
+
+pub fn parse_log_level(name: &str) -> Option<usize> {
+    return LOG_LEVELS.iter().position(|level| level.eq_ignore_ascii_case(name.trim()));
+}
+
+impl NopeConfig {
+    pub const fn new() -> NopeConfig {
+        return NopeConfig {
+            debug: false,
+            trace: false,
+            trace_from: None,
+            echo_result: false,
+            no_fs: false,
+            no_net: false,
+            no_exec: false,
+            allowed_paths: Vec::new(),
+            memory_limit: None,
+            gc_log: false,
+            checked_int: false,
+            prelude: None,
+            repl_echo: true,
+            color: true,
+            history_size: 100,
+            repl_step_limit: 100_000_000,
+            max_call_depth: 10000,
+            log_level: 1,
+            log_timestamps: false,
+            typecheck: false,
+            run_tests: false,
+            snapshot_dir: None,
+            script_name: None,
+            script_args: Vec::new(),
+            display_unit: None,
+            float_digits: None,
+            decimal_comma: false,
+        };
+    }
+}
+
+impl Default for NopeConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The XDG variable is used when it is set, on all the platforms, otherwise the
+// usual directory of the platform
+fn user_dir(variable: &str, platform_dir: fn() -> Option<PathBuf>, app: &str) -> Option<PathBuf> {
+    let base = match env::var_os(variable) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => platform_dir()?,
+    };
+    return Some(base.join(app));
+}
+
+// Where an application keeps its settings, ~/.config/app on linux,
+// ~/Library/Application Support/app on macOS and %APPDATA%\app on windows
+pub fn app_config_dir(app: &str) -> Option<PathBuf> {
+    return user_dir("XDG_CONFIG_HOME", dirs::config_dir, app);
+}
+
+// Where an application keeps the files it can make again, ~/.cache/app on linux,
+// ~/Library/Caches/app on macOS and %LOCALAPPDATA%\app on windows
+pub fn app_cache_dir(app: &str) -> Option<PathBuf> {
+    return user_dir("XDG_CACHE_HOME", dirs::cache_dir, app);
+}
+
+// The user configuration of nope, ~/.config/nope is still used on the other
+// platforms than linux when it exists
+pub fn config_dir() -> Option<PathBuf> {
+    if env::var_os("XDG_CONFIG_HOME").is_none_or(|dir| dir.is_empty()) {
+        let previous = dirs::home_dir().map(|home| home.join(".config").join("nope"));
+        if let Some(previous) = previous.filter(|dir| dir.is_dir()) {
+            return Some(previous);
+        }
+    }
+    return app_config_dir("nope");
+}
+
+// The prelude is evaluated before the scripts and the REPL, so users
+// can define their own constants and helpers once
+pub fn default_prelude_path() -> Option<String> {
+    return config_dir().map(|dir| dir.join("prelude.nope").to_string_lossy().into_owned());
+}
+
+pub fn default_config_file_path() -> Option<String> {
+    return config_dir().map(|dir| dir.join("nope.toml").to_string_lossy().into_owned());
+}
+
+// Values of the small subset of toml used by the config file
+#[derive(PartialEq, Debug)]
+enum ConfigValue {
+    Bool(bool),
+    Int(usize),
+    Str(String),
+    List(Vec<String>),
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (idx, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..idx],
+            None => {},
+        }
+    }
+    return line;
+}
+
+fn parse_string(value: &str) -> Option<String> {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return Some(value[1..value.len()-1].to_owned());
+        }
+    }
+    return None;
+}
+
+fn parse_config_value(value: &str) -> Option<ConfigValue> {
+    let value = value.trim();
+    if value == "true" {
+        return Some(ConfigValue::Bool(true));
+    } else if value == "false" {
+        return Some(ConfigValue::Bool(false));
+    } else if let Ok(int) = value.replace('_', "").parse::<usize>() {
+        return Some(ConfigValue::Int(int));
+    } else if let Some(string) = parse_string(value) {
+        return Some(ConfigValue::Str(string));
+    } else if value.starts_with('[') && value.ends_with(']') {
+        let items = value[1..value.len()-1].trim().trim_end_matches(',');
+        if items.trim().is_empty() {
+            return Some(ConfigValue::List(vec![]));
+        }
+        let list: Option<Vec<String>> = items.split(',').map(|item| parse_string(item.trim())).collect();
+        return list.map(ConfigValue::List);
+    }
+    return None;
+}
+
+fn expand_home(path: &str) -> String {
+    if let (Some(rest), Some(home)) = (path.strip_prefix("~/"), dirs::home_dir()) {
+        return home.join(rest).to_string_lossy().into_owned();
+    }
+    return path.to_owned();
+}
+
+fn apply_config_value(config: &mut NopeConfig, key: &str, value: ConfigValue) -> Result<(), String> {
+    match (key, value) {
+        ("debug", ConfigValue::Bool(debug)) => config.debug = debug,
+        ("trace", ConfigValue::Bool(trace)) => config.trace = trace,
+        ("echo", ConfigValue::Bool(echo)) => config.repl_echo = echo,
+        ("color", ConfigValue::Bool(color)) => config.color = color,
+        ("history_size", ConfigValue::Int(size)) => config.history_size = size,
+        ("step_limit", ConfigValue::Int(limit)) => config.repl_step_limit = limit,
+        ("max_call_depth", ConfigValue::Int(depth)) => config.max_call_depth = depth,
+        ("checked_int", ConfigValue::Bool(checked)) => config.checked_int = checked,
+        ("typecheck", ConfigValue::Bool(typecheck)) => config.typecheck = typecheck,
+        ("display_unit", ConfigValue::Str(unit)) => {
+            if convert_si_to_unit(1.0, &unit).is_none() {
+                return Err(unknown_unit_message(&unit));
+            }
+            config.display_unit = Some(unit);
+        },
+        ("float_digits", ConfigValue::Int(digits)) => {
+            if !(1..=17).contains(&digits) {
+                return Err("float_digits must be between 1 and 17".to_owned());
+            }
+            config.float_digits = Some(digits);
+        },
+        ("decimal_comma", ConfigValue::Bool(comma)) => config.decimal_comma = comma,
+        ("log_level", ConfigValue::Str(level)) => {
+            config.log_level = parse_log_level(&level).ok_or(format!("unknown log level '{}'", level))?;
+        },
+        ("log_timestamps", ConfigValue::Bool(timestamps)) => config.log_timestamps = timestamps,
+        ("gc_log", ConfigValue::Bool(log)) => config.gc_log = log,
+        ("prelude", ConfigValue::Str(path)) => config.prelude = Some(expand_home(&path)),
+        ("prelude", ConfigValue::Bool(false)) => config.prelude = None,
+        ("memory_limit", ConfigValue::Int(size)) => config.memory_limit = Some(size),
+        ("memory_limit", ConfigValue::Str(size)) => {
+            config.memory_limit = Some(parse_memory_size(&size).ok_or("invalid memory size")?);
+        },
+        ("sandbox.no_fs", ConfigValue::Bool(no_fs)) => config.no_fs = no_fs,
+        ("sandbox.no_net", ConfigValue::Bool(no_net)) => config.no_net = no_net,
+        ("sandbox.no_exec", ConfigValue::Bool(no_exec)) => config.no_exec = no_exec,
+        ("sandbox.allow_paths", ConfigValue::List(paths)) => {
+            config.allowed_paths = paths.iter().map(|path| expand_home(path)).collect();
+        },
+        (_, value) => return Err(format!("unknown setting '{}' or invalid value {:?}", key, value)),
+    }
+    return Ok(());
+}
+
+// Reads the settings of a config file like this one into the config
+//
+//     debug = false
+//     echo = true
+//     history_size = 1000
+//     prelude = "~/nope/prelude.nope"
+//
+//     [sandbox]
+//     no_net = true
+//     allow_paths = ["~/data", "/tmp"]
+//
+// On error the config is left untouched
+pub fn parse_config_file(config: &mut NopeConfig, source: &str) -> Result<(), String> {
+    let mut parsed = config.clone();
+    let mut section = String::new();
+    for (idx, line) in source.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len()-1].trim().to_owned();
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or(format!("line {}: expected 'key = value'", idx + 1))?;
+        let key = if section.is_empty() {
+            key.trim().to_owned()
+        } else {
+            format!("{}.{}", section, key.trim())
+        };
+        let value = parse_config_value(value).ok_or(format!("line {}: invalid value for '{}'", idx + 1, key))?;
+        apply_config_value(&mut parsed, &key, value).map_err(|message| format!("line {}: {}", idx + 1, message))?;
+    }
+    *config = parsed;
+    return Ok(());
+}
+
+// The --allow-path directories are added to the allow_paths of the config file,
+// the command line can restrict the sandbox more but never less
+pub fn add_allowed_paths<'a>(config: &mut NopeConfig, paths: impl Iterator<Item = &'a str>) {
+    for path in paths {
+        if !config.allowed_paths.iter().any(|allowed| allowed == path) {
+            config.allowed_paths.push(path.to_owned());
+        }
+    }
+}
+
+// A missing config file is not an error
+pub fn load_config_file(config: &mut NopeConfig, path: &str) -> Result<(), String> {
+    match fs::read_to_string(path) {
+        Ok(source) => parse_config_file(config, &source),
+        Err(_) => Ok(()),
+    }
+}
+
+// Parses sizes like "512", "64k", "100M" or "2G" into a number of bytes
+pub fn parse_memory_size(size: &str) -> Option<usize> {
+    let size = size.trim();
+    let (digits, multiplier) = match size.chars().last()?.to_ascii_lowercase() {
+        'k' => (&size[..size.len()-1], 1024),
+        'm' => (&size[..size.len()-1], 1024 * 1024),
+        'g' => (&size[..size.len()-1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+    return digits.parse::<usize>().ok()?.checked_mul(multiplier);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_prelude_path() {
+        let path = default_prelude_path().unwrap();
+        assert!(path.ends_with("nope/prelude.nope"), "{}", path);
+    }
+
+    #[test]
+    fn test_user_dir() {
+        env::set_var("NOPE_TEST_USER_DIR", "/tmp/base");
+        assert_eq!(user_dir("NOPE_TEST_USER_DIR", dirs::cache_dir, "app"), Some(PathBuf::from("/tmp/base/app")));
+        env::set_var("NOPE_TEST_USER_DIR", "");
+        assert_eq!(user_dir("NOPE_TEST_USER_DIR", || Some(PathBuf::from("/platform")), "app"), Some(PathBuf::from("/platform/app")));
+        assert_eq!(user_dir("NOPE_TEST_USER_DIR", || None, "app"), None);
+        assert!(app_config_dir("myapp").is_some_and(|dir| dir.ends_with("myapp")));
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        let mut config = NopeConfig::new();
+        let source = "
+# my settings
+debug = true
+echo = false # no echo in the repl
+history_size = 1_000
+max_call_depth = 500
+step_limit = 0
+float_digits = 6
+decimal_comma = true
+log_level = \"warn\"
+prelude = '/tmp/my prelude.nope'
+memory_limit = \"64M\"
+
+[sandbox]
+no_net = true
+no_exec = true
+allow_paths = [\"/tmp\", 'data#1',]
+";
+        assert_eq!(parse_config_file(&mut config, source), Ok(()));
+        assert_eq!(config, NopeConfig {
+            debug: true,
+            repl_echo: false,
+            history_size: 1000,
+            max_call_depth: 500,
+            repl_step_limit: 0,
+            float_digits: Some(6),
+            decimal_comma: true,
+            log_level: 2,
+            prelude: Some("/tmp/my prelude.nope".to_owned()),
+            memory_limit: Some(64 * 1024 * 1024),
+            no_net: true,
+            no_exec: true,
+            allowed_paths: vec!["/tmp".to_owned(), "data#1".to_owned()],
+            ..NopeConfig::new()
+        });
+    }
+
+    #[test]
+    fn test_command_line_after_config_file() {
+        let mut config = NopeConfig::new();
+        assert_eq!(parse_config_file(&mut config, "[sandbox]\nno_net = true\nallow_paths = ['/tmp', 'data']"), Ok(()));
+        add_allowed_paths(&mut config, ["data", "out"].into_iter());
+        assert_eq!(config.allowed_paths, vec!["/tmp".to_owned(), "data".to_owned(), "out".to_owned()]);
+        assert!(config.no_net);
+    }
+
+    #[test]
+    fn test_parse_config_file_disable_prelude() {
+        let mut config = NopeConfig { prelude: Some("prelude.nope".to_owned()), ..NopeConfig::new() };
+        assert_eq!(parse_config_file(&mut config, "prelude = false"), Ok(()));
+        assert_eq!(config.prelude, None);
+    }
+
+    #[test]
+    fn test_parse_config_file_errors() {
+        for source in ["debug", "debug = 3", "colour = true", "history_size = -1", "no_net = true", "[sandbox]\nallow_paths = [1, 2]",
+                       "display_unit = 'furlong'", "float_digits = 0", "float_digits = 18"] {
+            let mut config = NopeConfig::new();
+            assert!(parse_config_file(&mut config, &format!("trace = true\n{}", source)).is_err(), "{}", source);
+            assert_eq!(config, NopeConfig::new());
+        }
+    }
+
+    #[test]
+    fn test_parse_log_level() {
+        assert_eq!(parse_log_level("debug"), Some(0));
+        assert_eq!(parse_log_level("ERROR"), Some(3));
+        assert_eq!(parse_log_level("off"), Some(4));
+        assert_eq!(parse_log_level("verbose"), None);
+    }
+
+    #[test]
+    fn test_parse_memory_size() {
+        assert_eq!(parse_memory_size("512"), Some(512));
+        assert_eq!(parse_memory_size("64k"), Some(64 * 1024));
+        assert_eq!(parse_memory_size("100M"), Some(100 * 1024 * 1024));
+        assert_eq!(parse_memory_size("2G"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_memory_size(""), None);
+        assert_eq!(parse_memory_size("M"), None);
+        assert_eq!(parse_memory_size("12x"), None);
+    }
 }
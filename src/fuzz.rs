@@ -0,0 +1,35 @@
+// The entry points of the fuzz targets in fuzz/, built with the fuzz feature.
+// They must never panic, whatever the bytes: an invalid source only gives errors.
+use crate::{config::NopeConfig, parser::Parser, tokenizer::Tokenizer};
+
+fn source(data: &[u8]) -> Option<String> {
+    return std::str::from_utf8(data).ok().map(str::to_owned);
+}
+
+pub fn tokenize(data: &[u8]) {
+    if let Some(source) = source(data) {
+        let mut tokenizer = Tokenizer::new(source);
+        tokenizer.tokenize();
+    }
+}
+
+// The plain parse, not parse_no_panic, so that the fuzzer sees the panics
+pub fn parse(data: &[u8]) {
+    if let Some(source) = source(data) {
+        let mut parser = Parser::new(NopeConfig::new(), source);
+        parser.parse();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_malformed_sources() {
+        for data in [&b""[..], b"(", b"let", b"|a", b"'abc", b"1 +", b"if ,", b"%%", b"\xff\xfe", b"[[\n]] ))"] {
+            tokenize(data);
+            parse(data);
+        }
+    }
+}
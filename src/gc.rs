@@ -1,17 +1,18 @@
 use std::{
     any::Any,
     any::type_name,
-//    collections::VecDeque,
+    collections::VecDeque,
     marker::PhantomData,
     collections::HashMap,
     mem,
     fmt,
     hash,
+    time::{Duration, Instant},
 };
 
 use fmt::Debug;
 
-// use crate::chunk::{Table, Value};
+use crate::chunk::Value;
 
 pub trait GcTrace {
     fn format(&self, f: &mut fmt::Formatter, gc: &Gc) -> fmt::Result;
@@ -73,34 +74,97 @@ impl hash::Hash for GcRef<String> {
 }
 
 struct GcObjectHeader {
-//    is_marked: bool,
-//    size: usize,
+    is_marked: bool,
+    size: usize,
     obj: Box<dyn GcTrace>,
 }
 
 pub struct Gc {
     bytes_allocated: usize,
-//    next_gc: usize,
+    peak_bytes_allocated: usize,
+    next_gc: usize,
+    memory_limit: Option<usize>,
+    log: bool,
+    collections: usize,
+    total_pause: Duration,
     free_slots: Vec<usize>,
     objects: Vec<Option<GcObjectHeader>>,
     strings: HashMap<String, GcRef<String>>,
-//    grey_stack: VecDeque<usize>,
+    grey_stack: VecDeque<usize>,
 }
 
 impl Gc {
-//    const HEAP_GROW_FACTOR: usize = 2;
+    const HEAP_GROW_FACTOR: usize = 2;
+    const MIN_NEXT_GC: usize = 1024 * 1024;
+    const SHRINK_FACTOR: usize = 4;
 
     pub fn new() -> Self {
         Gc {
             bytes_allocated: 0,
-//            next_gc: 1024 * 1024,
+            peak_bytes_allocated: 0,
+            next_gc: Gc::MIN_NEXT_GC,
+            memory_limit: None,
+            log: false,
+            collections: 0,
+            total_pause: Duration::ZERO,
             free_slots: Vec::new(),
             objects: Vec::new(),
             strings: HashMap::new(),
-//            grey_stack: VecDeque::new(),
+            grey_stack: VecDeque::new(),
         }
     }
 
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    pub fn set_log(&mut self, log: bool) {
+        self.log = log;
+    }
+
+    #[allow(dead_code)]
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    #[allow(dead_code)]
+    pub fn peak_bytes_allocated(&self) -> usize {
+        self.peak_bytes_allocated
+    }
+
+    #[allow(dead_code)]
+    pub fn interned_count(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    pub fn over_limit(&self) -> bool {
+        match self.memory_limit {
+            Some(limit) => self.bytes_allocated > limit,
+            None => false,
+        }
+    }
+
+    pub fn stats(&self) -> String {
+        let limit = match self.memory_limit {
+            Some(limit) => format!("{} bytes", limit),
+            None => "none".to_owned(),
+        };
+        format!(
+            "allocated: {} bytes, peak: {} bytes, objects: {}, interned: {}, collections: {}, pause: {}us, limit: {}",
+            self.bytes_allocated,
+            self.peak_bytes_allocated,
+            self.objects.len() - self.free_slots.len(),
+            self.strings.len(),
+            self.collections,
+            self.total_pause.as_micros(),
+            limit,
+        )
+    }
+
     pub fn alloc<T: GcTrace + 'static + Debug>(&mut self, object: T) -> GcRef<T> {
         #[cfg(feature = "debug_log_gc")]
         let repr = format!("{:?}", object)
@@ -110,9 +174,10 @@ impl Gc {
             .collect::<String>();
         let size = object.size() + mem::size_of::<GcObjectHeader>();
         self.bytes_allocated += size;
+        self.peak_bytes_allocated = self.peak_bytes_allocated.max(self.bytes_allocated);
         let entry = GcObjectHeader {
-//            is_marked: false,
-//            size,
+            is_marked: false,
+            size,
             obj: Box::new(object),
         };
         let index = match self.free_slots.pop() {
@@ -160,118 +225,215 @@ impl Gc {
             .unwrap_or_else(|| panic!("Reference {} not found", reference.index))
     }
 
-//    pub fn deref_mut<T: GcTrace + 'static>(&mut self, reference: GcRef<T>) -> &mut T {
-//        self.objects[reference.index]
-//            .as_mut()
-//            .unwrap()
-//            .obj
-//            .as_any_mut()
-//            .downcast_mut()
-//            .unwrap_or_else(|| panic!("Reference {} not found", reference.index))
-//    }
-
-//    fn free(&mut self, index: usize) {
-//        #[cfg(feature = "debug_log_gc")]
-//        println!("free (id:{})", index,);
-//        if let Some(old) = self.objects[index].take() {       
-//            self.bytes_allocated -= old.size;
-//            self.free_slots.push(index)
-//        } else {
-//            panic!("Double free on {}", index)
-//        }
-//    }
-
-//    pub fn collect_garbage(&mut self) {
-//        #[cfg(feature = "debug_log_gc")]
-//        let before = self.bytes_allocated;
-//
-//        self.trace_references();
-//        self.remove_white_strings();
-//        self.sweep();
-//        self.next_gc = self.bytes_allocated * Gc::HEAP_GROW_FACTOR;
-//
-//        #[cfg(feature = "debug_log_gc")]
-//        println!(
-//            "collected {} bytes (from {} to {}) next at {}\n",
-//            before - self.bytes_allocated,
-//            before,
-//            self.bytes_allocated,
-//            self.next_gc
-//        );
-//    }
-
-//    fn trace_references(&mut self) {
-//        while let Some(index) = self.grey_stack.pop_back() {
-//            self.blacken_object(index);
-//        }
-//    }
-
-//    fn blacken_object(&mut self, index: usize) {
-//        #[cfg(feature = "debug_log_gc")]
-//        println!("blacken(id:{})", index);
-//
-//        // Hack to trick the borrow checker to be able to call trace on an element.
-//        let object = self.objects[index].take();
-//        object.as_ref().unwrap().obj.trace(self);
-//        self.objects[index] = object;
-//    }
-
-    //pub fn mark_value(&mut self, value: Value) {
-    //    value.trace(self);
-    //}
-
-//    pub fn mark_object<T: GcTrace>(&mut self, obj: GcRef<T>) {
-//        if let Some(object) = self.objects[obj.index].as_mut() {
-//            if object.is_marked {
-//                return;
-//            }
-//    
-//            #[cfg(feature = "debug_log_gc")]
-//            println!(
-//                "mark(id:{}, type:{}, val:{:?})",
-//                obj.index,
-//                type_name::<T>(),
-//                obj
-//            );
-//            object.is_marked = true;
-//            self.grey_stack.push_back(obj.index);
-//        } else {
-//            panic!("Marking already disposed object {}", obj.index)
-//        }
-//    }
-
-    // pub fn mark_table(&mut self, table: &Table) {
-    //     for (&k, &v) in table {
-    //         self.mark_object(k);
-    //         self.mark_value(v);
-    //     }
-    // }
+    pub fn deref_mut<T: GcTrace + 'static>(&mut self, reference: GcRef<T>) -> &mut T {
+        self.objects[reference.index]
+            .as_mut()
+            .unwrap()
+            .obj
+            .as_any_mut()
+            .downcast_mut()
+            .unwrap_or_else(|| panic!("Reference {} not found", reference.index))
+    }
+
+    // Updates the allocated bytes after an object was mutated in place
+    pub fn resize<T: GcTrace>(&mut self, reference: GcRef<T>) {
+        if let Some(object) = self.objects[reference.index].as_mut() {
+            let size = object.obj.size() + mem::size_of::<GcObjectHeader>();
+            self.bytes_allocated = self.bytes_allocated - object.size + size;
+            object.size = size;
+        }
+    }
+
+    fn free(&mut self, index: usize) {
+        #[cfg(feature = "debug_log_gc")]
+        println!("free (id:{})", index,);
+        if let Some(old) = self.objects[index].take() {
+            self.bytes_allocated -= old.size;
+            self.free_slots.push(index)
+        } else {
+            panic!("Double free on {}", index)
+        }
+    }
+
+    // The roots (stack, globals, constants) must have been marked
+    // with mark_value() and mark_object() before calling this.
+    pub fn collect_garbage(&mut self) {
+        let start = Instant::now();
+        let before = self.bytes_allocated;
+        let objects_before = self.objects.len() - self.free_slots.len();
+
+        self.trace_references();
+        self.remove_white_strings();
+        self.sweep();
+        self.shrink();
+        self.next_gc = (self.bytes_allocated * Gc::HEAP_GROW_FACTOR).max(Gc::MIN_NEXT_GC);
+
+        let pause = start.elapsed();
+        self.collections += 1;
+        self.total_pause += pause;
+
+        if self.log {
+            eprintln!(
+                "gc: collected {} bytes (from {} to {}), freed {} objects, next at {}, pause {}us",
+                before - self.bytes_allocated,
+                before,
+                self.bytes_allocated,
+                objects_before - (self.objects.len() - self.free_slots.len()),
+                self.next_gc,
+                pause.as_micros(),
+            );
+        }
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(index) = self.grey_stack.pop_back() {
+            self.blacken_object(index);
+        }
+    }
+
+    fn blacken_object(&mut self, index: usize) {
+        #[cfg(feature = "debug_log_gc")]
+        println!("blacken(id:{})", index);
+
+        // Hack to trick the borrow checker to be able to call trace on an element.
+        let object = self.objects[index].take();
+        object.as_ref().unwrap().obj.trace(self);
+        self.objects[index] = object;
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        match value {
+            Value::String(reference) => self.mark_object(reference),
+            Value::Buffer(reference) => self.mark_object(reference),
+            Value::Stats(reference) => self.mark_object(reference),
+            Value::Lazy(reference) => self.mark_object(reference),
+            Value::Generator(reference) => self.mark_object(reference),
+            Value::Task(reference) => self.mark_object(reference),
+            Value::Channel(reference) => self.mark_object(reference),
+            Value::Socket(reference) => self.mark_object(reference),
+            Value::Lines(reference) => self.mark_object(reference),
+            Value::Watch(reference) => self.mark_object(reference),
+            Value::Process(reference) => self.mark_object(reference),
+            Value::Request(reference) => self.mark_object(reference),
+            Value::Progress(reference) => self.mark_object(reference),
+            Value::Drawing(reference) => self.mark_object(reference),
+            _ => {},
+        }
+    }
+
+    pub fn mark_object<T: GcTrace>(&mut self, obj: GcRef<T>) {
+        if let Some(object) = self.objects[obj.index].as_mut() {
+            if object.is_marked {
+                return;
+            }
+
+            #[cfg(feature = "debug_log_gc")]
+            println!(
+                "mark(id:{}, type:{}, val:{:?})",
+                obj.index,
+                type_name::<T>(),
+                obj
+            );
+            object.is_marked = true;
+            self.grey_stack.push_back(obj.index);
+        } else {
+            panic!("Marking already disposed object {}", obj.index)
+        }
+    }
 
     #[cfg(feature = "debug_stress_gc")]
     pub fn should_gc(&self) -> bool {
         true
     }
 
-//    #[cfg(not(feature = "debug_stress_gc"))]
-//    pub fn should_gc(&self) -> bool {
-//        self.bytes_allocated > self.next_gc
-//    }
-
-//    fn sweep(&mut self) {
-//        for i in 0..self.objects.len() {
-//            if let Some(mut object) = self.objects[i].as_mut() {
-//                if object.is_marked {
-//                    object.is_marked = false;
-//                } else {
-//                    self.free(i);
-//                }
-//            }
-//        }
-//    }
-
-//    fn remove_white_strings(&mut self) {
-//        let strings = &mut self.strings;
-//        let objects = &self.objects;
-//        strings.retain(|_k, v| objects[v.index].as_ref().unwrap().is_marked);
-//    }
+    #[cfg(not(feature = "debug_stress_gc"))]
+    pub fn should_gc(&self) -> bool {
+        self.bytes_allocated > self.next_gc || self.over_limit()
+    }
+
+    fn sweep(&mut self) {
+        for i in 0..self.objects.len() {
+            if let Some(object) = self.objects[i].as_mut() {
+                if object.is_marked {
+                    object.is_marked = false;
+                } else {
+                    self.free(i);
+                }
+            }
+        }
+    }
+
+    // The intern table doesn't keep strings alive, unreferenced
+    // strings are removed from it before being swept.
+    fn remove_white_strings(&mut self) {
+        let strings = &mut self.strings;
+        let objects = &self.objects;
+        strings.retain(|_k, v| objects[v.index].as_ref().unwrap().is_marked);
+    }
+
+    // Gives back the memory of the intern table and of the object slots
+    // when a collection freed most of them.
+    fn shrink(&mut self) {
+        if self.strings.capacity() > Gc::SHRINK_FACTOR * (self.strings.len() + 64) {
+            self.strings.shrink_to(self.strings.len() * 2);
+        }
+        while let Some(None) = self.objects.last() {
+            self.objects.pop();
+        }
+        let len = self.objects.len();
+        self.free_slots.retain(|&index| index < len);
+        if self.objects.capacity() > Gc::SHRINK_FACTOR * (len + 64) {
+            self.objects.shrink_to(len * 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unreferenced_strings_are_freed() {
+        let mut gc = Gc::new();
+        let kept = gc.intern("kept".to_owned());
+        for i in 0..1000 {
+            gc.intern(format!("temporary {}", i));
+        }
+        assert_eq!(gc.interned_count(), 1001);
+        gc.mark_object(kept);
+        gc.collect_garbage();
+        assert_eq!(gc.interned_count(), 1);
+        assert_eq!(gc.deref(kept), "kept");
+        assert_eq!(gc.intern("kept".to_owned()), kept);
+        assert_eq!(gc.bytes_allocated(), String::from("kept").size() + mem::size_of::<GcObjectHeader>());
+    }
+
+    #[test]
+    fn test_freed_strings_can_be_interned_again() {
+        let mut gc = Gc::new();
+        gc.intern("foo".to_owned());
+        gc.collect_garbage();
+        assert_eq!(gc.interned_count(), 0);
+        assert_eq!(gc.bytes_allocated(), 0);
+        let foo = gc.intern("foo".to_owned());
+        assert_eq!(gc.deref(foo), "foo");
+        assert_eq!(gc.interned_count(), 1);
+    }
+
+    #[test]
+    fn test_marked_values_are_kept() {
+        let mut gc = Gc::new();
+        let a = gc.intern("a".to_owned());
+        let b = gc.intern("b".to_owned());
+        gc.intern("c".to_owned());
+        gc.mark_value(Value::String(a));
+        gc.mark_value(Value::String(b));
+        gc.mark_value(Value::Num(3.0));
+        gc.collect_garbage();
+        assert_eq!(gc.interned_count(), 2);
+        gc.mark_value(Value::String(b));
+        gc.collect_garbage();
+        assert_eq!(gc.interned_count(), 1);
+        assert_eq!(gc.deref(b), "b");
+    }
 }
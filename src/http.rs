@@ -0,0 +1,105 @@
+use std::io::BufRead;
+
+// The largest request body the server accepts
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(PartialEq, Debug)]
+pub struct ParsedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+// Reads the request line, the headers and the body of an HTTP/1.x request
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<ParsedRequest, String> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/") => {
+            (method.to_owned(), path.to_owned())
+        },
+        _ => return Err(format!("invalid request line '{}'", line.trim_end())),
+    };
+
+    let mut headers = vec![];
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Err("the connection was closed before the end of the headers".to_owned());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').ok_or(format!("invalid header '{}'", header))?;
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+
+    let length = match headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("content-length")) {
+        Some((_, length)) => length.parse::<usize>().map_err(|_| format!("invalid content length '{}'", length))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(format!("the request body of {} bytes is too large", length));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+
+    return Ok(ParsedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+}
+
+pub fn format_response(status: u16, body: &str) -> String {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "",
+    };
+    return format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_request() {
+        let mut input = Cursor::new("POST /notes?id=3 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 5\r\n\r\nhello and more");
+        assert_eq!(read_request(&mut input), Ok(ParsedRequest {
+            method: "POST".to_owned(),
+            path: "/notes?id=3".to_owned(),
+            headers: vec![
+                ("Host".to_owned(), "localhost".to_owned()),
+                ("content-length".to_owned(), "5".to_owned()),
+            ],
+            body: "hello".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn test_read_request_errors() {
+        for input in ["", "GET /\r\n\r\n", "GET / HTTP/1.1\r\nHost", "GET / HTTP/1.1\r\nbad header\r\n\r\n",
+                      "POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"] {
+            assert!(read_request(&mut Cursor::new(input)).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_format_response() {
+        assert_eq!(
+            format_response(404, "nope"),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 4\r\nConnection: close\r\n\r\nnope",
+        );
+    }
+}
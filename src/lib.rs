@@ -0,0 +1,31 @@
+#![allow(clippy::needless_return)]
+
+// The interpreter as a library, the nope binary is a command line over it and
+// the fuzz targets feed it random sources
+pub mod config;
+pub mod tokenizer;
+pub mod parser;
+mod penv;
+mod stdlib;
+mod natives;
+mod units;
+mod money;
+mod color;
+mod svg;
+mod archive;
+mod chunk;
+pub mod vm;
+pub mod repl;
+mod gc;
+mod objects;
+mod consts;
+pub mod vim;
+mod sandbox;
+mod http;
+mod clipboard;
+pub mod terminal;
+mod cli;
+mod typecheck;
+
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
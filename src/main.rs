@@ -1,30 +1,18 @@
 #![allow(clippy::needless_return)]
 
 use std::fs;
+use std::path::Path;
 use clap::{Arg, Command};
+use colored::Colorize;
 
-mod config;
-mod tokenizer;
-mod parser;
-mod penv;
-mod stdlib;
-mod units;
-mod chunk;
-mod vm;
-mod repl;
-mod gc;
-mod objects;
-mod consts;
-mod vim;
-
-
-use crate::{
+use nope_lang::{
     tokenizer::Tokenizer,
     parser::Parser,
-    vm::Vm,
-    config::NopeConfig,
+    vm::{Vm, InterpretResult},
+    config::{NopeConfig, parse_memory_size, parse_log_level, default_prelude_path, default_config_file_path, load_config_file, add_allowed_paths},
     repl::repl,
     vim::install_vim_plugin,
+    terminal::catch_interrupts,
 };
 
 
@@ -61,6 +49,15 @@ fn main() {
                 .help("Prints the ast of the program")
                 .required(false)
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(["text", "json", "dot"])
+                .help("Output format of --ast (text, json or dot) and --tokenize (text or json)")
+                .required(false)
+        )
         .arg(
             Arg::new("debug")
                 .long("debug")
@@ -76,12 +73,146 @@ fn main() {
                 .help("Print stack and instruction during execution")
                 .required(false)
         )
+        .arg(
+            Arg::new("trace-from")
+                .long("trace-from")
+                .takes_value(true)
+                .value_name("LINE")
+                .help("Start tracing when execution reaches LINE")
+                .required(false)
+        )
         .arg(
             Arg::new("eval")
                 .long("eval")
                 .short('e')
                 .takes_value(true)
-                .help("Evaluates the code provided as argument value")
+                .multiple_occurrences(true)
+                .help("Evaluates the code provided as argument value, can be repeated")
+                .required(false)
+        )
+        .arg(
+            Arg::new("json-output")
+                .long("json-output")
+                .takes_value(false)
+                .help("Print the result of the program as JSON")
+                .required(false)
+        )
+        .arg(
+            Arg::new("exit-with-result")
+                .long("exit-with-result")
+                .takes_value(false)
+                .help("Exit with status 0 if the result of the program is truthy, 1 otherwise")
+                .required(false)
+        )
+        .arg(
+            Arg::new("no-fs")
+                .long("no-fs")
+                .takes_value(false)
+                .help("Deny all filesystem access to the program")
+                .required(false)
+        )
+        .arg(
+            Arg::new("no-net")
+                .long("no-net")
+                .takes_value(false)
+                .help("Deny all network access to the program")
+                .required(false)
+        )
+        .arg(
+            Arg::new("no-exec")
+                .long("no-exec")
+                .takes_value(false)
+                .help("Deny running other commands from the program")
+                .required(false)
+        )
+        .arg(
+            Arg::new("allow-path")
+                .long("allow-path")
+                .takes_value(true)
+                .value_name("DIR")
+                .multiple_occurrences(true)
+                .help("Restrict filesystem access to DIR, can be repeated")
+                .required(false)
+        )
+        .arg(
+            Arg::new("memory-limit")
+                .long("memory-limit")
+                .takes_value(true)
+                .value_name("SIZE")
+                .help("Maximum heap size of the program, ex: 64M")
+                .required(false)
+        )
+        .arg(
+            Arg::new("gc-log")
+                .long("gc-log")
+                .takes_value(false)
+                .help("Print garbage collection events")
+                .required(false)
+        )
+        .arg(
+            Arg::new("max-call-depth")
+                .long("max-call-depth")
+                .takes_value(true)
+                .value_name("DEPTH")
+                .help("Maximum depth of nested function calls, 10000 by default")
+                .required(false)
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .takes_value(true)
+                .value_name("LEVEL")
+                .help("Minimum level of the log messages to show: debug, info, warn, error or off")
+                .required(false)
+        )
+        .arg(
+            Arg::new("log-timestamps")
+                .long("log-timestamps")
+                .takes_value(false)
+                .help("Prefix the log messages with the time")
+                .required(false)
+        )
+        .arg(
+            Arg::new("checked-int")
+                .long("checked-int")
+                .takes_value(false)
+                .help("Stop with an error when int32 arithmetic overflows instead of wrapping")
+                .required(false)
+        )
+        .arg(
+            Arg::new("typecheck")
+                .long("typecheck")
+                .takes_value(false)
+                .help("Check the type annotations before running")
+                .required(false)
+        )
+        .arg(
+            Arg::new("test")
+                .long("test")
+                .takes_value(false)
+                .help("Run the test blocks of the program after it")
+                .required(false)
+        )
+        .arg(
+            Arg::new("no-prelude")
+                .long("no-prelude")
+                .takes_value(false)
+                .help("Don't evaluate ~/.config/nope/prelude.nope before the program")
+                .required(false)
+        )
+        .arg(
+            Arg::new("no-color")
+                .long("no-color")
+                .takes_value(false)
+                .help("Disable colored output")
+                .required(false)
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Read the settings from FILE instead of ~/.config/nope/nope.toml")
                 .required(false)
         )
         .arg(
@@ -93,18 +224,97 @@ fn main() {
         )
         .arg(
             Arg::new("filename")
-                .help("The path to the source code")
+                .help("The path to the source code, followed by the arguments of the script")
                 .index(1)
+                .multiple_values(true)
                 .required(false)
         )
+        .trailing_var_arg(true)
         .after_help("")
         .get_matches();
 
+    // Settings come from the config file first, the command line flags override them
     let mut config = NopeConfig {
-        debug: m.is_present("debug"),
-        trace: m.is_present("trace"),
-        echo_result: false,
+        prelude: default_prelude_path(),
+        ..NopeConfig::new()
     };
+    let config_path = match m.value_of("config") {
+        Some(path) => Some(String::from(path)),
+        None => default_config_file_path(),
+    };
+    if let Some(path) = config_path {
+        if let Err(message) = load_config_file(&mut config, &path) {
+            eprintln!("{} {}: {}", "warning:".yellow(), path, message);
+        }
+    }
+    if m.is_present("debug") {
+        config.debug = true;
+    }
+    if m.is_present("trace") {
+        config.trace = true;
+    }
+    if let Some(line) = m.value_of("trace-from") {
+        config.trace_from = Some(line.parse::<usize>().expect("Invalid --trace-from line number"));
+    }
+    if m.is_present("no-fs") {
+        config.no_fs = true;
+    }
+    if m.is_present("no-net") {
+        config.no_net = true;
+    }
+    if m.is_present("no-exec") {
+        config.no_exec = true;
+    }
+    if let Some(paths) = m.values_of("allow-path") {
+        add_allowed_paths(&mut config, paths);
+    }
+    if let Some(size) = m.value_of("memory-limit") {
+        config.memory_limit = Some(parse_memory_size(size).expect("Invalid --memory-limit size"));
+    }
+    if m.is_present("gc-log") {
+        config.gc_log = true;
+    }
+    if let Some(depth) = m.value_of("max-call-depth") {
+        config.max_call_depth = depth.parse::<usize>().expect("Invalid --max-call-depth depth");
+    }
+    if let Some(level) = m.value_of("log-level") {
+        config.log_level = parse_log_level(level).expect("Invalid --log-level level");
+    }
+    if m.is_present("log-timestamps") {
+        config.log_timestamps = true;
+    }
+    if m.is_present("checked-int") {
+        config.checked_int = true;
+    }
+    if m.is_present("typecheck") {
+        config.typecheck = true;
+    }
+    if m.is_present("test") {
+        config.run_tests = true;
+    }
+    // the snapshots of a script are kept next to it
+    if let Some(dir) = m.value_of("filename").and_then(|filename| Path::new(filename).parent()) {
+        config.snapshot_dir = Some(dir.join("__snapshots__").to_string_lossy().into_owned());
+    }
+    // everything after the script is for the script, with -e there is no script to skip
+    let script_args = m.values_of("filename").into_iter().flatten().map(String::from);
+    if m.is_present("eval") {
+        config.script_args = script_args.collect();
+    } else {
+        config.script_name = m.value_of("filename").map(String::from);
+        config.script_args = script_args.skip(1).collect();
+    }
+    if m.is_present("no-prelude") {
+        config.prelude = None;
+    }
+    if m.is_present("no-color") {
+        config.color = false;
+    }
+    if !config.color {
+        colored::control::set_override(false);
+    }
+
+    catch_interrupts();
 
     if m.is_present("install-vim-plugin") {
         install_vim_plugin().expect("Couldn't install vim plugin");
@@ -112,34 +322,76 @@ fn main() {
     }
 
     if !(m.is_present("eval") || m.is_present("filename")) {
-        config.echo_result = true;
+        config.echo_result = config.repl_echo;
         let mut vm = Vm::new(config);
+        vm.load_prelude();
         repl(&mut vm);
         return;
     }
 
-    let source = if m.is_present("eval") {
-        String::from(m.value_of("eval").expect("no code provided to --eval argument"))
-    } else {
-        let filename = m.value_of("filename").expect("No file argument provided");
-        fs::read_to_string(filename).expect("Could not read file")
+    // Repeated -e are evaluated in order and share their globals
+    let sources: Vec<String> = match m.values_of("eval") {
+        Some(codes) => codes.map(String::from).collect(),
+        None => {
+            let filename = m.value_of("filename").expect("No file argument provided");
+            vec![fs::read_to_string(filename).expect("Could not read file")]
+        },
     };
+    let source = sources.join("\n");
 
     if m.is_present("tokenize") {
         let mut tokenizer = Tokenizer::new(source);
         tokenizer.tokenize();
-        tokenizer.print();
+        if m.value_of("format") == Some("json") && !tokenizer.failed() {
+            println!("{}", tokenizer.tokens_to_json());
+        } else {
+            tokenizer.print();
+        }
     } else if m.is_present("parse") {
         let mut parser = Parser::new(config, source);
-        parser.parse();
+        parser.parse_no_panic();
         parser.tokenizer.print();
         parser.print();
     } else if m.is_present("ast") {
         let mut parser = Parser::new(config, source);
-        parser.parse();
-        parser.pretty_print();
+        parser.parse_no_panic();
+        match m.value_of("format") {
+            Some("json") if !parser.failed() => println!("{}", parser.ast_to_json()),
+            Some("dot") if !parser.failed() => println!("{}", parser.ast_to_dot()),
+            _ => parser.pretty_print(),
+        }
     } else {
         let mut vm = Vm::new(config);
-        vm.interpret(source);
+        vm.load_prelude();
+        let mut result = InterpretResult::Ok;
+        for source in sources {
+            result = vm.interpret(source);
+            if !matches!(result, InterpretResult::Ok) {
+                break;
+            }
+        }
+        if m.is_present("test") {
+            let status = match result {
+                InterpretResult::Ok if vm.run_tests() == 0 => 0,
+                InterpretResult::Ok => 1,
+                _ => 2,
+            };
+            // exit doesn't drop the vm, which removes the temporary files
+            drop(vm);
+            std::process::exit(status);
+        }
+        if m.is_present("json-output") && matches!(result, InterpretResult::Ok) {
+            println!("{}", vm.value_to_json(&vm.last_result()));
+        }
+        if m.is_present("exit-with-result") {
+            // errors get their own status so they can't be mistaken for a false result
+            let status = match result {
+                InterpretResult::Ok if vm.last_result().is_truthy() => 0,
+                InterpretResult::Ok => 1,
+                _ => 2,
+            };
+            drop(vm);
+            std::process::exit(status);
+        }
     }
 }
@@ -0,0 +1,85 @@
+// How the amounts of a currency are written: its symbol, on which side of the amount
+// (with the space between them when there is one), the number of decimals, and the
+// separators of the thousands and of the decimals
+struct Currency {
+    code: &'static str,
+    symbol: &'static str,
+    prefix: bool,
+    decimals: usize,
+    group: &'static str,
+    point: &'static str,
+}
+
+const CURRENCIES: [Currency; 16] = [
+    Currency { code: "USD", symbol: "$", prefix: true, decimals: 2, group: ",", point: "." },
+    Currency { code: "EUR", symbol: " €", prefix: false, decimals: 2, group: ".", point: "," },
+    Currency { code: "GBP", symbol: "£", prefix: true, decimals: 2, group: ",", point: "." },
+    Currency { code: "JPY", symbol: "¥", prefix: true, decimals: 0, group: ",", point: "." },
+    Currency { code: "CNY", symbol: "¥", prefix: true, decimals: 2, group: ",", point: "." },
+    Currency { code: "CHF", symbol: "CHF ", prefix: true, decimals: 2, group: "'", point: "." },
+    Currency { code: "CAD", symbol: "CA$", prefix: true, decimals: 2, group: ",", point: "." },
+    Currency { code: "AUD", symbol: "A$", prefix: true, decimals: 2, group: ",", point: "." },
+    Currency { code: "MXN", symbol: "MX$", prefix: true, decimals: 2, group: ",", point: "." },
+    Currency { code: "BRL", symbol: "R$ ", prefix: true, decimals: 2, group: ".", point: "," },
+    Currency { code: "INR", symbol: "₹", prefix: true, decimals: 2, group: ",", point: "." },
+    Currency { code: "KRW", symbol: "₩", prefix: true, decimals: 0, group: ",", point: "." },
+    Currency { code: "SEK", symbol: " kr", prefix: false, decimals: 2, group: " ", point: "," },
+    Currency { code: "NOK", symbol: " kr", prefix: false, decimals: 2, group: " ", point: "," },
+    Currency { code: "DKK", symbol: " kr.", prefix: false, decimals: 2, group: ".", point: "," },
+    Currency { code: "PLN", symbol: " zł", prefix: false, decimals: 2, group: " ", point: "," },
+];
+
+pub fn currency_codes() -> Vec<&'static str> {
+    return CURRENCIES.iter().map(|currency| currency.code).collect();
+}
+
+// The digits of the integer and decimal parts of a positive amount, rounded half away from
+// zero on its shortest decimal representation, so that 1.005 rounds to 1.01 even though the
+// closest float is slightly below it
+fn round_decimal(amount: f64, decimals: usize) -> (String, String) {
+    let text = format!("{}", amount);
+    let (int_part, fract_part) = text.split_once('.').unwrap_or((&text, ""));
+    let mut digits: Vec<u8> = int_part.bytes().chain(fract_part.bytes().chain(std::iter::repeat(b'0')).take(decimals)).collect();
+    if fract_part.as_bytes().get(decimals).is_some_and(|digit| *digit >= b'5') {
+        let mut idx = digits.len();
+        loop {
+            if idx == 0 {
+                digits.insert(0, b'1');
+                break;
+            }
+            idx -= 1;
+            if digits[idx] == b'9' {
+                digits[idx] = b'0';
+            } else {
+                digits[idx] += 1;
+                break;
+            }
+        }
+    }
+    let fract_digits = digits.split_off(digits.len() - decimals);
+    return (String::from_utf8(digits).unwrap_or_default(), String::from_utf8(fract_digits).unwrap_or_default());
+}
+
+// An amount written the way of its currency, like $1,234.50 or 1.234,50 €, or None if
+// the currency code is unknown
+pub fn format_money(amount: f64, code: &str) -> Option<String> {
+    let currency = CURRENCIES.iter().find(|currency| currency.code.eq_ignore_ascii_case(code))?;
+    let (int_digits, fract_digits) = round_decimal(amount.abs(), currency.decimals);
+    let mut number = String::new();
+    for (idx, digit) in int_digits.chars().enumerate() {
+        if idx > 0 && (int_digits.len() - idx) % 3 == 0 {
+            number.push_str(currency.group);
+        }
+        number.push(digit);
+    }
+    if !fract_digits.is_empty() {
+        number.push_str(currency.point);
+        number.push_str(&fract_digits);
+    }
+    let is_zero = int_digits.bytes().chain(fract_digits.bytes()).all(|digit| digit == b'0');
+    let sign = if amount < 0.0 && !is_zero { "-" } else { "" };
+    if currency.prefix {
+        return Some(format!("{}{}{}", sign, currency.symbol, number));
+    }
+    return Some(format!("{}{}{}", sign, number, currency.symbol));
+}
@@ -0,0 +1,1270 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use colored::*;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::{
+    chunk::Value,
+    sandbox::{check_fs_access, check_exec_access},
+    config::{LOG_LEVELS, parse_log_level, app_config_dir, app_cache_dir},
+    units::{convert_si_to_unit, convert_units, unknown_unit_message, to_human_bytes, from_human_bytes, UNIT_GROUPS},
+    money::{format_money, currency_codes},
+    color::{self, Rgb},
+    svg,
+    archive,
+    terminal,
+    cli::{self, OptionValue},
+    gc::GcRef,
+    objects::{RunningStats, HttpRequest, ProgressBar, Process, Drawing},
+    vm::Vm,
+};
+
+pub fn reverse(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let text = vm.value_to_str(&args[0]);
+    let reversed = vm.intern(text.chars().rev().collect());
+    return Ok(Value::String(reversed));
+}
+
+pub fn count(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let pattern = vm.value_to_str(&args[0]);
+    let text = vm.value_to_str(&args[1]);
+    if pattern.is_empty() {
+        return Ok(Value::Num(0.0));
+    }
+    return Ok(Value::Num(text.matches(&pattern).count() as f64));
+}
+
+// Modulo with the sign of the divisor, -7 %% 3 is 2
+pub fn floor_mod(a: f64, b: f64) -> f64 {
+    let rem = a % b;
+    if rem == 0.0 {
+        return f64::copysign(0.0, b);
+    } else if (rem < 0.0) != (b < 0.0) {
+        return rem + b;
+    }
+    return rem;
+}
+
+pub fn clamp(_vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let (x, lo, hi) = (args[0].num_equiv(), args[1].num_equiv(), args[2].num_equiv());
+    if x < lo {
+        return Ok(Value::Num(lo));
+    } else if x > hi {
+        return Ok(Value::Num(hi));
+    }
+    return Ok(Value::Num(x));
+}
+
+pub fn lerp(_vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let (a, b, t) = (args[0].num_equiv(), args[1].num_equiv(), args[2].num_equiv());
+    return Ok(Value::Num(a + (b - a) * t));
+}
+
+pub fn map_range(_vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let x = args[0].num_equiv();
+    let (a1, b1) = (args[1].num_equiv(), args[2].num_equiv());
+    let (a2, b2) = (args[3].num_equiv(), args[4].num_equiv());
+    return Ok(Value::Num(a2 + (x - a1) * (b2 - a2) / (b1 - a1)));
+}
+
+pub fn wrap(_vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let (x, lo, hi) = (args[0].num_equiv(), args[1].num_equiv(), args[2].num_equiv());
+    return Ok(Value::Num(lo + floor_mod(x - lo, hi - lo)));
+}
+
+// Rounds x to a multiple of the step. The quotient is snapped to the nearest integer
+// when it only misses it by a rounding error, so `floor_to 0.3 0.1` is 0.3, and the
+// result is rounded to the decimals of the step, so `round_to 1.26 0.05` is 1.25
+fn round_to_step(vm: &Vm, name: &str, args: &[Value], round: fn(f64) -> f64) -> Result<Value, String> {
+    let (x, step) = (args[0].num_equiv(), args[1].num_equiv());
+    if !(step > 0.0 && step.is_finite()) {
+        return Err(format!("{} expects a positive step, got {}", name, vm.value_to_repr(&args[1])));
+    }
+    let mut quotient = x / step;
+    if (quotient - quotient.round()).abs() < 1e-9 * quotient.abs().max(1.0) {
+        quotient = quotient.round();
+    }
+    let multiple = round(quotient) * step;
+    let decimals = format!("{}", step).split_once('.').map_or(0, |(_, fraction)| fraction.len());
+    return Ok(Value::Num(format!("{:.*}", decimals, multiple).parse().unwrap_or(multiple)));
+}
+
+pub fn round_to(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return round_to_step(vm, "round_to", args, f64::round);
+}
+
+pub fn ceil_to(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return round_to_step(vm, "ceil_to", args, f64::ceil);
+}
+
+pub fn floor_to(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return round_to_step(vm, "floor_to", args, f64::floor);
+}
+
+// Ties go to the even integer, `round_half_even 2.5` is 2 where `round 2.5` is 3
+pub fn round_half_even(_vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::Num(args[0].num_equiv().round_ties_even()));
+}
+
+// Integer arguments must be whole numbers that fit in an i64
+fn int_arg(vm: &Vm, name: &str, value: &Value) -> Result<i64, String> {
+    let num = value.num_equiv();
+    if num.fract() != 0.0 || num.is_nan() || num.abs() >= i64::MAX as f64 {
+        return Err(format!("{} expects integers, got {}", name, vm.value_to_repr(value)));
+    }
+    return Ok(num as i64);
+}
+
+fn natural_arg(vm: &Vm, name: &str, value: &Value) -> Result<u64, String> {
+    let int = int_arg(vm, name, value)?;
+    if int < 0 {
+        return Err(format!("{} expects non negative integers, got {}", name, int));
+    }
+    return Ok(int as u64);
+}
+
+fn overflow(name: &str) -> String {
+    return format!("integer overflow in {}", name);
+}
+
+// Above 2^53 the numbers are not all exact, a result that would be rounded
+// is an error instead
+fn exact_result(name: &str, result: u64) -> Result<Value, String> {
+    let num = result as f64;
+    if num as u128 != result as u128 {
+        return Err(format!("the result of {} is too large to be exact", name));
+    }
+    return Ok(Value::Num(num));
+}
+
+fn gcd_u64(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    return a;
+}
+
+pub fn gcd(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let a = int_arg(vm, "gcd", &args[0])?.unsigned_abs();
+    let b = int_arg(vm, "gcd", &args[1])?.unsigned_abs();
+    return exact_result("gcd", gcd_u64(a, b));
+}
+
+pub fn lcm(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let a = int_arg(vm, "lcm", &args[0])?.unsigned_abs();
+    let b = int_arg(vm, "lcm", &args[1])?.unsigned_abs();
+    if a == 0 || b == 0 {
+        return Ok(Value::Num(0.0));
+    }
+    let lcm = (a / gcd_u64(a, b)).checked_mul(b).ok_or_else(|| overflow("lcm"))?;
+    return exact_result("lcm", lcm);
+}
+
+pub fn factorial(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let n = natural_arg(vm, "factorial", &args[0])?;
+    let mut result: u64 = 1;
+    for i in 2..=n {
+        result = result.checked_mul(i).ok_or_else(|| overflow("factorial"))?;
+    }
+    return exact_result("factorial", result);
+}
+
+// The fraction closest to x with a denominator of at most max_den, chosen among the
+// convergents of the continued fraction of x and the semiconvergents after the last one
+fn best_fraction(x: f64, max_den: u64) -> (u128, u128) {
+    let max_den = max_den as u128;
+    let (mut p0, mut q0, mut p1, mut q1) = (0u128, 1u128, 1u128, 0u128);
+    let mut rest = x;
+    loop {
+        let a = rest.floor();
+        if q0 as f64 + a * q1 as f64 > max_den as f64 {
+            break;
+        }
+        let a = a as u128;
+        (p0, q0, p1, q1) = (p1, q1, p0 + a * p1, q0 + a * q1);
+        let fract = rest - rest.floor();
+        if fract < 1e-12 || p1 as f64 / q1 as f64 == x {
+            return (p1, q1);
+        }
+        rest = 1.0 / fract;
+    }
+    let k = (max_den - q0) / q1;
+    let (p2, q2) = (p0 + k * p1, q0 + k * q1);
+    if (p2 as f64 / q2 as f64 - x).abs() < (p1 as f64 / q1 as f64 - x).abs() {
+        return (p2, q2);
+    }
+    return (p1, q1);
+}
+
+pub fn to_fraction(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let x = args[0].num_equiv();
+    let max_den = natural_arg(vm, "to_fraction", &args[1])?;
+    if !x.is_finite() {
+        return Err(format!("to_fraction expects a finite number, got {}", vm.value_to_repr(&args[0])));
+    } else if max_den == 0 {
+        return Err("to_fraction expects a denominator of at least 1".to_owned());
+    }
+    let sign = if x < 0.0 { "-" } else { "" };
+    let text = if x.fract() == 0.0 {
+        format!("{}", x)
+    } else {
+        match best_fraction(x.abs(), max_den) {
+            (0, _) => "0".to_owned(),
+            (numerator, 1) => format!("{}{}", sign, numerator),
+            (numerator, denominator) => format!("{}{}/{}", sign, numerator, denominator),
+        }
+    };
+    let text = vm.intern(text);
+    return Ok(Value::String(text));
+}
+
+pub fn fmt_money(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let amount = args[0].num_equiv();
+    let code = vm.value_to_str(&args[1]);
+    if !amount.is_finite() {
+        return Err(format!("fmt_money expects a finite amount, got {}", vm.value_to_repr(&args[0])));
+    }
+    let text = format_money(amount, &code).ok_or_else(|| {
+        format!("unknown currency '{}', expected one of {}", code, currency_codes().join(", "))
+    })?;
+    let text = vm.intern(text);
+    return Ok(Value::String(text));
+}
+
+// number of ways to choose k elements out of n, without order
+pub fn comb(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let n = natural_arg(vm, "comb", &args[0])?;
+    let k = natural_arg(vm, "comb", &args[1])?;
+    if k > n {
+        return Ok(Value::Num(0.0));
+    }
+    let k = u64::min(k, n - k);
+    // each intermediate result is comb(n - k + i, i), which is
+    // smaller than the final result, so u128 can't overflow
+    let mut result: u128 = 1;
+    for i in 1..=k {
+        result = result * (n - k + i) as u128 / i as u128;
+        if result > u64::MAX as u128 {
+            return Err(overflow("comb"));
+        }
+    }
+    return exact_result("comb", result as u64);
+}
+
+// number of ways to choose k elements out of n, in order
+pub fn perm(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let n = natural_arg(vm, "perm", &args[0])?;
+    let k = natural_arg(vm, "perm", &args[1])?;
+    if k > n {
+        return Ok(Value::Num(0.0));
+    }
+    let mut result: u64 = 1;
+    for i in (n - k + 1)..=n {
+        result = result.checked_mul(i).ok_or_else(|| overflow("perm"))?;
+    }
+    return exact_result("perm", result);
+}
+
+fn stats_arg(vm: &Vm, name: &str, value: &Value) -> Result<GcRef<RunningStats>, String> {
+    match value {
+        Value::Stats(stats_ref) => Ok(*stats_ref),
+        _ => Err(format!("{} expects stats, got {}", name, vm.value_to_repr(value))),
+    }
+}
+
+pub fn stats_new(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::Stats(vm.gc.alloc(RunningStats::default())));
+}
+
+pub fn stats_push(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let stats_ref = stats_arg(vm, "stats_push", &args[1])?;
+    vm.gc.deref_mut(stats_ref).push(args[0].num_equiv());
+    return Ok(args[1]);
+}
+
+pub fn stats_count(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let stats_ref = stats_arg(vm, "stats_count", &args[0])?;
+    return Ok(Value::Num(vm.gc.deref(stats_ref).count as f64));
+}
+
+pub fn stats_mean(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let stats_ref = stats_arg(vm, "stats_mean", &args[0])?;
+    return Ok(Value::Num(vm.gc.deref(stats_ref).mean));
+}
+
+pub fn stats_var(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let stats_ref = stats_arg(vm, "stats_var", &args[0])?;
+    return Ok(Value::Num(vm.gc.deref(stats_ref).variance()));
+}
+
+pub fn stats_std(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let stats_ref = stats_arg(vm, "stats_std", &args[0])?;
+    return Ok(Value::Num(vm.gc.deref(stats_ref).variance().sqrt()));
+}
+
+pub fn stats_min(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let stats_ref = stats_arg(vm, "stats_min", &args[0])?;
+    return Ok(Value::Num(vm.gc.deref(stats_ref).min));
+}
+
+pub fn stats_max(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let stats_ref = stats_arg(vm, "stats_max", &args[0])?;
+    return Ok(Value::Num(vm.gc.deref(stats_ref).max));
+}
+
+fn request_arg(vm: &Vm, name: &str, value: &Value) -> Result<GcRef<HttpRequest>, String> {
+    match value {
+        Value::Request(request_ref) => Ok(*request_ref),
+        _ => Err(format!("{} expects a request, got {}", name, vm.value_to_repr(value))),
+    }
+}
+
+pub fn req_method(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let request_ref = request_arg(vm, "req_method", &args[0])?;
+    let method = vm.gc.deref(request_ref).request.method.to_owned();
+    return Ok(Value::String(vm.intern(method)));
+}
+
+pub fn req_path(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let request_ref = request_arg(vm, "req_path", &args[0])?;
+    let path = vm.gc.deref(request_ref).request.path.to_owned();
+    return Ok(Value::String(vm.intern(path)));
+}
+
+// Header names are case insensitive, a missing header is null
+pub fn req_header(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let request_ref = request_arg(vm, "req_header", &args[0])?;
+    let name = vm.value_to_str(&args[1]);
+    let header = vm.gc.deref(request_ref).request.headers.iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(&name))
+        .map(|(_, value)| value.to_owned());
+    return Ok(match header {
+        Some(value) => Value::String(vm.intern(value)),
+        None => Value::Null,
+    });
+}
+
+pub fn req_body(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let request_ref = request_arg(vm, "req_body", &args[0])?;
+    let body = vm.gc.deref(request_ref).request.body.to_owned();
+    return Ok(Value::String(vm.intern(body)));
+}
+
+// Saves the value as json, to a temporary file that then replaces the
+// previous one, so a crash never leaves a half written file behind
+pub fn store(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let path = vm.value_to_str(&args[0]);
+    check_fs_access(vm.config(), &path)?;
+    let json = match args[1] {
+        Value::Null | Value::Void | Value::Boolean(_) | Value::Num(_) | Value::String(_) | Value::Buffer(_) => {
+            vm.value_to_json(&args[1])
+        },
+        _ => return Err(format!("store can't save {}", vm.value_to_repr(&args[1]))),
+    };
+    let tmp_path = format!("{}.tmp{}", path, std::process::id());
+    let written = fs::File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(json.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&tmp_path, &path)) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(format!("store can't write '{}': {}", path, e));
+    }
+    return Ok(Value::Void);
+}
+
+// Adds the text at the end of the file, which is created when it doesn't exist. Like
+// write_text it returns the error message when the file can't be written
+pub fn append_text(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let path = vm.value_to_str(&args[0]);
+    check_fs_access(vm.config(), &path)?;
+    let text = vm.value_to_str(&args[1]);
+    let appended = fs::OpenOptions::new().create(true).append(true).open(Path::new(&path))
+        .and_then(|mut file| file.write_all(text.as_bytes()));
+    return Ok(match appended {
+        Ok(_) => Value::Void,
+        Err(e) => Value::String(vm.intern(e.to_string())),
+    });
+}
+
+// The metadata of the file at the path, or None when it doesn't exist
+fn metadata_arg(vm: &mut Vm, name: &str, arg: &Value) -> Result<Option<fs::Metadata>, String> {
+    let path = vm.value_to_str(arg);
+    check_fs_access(vm.config(), &path)?;
+    return match fs::metadata(Path::new(&path)) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{} can't read '{}': {}", name, path, e)),
+    };
+}
+
+pub fn file_size(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let metadata = metadata_arg(vm, "file_size", &args[0])?;
+    return Ok(metadata.map_or(Value::Null, |metadata| Value::Num(metadata.len() as f64)));
+}
+
+// In seconds since the unix epoch, like the dates of the other scripts and tools
+pub fn file_modified(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let metadata = metadata_arg(vm, "file_modified", &args[0])?;
+    let modified = metadata.and_then(|metadata| metadata.modified().ok())
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+    return Ok(modified.map_or(Value::Null, |time| Value::Num(time.as_secs_f64())));
+}
+
+pub fn is_file(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let metadata = metadata_arg(vm, "is_file", &args[0])?;
+    return Ok(Value::Boolean(metadata.is_some_and(|metadata| metadata.is_file())));
+}
+
+pub fn is_dir(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let metadata = metadata_arg(vm, "is_dir", &args[0])?;
+    return Ok(Value::Boolean(metadata.is_some_and(|metadata| metadata.is_dir())));
+}
+
+pub fn is_readonly(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let metadata = metadata_arg(vm, "is_readonly", &args[0])?;
+    return Ok(metadata.map_or(Value::Null, |metadata| Value::Boolean(metadata.permissions().readonly())));
+}
+
+pub fn on_signal(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let name = vm.value_to_str(&args[0]);
+    vm.set_signal_handler(&name, args[1])?;
+    return Ok(Value::Void);
+}
+
+// The operating system, like linux, macos or windows
+pub fn platform(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::String(vm.intern(std::env::consts::OS.to_owned())));
+}
+
+// The processor architecture, like x86_64 or aarch64
+pub fn arch(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::String(vm.intern(std::env::consts::ARCH.to_owned())));
+}
+
+// unix or windows
+pub fn os_family(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::String(vm.intern(std::env::consts::FAMILY.to_owned())));
+}
+
+#[cfg(unix)]
+fn read_hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let res = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if res != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+    return Some(String::from_utf8_lossy(&buffer[..len]).into_owned());
+}
+
+#[cfg(not(unix))]
+fn read_hostname() -> Option<String> {
+    return std::env::var("COMPUTERNAME").ok();
+}
+
+pub fn hostname(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(match read_hostname() {
+        Some(name) => Value::String(vm.intern(name)),
+        None => Value::Null,
+    });
+}
+
+// The number of processors the script can use
+pub fn cpu_count(_vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    let count = std::thread::available_parallelism().map(|count| count.get()).unwrap_or(1);
+    return Ok(Value::Num(count as f64));
+}
+
+pub fn nope_version(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::String(vm.intern(env!("CARGO_PKG_VERSION").to_owned())));
+}
+
+pub fn home_dir(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(match dirs::home_dir() {
+        Some(home) => Value::String(vm.intern(home.to_string_lossy().into_owned())),
+        None => Value::Null,
+    });
+}
+
+fn app_dir(vm: &mut Vm, name: &str, app: &Value, dir: fn(&str) -> Option<PathBuf>) -> Result<Value, String> {
+    let app = vm.value_to_str(app);
+    if app.is_empty() || app.contains(['/', '\\']) || app == "." || app == ".." {
+        return Err(format!("{} expects the name of an application, got '{}'", name, app));
+    }
+    return Ok(match dir(&app) {
+        Some(dir) => Value::String(vm.intern(dir.to_string_lossy().into_owned())),
+        None => Value::Null,
+    });
+}
+
+pub fn config_dir(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return app_dir(vm, "config_dir", &args[0], app_config_dir);
+}
+
+pub fn cache_dir(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return app_dir(vm, "cache_dir", &args[0], app_cache_dir);
+}
+
+// Parses the arguments of the script with the spec, for opt and arg. With --help
+// the help is printed and the script stops.
+pub fn parse_args(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let spec = vm.value_to_str(&args[0]);
+    let (options, positionals) = cli::parse_spec(&spec).map_err(|err| format!("parse_args: {}", err))?;
+    let script = vm.config().script_name.clone().unwrap_or_else(|| "nope".to_owned());
+    let help = cli::help_text(&script, &options, &positionals);
+    let parsed = match cli::parse_args(&options, &vm.config().script_args) {
+        Ok(parsed) => parsed,
+        Err(err) => return Err(format!("{}\n\n{}", err, help)),
+    };
+    if parsed.help {
+        println!("{}", help);
+        // exiting doesn't drop the vm
+        vm.remove_temp_paths();
+        std::process::exit(0);
+    }
+    vm.set_parsed_args(parsed);
+    return Ok(Value::Void);
+}
+
+pub fn opt(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let name = vm.value_to_str(&args[0]);
+    let value = match vm.parsed_args() {
+        Some(parsed) => parsed.get(&name).cloned(),
+        None => return Err("opt needs the options to be parsed with parse_args first".to_owned()),
+    };
+    return match value {
+        Some(OptionValue::Flag(flag)) => Ok(Value::Boolean(flag)),
+        Some(OptionValue::Text(Some(text))) => Ok(Value::String(vm.intern(text))),
+        Some(OptionValue::Text(None)) => Ok(Value::Null),
+        None => Err(format!("'{}' is not an option of parse_args", name)),
+    };
+}
+
+// The positional arguments once parse_args removed the options, before that all of them
+pub fn arg(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let idx = args[0].num_equiv();
+    if idx < 0.0 || idx.fract() != 0.0 {
+        return Err(format!("arg expects a position like 0 or 1, got {}", idx));
+    }
+    let found = match vm.parsed_args() {
+        Some(parsed) => parsed.positionals.get(idx as usize).cloned(),
+        None => vm.config().script_args.get(idx as usize).cloned(),
+    };
+    return Ok(match found {
+        Some(text) => Value::String(vm.intern(text)),
+        None => Value::Null,
+    });
+}
+
+// The words of a command line, split on the spaces outside of quotes. There is no
+// shell, so nothing else is interpreted.
+fn split_command_line(command: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            },
+            (None, _) if c.is_whitespace() => words.extend(word.take()),
+            (None, _) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(format!("proc_spawn: the command has an unclosed quote: {}", command));
+    }
+    words.extend(word);
+    return Ok(words);
+}
+
+fn process_arg(vm: &Vm, name: &str, value: &Value) -> Result<GcRef<Process>, String> {
+    match value {
+        Value::Process(proc_ref) => Ok(*proc_ref),
+        _ => Err(format!("{} expects a process, got {}", name, vm.value_to_repr(value))),
+    }
+}
+
+// Starts a command with its input and output connected to the script, its errors
+// are shown on stderr
+fn spawn(vm: &mut Vm, name: &str, command: String, cwd: Option<String>, env: Vec<(String, String)>) -> Result<Value, String> {
+    check_exec_access(vm.config(), &command)?;
+    let words = split_command_line(&command)?;
+    let (program, program_args) = words.split_first().ok_or_else(|| format!("{} expects a command, got an empty string", name))?;
+    let mut builder = std::process::Command::new(program);
+    builder.args(program_args).envs(env).stdin(std::process::Stdio::piped()).stdout(std::process::Stdio::piped());
+    if let Some(cwd) = cwd {
+        builder.current_dir(cwd);
+    }
+    let mut child = builder.spawn().map_err(|e| format!("{} can't run '{}': {}", name, command, e))?;
+    let stdout = child.stdout.take().map(std::io::BufReader::new);
+    let proc_ref = vm.gc.alloc(Process { command, child, stdout });
+    return Ok(Value::Process(proc_ref));
+}
+
+pub fn proc_spawn(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let command = vm.value_to_str(&args[0]);
+    return spawn(vm, "proc_spawn", command, None, vec![]);
+}
+
+// Like proc_spawn, in the cwd directory unless it is null or empty, with the
+// variables of env added to the environment, written like 'NAME=value OTHER="a b"'
+pub fn proc_spawn_in(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let command = vm.value_to_str(&args[0]);
+    let cwd = match args[1] {
+        Value::Null => None,
+        _ => Some(vm.value_to_str(&args[1])).filter(|cwd| !cwd.is_empty()),
+    };
+    let env_text = match args[2] {
+        Value::Null => String::new(),
+        _ => vm.value_to_str(&args[2]),
+    };
+    let mut env = vec![];
+    for word in split_command_line(&env_text)? {
+        match word.split_once('=') {
+            Some((var, value)) if !var.is_empty() => env.push((var.to_owned(), value.to_owned())),
+            _ => return Err(format!("proc_spawn_in expects variables like NAME=value, got '{}'", word)),
+        }
+    }
+    return spawn(vm, "proc_spawn_in", command, cwd, env);
+}
+
+// Returns the process, or the error message when it doesn't read its input anymore
+pub fn proc_write(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let proc_ref = process_arg(vm, "proc_write", &args[0])?;
+    let text = vm.value_to_str(&args[1]);
+    let written = match vm.gc.deref_mut(proc_ref).child.stdin.as_mut() {
+        Some(stdin) => stdin.write_all(text.as_bytes()).and_then(|_| stdin.flush()),
+        None => Err(std::io::Error::new(ErrorKind::BrokenPipe, "the input of the process is closed")),
+    };
+    return Ok(match written {
+        Ok(_) => args[0],
+        Err(e) => Value::String(vm.intern(e.to_string())),
+    });
+}
+
+// The next line written by the process without its line ending, or null once it
+// closed its output
+pub fn proc_read_line(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let proc_ref = process_arg(vm, "proc_read_line", &args[0])?;
+    let mut line = String::new();
+    let read = match vm.gc.deref_mut(proc_ref).stdout.as_mut() {
+        Some(stdout) => std::io::BufRead::read_line(stdout, &mut line),
+        None => Ok(0),
+    };
+    return Ok(match read {
+        Ok(0) => Value::Null,
+        Ok(_) => {
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = line.strip_suffix('\r').unwrap_or(line).to_owned();
+            Value::String(vm.intern(line))
+        },
+        Err(e) => Value::String(vm.intern(e.to_string())),
+    });
+}
+
+// Closes the input of the process, waits for it to finish and returns its exit
+// code, or null when it was stopped by a signal
+pub fn proc_wait(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let proc_ref = process_arg(vm, "proc_wait", &args[0])?;
+    let process = vm.gc.deref_mut(proc_ref);
+    process.child.stdin.take();
+    let status = process.child.wait().map_err(|e| format!("proc_wait can't wait for '{}': {}", process.command, e))?;
+    return Ok(status.code().map_or(Value::Null, |code| Value::Num(code as f64)));
+}
+
+pub fn proc_kill(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let proc_ref = process_arg(vm, "proc_kill", &args[0])?;
+    let process = vm.gc.deref_mut(proc_ref);
+    // a process that already finished can't be killed, that's fine
+    let _ = process.child.kill();
+    let _ = process.child.wait();
+    return Ok(Value::Void);
+}
+
+// Zips the content of a directory, or a single file, and returns the number of entries
+pub fn zip_create(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let path = vm.value_to_str(&args[0]);
+    let source = vm.value_to_str(&args[1]);
+    check_fs_access(vm.config(), &path)?;
+    check_fs_access(vm.config(), &source)?;
+    let count = archive::zip_create(Path::new(&path), Path::new(&source))
+        .map_err(|e| format!("zip_create can't zip '{}': {}", source, e))?;
+    return Ok(Value::Num(count as f64));
+}
+
+// The names of the entries of a zip, a line for each
+pub fn zip_list(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let path = vm.value_to_str(&args[0]);
+    check_fs_access(vm.config(), &path)?;
+    let names = archive::zip_list(Path::new(&path)).map_err(|e| format!("zip_list can't read '{}': {}", path, e))?;
+    return Ok(Value::String(vm.intern(names.join("\n"))));
+}
+
+// Extracts a zip in a directory and returns the number of files extracted
+pub fn zip_extract(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let path = vm.value_to_str(&args[0]);
+    let dest = vm.value_to_str(&args[1]);
+    check_fs_access(vm.config(), &path)?;
+    check_fs_access(vm.config(), &dest)?;
+    let count = archive::zip_extract(Path::new(&path), Path::new(&dest))
+        .map_err(|e| format!("zip_extract can't extract '{}': {}", path, e))?;
+    return Ok(Value::Num(count as f64));
+}
+
+// A new file or directory in the temporary directory, with a name starting with
+// the prefix, that is removed when the vm stops
+fn create_temp(vm: &mut Vm, name: &str, prefix: &Value, is_dir: bool) -> Result<Value, String> {
+    let prefix = vm.value_to_str(prefix);
+    if prefix.contains(['/', '\\']) {
+        return Err(format!("{} expects a prefix without a path separator, got '{}'", name, prefix));
+    }
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+    for attempt in 0..100 {
+        let path = std::env::temp_dir().join(format!("{}{}_{}", prefix, std::process::id(), nanos + attempt));
+        let path_str = path.to_string_lossy().to_string();
+        check_fs_access(vm.config(), &path_str)?;
+        let created = if is_dir {
+            fs::create_dir(&path)
+        } else {
+            fs::OpenOptions::new().write(true).create_new(true).open(&path).map(|_| ())
+        };
+        match created {
+            Ok(_) => {
+                vm.track_temp_path(PathBuf::from(&path));
+                return Ok(Value::String(vm.intern(path_str)));
+            },
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("{} can't create '{}': {}", name, path_str, e)),
+        }
+    }
+    return Err(format!("{} can't find a free name starting with '{}'", name, prefix));
+}
+
+pub fn temp_file(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return create_temp(vm, "temp_file", &args[0], false);
+}
+
+pub fn temp_dir(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return create_temp(vm, "temp_dir", &args[0], true);
+}
+
+// Loads a value saved by store, null when the file doesn't exist
+pub fn load(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let path = vm.value_to_str(&args[0]);
+    check_fs_access(vm.config(), &path)?;
+    let json = match fs::read_to_string(Path::new(&path)) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Value::Null),
+        Err(e) => return Err(format!("load can't read '{}': {}", path, e)),
+    };
+    let value = parse_json_scalar(json.trim()).ok_or(format!("load: '{}' doesn't contain a saved value", path))?;
+    return Ok(match value {
+        JsonScalar::Null => Value::Null,
+        JsonScalar::Boolean(val) => Value::Boolean(val),
+        JsonScalar::Num(num) => Value::Num(num),
+        JsonScalar::String(text) => Value::String(vm.intern(text)),
+    });
+}
+
+#[derive(PartialEq, Debug)]
+enum JsonScalar {
+    Null,
+    Boolean(bool),
+    Num(f64),
+    String(String),
+}
+
+fn parse_json_scalar(json: &str) -> Option<JsonScalar> {
+    match json {
+        "null" => return Some(JsonScalar::Null),
+        "true" => return Some(JsonScalar::Boolean(true)),
+        "false" => return Some(JsonScalar::Boolean(false)),
+        _ => {},
+    }
+    let quoted = json.strip_prefix('"').and_then(|quoted| quoted.strip_suffix('"'));
+    let quoted = match quoted {
+        Some(quoted) => quoted,
+        None => return json.parse::<f64>().ok().filter(|num| num.is_finite()).map(JsonScalar::Num),
+    };
+    let mut text = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return None,
+            '\\' => match chars.next()? {
+                '"' => text.push('"'),
+                '\\' => text.push('\\'),
+                '/' => text.push('/'),
+                'n' => text.push('\n'),
+                'r' => text.push('\r'),
+                't' => text.push('\t'),
+                'b' => text.push('\u{8}'),
+                'f' => text.push('\u{c}'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    text.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                },
+                _ => return None,
+            },
+            c => text.push(c),
+        }
+    }
+    return Some(JsonScalar::String(text));
+}
+
+pub fn term_clear(_vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    terminal::write_control("\x1b[2J\x1b[H");
+    return Ok(Value::Void);
+}
+
+// Moves the cursor to the column x and the line y, counted from 0 at the top left
+pub fn term_move(_vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    terminal::write_control(&terminal::move_sequence(args[0].num_equiv(), args[1].num_equiv()));
+    return Ok(Value::Void);
+}
+
+pub fn confirm(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let question = format!("{} (y/n)", vm.value_to_str(&args[0]));
+    return Ok(Value::Boolean(terminal::ask_yes_no(&question)));
+}
+
+// The options are the lines of a string, like what zip_list or units return
+pub fn choose(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let question = vm.value_to_str(&args[0]);
+    let options: Vec<String> = vm.value_to_str(&args[1]).lines()
+        .map(|option| option.trim().to_owned())
+        .filter(|option| !option.is_empty())
+        .collect();
+    if options.is_empty() {
+        return Err("choose expects some options, one on each line".to_owned());
+    }
+    return Ok(match terminal::choose(&question, &options) {
+        Some(idx) => Value::String(vm.intern(options[idx].to_owned())),
+        None => Value::Null,
+    });
+}
+
+pub fn term_getch(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(match terminal::read_key() {
+        Some(key) => Value::String(vm.intern(key)),
+        None => Value::Null,
+    });
+}
+
+pub fn term_width(_vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::Num(terminal::size().0 as f64));
+}
+
+pub fn term_height(_vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::Num(terminal::size().1 as f64));
+}
+
+const PROGRESS_WIDTH: usize = 30;
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+fn progress_line(progress: &ProgressBar) -> String {
+    let ratio = if progress.total > 0.0 { (progress.current / progress.total).clamp(0.0, 1.0) } else { 1.0 };
+    let filled = (ratio * PROGRESS_WIDTH as f64).round() as usize;
+    return format!(
+        "[{}{}] {}/{} {}% {:.1}s",
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_WIDTH - filled),
+        progress.current,
+        progress.total,
+        (ratio * 100.0).floor(),
+        progress.started.elapsed().as_secs_f64(),
+    );
+}
+
+pub fn progress_new(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let total = args[0].num_equiv();
+    if total.is_nan() || total < 0.0 {
+        return Err(format!("progress_new expects a positive total, got {}", vm.value_to_repr(&args[0])));
+    }
+    return Ok(Value::Progress(vm.gc.alloc(ProgressBar {
+        total,
+        current: 0.0,
+        started: Instant::now(),
+        drawn_percent: None,
+    })));
+}
+
+// Advances the bar by one step, it's only drawn again when the percentage changes
+pub fn progress_tick(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let progress_ref = match args[0] {
+        Value::Progress(progress_ref) => progress_ref,
+        _ => return Err(format!("progress_tick expects a progress bar, got {}", vm.value_to_repr(&args[0]))),
+    };
+    let progress = vm.gc.deref_mut(progress_ref);
+    progress.current += 1.0;
+    let percent = if progress.total > 0.0 { (progress.current * 100.0 / progress.total).floor() as i64 } else { 100 };
+    if progress.drawn_percent != Some(percent) {
+        progress.drawn_percent = Some(percent);
+        terminal::write_status(&progress_line(progress), progress.current == progress.total);
+    }
+    return Ok(args[0]);
+}
+
+// Each call shows the next frame of the animation, null clears it
+pub fn spinner(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    if args[0] == Value::Null {
+        terminal::write_status("", false);
+        return Ok(Value::Void);
+    }
+    let ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let frame = SPINNER_FRAMES[(ms / 100) as usize % SPINNER_FRAMES.len()];
+    terminal::write_status(&format!("{} {}", frame, vm.value_to_str(&args[0])), false);
+    return Ok(Value::Void);
+}
+
+// Formats a time as "2024-01-31 12:34:56" in UTC
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let (hours, minutes, seconds) = (secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+    // civil from days, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    return format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hours, minutes, seconds);
+}
+
+// Log messages go to stderr so that they don't mix with the output of the script
+fn log_message(vm: &mut Vm, level: usize, message: &Value) -> Result<Value, String> {
+    if level < vm.config().log_level {
+        return Ok(*message);
+    }
+    let name = LOG_LEVELS[level].to_uppercase();
+    let name = match level {
+        0 => name.dimmed(),
+        1 => name.green(),
+        2 => name.yellow(),
+        _ => name.red(),
+    };
+    let text = vm.value_to_str(message);
+    if vm.config().log_timestamps {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        eprintln!("{} {:5} {}", format_utc(secs).dimmed(), name, text);
+    } else {
+        eprintln!("{:5} {}", name, text);
+    }
+    return Ok(*message);
+}
+
+pub fn log_debug(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return log_message(vm, 0, &args[0]);
+}
+
+pub fn log_info(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return log_message(vm, 1, &args[0]);
+}
+
+pub fn log_warn(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return log_message(vm, 2, &args[0]);
+}
+
+pub fn log_error(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    return log_message(vm, 3, &args[0]);
+}
+
+pub fn set_log_level(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let name = vm.value_to_str(&args[0]);
+    let level = parse_log_level(&name).ok_or(format!("unknown log level '{}', expected one of {}", name, LOG_LEVELS.join(", ")))?;
+    vm.config_mut().log_level = level;
+    return Ok(Value::Void);
+}
+
+pub fn undef(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let name = vm.value_to_str(&args[0]);
+    vm.undefine_global(&name)?;
+    return Ok(Value::Void);
+}
+
+pub fn help(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let name = vm.value_to_str(&args[0]);
+    let usage = vm.stdlib().describe(&name).ok_or(format!("'{}' is not a stdlib function", name))?;
+    println!("{}", usage);
+    return Ok(Value::Void);
+}
+
+// There are no arrays yet, the names are returned as lines of a string
+pub fn builtins(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    let names = vm.stdlib().names().join("\n");
+    return Ok(Value::String(vm.intern(names)));
+}
+
+pub fn apropos(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let text = vm.value_to_str(&args[0]);
+    for function in vm.stdlib().search(&text) {
+        println!("{}  {}", function.name, function.doc.dimmed());
+    }
+    return Ok(Value::Void);
+}
+
+pub fn assert(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    if !args[0].is_truthy() {
+        return Err(format!("assertion failed: {}", vm.value_to_str(&args[1])));
+    }
+    return Ok(args[0]);
+}
+
+// The lines that differ between the expected and the actual text, like
+//
+//     line 2
+//     - expected
+//     + actual
+fn line_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut diff = String::new();
+    for idx in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(idx), actual.get(idx));
+        if old == new {
+            continue;
+        }
+        diff.push_str(&format!("\nline {}", idx + 1));
+        if let Some(old) = old {
+            diff.push_str(&format!("\n- {}", old));
+        }
+        if let Some(new) = new {
+            diff.push_str(&format!("\n+ {}", new));
+        }
+    }
+    return diff;
+}
+
+// Compares the value to the one saved in __snapshots__/name.snap by a previous run,
+// the snapshot is created when it doesn't exist yet
+pub fn assert_snapshot(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let name = vm.value_to_str(&args[0]);
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(format!("invalid snapshot name '{}'", name));
+    }
+    let text = match args[1] {
+        Value::String(_) | Value::Buffer(_) => vm.value_to_str(&args[1]),
+        _ => vm.value_to_json(&args[1]),
+    };
+    let dir = vm.config().snapshot_dir.clone().unwrap_or("__snapshots__".to_owned());
+    let path = Path::new(&dir).join(format!("{}.snap", name)).to_string_lossy().into_owned();
+    check_fs_access(vm.config(), &path)?;
+    match fs::read_to_string(&path) {
+        Ok(expected) if expected == text => {},
+        Ok(expected) => {
+            return Err(format!("snapshot '{}' changed, delete {} to record it again:{}", name, path, line_diff(&expected, &text)));
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            fs::create_dir_all(&dir).and_then(|_| fs::write(&path, &text))
+                .map_err(|e| format!("assert_snapshot can't write '{}': {}", path, e))?;
+        },
+        Err(e) => return Err(format!("assert_snapshot can't read '{}': {}", path, e)),
+    }
+    return Ok(Value::Boolean(true));
+}
+
+pub fn set_float_digits(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let digits = match &args[0] {
+        Value::Null => None,
+        Value::Num(num) if num.fract() == 0.0 && (1.0..=17.0).contains(num) => Some(*num as usize),
+        _ => return Err("set_float_digits expects a number of digits between 1 and 17, or null".to_owned()),
+    };
+    vm.config_mut().float_digits = digits;
+    return Ok(Value::Void);
+}
+
+pub fn set_decimal_comma(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    vm.config_mut().decimal_comma = args[0].is_truthy();
+    return Ok(Value::Void);
+}
+
+pub fn convert(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let num = args[0].num_equiv();
+    let (from, to) = (vm.value_to_str(&args[1]), vm.value_to_str(&args[2]));
+    return Ok(Value::Num(convert_units(num, &from, &to)?));
+}
+
+pub fn to_human_bytes_native(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let text = to_human_bytes(args[0].num_equiv());
+    return Ok(Value::String(vm.intern(text)));
+}
+
+pub fn from_human_bytes_native(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let text = vm.value_to_str(&args[0]);
+    let bytes = from_human_bytes(&text).ok_or_else(|| format!("from_human_bytes expects a size like '1.4Gi', got '{}'", text))?;
+    return Ok(Value::Num(bytes));
+}
+
+fn color_arg(vm: &Vm, name: &str, value: &Value) -> Result<Rgb, String> {
+    let text = vm.value_to_str(value);
+    return color::parse_color(&text)
+        .ok_or_else(|| format!("{} expects a color like '#ff8800' or 'rgb(255, 136, 0)', got '{}'", name, text));
+}
+
+fn hex_value(vm: &mut Vm, color: Rgb) -> Value {
+    return Value::String(vm.intern(color::to_hex(color)));
+}
+
+pub fn hex_to_rgb(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let color = color_arg(vm, "hex_to_rgb", &args[0])?;
+    return Ok(Value::String(vm.intern(color::to_rgb_text(color))));
+}
+
+pub fn rgb_to_hex(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let color = (args[0].num_equiv(), args[1].num_equiv(), args[2].num_equiv());
+    return Ok(hex_value(vm, color));
+}
+
+pub fn hsl_to_rgb(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let color = color::hsl_to_rgb(args[0].num_equiv(), args[1].num_equiv(), args[2].num_equiv());
+    return Ok(hex_value(vm, color));
+}
+
+pub fn mix(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let a = color_arg(vm, "mix", &args[0])?;
+    let b = color_arg(vm, "mix", &args[1])?;
+    return Ok(hex_value(vm, color::mix(a, b, args[2].num_equiv())));
+}
+
+pub fn lighten(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let color = color_arg(vm, "lighten", &args[0])?;
+    return Ok(hex_value(vm, color::lighten(color, args[1].num_equiv())));
+}
+
+fn drawing_arg(vm: &Vm, name: &str, value: &Value) -> Result<GcRef<Drawing>, String> {
+    match value {
+        Value::Drawing(drawing_ref) => Ok(*drawing_ref),
+        _ => Err(format!("{} expects a drawing made by svg_new, got {}", name, vm.value_to_repr(value))),
+    }
+}
+
+// The numbers given to a drawing function, NaN or infinite ones would make a broken file
+fn finite_args(vm: &Vm, name: &str, args: &[Value]) -> Result<Vec<f64>, String> {
+    return args.iter().map(|arg| match arg.num_equiv() {
+        num if num.is_finite() => Ok(num),
+        _ => Err(format!("{} expects numbers, got {}", name, vm.value_to_repr(arg))),
+    }).collect();
+}
+
+pub fn svg_new(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let size = finite_args(vm, "svg_new", args)?;
+    if size[0] <= 0.0 || size[1] <= 0.0 {
+        return Err(format!("svg_new expects a positive width and height, got {} and {}", size[0], size[1]));
+    }
+    return Ok(Value::Drawing(vm.gc.alloc(Drawing {
+        width: size[0],
+        height: size[1],
+        shapes: vec![],
+        style: svg::Style::new(),
+        turtle: (size[0] / 2.0, size[1] / 2.0, 0.0),
+    })));
+}
+
+fn push_shape(vm: &mut Vm, drawing_ref: GcRef<Drawing>, shape: String) -> Value {
+    vm.gc.deref_mut(drawing_ref).shapes.push(shape);
+    return Value::Drawing(drawing_ref);
+}
+
+pub fn svg_line(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let drawing_ref = drawing_arg(vm, "svg_line", &args[0])?;
+    let at = finite_args(vm, "svg_line", &args[1..])?;
+    let shape = svg::line(at[0], at[1], at[2], at[3], &vm.gc.deref(drawing_ref).style);
+    return Ok(push_shape(vm, drawing_ref, shape));
+}
+
+pub fn svg_circle(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let drawing_ref = drawing_arg(vm, "svg_circle", &args[0])?;
+    let at = finite_args(vm, "svg_circle", &args[1..])?;
+    if at[2] < 0.0 {
+        return Err(format!("svg_circle expects a positive radius, got {}", at[2]));
+    }
+    let shape = svg::circle(at[0], at[1], at[2], &vm.gc.deref(drawing_ref).style);
+    return Ok(push_shape(vm, drawing_ref, shape));
+}
+
+pub fn svg_path(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let drawing_ref = drawing_arg(vm, "svg_path", &args[0])?;
+    let data = vm.value_to_str(&args[1]);
+    let shape = svg::path(&data, &vm.gc.deref(drawing_ref).style).map_err(|err| format!("svg_path: {}", err))?;
+    return Ok(push_shape(vm, drawing_ref, shape));
+}
+
+// A null color doesn't paint, like a fill of none
+pub fn svg_style(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let drawing_ref = drawing_arg(vm, "svg_style", &args[0])?;
+    let mut paints = vec![];
+    for arg in &args[1..3] {
+        paints.push(match arg {
+            Value::Null => "none".to_owned(),
+            _ => color::to_hex(color_arg(vm, "svg_style", arg)?),
+        });
+    }
+    let width = finite_args(vm, "svg_style", &args[3..])?[0].max(0.0);
+    let fill = paints.pop().unwrap_or_default();
+    let stroke = paints.pop().unwrap_or_default();
+    vm.gc.deref_mut(drawing_ref).style = svg::Style { stroke, fill, width };
+    return Ok(Value::Drawing(drawing_ref));
+}
+
+// The turtle starts at the center of the drawing heading up, and draws a line as it moves
+pub fn svg_forward(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let drawing_ref = drawing_arg(vm, "svg_forward", &args[0])?;
+    let distance = finite_args(vm, "svg_forward", &args[1..])?[0];
+    let drawing = vm.gc.deref_mut(drawing_ref);
+    let (x, y, heading) = drawing.turtle;
+    let (to_x, to_y) = (x + heading.to_radians().sin() * distance, y - heading.to_radians().cos() * distance);
+    drawing.turtle = (to_x, to_y, heading);
+    let shape = svg::line(x, y, to_x, to_y, &drawing.style);
+    return Ok(push_shape(vm, drawing_ref, shape));
+}
+
+pub fn svg_turn(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let drawing_ref = drawing_arg(vm, "svg_turn", &args[0])?;
+    let degrees = finite_args(vm, "svg_turn", &args[1..])?[0];
+    let turtle = &mut vm.gc.deref_mut(drawing_ref).turtle;
+    turtle.2 = (turtle.2 + degrees).rem_euclid(360.0);
+    return Ok(Value::Drawing(drawing_ref));
+}
+
+pub fn svg_save(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let drawing_ref = drawing_arg(vm, "svg_save", &args[0])?;
+    let path = vm.value_to_str(&args[1]);
+    check_fs_access(vm.config(), &path)?;
+    let drawing = vm.gc.deref(drawing_ref);
+    let text = svg::document(drawing.width, drawing.height, &drawing.shapes);
+    return Ok(match fs::write(Path::new(&path), text) {
+        Ok(_) => Value::Void,
+        Err(e) => Value::String(vm.intern(e.to_string())),
+    });
+}
+
+// The known units, a line for each kind of measure
+pub fn units(vm: &mut Vm, _args: &[Value]) -> Result<Value, String> {
+    let lines: Vec<String> = UNIT_GROUPS.iter().map(|(measure, _, units)| {
+        let names: Vec<&str> = units.iter().map(|(name, _)| *name).collect();
+        format!("{}: {}", measure, names.join(" "))
+    }).collect();
+    return Ok(Value::String(vm.intern(lines.join("\n"))));
+}
+
+pub fn set_display_unit(vm: &mut Vm, args: &[Value]) -> Result<Value, String> {
+    let unit = match &args[0] {
+        Value::Null => None,
+        value => Some(vm.value_to_str(value)),
+    };
+    if let Some(unit) = &unit {
+        if convert_si_to_unit(1.0, unit).is_none() {
+            return Err(unknown_unit_message(unit));
+        }
+    }
+    vm.config_mut().display_unit = unit;
+    return Ok(Value::Void);
+}
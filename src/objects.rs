@@ -1,5 +1,16 @@
-use std::{any::Any, fmt, mem};
-use crate::gc::{GcTrace, Gc};
+use std::{any::Any, collections::VecDeque, fmt, mem};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, ChildStdout};
+use std::time::{Instant, SystemTime};
+use crate::{
+    gc::{GcTrace, Gc, GcRef},
+    chunk::Value,
+    http::ParsedRequest,
+    svg::Style,
+    vm::CallFrame,
+};
 
 
 impl GcTrace for String {
@@ -17,3 +28,402 @@ impl GcTrace for String {
         self
     }
 }
+
+#[derive(Debug, Default)]
+pub struct StringBuffer {
+    pub text: String,
+}
+
+impl GcTrace for StringBuffer {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<StringBuffer>() + self.text.capacity()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Aggregates a stream of numbers without keeping them, the
+// variance is computed with Welford's online algorithm
+#[derive(Debug)]
+pub struct RunningStats {
+    pub count: usize,
+    pub mean: f64,
+    pub m2: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for RunningStats {
+    fn default() -> Self {
+        RunningStats {
+            count: 0,
+            mean: f64::NAN,
+            m2: 0.0,
+            min: f64::NAN,
+            max: f64::NAN,
+        }
+    }
+}
+
+impl RunningStats {
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        if self.count == 1 {
+            self.mean = value;
+            self.min = value;
+            self.max = value;
+            return;
+        }
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = f64::min(self.min, value);
+        self.max = f64::max(self.max, value);
+    }
+
+    // sample variance, NaN with less than two values
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return f64::NAN;
+        }
+        return self.m2 / (self.count - 1) as f64;
+    }
+}
+
+impl GcTrace for RunningStats {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "stats(count: {}, mean: {}, std: {})", self.count, self.mean, self.variance().sqrt())
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<RunningStats>()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// A deferred expression, compiled to a function without arguments.
+// The value is kept once the thunk has been forced
+#[derive(Debug)]
+pub struct Thunk {
+    pub function: usize,
+    pub value: Option<Value>,
+}
+
+impl GcTrace for Thunk {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<lazy>")
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Thunk>()
+    }
+    fn trace(&self, gc: &mut Gc) {
+        if let Some(value) = self.value {
+            gc.mark_value(value);
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// A suspended call of a generator function: its stack slice and the
+// position of the instruction following the last yield
+#[derive(Debug)]
+pub struct Generator {
+    pub function: usize,
+    pub ip: usize,
+    pub stack: Vec<Value>,
+    pub started: bool,
+    pub running: bool,
+    pub done: bool,
+}
+
+impl GcTrace for Generator {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<generator>")
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Generator>() + self.stack.capacity() * mem::size_of::<Value>()
+    }
+    fn trace(&self, gc: &mut Gc) {
+        for value in &self.stack {
+            gc.mark_value(*value);
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// A task created by `spawn`. While it is paused, it keeps the part of the
+// stack and the call frames that were above the frame that resumed it, with
+// bases relative to the start of its stack.
+#[derive(Debug)]
+pub struct Task {
+    pub function: usize,
+    pub started: bool,
+    pub running: bool,
+    pub wake_at: f64, // ms since the epoch, it's not resumed before
+    pub result: Option<Value>,
+    pub receiving: Option<GcRef<Channel>>, // it can't resume before this channel has a value
+    pub paused_function: Option<usize>,
+    pub paused_ip: usize,
+    pub paused_base: usize,
+    pub paused_frames: Vec<CallFrame>,
+    pub paused_stack: Vec<Value>,
+}
+
+impl GcTrace for Task {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<task>")
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Task>()
+            + self.paused_frames.capacity() * mem::size_of::<CallFrame>()
+            + self.paused_stack.capacity() * mem::size_of::<Value>()
+    }
+    fn trace(&self, gc: &mut Gc) {
+        if let Some(value) = self.result {
+            gc.mark_value(value);
+        }
+        if let Some(chan_ref) = self.receiving {
+            gc.mark_object(chan_ref);
+        }
+        for frame in &self.paused_frames {
+            frame.trace(gc);
+        }
+        for value in &self.paused_stack {
+            gc.mark_value(*value);
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// The values sent to a channel that are not received yet, in order
+#[derive(Debug, Default)]
+pub struct Channel {
+    pub queue: VecDeque<Value>,
+}
+
+impl GcTrace for Channel {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<channel>")
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Channel>() + self.queue.capacity() * mem::size_of::<Value>()
+    }
+    fn trace(&self, gc: &mut Gc) {
+        for value in &self.queue {
+            gc.mark_value(*value);
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum Socket {
+    Stream(BufReader<TcpStream>),
+    Listener(TcpListener),
+    Closed,
+}
+
+impl GcTrace for Socket {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<socket>")
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Socket>()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// The file read by `each_line`, closed once all its lines were handled or
+// when the handler returned false
+#[derive(Debug)]
+pub struct LineReader {
+    pub reader: Option<BufReader<File>>,
+}
+
+impl GcTrace for LineReader {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<lines>")
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<LineReader>()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// The path followed by `watch`, with the latest modification time and the
+// number of files seen under it, to tell when something changed
+#[derive(Debug)]
+pub struct FileWatch {
+    pub path: String,
+    pub seen: Option<(SystemTime, usize)>,
+    pub stopped: bool,
+}
+
+impl GcTrace for FileWatch {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<watch {}>", self.path)
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<FileWatch>() + self.path.capacity()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// A command started by `proc_spawn`, its output is read line by line
+#[derive(Debug)]
+pub struct Process {
+    pub command: String,
+    pub child: Child,
+    pub stdout: Option<BufReader<ChildStdout>>,
+}
+
+impl GcTrace for Process {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<process {}>", self.child.id())
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Process>() + self.command.capacity()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// A process that is collected, or still running when the script ends, is stopped
+// and waited for so that it doesn't outlive the script
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// A request received by `serve`, the response is written to its
+// stream when the handler returns
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub request: ParsedRequest,
+    pub stream: Option<TcpStream>,
+}
+
+impl GcTrace for HttpRequest {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<request {} {}>", self.request.method, self.request.path)
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<HttpRequest>() + self.request.body.capacity()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// A picture made by `svg_new`, its shapes are kept as svg elements. The turtle
+// is a pen at a position, heading in degrees clockwise from up.
+#[derive(Debug)]
+pub struct Drawing {
+    pub width: f64,
+    pub height: f64,
+    pub shapes: Vec<String>,
+    pub style: Style,
+    pub turtle: (f64, f64, f64),
+}
+
+impl GcTrace for Drawing {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<svg {}x{}>", self.width, self.height)
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Drawing>() + self.shapes.iter().map(|shape| shape.capacity()).sum::<usize>()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct ProgressBar {
+    pub total: f64,
+    pub current: f64,
+    pub started: Instant,
+    pub drawn_percent: Option<i64>,
+}
+
+impl GcTrace for ProgressBar {
+    fn format(&self, f: &mut fmt::Formatter, _gc: &Gc) -> fmt::Result {
+        write!(f, "<progress {}/{}>", self.current, self.total)
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<ProgressBar>()
+    }
+    fn trace(&self, _gc: &mut Gc) {}
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
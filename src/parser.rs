@@ -3,13 +3,19 @@ use crate::tokenizer::Tokenizer;
 use crate::tokenizer::Token;
 use crate::tokenizer::TokenValue;
 use crate::tokenizer::TokenizerState;
-use crate::units::convert_unit_to_si;
+use crate::units::{convert_unit_to_si, unknown_unit_message};
 use crate::config::NopeConfig;
 use crate::stdlib::Stdlib;
 use crate::penv::{
     FunctionArg,
     Env,
 };
+use crate::typecheck::check_types;
+use crate::chunk::TYPE_NAMES;
+use crate::vm::json_string;
+
+use std::collections::HashMap;
+use std::ops::Range;
 
 use colored::*;
 
@@ -36,6 +42,8 @@ pub enum BinaryOperator {
     Multiply,
     Divide,
     Modulo,
+    FloorDivide,
+    FloorModulo,
     Power,
     And,
     Or,
@@ -76,6 +84,8 @@ fn operator_precedence(op: BinaryOperator) -> usize {
         BinaryOperator::Multiply => 12,
         BinaryOperator::Divide => 12,
         BinaryOperator::Modulo => 12,
+        BinaryOperator::FloorDivide => 12,
+        BinaryOperator::FloorModulo => 12,
         BinaryOperator::Power => 13,
 
         BinaryOperator::BitwiseAnd => 7,
@@ -92,6 +102,14 @@ fn operator_precedence(op: BinaryOperator) -> usize {
     }
 }
 
+// comparisons that can be chained, as in a < b <= c
+fn operator_chains(op: BinaryOperator) -> bool {
+    matches!(op,
+        BinaryOperator::Less | BinaryOperator::LessOrEqual |
+        BinaryOperator::Greater | BinaryOperator::GreaterOrEqual
+    )
+}
+
 fn operator_associates_right(op: BinaryOperator) -> bool {
     match op {
         BinaryOperator::Power => true,
@@ -116,22 +134,94 @@ pub enum AstNode {
     LocalSet(usize, usize, usize), // set $target $expr
     GlobalLet(usize, String, usize, usize),
     GlobalSet(usize, usize, usize), // set $target $expr
+    LocalIncr(usize, usize, bool), // inc $target, or dec $target if bool is true
+    GlobalIncr(usize, usize, bool),
     Do(usize, usize, usize), // do $expr1 $expr1
     IfElse(usize, usize, usize, usize), // ife $cond $expr1 $expr2
     GlobalValueReference(usize, String),    // reference to the variable 'String' that contains a value
     LocalValueReference(usize, String),    // reference to the variable 'String' that contains a value
     FunctionCall(usize, String, Vec<usize>),    // function call to function named 'String'
     FunctionDef(usize, Vec<FunctionArg>, usize), // last usize is ref to function expression 
+    Memo(usize, usize), // usize is ref to the memoized function definition
+    Lazy(usize, usize), // usize is ref to the deferred expression
+    Spawn(usize, usize), // usize is ref to the expression run by the task
+    Test(usize, String, usize), // test 'name' $expr, only run with --test
     StaticKeyAccess(usize, String, usize),  // string is name of key, last usize is expression of
                                             // which we access the key from
     DynamicKeyAccess(usize, usize, usize), // second usize is the expression that gives the key,
                                             // last usize is the expression that gives the array,
     UnaryOperator(usize, UnaryOperator, usize), 
     BinaryOperator(usize, BinaryOperator, usize, usize), 
+    ChainedComparison(usize, BinaryOperator, usize, usize), // a < b < c, first usize is the comparison
+                                                            // a < b, which shares b with the next one
     TopLevelBlock(usize, Vec<usize>), //Vec<usize> is the expressions, 
     WhileLoop(usize, usize, usize), // while $cond $expr
     Continue(usize),
     Break(usize, usize),
+    Return(usize, usize),
+    Yield(usize, usize),
+}
+
+impl AstNode {
+    pub fn token_index(&self) -> usize {
+        match self {
+            AstNode::Number(idx, ..) => *idx,
+            AstNode::String(idx, ..) => *idx,
+            AstNode::Boolean(idx, ..) => *idx,
+            AstNode::Null(idx) => *idx,
+            AstNode::Void(idx) => *idx,
+            AstNode::KeyValue(idx, ..) => *idx,
+            AstNode::Array(idx, ..) => *idx,
+            AstNode::LocalLet(idx, ..) => *idx,
+            AstNode::LocalSet(idx, ..) => *idx,
+            AstNode::GlobalLet(idx, ..) => *idx,
+            AstNode::GlobalSet(idx, ..) => *idx,
+            AstNode::LocalIncr(idx, ..) => *idx,
+            AstNode::GlobalIncr(idx, ..) => *idx,
+            AstNode::Do(idx, ..) => *idx,
+            AstNode::IfElse(idx, ..) => *idx,
+            AstNode::GlobalValueReference(idx, ..) => *idx,
+            AstNode::LocalValueReference(idx, ..) => *idx,
+            AstNode::FunctionCall(idx, ..) => *idx,
+            AstNode::FunctionDef(idx, ..) => *idx,
+            AstNode::Memo(idx, ..) => *idx,
+            AstNode::Lazy(idx, ..) => *idx,
+            AstNode::Spawn(idx, ..) => *idx,
+            AstNode::Test(idx, ..) => *idx,
+            AstNode::StaticKeyAccess(idx, ..) => *idx,
+            AstNode::DynamicKeyAccess(idx, ..) => *idx,
+            AstNode::UnaryOperator(idx, ..) => *idx,
+            AstNode::BinaryOperator(idx, ..) => *idx,
+            AstNode::ChainedComparison(idx, ..) => *idx,
+            AstNode::TopLevelBlock(idx, ..) => *idx,
+            AstNode::WhileLoop(idx, ..) => *idx,
+            AstNode::Continue(idx) => *idx,
+            AstNode::Break(idx, ..) => *idx,
+            AstNode::Return(idx, ..) => *idx,
+            AstNode::Yield(idx, ..) => *idx,
+        }
+    }
+
+    // Indexes of the ast nodes of the sub expressions
+    pub fn children(&self) -> Vec<usize> {
+        match self {
+            AstNode::Number(..) | AstNode::String(..) | AstNode::Boolean(..) |
+            AstNode::Null(..) | AstNode::Void(..) | AstNode::Continue(..) |
+            AstNode::GlobalValueReference(..) | AstNode::LocalValueReference(..) => vec![],
+            AstNode::KeyValue(_, _, expr) | AstNode::LocalIncr(_, expr, _) |
+            AstNode::GlobalIncr(_, expr, _) | AstNode::FunctionDef(_, _, expr) |
+            AstNode::Memo(_, expr) | AstNode::Lazy(_, expr) | AstNode::Spawn(_, expr) | AstNode::Test(_, _, expr) |
+            AstNode::StaticKeyAccess(_, _, expr) | AstNode::UnaryOperator(_, _, expr) |
+            AstNode::Break(_, expr) | AstNode::Return(_, expr) | AstNode::Yield(_, expr) => vec![*expr],
+            AstNode::LocalLet(_, _, first, second) | AstNode::GlobalLet(_, _, first, second) |
+            AstNode::LocalSet(_, first, second) | AstNode::GlobalSet(_, first, second) |
+            AstNode::Do(_, first, second) | AstNode::WhileLoop(_, first, second) |
+            AstNode::DynamicKeyAccess(_, first, second) | AstNode::BinaryOperator(_, _, first, second) |
+            AstNode::ChainedComparison(_, _, first, second) => vec![*first, *second],
+            AstNode::IfElse(_, cond, then, other) => vec![*cond, *then, *other],
+            AstNode::Array(_, exprs) | AstNode::TopLevelBlock(_, exprs) | AstNode::FunctionCall(_, _, exprs) => exprs.clone(),
+        }
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -169,23 +259,53 @@ pub struct Parser {
     pub tokenizer: Tokenizer,
     pub ast: Vec<AstNode>,
     pub env: Env,
+    base_env: Env, // the env before parsing, to parse again after an edit
     nextindex: usize,
     index: usize,
     state: ParserState,
     errors: Vec<ParserError>,
     in_loop: Vec<bool>,
+    function_depth: usize,
+    pub let_types: HashMap<usize, String>, // annotated type of the value expression of a let
 }
 
 fn is_reserved_keyword(name: &String) -> bool {
     return name == "true" ||  name == "false" || name == "null" ||
         name == "void" || name == "let" || name == "if" ||
-        name == "ife" || name == "do" || name == "end";
+        name == "ife" || name == "do" || name == "end" ||
+        name == "inc" || name == "dec" || name == "return" ||
+        name == "memo" || name == "lazy" || name == "yield" ||
+        name == "spawn" || name == "const" || name == "test" ||
+        name == "of";
+}
+
+// The line before the error, the line of the error and the dashes leading to
+// the error column. Positions past the end of the source, like an unexpected
+// end of input after a trailing newline, point after the last character.
+fn error_context(source: &str, line: usize, col: usize) -> (Option<&str>, &str, String) {
+    let lines: Vec<&str> = source.lines().collect();
+    let lineidx = line.saturating_sub(1);
+    let error_line = lines.get(lineidx).copied().unwrap_or("");
+    let previous_line = if lineidx >= 1 {
+        lines.get(lineidx - 1).copied()
+    } else {
+        None
+    };
+    // tabs are kept so the caret lines up with the source as displayed
+    let underline = error_line.chars()
+        .chain(std::iter::repeat(' '))
+        .take(col.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { '-' })
+        .collect();
+    return (previous_line, error_line, underline);
 }
 
 impl Parser {
     pub fn new_with_env(config: NopeConfig, env: Env, source: String) -> Parser {
+        let env = env.snapshot();
         return Parser{
             config,
+            base_env: env.clone(),
             env,
             tokenizer: Tokenizer::new(source),
             ast: vec![],
@@ -194,6 +314,8 @@ impl Parser {
             state: ParserState::Wip,
             errors: vec![],
             in_loop: vec![false],
+            function_depth: 0,
+            let_types: HashMap::new(),
         };
     }
 
@@ -292,6 +414,14 @@ impl Parser {
                 self._pretty_print_ast(*target, indent + 2, false);
                 self._pretty_print_ast(*expr, indent + 2, false);
             },
+            AstNode::GlobalIncr(_, target, decrement) => {
+                println!("{}{} (global)", " ".repeat(original_indent), if *decrement { "dec" } else { "inc" });
+                self._pretty_print_ast(*target, indent + 2, false);
+            },
+            AstNode::LocalIncr(_, target, decrement) => {
+                println!("{}{} (local)", " ".repeat(original_indent), if *decrement { "dec" } else { "inc" });
+                self._pretty_print_ast(*target, indent + 2, false);
+            },
             AstNode::IfElse(_, cond, expr, expr_2) => {
                 println!("{}ife", " ".repeat(original_indent));
                 self._pretty_print_ast(*cond, indent + 2, false);
@@ -307,6 +437,30 @@ impl Parser {
                 println!("{}break", " ".repeat(original_indent));
                 self._pretty_print_ast(*expr, indent + 2, false);
             }
+            AstNode::Return(_, expr) => {
+                println!("{}return", " ".repeat(original_indent));
+                self._pretty_print_ast(*expr, indent + 2, false);
+            }
+            AstNode::Yield(_, expr) => {
+                println!("{}yield", " ".repeat(original_indent));
+                self._pretty_print_ast(*expr, indent + 2, false);
+            }
+            AstNode::Memo(_, def) => {
+                println!("{}memo", " ".repeat(original_indent));
+                self._pretty_print_ast(*def, indent + 2, false);
+            }
+            AstNode::Lazy(_, expr) => {
+                println!("{}lazy", " ".repeat(original_indent));
+                self._pretty_print_ast(*expr, indent + 2, false);
+            }
+            AstNode::Spawn(_, expr) => {
+                println!("{}spawn", " ".repeat(original_indent));
+                self._pretty_print_ast(*expr, indent + 2, false);
+            }
+            AstNode::Test(_, name, expr) => {
+                println!("{}test '{}'", " ".repeat(original_indent), name);
+                self._pretty_print_ast(*expr, indent + 2, false);
+            }
             AstNode::FunctionDef(_, args, expr_body) => {
                 print!("{}|", " ".repeat(original_indent));
                 for arg in args {
@@ -337,35 +491,26 @@ impl Parser {
                 self._pretty_print_ast(*lexpr, indent + 2, false);
                 self._pretty_print_ast(*rexpr, indent + 2, false);
             },
+            AstNode::ChainedComparison(_, op, lexpr, rexpr) => {
+                print!("{}chained {:?}:", " ".repeat(original_indent), op);
+                self._pretty_print_ast(*lexpr, indent + 2, false);
+                self._pretty_print_ast(*rexpr, indent + 2, false);
+            },
         }
     }
 
-    fn _pretty_print_error_line(&self, line:usize, col:usize, severity:Severity, message: &String) {
-        let lines: Vec<&str> = self.tokenizer.source.lines().collect();
-        let lineidx = line - 1;
-        if lineidx >= 1 {
-            println!("  {}", lines[lineidx-1].italic());
-        }
-        println!("  {}", lines[lineidx].italic());
-        let colidx = col -1;
-        let mut i:usize = 0;
-        print!("  ");
-        loop {
-            if i == colidx {
-                match severity {
-                    Severity::Critical => {
-                        println!("{}", "^".red());
-                    },
-                    Severity::Info => {
-                        println!("{}", "^".blue());
-                    },
-                };
-                break;
-            } else {
-                print!("-");
-                i += 1;
+    fn _pretty_print_error_line(&self, line:usize, col:usize, severity:Severity, message: &String, show_source: bool) {
+        let (previous_line, error_line, underline) = error_context(&self.tokenizer.source, line, col);
+        if show_source {
+            if let Some(previous_line) = previous_line {
+                println!("  {}", previous_line.italic());
             }
+            println!("  {}", error_line.italic());
         }
+        match severity {
+            Severity::Critical => println!("  {}{}", underline, "^".red()),
+            Severity::Info => println!("  {}{}", underline, "^".blue()),
+        };
         println!("  line: {}, col: {}   {}", line, col, 
             match severity {
                 Severity::Critical => message.red(),
@@ -384,14 +529,20 @@ impl Parser {
     }
 
     pub fn print_errors(&self) {
-        println!();
+        // A one line input in the REPL is still on screen right above, after a
+        // prompt as wide as the margin, so a single error is underlined in place
+        let single_error = self.tokenizer.failed() || self.errors.len() == 1;
+        let in_place = self.config.echo_result && single_error && self.tokenizer.source.lines().count() <= 1;
+        if !in_place {
+            println!();
+        }
         if let TokenizerState::Error(message) = &self.tokenizer.state {
-            self._pretty_print_error_line(self.tokenizer.line, self.tokenizer.col, Severity::Critical, message);
+            self._pretty_print_error_line(self.tokenizer.line, self.tokenizer.col, Severity::Critical, message, !in_place);
             return;
         }
         if self.parsing_failed() {
             for error in &self.errors {
-                self._pretty_print_error_line(error.line, error.col, error.severity, &error.message);
+                self._pretty_print_error_line(error.line, error.col, error.severity, &error.message, !in_place);
             }
             return;
         }
@@ -399,18 +550,93 @@ impl Parser {
 
     pub fn pretty_print(&self) {
         if let TokenizerState::Error(message) = &self.tokenizer.state {
-            self._pretty_print_error_line(self.tokenizer.line, self.tokenizer.col, Severity::Critical, message);
+            self._pretty_print_error_line(self.tokenizer.line, self.tokenizer.col, Severity::Critical, message, true);
             return;
         }
         if self.parsing_failed() {
             for error in &self.errors {
-                self._pretty_print_error_line(error.line, error.col, error.severity, &error.message);
+                self._pretty_print_error_line(error.line, error.col, error.severity, &error.message, true);
             }
             return;
         }
         if !self.ast.is_empty() {
-            self._pretty_print_ast(self.cur_ast_node_index(), 0, false);
+            self._pretty_print_ast(self.ast.len() - 1, 0, false);
+        }
+    }
+
+    // The name of the kind of an ast node, and what it holds besides its sub expressions
+    fn describe_ast_node(&self, index: usize) -> (String, Option<String>) {
+        let node = &self.ast[index];
+        let debug = format!("{:?}", node);
+        let kind = debug.split('(').next().unwrap_or_default().to_owned();
+        let value = match node {
+            AstNode::Number(_, num) => Some(num.to_string()),
+            AstNode::String(_, text) => Some(text.to_owned()),
+            AstNode::Boolean(_, val) => Some(val.to_string()),
+            AstNode::KeyValue(_, name, _) | AstNode::LocalLet(_, name, ..) | AstNode::GlobalLet(_, name, ..) |
+            AstNode::GlobalValueReference(_, name) | AstNode::LocalValueReference(_, name) |
+            AstNode::FunctionCall(_, name, _) | AstNode::StaticKeyAccess(_, name, _) | AstNode::Test(_, name, _) => {
+                Some(name.to_owned())
+            },
+            AstNode::LocalIncr(_, _, decr) | AstNode::GlobalIncr(_, _, decr) => {
+                Some(if *decr { "dec" } else { "inc" }.to_owned())
+            },
+            AstNode::FunctionDef(_, args, _) => {
+                Some(args.iter().map(|arg| arg.name.to_owned()).collect::<Vec<String>>().join(" "))
+            },
+            AstNode::UnaryOperator(_, op, _) => Some(format!("{:?}", op)),
+            AstNode::BinaryOperator(_, op, ..) | AstNode::ChainedComparison(_, op, ..) => Some(format!("{:?}", op)),
+            _ => None,
+        };
+        return (kind, value);
+    }
+
+    fn ast_node_to_json(&self, index: usize, json: &mut String) {
+        let (kind, value) = self.describe_ast_node(index);
+        let (line, col) = self.get_ast_node_line_col(index);
+        json.push_str(&format!("{{\"kind\": {}", json_string(&kind)));
+        if let Some(value) = value {
+            json.push_str(&format!(", \"value\": {}", json_string(&value)));
+        }
+        json.push_str(&format!(", \"line\": {}, \"col\": {}, \"children\": [", line, col));
+        for (idx, child) in self.ast[index].children().into_iter().enumerate() {
+            if idx > 0 {
+                json.push_str(", ");
+            }
+            self.ast_node_to_json(child, json);
+        }
+        json.push_str("]}");
+    }
+
+    // The ast as nested json objects {"kind", "value", "line", "col", "children"},
+    // "value" is only there for the nodes that hold a name, a literal or an operator
+    pub fn ast_to_json(&self) -> String {
+        let mut json = String::new();
+        if self.ast.is_empty() {
+            json.push_str("null");
+        } else {
+            self.ast_node_to_json(self.ast.len() - 1, &mut json);
+        }
+        return json;
+    }
+
+    // The ast as a graphviz graph, to render with `dot -Tsvg`
+    pub fn ast_to_dot(&self) -> String {
+        let mut dot = String::from("digraph ast {\n    node [shape=box];\n");
+        let mut pending: Vec<usize> = if self.ast.is_empty() { vec![] } else { vec![self.ast.len() - 1] };
+        while let Some(index) = pending.pop() {
+            let label = match self.describe_ast_node(index) {
+                (kind, Some(value)) => format!("{}\n{}", kind, value),
+                (kind, None) => kind,
+            };
+            dot.push_str(&format!("    n{} [label={}];\n", index, json_string(&label)));
+            for child in self.ast[index].children() {
+                dot.push_str(&format!("    n{} -> n{};\n", index, child));
+                pending.push(child);
+            }
         }
+        dot.push('}');
+        return dot;
     }
 
     fn is_in_loop(&self) -> bool {
@@ -487,12 +713,28 @@ impl Parser {
             Token {value: TokenValue::RightSqBrkt, ..} => {
                 return true;
             },
+            Token {value: TokenValue::Semicolon, ..} => {
+                return true;
+            },
             _ => {
                 return false;
             }
         }
     }
 
+    // An expression that can be one more argument of a variadic call without commas
+    fn peek_variadic_argument(&self, line: usize) -> bool {
+        let token = self.peekt();
+        if token.line != line {
+            return false;
+        }
+        return match &token.value {
+            TokenValue::Number(..) | TokenValue::String(..) | TokenValue::LeftP | TokenValue::LeftSqBrkt => true,
+            TokenValue::Name(name) => !is_reserved_keyword(name) && self.env.get_entry(name).is_some(),
+            _ => false,
+        };
+    }
+
     fn peek_else(&self) -> bool {
         let token = &self.peekt();
         match token {
@@ -521,6 +763,8 @@ impl Parser {
                     "*"    => Some(BinaryOperator::Multiply),
                     "/"    => Some(BinaryOperator::Divide),
                     "%"    => Some(BinaryOperator::Modulo),
+                    "//"   => Some(BinaryOperator::FloorDivide),
+                    "%%"   => Some(BinaryOperator::FloorModulo),
                     "&&"   => Some(BinaryOperator::And),
                     "||"   => Some(BinaryOperator::Or),
                     "??"   => Some(BinaryOperator::NullishOr),
@@ -537,6 +781,7 @@ impl Parser {
                     _ => None, 
                 }
             }
+            Token {value: TokenValue::Name(name), ..} if name == "of" => Some(BinaryOperator::Multiply),
             _ => {
                 None
             }
@@ -557,6 +802,21 @@ impl Parser {
         return matches!(token.value, TokenValue::RightP);
     }
 
+    fn peek_leftbrkt(&self) -> bool {
+        let token = &self.peekt();
+        return matches!(token.value, TokenValue::LeftBrkt);
+    }
+
+    fn peek_rightbrkt(&self) -> bool {
+        let token = &self.peekt();
+        return matches!(token.value, TokenValue::RightBrkt);
+    }
+
+    fn peek_pipe(&self) -> bool {
+        let token = &self.peekt();
+        return matches!(token.value, TokenValue::Pipe);
+    }
+
     fn peek_swp(&self) -> bool {
         let token = &self.peekt();
         return matches!(token.value, TokenValue::Swp);
@@ -567,6 +827,11 @@ impl Parser {
         return matches!(token.value, TokenValue::Comma);
     }
 
+    fn peek_semicolon(&self) -> bool {
+        let token = &self.peekt();
+        return matches!(token.value, TokenValue::Semicolon);
+    }
+
     fn peek_equal(&self) -> bool {
         let token = &self.peekt();
         return matches!(token.value, TokenValue::Equal);
@@ -587,6 +852,21 @@ impl Parser {
         return matches!(token.value, TokenValue::Dot);
     }
 
+    // The name of the function for the next `namespace.name` tokens, like `math.sin`,
+    // when such a function exists
+    fn peek_namespaced_func_name(&self) -> Option<String> {
+        let tokens = &self.tokenizer.tokens;
+        let name = match (tokens.get(self.nextindex)?, tokens.get(self.nextindex + 1)?, tokens.get(self.nextindex + 2)?) {
+            (
+                Token {value: TokenValue::Name(namespace), ..},
+                Token {value: TokenValue::Dot, ..},
+                Token {value: TokenValue::Name(member), ..},
+            ) => format!("{}.{}", namespace, member),
+            _ => return None,
+        };
+        return self.env.get_entry(&name).filter(|entry| entry.is_func).map(|_| name);
+    }
+
     fn cur_line_col(&self) ->  (usize, usize){
         let token = &self.tokenizer.tokens[self.index];
         return (token.line, token.col);
@@ -617,30 +897,77 @@ impl Parser {
         );
     }
 
-    fn cur_ast_node_index(&self) -> usize {
-        if self.ast.is_empty() {
-            panic!("should not happen");
+    // An error because the input ended too early is incomplete, the REPL then asks for
+    // the following lines instead of reporting it
+    fn push_unfinished(&mut self, line: usize, col: usize, message: String) {
+        if self.peek_eof() {
+            self.push_incomplete(line, col, message);
         } else {
-            return self.ast.len() - 1;
+            self.push_error(line, col, message);
         }
     }
 
-    fn cur_ast_node(&self) -> &AstNode{
+    // The last parsed expression. There always is one when this is called, but if a bug
+    // in the parser breaks this it is reported as an error instead of a panic.
+    fn cur_ast_node_index(&mut self) -> usize {
         if self.ast.is_empty() {
-            panic!("should not happen");
-        } else {
-            return &self.ast[self.ast.len() - 1];
+            let (line, col) = (self.peekt().line, self.peekt().col);
+            self.push_error(line, col, "ERROR: internal parser error: no expression was parsed".to_owned());
+            self.ast.push(AstNode::Void(self.index));
         }
+        return self.ast.len() - 1;
+    }
+
+    fn cur_ast_node(&mut self) -> &AstNode {
+        let idx = self.cur_ast_node_index();
+        return &self.ast[idx];
     }
 
     pub fn get_ast_node(&self, index: usize) -> AstNode{
         return self.ast[index].clone();
     }
 
+    // Source position of the token from which the ast node was parsed
+    pub fn get_ast_node_line_col(&self, index: usize) -> (usize, usize) {
+        let tokens = &self.tokenizer.tokens;
+        if index >= self.ast.len() || tokens.is_empty() {
+            return (0, 0);
+        }
+        let token = &tokens[self.ast[index].token_index().min(tokens.len() - 1)];
+        return (token.line, token.col);
+    }
+
     pub fn parsing_failed(&self) -> bool {
         return self.state == ParserState::Error || self.state == ParserState::Incomplete;
     }
 
+    // parses the type name after the `:` of an annotation, the types are
+    // only verified by the --typecheck pass
+    fn parse_type_annotation(&mut self) -> Option<String> {
+        let token = self.nextt().clone();
+        let (line, col) = self.cur_line_col();
+        return self.check_type_name(&token, line, col);
+    }
+
+    fn check_type_name(&mut self, token: &Token, line: usize, col: usize) -> Option<String> {
+        match &token.value {
+            TokenValue::Name(name) if TYPE_NAMES.contains(&name.as_str()) => {
+                return Some(name.to_owned());
+            },
+            TokenValue::Name(name) => {
+                let message = format!("ERROR: unknown type '{}', expected one of {}", name, TYPE_NAMES.join(", "));
+                self.push_error(line, col, message);
+            },
+            TokenValue::Eof => {
+                self.push_incomplete(line, col, "ERROR: expected a type name".to_owned());
+            },
+            _ => {
+                self.push_error(line, col, "ERROR: expected a type name".to_owned());
+            },
+        }
+        return None;
+    }
+
     fn parse_function_def(&mut self, func_name: Option<&str>) {
         // parses a function definiton |a b:n| body
         // when starting the `|` must have already been consumed
@@ -658,7 +985,7 @@ impl Parser {
             if self.peek_closing_element() {
                 let (line, col) = self.peek_line_col();
                 self.push_info(fline, fcol, "start of unterminated function".to_owned());
-                self.push_error(line, col,  "ERROR: missing | to close the function argument list".to_owned());
+                self.push_unfinished(line, col, "ERROR: missing | to close the function argument list".to_owned());
                 return;
             }
             let argname_token = &self.nextt().clone();
@@ -671,6 +998,7 @@ impl Parser {
                     }
                     let mut argc: usize = 0;
                     let mut is_func: bool = false;
+                    let mut type_name: Option<String> = None;
 
                     // FIXME handle duplicate arguments names
                     // TODO handle _ dummy arguments
@@ -681,7 +1009,7 @@ impl Parser {
 
                         if self.peek_closing_element() {
                             let (line, col) = self.peek_line_col();
-                            self.push_error(line, col, "ERROR: missing function argument argcount".to_owned());
+                            self.push_unfinished(line, col, "ERROR: missing function argument argcount".to_owned());
                             return;
                         }
                         let argcount_token = &self.nextt().clone();
@@ -699,6 +1027,12 @@ impl Parser {
                                 argc = *num as usize;
                                 is_func = true;
                             }
+                            Token {value: TokenValue::Name(..), ..} => { // or the type of |arg:num|
+                                type_name = self.check_type_name(argcount_token, line, col);
+                                if self.parsing_failed() {
+                                    return;
+                                }
+                            }
                             _ => {
                                 self.push_error(line, col, "ERROR: expected integer here".to_owned());
                                 return;
@@ -710,6 +1044,8 @@ impl Parser {
                         name:name.to_owned(),
                         is_func,
                         func_arity:argc,
+                        type_name,
+                        is_variadic: false,
                     });
                 },
                 Token {value: TokenValue::Pipe, ..} => {
@@ -725,7 +1061,7 @@ impl Parser {
 
         if self.peek_closing_element() {
             let (line, col) = self.peek_line_col();
-            self.push_error(line, col, "ERROR: missing function body".to_owned());
+            self.push_unfinished(line, col, "ERROR: missing function body".to_owned());
             return;
         }
 
@@ -746,6 +1082,7 @@ impl Parser {
 
 
         self.push_loop_status(false);
+        self.function_depth += 1;
 
         // parsing the function body
         self.parse_expression(ExpressionMode::Single, None);
@@ -753,6 +1090,7 @@ impl Parser {
             return;
         }
 
+        self.function_depth -= 1;
         self.pop_loop_status();
 
         for _ in &func_args {
@@ -763,7 +1101,8 @@ impl Parser {
             self.env.pop_entry();
         }
 
-        self.ast.push(AstNode::FunctionDef(func_token_index, func_args, self.cur_ast_node_index()));
+        let body_idx = self.cur_ast_node_index();
+        self.ast.push(AstNode::FunctionDef(func_token_index, func_args, body_idx));
     }
 
     fn parse_array_or_dynamic_key_access(&mut self) {
@@ -781,7 +1120,7 @@ impl Parser {
             if self.peek_eof() || self.peek_rightp() {
                 let (line, col) = self.peek_line_col();
                 self.push_info(aline, acol, "start of unfinished array".to_owned());
-                self.push_error(line, col, "ERROR: unfinished array".to_owned());
+                self.push_unfinished(line, col, "ERROR: unfinished array".to_owned());
                 return;
             } else if self.peek_rsqbrkt() {
                 self.nextt();
@@ -841,7 +1180,8 @@ impl Parser {
                 if self.parsing_failed() {
                     return;
                 }
-                self.ast.push(AstNode::KeyValue(keytoken_index, keystr, self.cur_ast_node_index()));
+                let value_idx = self.cur_ast_node_index();
+                self.ast.push(AstNode::KeyValue(keytoken_index, keystr, value_idx));
                 value_node_indexes.push(self.cur_ast_node_index())
             } else {
                 let (line, col) = self.peek_line_col();
@@ -865,20 +1205,19 @@ impl Parser {
         }
     }
 
-    fn parse_set(&mut self) {
-        // parses set target expr
-        // - set must have already been consumed
-        
+    fn parse_set_target(&mut self, keyword: &str) -> Option<(usize, bool)> {
+        // parses the variable targeted by set, inc or dec
+        // returns the index of the target node and if it is a global variable
+
         let (line, col) = self.peek_line_col();
         if self.peek_closing_element() {
-            self.push_error(line, col, "ERROR: expected target after 'set'".to_owned());
-            return;
+            self.push_unfinished(line, col, format!("ERROR: expected target after '{}'", keyword));
+            return None;
         }
 
-        let set_idx = self.index;
         self.parse_expression(ExpressionMode::Single, None);
         if self.parsing_failed() {
-            return;
+            return None;
         }
 
         let target_idx = self.cur_ast_node_index();
@@ -894,18 +1233,23 @@ impl Parser {
             AstNode::LocalValueReference(_, name) => {
                 var_name = name;
             },
+            AstNode::StaticKeyAccess(..) => {
+                self.push_error(line, col, format!("ERROR: invalid {} target, keys cannot be modified", keyword));
+                return None;
+            },
             _ => {
-                self.push_error(line, col, "ERROR: invalid set target".to_owned());
-                return;
+                self.push_error(line, col, format!("ERROR: invalid {} target, expected a variable", keyword));
+                return None;
             }
         };
 
         match self.env.get_entry(&var_name.to_owned()) {
             Some(entry) => {
                 if entry.is_global != global_set {
-                    self.push_error(line, col, "ERROR: globality type mismatch in set".to_owned());
+                    self.push_error(line, col, format!("ERROR: globality type mismatch in {}", keyword));
                 } else if entry.is_const {
-                    self.push_error(line, col, "ERROR: cannot assign to a constant variable (use 'var' instead of 'let')".to_owned());
+                    let declared_with = if entry.is_fixed { "const" } else { "let" };
+                    self.push_error(line, col, format!("ERROR: cannot assign to a constant variable (use 'var' instead of '{}')", declared_with));
                 }
                 // FIXME: typecheck the function / value and number of args
             },
@@ -915,14 +1259,29 @@ impl Parser {
         };
 
         if self.parsing_failed() {
-            return;
+            return None;
         }
 
+        return Some((target_idx, global_set));
+    }
+
+    fn parse_set(&mut self) {
+        // parses set target expr
+        // - set must have already been consumed
+        
+        let (line, col) = self.peek_line_col();
+        let set_idx = self.index;
+
+        let (target_idx, global_set) = match self.parse_set_target("set") {
+            Some(target) => target,
+            None => return,
+        };
+
         if self.peek_equal() { // we accept an optional '='; "let x = 42" or "let x 42"
             self.nextt();
         } else {
             let (line, col) = self.peek_line_col();
-            self.push_error(line, col, "ERROR: expected '='".to_owned());
+            self.push_unfinished(line, col, "ERROR: expected '='".to_owned());
             return;
         }
 
@@ -930,7 +1289,7 @@ impl Parser {
         if self.peek_closing_element() {
             let (eline, ecol) = self.peek_line_col();
             self.push_info(line, col, "this set is missing an expression".to_owned());
-            self.push_error(eline, ecol, "ERROR: expected expression for 'set'".to_owned());
+            self.push_unfinished(eline, ecol, "ERROR: expected expression for 'set'".to_owned());
             return;
         }
 
@@ -947,6 +1306,25 @@ impl Parser {
         }
     }
 
+    fn parse_inc(&mut self, decrement: bool) {
+        // parses inc target or dec target
+        // - inc or dec must have already been consumed
+
+        let inc_idx = self.index;
+        let keyword = if decrement { "dec" } else { "inc" };
+
+        let (target_idx, global_set) = match self.parse_set_target(keyword) {
+            Some(target) => target,
+            None => return,
+        };
+
+        if global_set {
+            self.ast.push(AstNode::GlobalIncr(inc_idx, target_idx, decrement));
+        } else {
+            self.ast.push(AstNode::LocalIncr(inc_idx, target_idx, decrement));
+        }
+    }
+
     fn check_comma_parenthesis_or_newline(&mut self) {
         let (line, _) = self.cur_line_col();
 
@@ -962,7 +1340,7 @@ impl Parser {
                 self.push_error(nline, ncol, "ERROR: must be on the previous line after the comma".to_owned());
                 return;
             }
-        } else if self.peek_rightp() {
+        } else if self.peek_rightp() || self.peek_rightbrkt() {
             return;
         } else if self.peek_leftp() {
             let (cline, ccol) = self.peek_line_col();
@@ -970,7 +1348,13 @@ impl Parser {
                 self.push_error(cline, ccol, "ERROR: parenthesis must be on the previous line".to_owned());
                 return;
             }
-        } else if self.peek_eof() {
+        } else if self.peek_leftbrkt() {
+            let (cline, ccol) = self.peek_line_col();
+            if line != cline {
+                self.push_error(cline, ccol, "ERROR: '{' must be on the previous line".to_owned());
+                return;
+            }
+        } else if self.peek_eof() || self.peek_semicolon() {
             return;
         } else {
             let (nline, ncol) = self.peek_line_col();
@@ -984,7 +1368,7 @@ impl Parser {
     fn parse_do(&mut self) {
         let (line, col) = self.peek_line_col();
         if self.peek_closing_element() {
-            self.push_error(line, col, "ERROR: expected expression after 'do'".to_owned());
+            self.push_unfinished(line, col, "ERROR: expected expression after 'do'".to_owned());
             return;
         }
 
@@ -1025,7 +1409,7 @@ impl Parser {
     fn parse_ife(&mut self) {
         let (line, col) = self.peek_line_col();
         if self.peek_closing_element() {
-            self.push_incomplete(line, col, "ERROR: expected condition after 'ife'".to_owned());
+            self.push_unfinished(line, col, "ERROR: expected condition after 'ife'".to_owned());
             return;
         }
 
@@ -1044,7 +1428,7 @@ impl Parser {
         if self.peek_closing_element() {
             let (eline, ecol) = self.peek_line_col();
             self.push_info(line, col, "this if is missing an expression".to_owned());
-            self.push_incomplete(eline, ecol, "ERROR: expected success expression for 'if'".to_owned());
+            self.push_unfinished(eline, ecol, "ERROR: expected success expression for 'if'".to_owned());
             return;
         }
 
@@ -1059,7 +1443,7 @@ impl Parser {
             if self.peek_closing_element() {
                 let (eline, ecol) = self.peek_line_col();
                 self.push_info(line, col, "this if is missing an expression".to_owned());
-                self.push_incomplete(eline, ecol, "ERROR: expected else expression for 'if'".to_owned());
+                self.push_unfinished(eline, ecol, "ERROR: expected else expression for 'if'".to_owned());
                 return;
             }
 
@@ -1078,7 +1462,7 @@ impl Parser {
     fn parse_while(&mut self) {
         let (line, col) = self.peek_line_col();
         if self.peek_closing_element() {
-            self.push_incomplete(line, col, "ERROR: expected condition after 'while'".to_owned());
+            self.push_unfinished(line, col, "ERROR: expected condition after 'while'".to_owned());
             return;
         }
 
@@ -1097,7 +1481,7 @@ impl Parser {
         if self.peek_closing_element() {
             let (eline, ecol) = self.peek_line_col();
             self.push_info(line, col, "this while is missing an expression".to_owned());
-            self.push_incomplete(eline, ecol, "ERROR: expected body expression for 'while'".to_owned());
+            self.push_unfinished(eline, ecol, "ERROR: expected body expression for 'while'".to_owned());
             return;
         }
 
@@ -1125,7 +1509,7 @@ impl Parser {
 
         if self.peek_closing_element() {
             let (line, col) = self.peek_line_col();
-            self.push_incomplete(line, col, "ERROR: expected body expression for 'loop'".to_owned());
+            self.push_unfinished(line, col, "ERROR: expected body expression for 'loop'".to_owned());
             return;
         }
 
@@ -1152,7 +1536,7 @@ impl Parser {
         }
 
         if self.peek_closing_element() {
-            self.push_incomplete(line, col, "ERROR: expected break value after 'break_as'".to_owned());
+            self.push_unfinished(line, col, "ERROR: expected break value after 'break_as'".to_owned());
             return;
         }
 
@@ -1168,6 +1552,139 @@ impl Parser {
         self.ast.push(AstNode::Break(break_idx, expr_idx));
     }
 
+    fn parse_return(&mut self) {
+        self.parse_function_exit("return");
+    }
+
+    fn parse_yield(&mut self) {
+        self.parse_function_exit("yield");
+    }
+
+    // `return expr` and `yield expr`, the value is void when the keyword is alone on its line
+    fn parse_function_exit(&mut self, keyword: &str) {
+        let (line, col) = self.cur_line_col();
+
+        if self.function_depth == 0 {
+            self.push_error(line, col, format!("ERROR: '{}' is only allowed in functions", keyword));
+            return
+        }
+
+        let exit_idx = self.index;
+
+        let (vline, _) = self.peek_line_col();
+        if self.peek_closing_element() || vline != line {
+            self.ast.push(AstNode::Void(exit_idx));
+        } else {
+            self.parse_expression(ExpressionMode::Single, None);
+            if self.parsing_failed() {
+                return;
+            }
+        }
+
+        let expr_idx = self.cur_ast_node_index();
+
+        if keyword == "yield" {
+            self.ast.push(AstNode::Yield(exit_idx, expr_idx));
+        } else {
+            self.ast.push(AstNode::Return(exit_idx, expr_idx));
+        }
+    }
+
+    // memo |n| body, a function caching its results by arguments
+    fn parse_memo(&mut self, var_name: Option<&str>) {
+        let (line, col) = self.peek_line_col();
+        let memo_idx = self.index;
+
+        if self.peek_eof() {
+            self.push_incomplete(line, col, "ERROR: expected a function definition after 'memo'".to_owned());
+            return;
+        } else if !self.peek_pipe() {
+            self.push_error(line, col, "ERROR: expected a function definition after 'memo'".to_owned());
+            return;
+        }
+        self.nextt();
+
+        self.parse_function_def(var_name);
+        if self.parsing_failed() {
+            return;
+        }
+
+        let def_idx = self.cur_ast_node_index();
+
+        self.ast.push(AstNode::Memo(memo_idx, def_idx));
+    }
+
+    // lazy expr, the expression is evaluated by the first force
+    fn parse_lazy(&mut self) {
+        let keyword_idx = self.index;
+        if let Some(expr_idx) = self.parse_deferred("lazy") {
+            self.ast.push(AstNode::Lazy(keyword_idx, expr_idx));
+        }
+    }
+
+    fn parse_spawn(&mut self) {
+        let keyword_idx = self.index;
+        if let Some(expr_idx) = self.parse_deferred("spawn") {
+            self.ast.push(AstNode::Spawn(keyword_idx, expr_idx));
+        }
+    }
+
+    // test 'name' expr
+    fn parse_test(&mut self) {
+        let keyword_idx = self.index;
+        let (line, col) = self.cur_line_col();
+        if self.function_depth > 0 {
+            self.push_error(line, col, "ERROR: tests are only allowed outside of functions".to_owned());
+            return;
+        }
+        let (nline, ncol) = self.peek_line_col();
+        let name = match &self.peekt().value {
+            TokenValue::String(name, ..) => name.to_owned(),
+            TokenValue::Eof => {
+                self.push_incomplete(nline, ncol, "ERROR: expected the name of the test".to_owned());
+                return;
+            },
+            _ => {
+                self.push_error(nline, ncol, "ERROR: expected the name of the test".to_owned());
+                return;
+            },
+        };
+        self.nextt();
+        if let Some(expr_idx) = self.parse_deferred("test") {
+            self.ast.push(AstNode::Test(keyword_idx, name, expr_idx));
+        }
+    }
+
+    // `lazy expr`, `spawn expr` and `test 'name' expr`, the expression is evaluated
+    // later, returns the index of its ast node
+    fn parse_deferred(&mut self, keyword: &str) -> Option<usize> {
+        let (line, col) = self.peek_line_col();
+
+        if self.peek_eof() {
+            self.push_incomplete(line, col, format!("ERROR: expected expression after '{}'", keyword));
+            return None;
+        } else if self.peek_closing_element() {
+            self.push_error(line, col, format!("ERROR: expected expression after '{}'", keyword));
+            return None;
+        }
+
+        // the expression is compiled as a function of its own,
+        // it can't break the enclosing loop or return from the enclosing function
+        let function_depth = self.function_depth;
+        self.function_depth = 0;
+        self.push_loop_status(false);
+
+        self.parse_expression(ExpressionMode::Single, None);
+        if self.parsing_failed() {
+            return None;
+        }
+
+        self.pop_loop_status();
+        self.function_depth = function_depth;
+
+        return Some(self.cur_ast_node_index());
+    }
+
     fn parse_break(&mut self) {
         let (line, col) = self.peek_line_col();
 
@@ -1185,13 +1702,15 @@ impl Parser {
         self.ast.push(AstNode::Break(break_idx, void_idx));
     }
 
-    fn parse_let(&mut self, mode: ExpressionMode, is_const: bool) {
+    fn parse_let(&mut self, mode: ExpressionMode, keyword: &str) {
+        // var can be set, let can be declared again, const can do neither
+        let is_const = keyword != "var";
         let global_scope: bool = matches!(mode, ExpressionMode::TopLevel);
 
         let (let_line, let_col) = self.cur_line_col();
         let (line, col) = self.peek_line_col();
         if self.peek_closing_element() {
-            self.push_error(line, col, "ERROR: expected identifier after 'let'".to_owned());
+            self.push_unfinished(line, col, format!("ERROR: expected identifier after '{}'", keyword));
             return;
         }
         
@@ -1205,6 +1724,10 @@ impl Parser {
                 } else {
 
                     if let Some(entry) =  self.env.get_entry(&var_name.to_owned()) {
+                        if entry.is_fixed && entry.is_global == global_scope {
+                            self.push_error(line, col, "ERROR: cannot redefine a constant".to_owned());
+                            return;
+                        }
                         if entry.is_const != is_const {
                             self.push_error(line, col, "ERROR: variable already declared with a different qualifier (var/let)".to_owned());
                             return;
@@ -1212,10 +1735,21 @@ impl Parser {
                         // FIXME: typecheck the function / value and number of args
                     }
 
+                    let type_name = if self.peek_colon() { // let x:num = 42
+                        self.nextt();
+                        let type_name = self.parse_type_annotation();
+                        if self.parsing_failed() {
+                            return;
+                        }
+                        type_name
+                    } else {
+                        None
+                    };
+
                     if self.peek_closing_element() {
                         let (vline, vcol) = self.peek_line_col();
                         self.push_info(line, col, "this variable definition doesn't have a value".to_owned());
-                        self.push_error(vline, vcol, "ERROR: expected value for the defined variable".to_owned());
+                        self.push_unfinished(vline, vcol, "ERROR: expected value for the defined variable".to_owned());
                         return;
                     }
 
@@ -1223,7 +1757,7 @@ impl Parser {
                         self.nextt();
                     } else {
                         let (vline, vcol) = self.peek_line_col();
-                        self.push_error(vline, vcol, "ERROR: expected '='".to_owned());
+                        self.push_unfinished(vline, vcol, "ERROR: expected '='".to_owned());
                         return;
                     }
         
@@ -1232,11 +1766,14 @@ impl Parser {
                         return;
                     }
                     let def_idx = self.cur_ast_node_index();
+                    if let Some(type_name) = type_name {
+                        self.let_types.insert(def_idx, type_name);
+                    }
 
 
-                    let value_node = &self.cur_ast_node();
+                    let value_node = self.cur_ast_node().clone();
 
-                    match value_node {
+                    match &value_node {
                         AstNode::FunctionDef(_, args,_) => {
                             self.env.push_func_entry(
                                 var_name.clone(),
@@ -1245,16 +1782,32 @@ impl Parser {
                                 args.clone(),
                             );
                         }
+                        AstNode::Memo(_, def_idx) => {
+                            let args = match &self.ast[*def_idx] {
+                                AstNode::FunctionDef(_, args, _) => args.clone(),
+                                _ => vec![],
+                            };
+                            self.env.push_func_entry(var_name.clone(), global_scope, true, args);
+                        }
                         _ => {
                             self.env.push_value_entry(var_name.clone(), global_scope, is_const);
                         }
                     };
+                    if keyword == "const" {
+                        self.env.fix_entry(var_name);
+                    }
 
                     self.check_comma_parenthesis_or_newline();
                     if self.parsing_failed() {
                         return;
                     }
 
+                    // in a block the rest of the block after a `;` is the let expression
+                    let after_semicolon = mode == ExpressionMode::Sequence && self.peek_semicolon();
+                    if after_semicolon {
+                        self.nextt();
+                    }
+
                     if !self.peek_closing_element() {
                         match mode {
                             ExpressionMode::TopLevel => {
@@ -1265,7 +1818,9 @@ impl Parser {
                             },
                             ExpressionMode::Sequence => {
                                 let (line, col) = self.peek_line_col();
-                                if line <= let_line {
+                                if after_semicolon {
+                                    // no layout constraint after a `;`
+                                } else if line <= let_line {
                                     self.push_error(line, col, "ERROR: cannot have two expressions on the same line in a block".to_owned());
                                     return;
                                 } else if col != let_col {
@@ -1278,8 +1833,20 @@ impl Parser {
                         if self.parsing_failed() {
                             return;
                         }
-                    } else {
+                    } else if global_scope {
                         self.ast.push(AstNode::Void(let_idx));
+                    } else {
+                        // a local variable is only visible in the following expression,
+                        // without one it is useless and the let evaluates to void
+                        let (eline, ecol) = self.peek_line_col();
+                        self.push_info(let_line, let_col, "this let needs a following expression; did you mean a code block?".to_owned());
+                        let message = "ERROR: expected an expression after the let".to_owned();
+                        if self.peek_eof() {
+                            self.push_incomplete(eline, ecol, message);
+                        } else {
+                            self.push_error(eline, ecol, message);
+                        }
+                        return;
                     }
 
                     if !global_scope {
@@ -1403,6 +1970,34 @@ impl Parser {
 
                         arg_node_indexes.push(self.cur_ast_node_index()); 
                     }
+
+                    // a variadic last argument takes the next comma separated expressions of
+                    // an explicit call, or else the next expressions on the same line
+                    if env_entry.func_args.last().is_some_and(|arg| arg.is_variadic) {
+                        loop {
+                            if explicit_func_call {
+                                if !self.peek_comma() {
+                                    break;
+                                }
+                                self.nextt();
+                                if self.peek_closing_element() {
+                                    let (vline, vcol) = self.peek_line_col();
+                                    self.push_info(line, col, "this function call is missing an argument".to_owned());
+                                    self.push_unfinished(vline, vcol, "ERROR: expected argument for function call".to_owned());
+                                    return;
+                                }
+                                self.parse_expression(ExpressionMode::Single, None);
+                            } else if !uses_commas && self.peek_variadic_argument(self.cur_line_col().0) {
+                                self.parse_unary(ExpressionMode::Single, None);
+                            } else {
+                                break;
+                            }
+                            if self.parsing_failed() {
+                                return;
+                            }
+                            arg_node_indexes.push(self.cur_ast_node_index());
+                        }
+                    }
                     self.ast.push(AstNode::FunctionCall(func_token_index, name, arg_node_indexes));
                     
                     if explicit_func_call {
@@ -1442,10 +2037,8 @@ impl Parser {
 
         let (dline, dcol) = self.cur_line_col();  // line col of the dot
 
-        if self.peek_eof() {
-            self.push_incomplete(dline, dcol, "ERROR: expected expression after key access".to_owned());
-        } else if self.peek_closing_element() {
-            self.push_error(dline, dcol, "ERROR: expected expression after key access".to_owned());
+        if self.peek_closing_element() {
+            self.push_unfinished(dline, dcol, "ERROR: expected expression after key access".to_owned());
             return;
         }
 
@@ -1455,7 +2048,8 @@ impl Parser {
             return;
         }
 
-        self.ast.push(AstNode::StaticKeyAccess(key_name_idx, key_name, self.cur_ast_node_index()));
+        let expr_idx = self.cur_ast_node_index();
+        self.ast.push(AstNode::StaticKeyAccess(key_name_idx, key_name, expr_idx));
     }
 
     // fn parse_expression(&mut self, global_scope: bool, code_block: bool, var_name: Option<&str>) {
@@ -1484,7 +2078,17 @@ impl Parser {
             return;
         }
 
+        // expressions separated by `;` don't need to be on their own line
+        let after_semicolon = self.peek_semicolon();
+        if after_semicolon {
+            self.nextt();
+        }
+
         if self.peek_closing_element() {
+            if self.peek_semicolon() {
+                let (line, col) = self.peek_line_col();
+                self.push_error(line, col, "ERROR: expected expression before ';'".to_owned());
+            }
             return;
         }
 
@@ -1497,7 +2101,9 @@ impl Parser {
             return;
         }
 
-        if line2 <= line1 {
+        if after_semicolon {
+            // no layout constraint after a `;`
+        } else if line2 <= line1 {
             self.push_error(line2, col2, "ERROR: cannot have two expressions on the same line in a block".to_owned());
             return;
         } else if col1 != col2 {
@@ -1511,6 +2117,33 @@ impl Parser {
         self.ast.push(AstNode::Do(do_idx, expr1_idx, expr2_idx));
     }
 
+    // `{ a; b }` or a `{` followed by one expression per line, closed by `}`.
+    // Evaluates to the last expression, the lets are scoped to the block
+    fn parse_code_block(&mut self) {
+        let (bline, bcol) = self.cur_line_col();
+        if self.peek_rightbrkt() {
+            self.ast.push(AstNode::Void(self.index));
+            self.nextt();
+            return;
+        }
+        self.parse_expression_sequence();
+
+        if self.parsing_failed() {
+            return;
+        }
+        if self.peek_eof() {
+            self.push_info(bline, bcol, "unclosed '{'".to_owned());
+            let (line, col) = self.peek_line_col();
+            self.push_incomplete(line, col, "ERROR: expected closing '}'".to_owned());
+        } else if !self.peek_rightbrkt() {
+            self.push_info(bline, bcol, "unclosed '{'".to_owned());
+            let (line, col) = self.peek_line_col();
+            self.push_error(line, col, "ERROR: expected closing '}'".to_owned());
+        } else {
+            self.nextt();
+        }
+    }
+
     fn parse_binary(
         &mut self,
         mut left_node_index: usize,
@@ -1519,6 +2152,8 @@ impl Parser {
     ) {
         // https://en.wikipedia.org/wiki/Operator-precedence_parser
 
+        let mut left_is_comparison = false;
+
         loop {
             if let Some(op) = self.peek_binary_op() {
                 if operator_precedence(op) < min_precedence {
@@ -1571,9 +2206,14 @@ impl Parser {
                     }
                 }
 
-                self.ast.push(AstNode::BinaryOperator(op_token_index, op, left_node_index, right_node_index));
+                if left_is_comparison && operator_chains(op) {
+                    self.ast.push(AstNode::ChainedComparison(op_token_index, op, left_node_index, right_node_index));
+                } else {
+                    self.ast.push(AstNode::BinaryOperator(op_token_index, op, left_node_index, right_node_index));
+                }
 
                 left_node_index = self.cur_ast_node_index();
+                left_is_comparison = operator_chains(op);
             } else {
                 break;
             }
@@ -1583,6 +2223,7 @@ impl Parser {
     fn parse_unary(&mut self, mode: ExpressionMode, var_name: Option<&str>) {
 
         let dot_after_token = self.peek2_dot();
+        let namespaced_func_name = self.peek_namespaced_func_name();
         let token = &self.nextt();
         match token {
             Token {value: TokenValue::String(ref string, ..), ..} => {
@@ -1600,8 +2241,9 @@ impl Parser {
                         self.ast.push(AstNode::Number(self.index, __num));
                     }
                     None => {
+                        let message = format!("ERROR: {}", unknown_unit_message(unit));
                         let (line, col) = self.cur_line_col();
-                        self.push_error(line, col, "ERROR: unknown unit".to_owned());
+                        self.push_error(line, col, message);
                     }
                 };
             },
@@ -1609,38 +2251,35 @@ impl Parser {
                 self.parse_expression(ExpressionMode::Single, None);
             },
             Token {value: TokenValue::Operator(ref operator), ..} => {
-                if operator == "!" || operator == "-"  || operator == "+" {
-
-                    let op = if operator == "!" { 
-                        UnaryOperator::Not 
-                    } else if operator == "-" {
-                        UnaryOperator::Negate 
-                    } else if operator == "~!" {
-                        UnaryOperator::BitwiseNot
-                    } else if operator == "+" { 
-                        UnaryOperator::Add 
-                    } else {
-                        panic!("unknown operator");
-                    };
+                let unary_op = match operator.as_str() {
+                    "!"  => Some(UnaryOperator::Not),
+                    "-"  => Some(UnaryOperator::Negate),
+                    "+"  => Some(UnaryOperator::Add),
+                    "~!" => Some(UnaryOperator::BitwiseNot),
+                    _ => None,
+                };
 
+                if let Some(op) = unary_op {
                     let op_token_index = self.index;
 
                     self.parse_unary(ExpressionMode::Single, None);
                     if self.parsing_failed() {
                         return;
                     }
-                    self.ast.push(AstNode::UnaryOperator(
-                        op_token_index,
-                        op,
-                        self.cur_ast_node_index(),
-                    ));
+                    let expr_idx = self.cur_ast_node_index();
+                    self.ast.push(AstNode::UnaryOperator(op_token_index, op, expr_idx));
                 } else {
                     let (line, col) = self.cur_line_col();
                     self.push_error(line, col, "ERROR: unexpected operator".to_owned());
                 }
             },
             Token {value: TokenValue::Name(ref name, ..), ..} => {
-                if dot_after_token {
+                if let Some(func_name) = namespaced_func_name {
+                    // `math.sin` is a call to a namespaced function, not a key access
+                    self.nextt();
+                    self.nextt();
+                    self.parse_func_call(func_name);
+                } else if dot_after_token {
                     let key_name:String = name.to_owned();
                     self.parse_static_key_access(key_name);
                 } else if name == "true" {
@@ -1651,12 +2290,15 @@ impl Parser {
                     self.ast.push(AstNode::Null(self.index));
                 } else if name == "void" || name == "_" || name == "end" {
                     self.ast.push(AstNode::Void(self.index));
-                } else if name == "let" {
-                    self.parse_let(mode, true);
-                } else if name == "var" {
-                    self.parse_let(mode, false);
+                } else if name == "let" || name == "var" || name == "const" {
+                    let keyword = name.to_owned();
+                    self.parse_let(mode, &keyword);
                 } else if name == "set" {
                     self.parse_set();
+                } else if name == "inc" {
+                    self.parse_inc(false);
+                } else if name == "dec" {
+                    self.parse_inc(true);
                 } else if name == "if" {
                     self.parse_ife();
                 } else if name == "do" {
@@ -1669,6 +2311,18 @@ impl Parser {
                     self.parse_break();
                 } else if name == "break_as" {
                     self.parse_break_as();
+                } else if name == "return" {
+                    self.parse_return();
+                } else if name == "yield" {
+                    self.parse_yield();
+                } else if name == "memo" {
+                    self.parse_memo(var_name);
+                } else if name == "lazy" {
+                    self.parse_lazy();
+                } else if name == "spawn" {
+                    self.parse_spawn();
+                } else if name == "test" {
+                    self.parse_test();
                 } else if name == "continue" {
                     if self.is_in_loop() {
                         self.ast.push(AstNode::Continue(self.index));
@@ -1705,6 +2359,9 @@ impl Parser {
                     self.nextt();
                 }
             },
+            Token {value: TokenValue::LeftBrkt, ..} => {
+                self.parse_code_block();
+            },
             Token {value: TokenValue::LeftSqBrkt, ..} => {
                 self.parse_array_or_dynamic_key_access();
             },
@@ -1729,19 +2386,24 @@ impl Parser {
         let mut prev_line = 0;
         let mut prev_col = 0;
         let mut first_line = true;
+        // expressions separated by `;` don't need to be on their own line
+        let mut after_semicolon = false;
 
         loop {
-            if self.peek_closing_element() {
+            let (line, col) = self.peek_line_col();
+            if self.peek_semicolon() {
+                self.push_error(line, col, "ERROR: expected expression before ';'".to_owned());
+                return;
+            } else if self.peek_closing_element() {
                 break;
             }
 
-            let (line, col) = self.peek_line_col();
             self.parse_expression(ExpressionMode::TopLevel, None);
             if self.parsing_failed() {
                 return;
             }
 
-            if !first_line {
+            if !first_line && !after_semicolon {
                 if line == prev_line {
                     self.push_error(line, col, "ERROR: cannot have two expressions on the same line".to_owned());
                     return;
@@ -1754,10 +2416,16 @@ impl Parser {
 
             expressions_indexes.push(self.cur_ast_node_index());
 
-            first_line = false;
-            prev_line = line;
-            prev_col = col;
+            if !after_semicolon {
+                first_line = false;
+                prev_line = line;
+                prev_col = col;
+            }
 
+            after_semicolon = self.peek_semicolon();
+            if after_semicolon {
+                self.nextt();
+            }
         }
 
         if expressions_indexes.len() >= 2 {
@@ -1772,6 +2440,28 @@ impl Parser {
         }
 
         self.tokenizer.tokenize();
+        self.build_ast();
+    }
+
+    // Parses the source again after replacing the characters in `range` by `new_text`,
+    // for the tools that validate a buffer as it is edited. Only the tokens before the
+    // edit are reused: the ast is built again as a top level let holds the rest of the
+    // script, so no ast node before an edit stays the same.
+    pub fn reparse(&mut self, range: Range<usize>, new_text: &str) {
+        self.tokenizer.retokenize(range, new_text);
+        self.ast.clear();
+        self.env = self.base_env.clone();
+        self.nextindex = 0;
+        self.index = 0;
+        self.state = ParserState::Wip;
+        self.errors.clear();
+        self.in_loop = vec![false];
+        self.function_depth = 0;
+        self.let_types.clear();
+        self.build_ast();
+    }
+
+    fn build_ast(&mut self) {
         if self.tokenizer.failed() {
             return;
         }
@@ -1782,10 +2472,38 @@ impl Parser {
 
         self.parse_global_block();
 
+        if self.config.typecheck && !self.parsing_failed() {
+            for (node_idx, message) in check_types(self) {
+                let (line, col) = self.get_ast_node_line_col(node_idx);
+                self.push_error(line, col, message);
+            }
+        }
+
         if !self.parsing_failed() {
             self.state = ParserState::Done;
         }
     }
+
+    // Same as parse but a bug in the parser is reported as an error instead of a panic,
+    // for the tools that parse code as it is being written, like the REPL validation
+    pub fn parse_no_panic(&mut self) {
+        self.catching_panics(Parser::parse);
+    }
+
+    pub fn reparse_no_panic(&mut self, range: Range<usize>, new_text: &str) {
+        self.catching_panics(|parser| parser.reparse(range, new_text));
+    }
+
+    fn catching_panics(&mut self, parse: impl FnOnce(&mut Parser)) {
+        let parsed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| parse(self)));
+        if let Err(cause) = parsed {
+            let message = cause.downcast_ref::<&str>().map(|msg| msg.to_string())
+                .or_else(|| cause.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            let (line, col) = self.tokenizer.tokens.last().map(|token| (token.line, token.col)).unwrap_or((1, 1));
+            self.push_error(line, col, format!("ERROR: internal parser error: {}", message));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1795,7 +2513,31 @@ mod tests {
     const CONFIG: NopeConfig = NopeConfig {
         debug: true,
         trace: false,
+        trace_from: None,
         echo_result: false,
+        no_fs: false,
+        no_net: false,
+        no_exec: false,
+        allowed_paths: Vec::new(),
+        memory_limit: None,
+        gc_log: false,
+        checked_int: false,
+        prelude: None,
+        repl_echo: true,
+        color: true,
+        history_size: 100,
+        repl_step_limit: 100_000_000,
+        max_call_depth: 10000,
+        log_level: 1,
+        log_timestamps: false,
+        typecheck: false,
+        run_tests: false,
+        snapshot_dir: None,
+        script_name: None,
+        script_args: Vec::new(),
+        display_unit: None,
+        float_digits: None,
+        decimal_comma: false,
     };
     
     #[test]
@@ -1982,7 +2724,7 @@ mod tests {
         assert_eq!(parser.ast, vec![
             AstNode::Array(2, vec![]),
         ]);
-        assert_eq!(parser.state, ParserState::Error);
+        assert_eq!(parser.state, ParserState::Incomplete);
     }
 
     #[test]
@@ -2185,6 +2927,55 @@ mod tests {
         assert_eq!(parser.state, ParserState::Done);
     }
 
+    #[test]
+    fn test_parse_namespaced_call() {
+        let mut parser = Parser::new(CONFIG, String::from("math.sqrt 4"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(3, 4.0),
+            AstNode::FunctionCall(2, "math.sqrt".to_owned(), vec![0]),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+
+        // not a namespaced function, this stays a key access
+        let mut parser = Parser::new(CONFIG, String::from("let foo = 3, math.foo"));
+        parser.parse();
+        assert!(parser.ast.iter().any(|node| matches!(node, AstNode::StaticKeyAccess(_, key, _) if key == "math")));
+    }
+
+    #[test]
+    fn test_parse_const() {
+        let mut parser = Parser::new(CONFIG, String::from("const x = 3, x"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(3, 3.0),
+            AstNode::GlobalValueReference(5, "x".to_owned()),
+            AstNode::GlobalLet(0, "x".to_owned(), 0, 1)
+        ]);
+        let entry = parser.env.get_entry(&"x".to_owned()).unwrap();
+        assert_eq!((entry.is_global, entry.is_const, entry.is_fixed), (true, true, true));
+        assert_eq!(parser.state, ParserState::Done);
+
+        for code in [
+            "const x = 3\nset x = 4",
+            "const x = 3\nlet x = 4",
+            "const x = 3\nvar x = 4",
+            "const f = |a| a\nconst f = |a| a",
+            "const x = 3\nlet f = |a| set x = a",
+            "let f = |a| { const y = a; set y = 3 }",
+            "const const = 1",
+        ] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert!(parser.failed(), "{}", code);
+        }
+
+        // a local can still shadow a global constant
+        let mut parser = Parser::new(CONFIG, String::from("const x = 3\nlet f = |a| { let x = a; x }"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
     #[test]
     fn test_parse_let_with_equal() {
         let mut parser = Parser::new(CONFIG, String::from("let x = 3, x"));
@@ -2281,7 +3072,7 @@ mod tests {
         let mut parser = Parser::new(CONFIG, String::from("let x"));
         parser.parse();
         assert_eq!(parser.ast, vec![]);
-        assert_eq!(parser.state, ParserState::Error);
+        assert_eq!(parser.state, ParserState::Incomplete);
     }
 
     #[test]
@@ -2289,12 +3080,12 @@ mod tests {
         let mut parser = Parser::new(CONFIG, String::from("let"));
         parser.parse();
         assert_eq!(parser.ast, vec![]);
-        assert_eq!(parser.state, ParserState::Error);
+        assert_eq!(parser.state, ParserState::Incomplete);
     }
 
     #[test]
     fn test_parse_let_redefine_keyword() {
-        for kw in ["null", "true", "false", "void", "do", "if", "ife", "end"] {
+        for kw in ["null", "true", "false", "void", "do", "if", "ife", "end", "inc", "dec"] {
             let mut parser = Parser::new(CONFIG, String::from(format!("let {} = 3, _", kw)));
             parser.parse();
             assert_eq!(parser.ast, vec![]);
@@ -2312,6 +3103,112 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_inc_dec() {
+        let mut parser = Parser::new(CONFIG, String::from("var x = 3, inc x"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(3, 3.0),
+            AstNode::GlobalValueReference(6, "x".to_owned()),
+            AstNode::GlobalIncr(5, 1, false),
+            AstNode::GlobalLet(0, "x".to_owned(), 0, 2),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("let x = var y = 3, dec y, _"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(6, 3.0),
+            AstNode::LocalValueReference(9, "y".to_owned()),
+            AstNode::LocalIncr(8, 1, true),
+            AstNode::LocalLet(3, "y".to_owned(), 0, 2),
+            AstNode::Void(11),
+            AstNode::GlobalLet(0, "x".to_owned(), 3, 4),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_inc_invalid_target() {
+        for code in ["inc 3", "inc x", "let x = 3, inc x", "var x = 3, inc x + 1"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Error, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_incomplete_everywhere() {
+        // input that ends too early can be continued on the next lines
+        for code in [
+            "let", "let x", "let x =", "let x:", "var x = 1, set", "var x = 1, set x", "var x = 1, set x =",
+            "var x = 1, inc", "|", "|a", "|a:", "|a|", "[1 2", "(1 +", "3 *", "{", "{ 1;", "do", "do 1,",
+            "if", "if true,", "if true, 1 else", "while", "while true,", "loop", "loop break_as",
+            "memo", "memo |n", "lazy", "spawn", "test", "test 'a'", "sqrt(", "sqrt", "foo.", "let f = |x| (x",
+            "let f = |a b| a\nf(1,", "!", "-",
+        ] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Incomplete, "{:?}", code);
+        }
+        // but not when more input can't fix it
+        for code in ["(if)", "(while)", "(loop)", "(let)", "(let x)", "(do)", "(|a)", "[1)", "(set)", "let x:3",
+                     "let x:int", "(1 + )", "1 2"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Error, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_unknown_unit() {
+        for (code, message) in [("10Km", "ERROR: unknown unit 'Km', did you mean 'km'?"),
+                                ("3milse", "ERROR: unknown unit 'milse', did you mean 'mile'?"),
+                                ("5kgg", "ERROR: unknown unit 'kgg', did you mean 'kg'?"),
+                                ("2parsec", "ERROR: unknown unit 'parsec'")] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert!(parser.failed(), "{}", code);
+            assert_eq!(parser.errors[0].message, message);
+        }
+    }
+
+    #[test]
+    fn test_parse_variadic_call() {
+        for (code, argc) in [("max 1 2", 2), ("max 1 2 3 4", 4), ("max(1, 2, 3)", 3), ("max 1, 2", 2),
+                             ("max 1 2 + 3", 2), ("max 1 (2) x", 3), ("max 1 2\nx", 2)] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.env.push_value_entry("x".to_owned(), true, false);
+            parser.parse();
+            assert!(!parser.failed(), "{:?}", code);
+            let calls: Vec<usize> = parser.ast.iter().filter_map(|node| match node {
+                AstNode::FunctionCall(_, name, args) if name == "max" => Some(args.len()),
+                _ => None,
+            }).collect();
+            assert_eq!(calls, vec![argc], "{:?}", code);
+        }
+        for (code, state) in [("max 1", ParserState::Incomplete), ("max(1, 2,", ParserState::Incomplete),
+                              ("max(1, 2, )", ParserState::Error), ("max 1 2 3\nsqrt 4 5", ParserState::Error)] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, state, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_set_invalid_target() {
+        for code in ["set 3 4", "set 3 = 4", "var x = 1, set x + 1 = 2", "var x = 1, set sqrt(x) = 2",
+                     "var x = 1, set foo.x = 2", "set 'x' = 1", "var x = 1, set |a| x = 2"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Error, "{}", code);
+            assert!(parser.errors.iter().any(|error| error.message.starts_with("ERROR: invalid set target")), "{}", code);
+        }
+        let mut parser = Parser::new(CONFIG, String::from("var x = 1, set (x) = 2"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
     #[test]
     fn test_parse_chained_let() {
         let mut parser = Parser::new(CONFIG, String::from("let x = 3, let y = 4, [x y]"));
@@ -2460,7 +3357,7 @@ mod tests {
     fn test_parse_func_0_missing_body() {
         let mut parser = Parser::new(CONFIG, String::from("| |"));
         parser.parse();
-        assert_eq!(parser.state, ParserState::Error);
+        assert_eq!(parser.state, ParserState::Incomplete);
     }
 
     #[test]
@@ -2470,7 +3367,7 @@ mod tests {
         assert_eq!(parser.ast, vec![
            AstNode::LocalValueReference(3, "a".to_owned()),
            AstNode::FunctionDef(0, vec![
-                FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0 }
+                FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false }
            ], 0)
         ]);
         assert_eq!(parser.state, ParserState::Done);
@@ -2493,7 +3390,7 @@ mod tests {
     fn test_parse_func_1_missing_body() {
         let mut parser = Parser::new(CONFIG, String::from("|a|"));
         parser.parse();
-        assert_eq!(parser.state, ParserState::Error);
+        assert_eq!(parser.state, ParserState::Incomplete);
     }
 
     #[test]
@@ -2512,8 +3409,8 @@ mod tests {
            AstNode::LocalValueReference(6, "b".to_owned()),
            AstNode::Array(7, vec![0, 1]),
            AstNode::FunctionDef(0, vec![
-                FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0 },
-                FunctionArg { name: "b".to_owned(), is_func: false, func_arity: 0 }
+                FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
+                FunctionArg { name: "b".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false }
            ], 2)
         ]);
         assert_eq!(parser.state, ParserState::Done);
@@ -2562,7 +3459,7 @@ mod tests {
            AstNode::Number(7, 3.0),
            AstNode::Number(8, 4.0),
            AstNode::FunctionCall(6, "a".to_owned(), vec![0, 1]), 
-           AstNode::FunctionDef(1, vec![FunctionArg { name: "a".to_owned(), is_func: true, func_arity: 2 }], 2)
+           AstNode::FunctionDef(1, vec![FunctionArg { name: "a".to_owned(), is_func: true, func_arity: 2, type_name: None, is_variadic: false }], 2)
         ]);
         assert_eq!(parser.state, ParserState::Done);
     }
@@ -2615,24 +3512,24 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_ife_incomplete() {
+    fn test_parse_ife_closed_early() {
         let mut parser = Parser::new(CONFIG, String::from("(if)"));
         parser.parse();
-        assert_eq!(parser.state, ParserState::Incomplete);
+        assert_eq!(parser.state, ParserState::Error);
     }
 
     #[test]
-    fn test_parse_ife_incomplete2() {
+    fn test_parse_ife_closed_early2() {
         let mut parser = Parser::new(CONFIG, String::from("(if true)"));
         parser.parse();
-        assert_eq!(parser.state, ParserState::Incomplete);
+        assert_eq!(parser.state, ParserState::Error);
     }
 
     #[test]
-    fn test_parse_ife_incomplete3() {
+    fn test_parse_ife_closed_early3() {
         let mut parser = Parser::new(CONFIG, String::from("(if true, 99 else)"));
         parser.parse();
-        assert_eq!(parser.state, ParserState::Incomplete);
+        assert_eq!(parser.state, ParserState::Error);
     }
 
 
@@ -2820,8 +3717,8 @@ mod tests {
         assert_eq!(parser.ast, vec![
             AstNode::Boolean(5, true),
             AstNode::FunctionDef(1, vec![
-                 FunctionArg { name: "k".to_string(), is_func: false, func_arity: 0 },
-                 FunctionArg { name: "v".to_string(), is_func: false, func_arity: 0 }
+                 FunctionArg { name: "k".to_string(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
+                 FunctionArg { name: "v".to_string(), is_func: false, func_arity: 0, type_name: None, is_variadic: false }
             ], 0),
             AstNode::Number(7, 12.0),
             AstNode::DynamicKeyAccess(0, 1, 2)
@@ -2970,6 +3867,368 @@ mod tests {
         assert_eq!(parser.state, ParserState::Done);
     }
 
+    #[test]
+    fn test_parse_return() {
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| return x"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(parser.ast[1], AstNode::Return(6, 0));
+
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| {\n    return\n}"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(parser.ast[1], AstNode::Return(7, 0));
+        assert_eq!(parser.ast[0], AstNode::Void(7));
+
+        for code in ["return 3", "let f = |x| x, return 3", "let return = 3"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Error, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_yield() {
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| yield x"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(parser.ast[1], AstNode::Yield(6, 0));
+
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| {\n    yield\n}"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(parser.ast[1], AstNode::Yield(7, 0));
+        assert_eq!(parser.ast[0], AstNode::Void(7));
+
+        for code in ["yield 3", "let g = lazy yield 3", "let yield = 3"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Error, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_memo() {
+        let mut parser = Parser::new(CONFIG, String::from("let sq = memo |x| x * x\nsq(3)"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(parser.ast[4], AstNode::Memo(3, 3));
+
+        for code in ["memo 3", "let f = memo", "let memo = 3"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert!(parser.failed(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_lazy() {
+        let mut parser = Parser::new(CONFIG, String::from("lazy 1 + 2"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(1, 1.0),
+            AstNode::Number(3, 2.0),
+            AstNode::BinaryOperator(2, BinaryOperator::Add, 0, 1),
+            AstNode::Lazy(0, 2),
+        ]);
+
+        for code in ["lazy", "(lazy)", "loop lazy break", "let f = |x| lazy return x"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert!(parser.failed(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_spawn() {
+        let mut parser = Parser::new(CONFIG, String::from("let t = spawn sqrt(4)\nawait(t)"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(parser.ast[2], AstNode::Spawn(3, 1));
+
+        for code in ["spawn", "let f = |x| spawn return x", "let spawn = 3"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert!(parser.failed(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_test() {
+        let mut parser = Parser::new(CONFIG, String::from("test 'sqrt' sqrt(4) == 2"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert!(matches!(parser.ast.last(), Some(AstNode::Test(0, name, _)) if name == "sqrt"));
+
+        for code in ["test", "test 'a'", "test sqrt(4)", "let f = |x| test 'a' x", "let test = 3"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert!(parser.failed(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_random_input() {
+        use rand::{Rng, SeedableRng};
+        let words = [
+            "let", "var", "const", "set", "x", "=", "|", "(", ")", "[", "]", "{", "}", ":", ".", ",", ";", "'a'",
+            "\"", "0b", "0x_", "0b1_", "1.5e", "3km", "if", "else", "while", "loop", "break", "break_as",
+            "continue", "return", "yield", "memo", "lazy", "spawn", "test", "do", "<-", "+", "-", "!", "*:",
+            "<", "==", "??", "#", "\n", "sqrt", "math.sin", "f:2", "a:num", "~str", "@", "void", "iter",
+        ];
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for _ in 0..2000 {
+            let code: Vec<&str> = (0..rng.gen_range(1..12)).map(|_| words[rng.gen_range(0..words.len())]).collect();
+            let code = code.join(if rng.gen_bool(0.5) { " " } else { "" });
+            let config = NopeConfig { typecheck: true, ..CONFIG };
+            let parsed = std::panic::catch_unwind(|| Parser::new(config, code.clone()).parse());
+            assert!(parsed.is_ok(), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn test_reparse() {
+        use rand::{Rng, SeedableRng};
+        let mut parser = Parser::new(CONFIG, String::from("let x = 3\nlet y = x + 1\nprint(y)"));
+        parser.parse();
+        parser.reparse(14..15, "foo");
+        assert_eq!(parser.tokenizer.source, "let x = 3\nlet foo = x + 1\nprint(y)");
+        assert!(parser.failed());
+        parser.reparse(32..33, "foo");
+        assert_eq!(parser.state, ParserState::Done);
+
+        // an edit gives the same result as parsing the edited source
+        let words = [
+            "let", "x", "=", "|", "(", ")", "[", "]", " ", "  ", "\n", "'a b'", "\"\n\"", "0x", "1.5e3",
+            "3km", "if", "else", "+", "-", "==", "#", "~str", "sqrt(", "x:", "foo", ";",
+        ];
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        for _ in 0..500 {
+            let code: String = (0..rng.gen_range(1..16)).map(|_| words[rng.gen_range(0..words.len())]).collect();
+            let new_text: String = (0..rng.gen_range(0..3)).map(|_| words[rng.gen_range(0..words.len())]).collect();
+            let start = rng.gen_range(0..=code.len());
+            let end = rng.gen_range(start..=code.len());
+            let mut parser = Parser::new(NopeConfig::new(), code.clone());
+            parser.parse();
+            parser.reparse(start..end, &new_text);
+            let mut fresh = Parser::new(NopeConfig::new(), format!("{}{}{}", &code[..start], new_text, &code[end..]));
+            fresh.parse();
+            assert_eq!(parser.tokenizer.tokens, fresh.tokenizer.tokens, "{:?} {:?} {:?}", code, start..end, new_text);
+            assert!(parser == fresh, "{:?} {:?} {:?}", code, start..end, new_text);
+        }
+    }
+
+    // cargo test --release bench_reparse -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_reparse() {
+        let code: String = (0..5000).map(|i| format!("print(sqrt({}) + 2km * 3) # number {}\n", i, i)).collect();
+        let mut parser = Parser::new(NopeConfig::new(), code.clone());
+        let now = std::time::Instant::now();
+        parser.parse();
+        println!("parse: {:?}", now.elapsed());
+        let now = std::time::Instant::now();
+        for _ in 0..10 {
+            parser.reparse(code.len() - 10..code.len() - 10, "1");
+            parser.reparse(code.len() - 10..code.len() - 9, "");
+        }
+        println!("reparse at the end: {:?}", now.elapsed() / 20);
+        let now = std::time::Instant::now();
+        for _ in 0..10 {
+            parser.reparse(10..10, "1");
+            parser.reparse(10..11, "");
+        }
+        println!("reparse at the start: {:?}", now.elapsed() / 20);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    // cargo test --release bench_parse_bindings -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_parse_bindings() {
+        let code: String = (0..2000).map(|i| format!("let x{} = |a| a + x{}(a)\n", i + 1, i)).collect();
+        let mut env = Stdlib::new().make_env();
+        env.push_arg_func_entry("x0".to_owned(), true, true, 1);
+        let mut parser = Parser::new_with_env(NopeConfig::new(), env, code);
+        let now = std::time::Instant::now();
+        parser.parse();
+        println!("parse: {:?}", now.elapsed());
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_missing_expression_is_error() {
+        let mut parser = Parser::new(CONFIG, String::from("x"));
+        parser.tokenizer.tokenize();
+        assert_eq!(parser.cur_ast_node_index(), 0);
+        assert!(parser.parsing_failed());
+        assert!(parser.errors[0].message.contains("internal parser error"));
+    }
+
+    #[test]
+    fn test_parse_type_annotations() {
+        let mut parser = Parser::new(CONFIG, String::from("let x:num = 3\nlet f = |a:str b:1| a"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(parser.let_types.get(&0), Some(&"num".to_owned()));
+        match &parser.ast[2] {
+            AstNode::FunctionDef(_, args, _) => {
+                assert_eq!(args[0].type_name, Some("str".to_owned()));
+                assert_eq!((args[1].is_func, args[1].type_name.clone()), (true, None));
+            },
+            node => panic!("unexpected node {:?}", node),
+        }
+
+        for code in ["let x:int = 3", "let x: = 3", "let f = |a:'num'| a"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert!(parser.failed(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_code_block() {
+        let mut parser = Parser::new(CONFIG, String::from("{ 1; 2 }"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(1, 1.0),
+            AstNode::Number(3, 2.0),
+            AstNode::Do(0, 0, 1),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("{}"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![AstNode::Void(0)]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_code_block_scope() {
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| {\n    let y = x * 2\n    y + 1\n}\nf(1)"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| { let y = x; y }, y"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Error);
+
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| {\n    x"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Incomplete);
+
+        let mut parser = Parser::new(CONFIG, String::from("{ 1\n 2 }"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Error);
+    }
+
+    #[test]
+    fn test_parse_if_body_scope() {
+        let mut parser = Parser::new(CONFIG, String::from("if true (\n    let y = 2\n    y\n)"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(None, parser.env.get_entry(&"y".to_owned()));
+
+        for code in [
+            "if true (\n    let y = 2\n    y\n)\ny",
+            "if true { let y = 2; y }, y",
+            "if true, 1 else { let z = 2; z }, z",
+            "if true, let y = 3, y else 0\ny",
+        ] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Error, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_loop_body_scope() {
+        let mut parser = Parser::new(CONFIG, String::from("var i = 0\nwhile i < 3 {\n    let y = i\n    inc i\n}\ni"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert_eq!(None, parser.env.get_entry(&"y".to_owned()));
+
+        for code in [
+            "var i = 0\nwhile i < 3 {\n    let y = i\n    inc i\n}\ny",
+            "loop { let y = 1; break }, y",
+            "var i = 0\nwhile i < 3 { let y = i; inc i }, set y = 2",
+        ] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Error, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parse_nested_body_scope() {
+        // the inner body sees the outer locals, but not the other way around
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| {\n    let a = x\n    if a > 0 {\n        let b = a * 2\n        b + a\n    } else a\n}"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| {\n    if x > 0 { let b = 2; b } else 0\n    b\n}"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Error);
+
+        // a body can shadow a global, which is visible again after it
+        let mut parser = Parser::new(CONFIG, String::from("let x = 1\nif true { let x = 'two'; x }\nx"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+        assert!(parser.env.get_entry(&"x".to_owned()).unwrap().is_global);
+    }
+
+    #[test]
+    fn test_parse_local_let_without_expression() {
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| (let y = x * 2)"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Error);
+        assert_eq!(parser.errors.len(), 2);
+        assert_eq!((parser.errors[0].line, parser.errors[0].col, parser.errors[0].severity), (1, 14, Severity::Info));
+        assert_eq!((parser.errors[1].line, parser.errors[1].col, parser.errors[1].severity), (1, 27, Severity::Critical));
+
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| let y = x * 2"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Incomplete);
+
+        let mut parser = Parser::new(CONFIG, String::from("let f = |x| let y = x * 2, y + 1"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_error_context() {
+        assert_eq!(error_context("1 + ", 1, 4), (None, "1 + ", "---".to_owned()));
+        assert_eq!(error_context("let x = 3,\n\tx +", 2, 5), (Some("let x = 3,"), "\tx +", "\t---".to_owned()));
+        assert_eq!(error_context("1 +\n", 2, 1), (Some("1 +"), "", "".to_owned()));
+        assert_eq!(error_context("ab", 1, 5), (None, "ab", "----".to_owned()));
+        assert_eq!(error_context("", 0, 0), (None, "", "".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_global_block_semicolons() {
+        let mut parser = Parser::new(CONFIG, String::from("print 2.5; print 4.92;\n1"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(1, 2.5),
+            AstNode::FunctionCall(0, "print".to_owned(), vec![0]),
+            AstNode::Number(4, 4.92),
+            AstNode::FunctionCall(3, "print".to_owned(), vec![2]),
+            AstNode::Number(6, 1.0),
+            AstNode::TopLevelBlock(0, vec![1, 3, 4]),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_global_block_semicolon_errors() {
+        for code in ["; 1", "1;; 2", "[1; 2]", "1; 2 3"] {
+            let mut parser = Parser::new(CONFIG, String::from(code));
+            parser.parse();
+            assert_eq!(parser.state, ParserState::Error, "{}", code);
+        }
+    }
+
     #[test]
     fn test_parse_global_block_env_carry_over() {
         let mut parser = Parser::new(CONFIG, String::from("let a = 3, _\nprint a"));
@@ -3060,6 +4319,59 @@ mod tests {
         assert_eq!(parser.state, ParserState::Done);
     }
 
+    #[test]
+    fn test_parse_bitwise_not() {
+        let mut parser = Parser::new(CONFIG, String::from("~!32"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+              AstNode::Number(1, 32.0),
+              AstNode::UnaryOperator(0, UnaryOperator::BitwiseNot, 0),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_bitwise_not_nested() {
+        let mut parser = Parser::new(CONFIG, String::from("~!~!7"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+              AstNode::Number(2, 7.0),
+              AstNode::UnaryOperator(1, UnaryOperator::BitwiseNot, 0),
+              AstNode::UnaryOperator(0, UnaryOperator::BitwiseNot, 1),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_bitwise_not_precedence() {
+        let mut parser = Parser::new(CONFIG, String::from("~!5 ~& 3"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+              AstNode::Number(1, 5.0),
+              AstNode::UnaryOperator(0, UnaryOperator::BitwiseNot, 0),
+              AstNode::Number(3, 3.0),
+              AstNode::BinaryOperator(2, BinaryOperator::BitwiseAnd, 1, 2),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("3 ~| ~!5"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+              AstNode::Number(0, 3.0),
+              AstNode::Number(3, 5.0),
+              AstNode::UnaryOperator(2, UnaryOperator::BitwiseNot, 1),
+              AstNode::BinaryOperator(1, BinaryOperator::BitwiseOr, 0, 2),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_unknown_unary_operator() {
+        let mut parser = Parser::new(CONFIG, String::from("~& 3"));
+        parser.parse();
+        assert_eq!(parser.state, ParserState::Error);
+    }
+
     #[test]
     fn test_parse_binary_add() {
         let mut parser = Parser::new(CONFIG, String::from("1+1"));
@@ -3133,6 +4445,110 @@ mod tests {
         assert_eq!(parser.state, ParserState::Done);
     }
 
+    #[test]
+    fn test_parse_floor_division_and_modulo() {
+        let mut parser = Parser::new(CONFIG, String::from("1 + 7 // 2 %% 3"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(0, 1.0),
+            AstNode::Number(2, 7.0),
+            AstNode::Number(4, 2.0),
+            AstNode::BinaryOperator(3, BinaryOperator::FloorDivide, 1, 2),
+            AstNode::Number(6, 3.0),
+            AstNode::BinaryOperator(5, BinaryOperator::FloorModulo, 3, 4),
+            AstNode::BinaryOperator(1, BinaryOperator::Add, 0, 5),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_chained_comparison() {
+        let mut parser = Parser::new(CONFIG, String::from("1 < 2 <= 3"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(0, 1.0),
+            AstNode::Number(2, 2.0),
+            AstNode::BinaryOperator(1, BinaryOperator::Less, 0, 1),
+            AstNode::Number(4, 3.0),
+            AstNode::ChainedComparison(3, BinaryOperator::LessOrEqual, 2, 3),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("1 == 2 > 3 > 4"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(0, 1.0),
+            AstNode::Number(2, 2.0),
+            AstNode::Number(4, 3.0),
+            AstNode::BinaryOperator(3, BinaryOperator::Greater, 1, 2),
+            AstNode::Number(6, 4.0),
+            AstNode::ChainedComparison(5, BinaryOperator::Greater, 3, 4),
+            AstNode::BinaryOperator(1, BinaryOperator::Equal, 0, 5),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_parenthesized_comparison_does_not_chain() {
+        let mut parser = Parser::new(CONFIG, String::from("(1 < 2) < 3"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(1, 1.0),
+            AstNode::Number(3, 2.0),
+            AstNode::BinaryOperator(2, BinaryOperator::Less, 0, 1),
+            AstNode::Number(6, 3.0),
+            AstNode::BinaryOperator(5, BinaryOperator::Less, 2, 3),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("1 < 2 == 3"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(0, 1.0),
+            AstNode::Number(2, 2.0),
+            AstNode::BinaryOperator(1, BinaryOperator::Less, 0, 1),
+            AstNode::Number(4, 3.0),
+            AstNode::BinaryOperator(3, BinaryOperator::Equal, 2, 3),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
+    #[test]
+    fn test_parse_logical_op_precedence() {
+        let mut parser = Parser::new(CONFIG, String::from("1 || 2 && 3"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(0, 1.0),
+            AstNode::Number(2, 2.0),
+            AstNode::Number(4, 3.0),
+            AstNode::BinaryOperator(3, BinaryOperator::And, 1, 2),
+            AstNode::BinaryOperator(1, BinaryOperator::Or, 0, 3),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("1 ?? 2 || 3"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(0, 1.0),
+            AstNode::Number(2, 2.0),
+            AstNode::BinaryOperator(1, BinaryOperator::NullishOr, 0, 1),
+            AstNode::Number(4, 3.0),
+            AstNode::BinaryOperator(3, BinaryOperator::Or, 2, 3),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+
+        let mut parser = Parser::new(CONFIG, String::from("1 == 2 && 3"));
+        parser.parse();
+        assert_eq!(parser.ast, vec![
+            AstNode::Number(0, 1.0),
+            AstNode::Number(2, 2.0),
+            AstNode::BinaryOperator(1, BinaryOperator::Equal, 0, 1),
+            AstNode::Number(4, 3.0),
+            AstNode::BinaryOperator(3, BinaryOperator::And, 2, 3),
+        ]);
+        assert_eq!(parser.state, ParserState::Done);
+    }
+
     #[test]
     fn test_parse_func_no_parenthesis_mixed_with_operators() {
         let mut parser = Parser::new(CONFIG, String::from("neg 3 + neg 5"));
@@ -3226,4 +4642,30 @@ mod tests {
         ]);
         assert_eq!(parser.state, ParserState::Done);
     }
+
+    #[test]
+    fn test_ast_to_json() {
+        let mut parser = Parser::new(CONFIG, String::from("1 + 'a\"'"));
+        parser.parse();
+        assert_eq!(parser.ast_to_json(), concat!(
+            "{\"kind\": \"BinaryOperator\", \"value\": \"Add\", \"line\": 1, \"col\": 3, \"children\": [",
+            "{\"kind\": \"Number\", \"value\": \"1\", \"line\": 1, \"col\": 1, \"children\": []}, ",
+            "{\"kind\": \"String\", \"value\": \"a\\\"\", \"line\": 1, \"col\": 5, \"children\": []}]}",
+        ));
+        let mut parser = Parser::new(CONFIG, String::from(""));
+        parser.parse();
+        assert_eq!(parser.ast_to_json(), "null");
+    }
+
+    #[test]
+    fn test_ast_to_dot() {
+        let mut parser = Parser::new(CONFIG, String::from("let x = 1, -x"));
+        parser.parse();
+        let dot = parser.ast_to_dot();
+        assert!(dot.starts_with("digraph ast {\n"), "{}", dot);
+        assert!(dot.contains("n3 [label=\"GlobalLet\\nx\"];\n"), "{}", dot);
+        assert!(dot.contains("n3 -> n0;\n    n3 -> n2;\n"), "{}", dot);
+        assert!(dot.contains("n2 [label=\"UnaryOperator\\nNegate\"];\n"), "{}", dot);
+        assert!(dot.ends_with('}'));
+    }
 }
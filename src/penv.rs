@@ -1,8 +1,13 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct FunctionArg {
     pub name: String,
     pub is_func: bool,
     pub func_arity: usize,
+    pub type_name: Option<String>,
+    pub is_variadic: bool, // the last argument of some stdlib functions, it can be repeated
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -11,33 +16,102 @@ pub struct EnvEntry {
     pub is_func: bool,
     pub is_global: bool,
     pub is_const: bool,
+    pub is_fixed: bool, // declared with const, it can't be declared again
     pub func_args: Vec<FunctionArg>,
 }
 
+// The entries are in two layers: the ones of the env it was copied from, shared by all
+// the copies, and the ones added since, so that copying an env for each parse is cheap.
+// The shared layer only keeps the visible entry of each name. The own layer is a stack,
+// with the positions of the entries of each name so that a lookup doesn't scan it.
 #[derive(PartialEq, Debug, Clone)]
 pub struct Env {
+    shared: Rc<HashMap<String, EnvEntry>>,
     entries: Vec<EnvEntry>,
+    positions: HashMap<String, Vec<usize>>, // indexes in entries, the last one is visible
 }
 
 impl Env {
     pub fn new() -> Env {
         return Env {
+            shared: Rc::new(HashMap::new()),
             entries:vec![],
+            positions: HashMap::new(),
+        };
+    }
+
+    // The same env with all its entries in the shared layer, its copies
+    // don't copy any entry until they are modified
+    pub fn snapshot(&self) -> Env {
+        if self.entries.is_empty() {
+            return self.clone();
+        }
+        let mut shared = (*self.shared).clone();
+        for positions in self.positions.values() {
+            if let Some(&idx) = positions.last() {
+                shared.insert(self.entries[idx].name.clone(), self.entries[idx].clone());
+            }
+        }
+        return Env {
+            shared: Rc::new(shared),
+            entries: vec![],
+            positions: HashMap::new(),
         };
     }
 
     pub fn print(&self) {
         println!("Env:");
-        for entry in self.entries.iter() {
+        let mut shared: Vec<&EnvEntry> = self.shared.values().collect();
+        shared.sort_by(|a, b| a.name.cmp(&b.name));
+        for entry in shared.into_iter().chain(self.entries.iter()) {
             println!("  {}{}", entry.name, if entry.is_func { format!("|{}|", entry.func_args.len()) } else { "".to_string() });
         }
     }
 
+    fn own_entry_mut(&mut self, name: &str, global: bool) -> Option<&mut EnvEntry> {
+        let positions = self.positions.get(name)?;
+        let idx = *positions.iter().rev().find(|&&idx| !global || self.entries[idx].is_global)?;
+        return Some(&mut self.entries[idx]);
+    }
+
+    // A global declared again replaces the previous entry, so that a function
+    // redefined in the REPL is parsed with its new arguments
+    fn push_entry(&mut self, entry: EnvEntry) {
+        if entry.is_global {
+            if let Some(previous) = self.own_entry_mut(&entry.name, true) {
+                *previous = entry;
+                return;
+            }
+        }
+        self.positions.entry(entry.name.clone()).or_default().push(self.entries.len());
+        self.entries.push(entry);
+    }
+
+    fn index_positions(&mut self) {
+        self.positions.clear();
+        for (idx, entry) in self.entries.iter().enumerate() {
+            self.positions.entry(entry.name.clone()).or_default().push(idx);
+        }
+    }
+
+    pub fn global_entries(&self) -> Vec<EnvEntry> {
+        let hidden = |entry: &EnvEntry| self.positions.get(&entry.name)
+            .is_some_and(|positions| positions.iter().any(|&idx| self.entries[idx].is_global));
+        return self.shared.values().filter(|entry| entry.is_global && !hidden(entry))
+            .chain(self.entries.iter().filter(|entry| entry.is_global))
+            .cloned().collect();
+    }
+
+    pub fn set_global_entry(&mut self, entry: EnvEntry) {
+        self.push_entry(EnvEntry { is_global: true, ..entry });
+    }
+
     pub fn push_value_entry(&mut self, name: String, is_global: bool, is_const: bool) {
-        self.entries.push(EnvEntry {
+        self.push_entry(EnvEntry {
             name,
             is_global,
             is_const,
+            is_fixed: false,
             is_func:false,
             func_args:vec![],
         });
@@ -51,19 +125,21 @@ impl Env {
         args: Vec<FunctionArg>,
     ) {
         if name == "_" {    // _ must keep having the void value
-            self.entries.push(EnvEntry {
+            self.push_entry(EnvEntry {
                 name,
                 is_global: true,
                 is_const: true,
+                is_fixed: false,
                 is_func: false,
                 func_args:vec![],
             });
         } else {
-            self.entries.push(
+            self.push_entry(
                 EnvEntry {
                     name,
                     is_global,
                     is_const,
+                    is_fixed: false,
                     is_func:true,
                     func_args:args,
                 });
@@ -77,44 +153,97 @@ impl Env {
                 name: format!("arg{}",i+1),
                 is_func: false,
                 func_arity: 0,
+                type_name: None,
+                is_variadic: false,
             });
         }
-        self.entries.push(EnvEntry {
+        self.push_entry(EnvEntry {
             name,
             is_global,
             is_const,
+            is_fixed: false,
             is_func:true,
             func_args,
         });
     }
 
-    pub fn pop_entry(&mut self) {
-        self.entries.pop();
+    pub fn fix_entry(&mut self, name: &str) {
+        if let Some(entry) = self.own_entry_mut(name, false) {
+            entry.is_fixed = true;
+        } else if let Some(entry) = self.shared.get(name) {
+            let entry = EnvEntry { is_fixed: true, ..entry.clone() };
+            self.push_entry(entry);
+        }
     }
 
-    pub fn get_entry(&self, name: &String) -> Option<EnvEntry> {
-        if self.entries.is_empty() {
-            return None;
+    // Returns false when there is no global with that name
+    pub fn remove_global_entry(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| !(entry.is_global && entry.name == name));
+        let mut found = self.entries.len() != len;
+        if found {
+            self.index_positions();
         }
-        let mut i = self.entries.len() - 1;
-        loop {
-            let entry = &self.entries[i];
-            if entry.name == *name {
-                let _entry: EnvEntry = entry.clone();
-                return Some(_entry);
-            }
-            if i > 0 {
-                i -= 1;
-            } else {
-                break;
+        if self.shared.get(name).is_some_and(|entry| entry.is_global) {
+            Rc::make_mut(&mut self.shared).remove(name);
+            found = true;
+        }
+        return found;
+    }
+
+    pub fn pop_entry(&mut self) {
+        if let Some(entry) = self.entries.pop() {
+            if let Some(positions) = self.positions.get_mut(&entry.name) {
+                positions.pop();
+                if positions.is_empty() {
+                    self.positions.remove(&entry.name);
+                }
             }
         }
-        return None;
+    }
+
+    pub fn get_entry(&self, name: &String) -> Option<EnvEntry> {
+        if let Some(&idx) = self.positions.get(name).and_then(|positions| positions.last()) {
+            return Some(self.entries[idx].clone());
+        }
+        return self.shared.get(name).cloned();
     }
 
     #[allow(dead_code)]
     pub fn size(&self) -> usize {
-        self.entries.len()
+        self.shared.len() + self.entries.len()
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_scopes() {
+        let mut env = Env::new();
+        env.push_value_entry("x".to_owned(), true, true);
+        env.push_func_entry("f".to_owned(), true, true, vec![]);
+        let mut env = env.snapshot();
+        let copy = env.clone();
+
+        env.push_value_entry("x".to_owned(), false, false); // a local hides the global
+        assert_eq!(env.get_entry(&"x".to_owned()).map(|entry| entry.is_global), Some(false));
+        env.pop_entry();
+        assert_eq!(env.get_entry(&"x".to_owned()).map(|entry| entry.is_global), Some(true));
+
+        env.push_func_entry("x".to_owned(), true, true, vec![]); // a global replaces the global
+        env.fix_entry("f");
+        assert!(env.get_entry(&"x".to_owned()).unwrap().is_func);
+        assert!(env.get_entry(&"f".to_owned()).unwrap().is_fixed);
+        assert_eq!(env.global_entries().len(), 2);
+        assert!(env.remove_global_entry("x"));
+        assert_eq!(env.get_entry(&"x".to_owned()), None);
+        assert!(!env.remove_global_entry("x"));
+
+        // the copies are not changed
+        assert!(!copy.get_entry(&"x".to_owned()).unwrap().is_func);
+        assert!(!copy.get_entry(&"f".to_owned()).unwrap().is_fixed);
+        assert_eq!(env.snapshot().get_entry(&"f".to_owned()), env.get_entry(&"f".to_owned()));
+    }
+}
@@ -3,6 +3,7 @@
 use rand::seq::SliceRandom;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::ops::Range;
 
 //use rustyline::error::ReadlineError;
 //use rustyline::{DefaultEditor};
@@ -12,15 +13,16 @@ use std::cell::RefCell;
 //
 //use rustyline::completion::{Completer, Pair};
 use rustyline::error::ReadlineError;
-use rustyline::{Editor, Result};
+use rustyline::{Editor, Result, Cmd, ConditionalEventHandler, Event, EventContext, EventHandler, KeyCode, KeyEvent, Modifiers, RepeatCount};
 use rustyline::validate::{Validator, ValidationResult, ValidationContext};
 use rustyline_derive::{Completer, Helper, Highlighter, Hinter };
 
 use crate::{
     penv::Env,
     parser::Parser,
+    tokenizer::{Tokenizer, TokenValue},
     stdlib::Stdlib,
-    vm::Vm,
+    vm::{Vm, InterpretResult},
     config::NopeConfig,
 };
 
@@ -62,9 +64,82 @@ fn print_banner() {
     println!();
 }
 
-#[derive(PartialEq, Debug, Clone)]
 struct SharedEnv {
     env: Env,
+    last_parse: Option<Parser>, // the previous validation of the line being edited
+}
+
+// The range of characters of `old` that were replaced to obtain `new`, and their replacement
+fn find_edit(old: &str, new: &str) -> (Range<usize>, String) {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old.iter().rev().zip(new.iter().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+    return (prefix..old.len() - suffix, new[prefix..new.len() - suffix].iter().collect());
+}
+
+// The code run by `:convert 30C F` or `:convert (2 + 3) km mile`: a call to convert
+// with the number and its unit, written together or not, and the unit to convert to
+fn convert_command(args: &str) -> Option<String> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (num, from, to) = match words.as_slice() {
+        [num_unit, to] => {
+            let split = (1..num_unit.len()).rev()
+                .filter(|idx| num_unit.is_char_boundary(*idx))
+                .find(|idx| num_unit[..*idx].parse::<f64>().is_ok())?;
+            (&num_unit[..split], &num_unit[split..], *to)
+        },
+        [.., from, to] => {
+            let num = args.trim_end().strip_suffix(to)?.trim_end().strip_suffix(from)?;
+            (num.trim(), *from, *to)
+        },
+        _ => return None,
+    };
+    if [from, to].iter().any(|unit| unit.contains(['\'', '\\'])) {
+        return None;
+    }
+    return Some(format!("convert ({}) '{}' '{}'", num, from, to));
+}
+
+// The indentation of the line following an input with unclosed brackets: the column of
+// what follows the innermost bracket, or two more spaces than its line when nothing does
+fn continuation_indent(input: &str) -> Option<usize> {
+    let mut tokenizer = Tokenizer::new(input.to_owned());
+    tokenizer.tokenize();
+    if tokenizer.failed() {
+        return None;
+    }
+    let mut open: Vec<usize> = vec![];
+    for (idx, token) in tokenizer.tokens.iter().enumerate() {
+        match token.value {
+            TokenValue::LeftP | TokenValue::NameLeftP | TokenValue::LeftSqBrkt | TokenValue::LeftBrkt => open.push(idx),
+            TokenValue::RightP | TokenValue::RightSqBrkt | TokenValue::RightBrkt => {
+                open.pop();
+            },
+            _ => {},
+        }
+    }
+    let bracket_idx = *open.last()?;
+    let next = &tokenizer.tokens[bracket_idx + 1];
+    if next.value != TokenValue::Eof {
+        return Some(next.col - 1);
+    }
+    let line = input.lines().nth(tokenizer.tokens[bracket_idx].line - 1).unwrap_or_default();
+    return Some(line.len() - line.trim_start().len() + 2);
+}
+
+// Enter after an unclosed bracket starts an indented line instead of running the input
+struct BracketIndenter;
+
+impl ConditionalEventHandler for BracketIndenter {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if ctx.pos() != ctx.line().len() {
+            return None;
+        }
+        let indent = continuation_indent(ctx.line())?;
+        return Some(Cmd::Insert(1, format!("\n{}", " ".repeat(indent))));
+    }
 }
 
 #[derive(Completer, Highlighter, Helper, Hinter)]
@@ -72,20 +147,35 @@ struct InputValidator {
     shared_env: Rc<RefCell<SharedEnv>>,
 }
 
+impl InputValidator {
+    // Only the env of the previous line is shared with the validator, copying it is cheap
+    // as it is always a snapshot
+    fn is_incomplete(&self, input: &str) -> bool {
+        let mut shared = self.shared_env.borrow_mut();
+        // the line is parsed again at each validation, only the edited part is tokenized again
+        let parser = match shared.last_parse.take() {
+            Some(mut parser) => {
+                let (range, new_text) = find_edit(&parser.tokenizer.source, input);
+                parser.reparse_no_panic(range, &new_text);
+                parser
+            },
+            None => {
+                let mut parser = Parser::new_with_env(NopeConfig::new(), shared.env.clone(), input.to_string());
+                parser.parse_no_panic();
+                parser
+            },
+        };
+
+        let incomplete = parser.incomplete();
+        shared.last_parse = Some(parser);
+        return incomplete;
+    }
+}
+
 impl Validator for InputValidator {
     fn validate(&self, ctx: &mut ValidationContext) -> Result<ValidationResult> {
         use ValidationResult::{Incomplete, Valid};
-        let input = ctx.input();
-        let config = NopeConfig{ debug:false, trace: false, echo_result:false };
-        let shared = (*self.shared_env).clone();
-        let mut parser = Parser::new_with_env(
-            config,
-            shared.into_inner().env.clone(),
-            input.to_string()
-        );
-        parser.parse();
-
-        let result = if parser.incomplete() {
+        let result = if self.is_incomplete(ctx.input()) {
             Incomplete
         } else {
             Valid(None)
@@ -97,12 +187,28 @@ impl Validator for InputValidator {
 
 
 pub fn repl(vm: &mut Vm) {
-    let mut rl = Editor::new().expect("could not activate line editor");
-    let stdlib = Stdlib::new();
-    let env = stdlib.make_env();
-    let shared_env = Rc::new(RefCell::new(SharedEnv {env}));
+    let rl_config = rustyline::Config::builder()
+        .max_history_size(vm.config().history_size)
+        .expect("invalid history size")
+        .build();
+    let mut rl = Editor::with_config(rl_config).expect("could not activate line editor");
+    let env = match vm.get_copy_of_last_env() {
+        Some(env) => env,
+        None => Stdlib::new().make_env(),
+    };
+    let shared_env = Rc::new(RefCell::new(SharedEnv {env: env.snapshot(), last_parse: None}));
     let h = InputValidator {shared_env: Rc::clone(&shared_env)};
     rl.set_helper(Some(h));
+    rl.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::NONE), EventHandler::Conditional(Box::new(BracketIndenter)));
+
+    if vm.config().repl_step_limit > 0 {
+        vm.set_step_limit(Some(vm.config().repl_step_limit));
+    }
+
+    // panics are reported as runtime errors by the vm
+    if !vm.config().debug {
+        std::panic::set_hook(Box::new(|_| {}));
+    }
 
     print_banner();
     loop {
@@ -110,9 +216,30 @@ pub fn repl(vm: &mut Vm) {
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str()).ok();
-                vm.interpret(line);
+                shared_env.borrow_mut().last_parse = None;
+                if let Some(name) = line.trim().strip_prefix(":help") {
+                    let name = if name.trim().is_empty() { "help" } else { name.trim() };
+                    match vm.stdlib().describe(name) {
+                        Some(usage) => println!("{}", usage),
+                        None => println!("  {}", format!("'{}' is not a stdlib function", name).red()),
+                    }
+                    continue;
+                }
+                let line = match line.trim().strip_prefix(":convert") {
+                    Some(args) => match convert_command(args) {
+                        Some(code) => code,
+                        None => {
+                            println!("  {}", "usage: :convert 30C F".red());
+                            continue;
+                        },
+                    },
+                    None => line,
+                };
+                if let InterpretResult::Ok = vm.interpret_catching_panics(line) {
+                    vm.keep_result();
+                }
                 if let Some(env) = vm.get_copy_of_last_env() {
-                    shared_env.replace(SharedEnv {env: env.clone()});
+                    shared_env.replace(SharedEnv {env: env.snapshot(), last_parse: None});
                 }
             },
             Err(ReadlineError::Interrupted) => {
@@ -130,3 +257,84 @@ pub fn repl(vm: &mut Vm) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_edit() {
+        assert_eq!(find_edit("let x = 3", "let x = 34"), (9..9, "4".to_owned()));
+        assert_eq!(find_edit("let x = 3", "let xy = 3"), (5..5, "y".to_owned()));
+        assert_eq!(find_edit("aaa", "aa"), (2..3, "".to_owned()));
+        assert_eq!(find_edit("é(1)", "é(2)"), (2..3, "2".to_owned()));
+        assert_eq!(find_edit("", "sqrt(2)"), (0..0, "sqrt(2)".to_owned()));
+    }
+
+    #[test]
+    fn test_convert_command() {
+        assert_eq!(convert_command(" 30C F"), Some("convert (30) 'C' 'F'".to_owned()));
+        assert_eq!(convert_command("2.5e3m km"), Some("convert (2.5e3) 'm' 'km'".to_owned()));
+        assert_eq!(convert_command("20% percent"), Some("convert (20) '%' 'percent'".to_owned()));
+        assert_eq!(convert_command("3 ft m"), Some("convert (3) 'ft' 'm'".to_owned()));
+        assert_eq!(convert_command("(2 + 3) km mile"), Some("convert ((2 + 3)) 'km' 'mile'".to_owned()));
+        assert_eq!(convert_command("x km mile"), Some("convert (x) 'km' 'mile'".to_owned()));
+        assert_eq!(convert_command("km mile"), None);
+        assert_eq!(convert_command("30C"), None);
+        assert_eq!(convert_command(""), None);
+    }
+
+    #[test]
+    fn test_continuation_indent() {
+        assert_eq!(continuation_indent("(1 +"), Some(1));
+        assert_eq!(continuation_indent("let f = |x| ("), Some(2));
+        assert_eq!(continuation_indent("  foo("), Some(4));
+        assert_eq!(continuation_indent("let x = [1 2"), Some(9));
+        assert_eq!(continuation_indent("(\n   let x = 1"), Some(3));
+        assert_eq!(continuation_indent("{ (1)"), Some(2));
+        assert_eq!(continuation_indent("(1)"), None);
+        assert_eq!(continuation_indent("'('"), None);
+        assert_eq!(continuation_indent("let x ="), None);
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        let validator = InputValidator {
+            shared_env: Rc::new(RefCell::new(SharedEnv {env: Stdlib::new().make_env().snapshot(), last_parse: None})),
+        };
+        assert!(validator.is_incomplete("let f = |x| (x"));
+        assert!(validator.is_incomplete("let f = |x| (x\n  + 1"));
+        assert!(!validator.is_incomplete("let f = |x| (x\n  + 1)"));
+        assert!(!validator.is_incomplete("3 )"));
+    }
+
+    // The validation of each keystroke of a line in a session that defined many globals
+    // cargo test --release bench_validate -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_validate() {
+        let mut env = Stdlib::new().make_env();
+        for i in 0..5000 {
+            env.push_value_entry(format!("x{}", i), true, true);
+        }
+        let env = env.snapshot();
+        let line = "let total = sqrt(x12 * x4999) + (x1 * 2km) / to_num('12') + x300";
+        let keystrokes: Vec<&str> = (1..=line.len()).map(|len| &line[..len]).collect();
+
+        let now = std::time::Instant::now();
+        for input in keystrokes.iter() {
+            // what the validator did before: a copy of all the entries and a full parse
+            std::hint::black_box(env.global_entries());
+            let mut parser = Parser::new_with_env(NopeConfig::new(), env.clone(), input.to_string());
+            parser.parse_no_panic();
+        }
+        println!("new parser: {:?}", now.elapsed() / keystrokes.len() as u32);
+
+        let validator = InputValidator {shared_env: Rc::new(RefCell::new(SharedEnv {env, last_parse: None}))};
+        let now = std::time::Instant::now();
+        for input in keystrokes.iter() {
+            validator.is_incomplete(input);
+        }
+        println!("validator: {:?}", now.elapsed() / keystrokes.len() as u32);
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+use crate::config::NopeConfig;
+
+// Resolves a path to its canonical form so that it can be compared
+// against the allowed directories. Files that don't exist yet (for writes)
+// are resolved through their parent directory.
+fn resolve_path(path: &Path) -> Option<PathBuf> {
+    if let Ok(resolved) = path.canonicalize() {
+        return Some(resolved);
+    }
+    let name = path.file_name()?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    return parent.canonicalize().ok().map(|parent| parent.join(name));
+}
+
+pub fn check_fs_access(config: &NopeConfig, path: &str) -> Result<(), String> {
+    if config.no_fs {
+        return Err(format!("filesystem access is disabled, cannot access '{}'", path));
+    }
+    if config.allowed_paths.is_empty() {
+        return Ok(());
+    }
+    if let Some(resolved) = resolve_path(Path::new(path)) {
+        for allowed in &config.allowed_paths {
+            if let Ok(allowed) = Path::new(allowed).canonicalize() {
+                if resolved.starts_with(allowed) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    return Err(format!("access to '{}' is outside of the allowed paths", path));
+}
+
+pub fn check_net_access(config: &NopeConfig, address: &str) -> Result<(), String> {
+    if config.no_net {
+        return Err(format!("network access is disabled, cannot access '{}'", address));
+    }
+    return Ok(());
+}
+
+// A command can access any file, so it is denied too when the filesystem access
+// is disabled or restricted
+pub fn check_exec_access(config: &NopeConfig, command: &str) -> Result<(), String> {
+    if config.no_exec || config.no_fs || !config.allowed_paths.is_empty() {
+        return Err(format!("running commands is disabled, cannot run '{}'", command));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(paths: &[&str]) -> NopeConfig {
+        return NopeConfig {
+            allowed_paths: paths.iter().map(|p| p.to_string()).collect(),
+            ..NopeConfig::new()
+        };
+    }
+
+    #[test]
+    fn test_default_allows_everything() {
+        let config = NopeConfig::new();
+        assert!(check_fs_access(&config, "/etc/hostname").is_ok());
+        assert!(check_fs_access(&config, "does/not/exist.txt").is_ok());
+        assert!(check_net_access(&config, "example.com:80").is_ok());
+        assert!(check_exec_access(&config, "ls").is_ok());
+    }
+
+    #[test]
+    fn test_no_fs() {
+        let config = NopeConfig { no_fs: true, ..NopeConfig::new() };
+        assert!(check_fs_access(&config, "Cargo.toml").is_err());
+    }
+
+    #[test]
+    fn test_no_exec() {
+        assert!(check_exec_access(&NopeConfig { no_exec: true, ..NopeConfig::new() }, "ls").is_err());
+        assert!(check_exec_access(&NopeConfig { no_fs: true, ..NopeConfig::new() }, "ls").is_err());
+        assert!(check_exec_access(&allowed(&["src"]), "ls").is_err());
+    }
+
+    #[test]
+    fn test_no_net() {
+        let config = NopeConfig { no_net: true, ..NopeConfig::new() };
+        assert!(check_net_access(&config, "example.com:80").is_err());
+    }
+
+    #[test]
+    fn test_allowed_paths() {
+        let config = allowed(&["src"]);
+        assert!(check_fs_access(&config, "src/main.rs").is_ok());
+        assert!(check_fs_access(&config, "src/new_file.txt").is_ok());
+        assert!(check_fs_access(&config, "Cargo.toml").is_err());
+        assert!(check_fs_access(&config, "src/../Cargo.toml").is_err());
+        assert!(check_fs_access(&config, "src/missing_dir/file.txt").is_err());
+    }
+
+    #[test]
+    fn test_allowed_paths_multiple() {
+        let config = allowed(&["src", "samples"]);
+        assert!(check_fs_access(&config, "samples/hello.nope").is_ok());
+        assert!(check_fs_access(&config, "src/vm.rs").is_ok());
+        assert!(check_fs_access(&config, "README.md").is_err());
+    }
+}
@@ -4,18 +4,42 @@ use crate::penv::{
     FunctionArg,
     Env,
 };
-use crate::chunk::Instruction;
+use crate::chunk::{Instruction, Value};
+use crate::vm::Vm;
+use crate::natives;
 
-#[derive(PartialEq, Debug, Clone)]
+// Native functions receive their arguments in declaration order
+pub type NativeFunction = fn(&mut Vm, &[Value]) -> Result<Value, String>;
+
+#[derive(Debug, Clone)]
 pub struct StdlibFunction {
     pub name: String,
     pub args: Vec<FunctionArg>,
     pub instructions: Vec<Instruction>,
+    pub native: Option<NativeFunction>,
+    pub doc: String, // the one line description shown by help
+}
+
+// How a call to a stdlib function gets compiled. Small functions have
+// their instructions inlined at the call site, bigger ones are
+// implemented in rust and called with CallNative. The instructions of
+// variadic functions combine two values, they are repeated to fold all
+// the arguments
+pub enum StdlibCall<'a> {
+    Inline(&'a Vec<Instruction>),
+    Fold(&'a Vec<Instruction>),
+    Native(usize),
+}
+
+impl StdlibFunction {
+    pub fn is_variadic(&self) -> bool {
+        return self.args.last().is_some_and(|arg| arg.is_variadic);
+    }
 }
 
 pub struct Stdlib {
     functions: Vec<StdlibFunction>,
-    functions_map: HashMap<String, StdlibFunction>,
+    functions_map: HashMap<String, usize>,
 }
 
 impl Stdlib {
@@ -25,28 +49,36 @@ impl Stdlib {
             functions_map: HashMap::new(),
         };
 
-        let mut def_zero_arg = |name: &str, instructions: Vec<Instruction>| {
+        let mut def_zero_arg = |name: &str, doc: &str, instructions: Vec<Instruction>| {
             stdlib.functions.push(StdlibFunction {
                 instructions,
                 name: name.to_owned(),
                 args: vec![],
+                native: None,
+                doc: doc.to_owned(),
             });
         };
 
-        def_zero_arg("random", vec![Instruction::Random]);
-        def_zero_arg("rand100", vec![
+        def_zero_arg("random", "a random number between 0 and 1", vec![Instruction::Random]);
+        def_zero_arg("gc_stats", "prints the memory used by the garbage collector", vec![Instruction::GcStats]);
+        def_zero_arg("trace_on", "starts printing each executed instruction", vec![Instruction::TraceOn]);
+        def_zero_arg("trace_off", "stops printing the executed instructions", vec![Instruction::TraceOff]);
+        def_zero_arg("buf_new", "creates a new empty string buffer", vec![Instruction::BufNew]);
+        def_zero_arg("chan_new", "creates a new empty channel", vec![Instruction::ChanNew]);
+        def_zero_arg("clip_get", "returns the text of the clipboard", vec![Instruction::ClipGet]);
+        def_zero_arg("rand100", "a random integer between 0 and 99", vec![
             Instruction::Random,
             Instruction::PushNum(100.0),
             Instruction::Multiply,
             Instruction::Floor,
         ]);
-        def_zero_arg("flip_coin", vec![
+        def_zero_arg("flip_coin", "true or false, with the same probability", vec![
             Instruction::Random,
             Instruction::PushNum(0.5),
             Instruction::GreaterOrEqual,
         ]);
         for num in [4, 6, 8, 10, 12, 20, 100] {
-            def_zero_arg(&format!("d{}", num), vec![
+            def_zero_arg(&format!("d{}", num), &format!("rolls a {} sided die", num), vec![
                 Instruction::Random,
                 Instruction::PushNum(f64::from(num)),
                 Instruction::Multiply,
@@ -55,76 +87,88 @@ impl Stdlib {
         }
 
         let one_arg_func = vec![
-            FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0 },
+            FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
         ];
 
-        let mut def_one_arg = |name: &str, instructions: Vec<Instruction>| {
+        let mut def_one_arg = |name: &str, doc: &str, instructions: Vec<Instruction>| {
             stdlib.functions.push(StdlibFunction {
                 instructions,
                 name: name.to_owned(),
                 args: one_arg_func.clone(),
+                native: None,
+                doc: doc.to_owned(),
             });
         };
 
-        def_one_arg("to_num",    vec![Instruction::ParseNum]);
-        def_one_arg("print",  vec![Instruction::Print]);
-        def_one_arg("echo",   vec![Instruction::Echo]);
-        def_one_arg("len",    vec![Instruction::Len]);
-        def_one_arg("neg",    vec![Instruction::Negate]);
-        def_one_arg("return", vec![Instruction::Return,]);
-        def_one_arg("not",    vec![Instruction::Not]);
-        def_one_arg("to_bool",   vec![Instruction::Bool]);
-        def_one_arg("floor",  vec![Instruction::Floor]);
-        def_one_arg("ceil",   vec![Instruction::Ceil]);
-        def_one_arg("abs",    vec![Instruction::Abs]);
-        def_one_arg("acos",   vec![Instruction::Acos]);
-        def_one_arg("acosh",  vec![Instruction::Acosh]);
-        def_one_arg("decr",   vec![Instruction::Decr]);
-        def_one_arg("incr",   vec![Instruction::Incr]);
-        def_one_arg("sin",    vec![Instruction::Sin]);
-        def_one_arg("sinh",   vec![Instruction::Sinh]);
-        def_one_arg("asin",   vec![Instruction::Asin]);
-        def_one_arg("asinh",  vec![Instruction::Asinh]);
-        def_one_arg("cos",    vec![Instruction::Cos]);
-        def_one_arg("cosh",   vec![Instruction::Cosh]);
-        def_one_arg("tan",    vec![Instruction::Tan]);
-        def_one_arg("tanh",   vec![Instruction::Tanh]);
-        def_one_arg("atan",   vec![Instruction::Atan]);
-        def_one_arg("atanh",  vec![Instruction::Atanh]);
-        def_one_arg("inv",    vec![Instruction::Inv]);
-        def_one_arg("log2",   vec![Instruction::Log2]);
-        def_one_arg("log10",  vec![Instruction::Log10]);
-        def_one_arg("ln1p",   vec![Instruction::Ln1p]);
-        def_one_arg("ln",     vec![Instruction::Ln]);
-        def_one_arg("exp",    vec![Instruction::Exp]);
-        def_one_arg("expm1",  vec![Instruction::Expm1]);
-        def_one_arg("sqrt",   vec![Instruction::Sqrt]);
-        def_one_arg("cbrt",   vec![Instruction::Cbrt]);
-        def_one_arg("round",  vec![Instruction::Round]);
-        def_one_arg("fround", vec![Instruction::Fround]);
-        def_one_arg("trunc",  vec![Instruction::Trunc]);
-        def_one_arg("sign",   vec![Instruction::Sign]);
-        def_one_arg("to_str",    vec![Instruction::Str]);
-        def_one_arg("upper",  vec![Instruction::Upper]);
-        def_one_arg("lower",  vec![Instruction::Lower]);
-        def_one_arg("trim",   vec![Instruction::Trim]);
-        def_one_arg("shh",    vec![Instruction::Silence]);
-        def_one_arg("bitstr", vec![Instruction::Bitstr]);
-        def_one_arg("is_void",   vec![Instruction::IsVoid]);
-        def_one_arg("is_null",   vec![Instruction::IsNull]);
-        def_one_arg("is_bool",   vec![Instruction::IsBool]);
-        def_one_arg("is_num",    vec![Instruction::IsNum]);
-        def_one_arg("is_str",    vec![Instruction::IsStr]);
-        def_one_arg("is_nan",    vec![Instruction::IsNaN]);
-        def_one_arg("is_int",    vec![Instruction::IsInt]);
-        def_one_arg("read_text", vec![Instruction::ReadTextFileSync]);
-        def_one_arg("is_even", vec![
+        def_one_arg("to_num", "converts a value to a number", vec![Instruction::ParseNum]);
+        def_one_arg("print", "prints a value and returns it", vec![Instruction::Print]);
+        def_one_arg("echo", "prints the representation of a value and returns it", vec![Instruction::Echo]);
+        def_one_arg("len", "the number of characters of a string", vec![Instruction::Len]);
+        def_one_arg("neg", "the opposite of a number", vec![Instruction::Negate]);
+        def_one_arg("not", "the boolean negation of a value", vec![Instruction::Not]);
+        def_one_arg("to_bool", "converts a value to a boolean", vec![Instruction::Bool]);
+        def_one_arg("floor", "the largest integer less than or equal to a number", vec![Instruction::Floor]);
+        def_one_arg("ceil", "the smallest integer greater than or equal to a number", vec![Instruction::Ceil]);
+        def_one_arg("abs", "the absolute value of a number", vec![Instruction::Abs]);
+        def_one_arg("acos", "the arccosine of a number, in radians", vec![Instruction::Acos]);
+        def_one_arg("acosh", "the inverse hyperbolic cosine of a number", vec![Instruction::Acosh]);
+        def_one_arg("decr", "a number minus one", vec![Instruction::Decr]);
+        def_one_arg("incr", "a number plus one", vec![Instruction::Incr]);
+        def_one_arg("sin", "the sine of an angle in radians", vec![Instruction::Sin]);
+        def_one_arg("sinh", "the hyperbolic sine of a number", vec![Instruction::Sinh]);
+        def_one_arg("asin", "the arcsine of a number, in radians", vec![Instruction::Asin]);
+        def_one_arg("asinh", "the inverse hyperbolic sine of a number", vec![Instruction::Asinh]);
+        def_one_arg("cos", "the cosine of an angle in radians", vec![Instruction::Cos]);
+        def_one_arg("cosh", "the hyperbolic cosine of a number", vec![Instruction::Cosh]);
+        def_one_arg("tan", "the tangent of an angle in radians", vec![Instruction::Tan]);
+        def_one_arg("tanh", "the hyperbolic tangent of a number", vec![Instruction::Tanh]);
+        def_one_arg("atan", "the arctangent of a number, in radians", vec![Instruction::Atan]);
+        def_one_arg("atanh", "the inverse hyperbolic tangent of a number", vec![Instruction::Atanh]);
+        def_one_arg("inv", "one divided by a number", vec![Instruction::Inv]);
+        def_one_arg("log2", "the base 2 logarithm of a number", vec![Instruction::Log2]);
+        def_one_arg("log10", "the base 10 logarithm of a number", vec![Instruction::Log10]);
+        def_one_arg("ln1p", "the natural logarithm of one plus a number, precise for small numbers", vec![Instruction::Ln1p]);
+        def_one_arg("ln", "the natural logarithm of a number", vec![Instruction::Ln]);
+        def_one_arg("exp", "e raised to the power of a number", vec![Instruction::Exp]);
+        def_one_arg("expm1", "e raised to the power of a number, minus one, precise for small numbers", vec![Instruction::Expm1]);
+        def_one_arg("sqrt", "the square root of a number", vec![Instruction::Sqrt]);
+        def_one_arg("cbrt", "the cube root of a number", vec![Instruction::Cbrt]);
+        def_one_arg("round", "a number rounded to the nearest integer", vec![Instruction::Round]);
+        def_one_arg("fround", "a number rounded to 32bit float precision", vec![Instruction::Fround]);
+        def_one_arg("trunc", "the integer part of a number", vec![Instruction::Trunc]);
+        def_one_arg("sign", "1 for positive numbers, -1 for negative numbers", vec![Instruction::Sign]);
+        def_one_arg("to_str", "converts a value to a string", vec![Instruction::Str]);
+        def_one_arg("upper", "a string in upper case", vec![Instruction::Upper]);
+        def_one_arg("lower", "a string in lower case", vec![Instruction::Lower]);
+        def_one_arg("trim", "a string without the whitespace at its beginning and end", vec![Instruction::Trim]);
+        def_one_arg("shh", "discards a value and returns void", vec![Instruction::Silence]);
+        def_one_arg("bitstr", "the 32 bits of a number as a string of 0 and 1", vec![Instruction::Bitstr]);
+        def_one_arg("is_void", "true if the value is void", vec![Instruction::IsVoid]);
+        def_one_arg("is_null", "true if the value is null", vec![Instruction::IsNull]);
+        def_one_arg("is_bool", "true if the value is a boolean", vec![Instruction::IsBool]);
+        def_one_arg("is_num", "true if the value is a number", vec![Instruction::IsNum]);
+        def_one_arg("is_str", "true if the value is a string", vec![Instruction::IsStr]);
+        def_one_arg("is_fn", "true if the value is a function", vec![Instruction::IsFn]);
+        def_one_arg("type_of", "the name of the type of a value", vec![Instruction::TypeOf]);
+        def_one_arg("is_nan", "true if the value is NaN", vec![Instruction::IsNaN]);
+        def_one_arg("is_int", "true if the value is an integer number", vec![Instruction::IsInt]);
+        def_one_arg("read_text", "the content of a text file", vec![Instruction::ReadTextFileSync]);
+        def_one_arg("buf_str", "the content of a string buffer as a string", vec![Instruction::BufStr]);
+        def_one_arg("force", "computes the value of a lazy value, once", vec![Instruction::Force]);
+        def_one_arg("next", "resumes a generator and returns its next value", vec![Instruction::Resume]);
+        def_one_arg("await", "waits for a task and returns its result", vec![Instruction::Await]);
+        def_one_arg("sleep", "pauses for a number of milliseconds", vec![Instruction::Deadline, Instruction::Sleep]);
+        def_one_arg("recv", "waits for the next value of a channel and returns it", vec![Instruction::Recv]);
+        def_one_arg("sock_read_line", "reads the next line of a socket", vec![Instruction::SockReadLine]);
+        def_one_arg("sock_close", "closes a socket", vec![Instruction::SockClose]);
+        def_one_arg("clip_set", "replaces the text of the clipboard and returns it", vec![Instruction::ClipSet]);
+        def_one_arg("is_even", "true if the number is even", vec![
             Instruction::PushNum(2.0),
             Instruction::Modulo,
             Instruction::PushNum(0.0),
             Instruction::Equal,
         ]);
-        def_one_arg("is_odd", vec![
+        def_one_arg("is_odd", "true if the number is odd", vec![
             Instruction::PushNum(2.0),
             Instruction::Modulo,
             Instruction::PushNum(0.0),
@@ -134,81 +178,316 @@ impl Stdlib {
 
 
         let two_args_func = vec![
-            FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0 },
-            FunctionArg { name: "b".to_owned(), is_func: false, func_arity: 0 },
+            FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
+            FunctionArg { name: "b".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
         ];
 
-        let mut def_two_args = |name: &str, instructions: Vec<Instruction>| {
+        let mut def_two_args = |name: &str, doc: &str, instructions: Vec<Instruction>| {
             stdlib.functions.push(StdlibFunction {
                 instructions,
                 name: name.to_owned(),
                 args: two_args_func.clone(),
+                native: None,
+                doc: doc.to_owned(),
             });
         };
 
-        def_two_args("add",  vec![Instruction::Add]);
-        def_two_args("sub",  vec![Instruction::Subtract]);
-        def_two_args("le",    vec![Instruction::Less]);
-        def_two_args("leq",   vec![Instruction::LessOrEqual]);
-        def_two_args("ge",    vec![Instruction::Greater]);
-        def_two_args("geq",   vec![Instruction::GreaterOrEqual]);
-        def_two_args("eq",   vec![Instruction::Equal]);
-        def_two_args("aeq",   vec![Instruction::AlmostEqual]);
-        def_two_args("neq",   vec![Instruction::Equal, Instruction::Not]);
-        def_two_args("naeq",  vec![Instruction::AlmostEqual, Instruction::Not]);
-        def_two_args("max",  vec![Instruction::Max]);
-        def_two_args("min",  vec![Instruction::Min]);
-        def_two_args("mult", vec![Instruction::Multiply]);
-        def_two_args("div",  vec![Instruction::Divide]);
-        def_two_args("pow", vec![Instruction::Power]);
-        def_two_args("atan2",  vec![Instruction::Atan2]);
-        def_two_args("modulo",     vec![Instruction::Modulo]);
-        def_two_args("join_paths", vec![Instruction::JoinPaths]);
-        def_two_args("write_text", vec![Instruction::WriteTextFileSync]);
-        def_two_args("from_unit", vec![Instruction::FromUnit]);
-        def_two_args("to_unit", vec![Instruction::ToUnit]);
-        def_two_args("char_at", vec![Instruction::CharAt]);
-        def_two_args("find", vec![Instruction::Find]);
-        def_two_args("contains", vec![
+        def_two_args("add", "the sum of two values", vec![Instruction::Add]);
+        def_two_args("sub", "a minus b", vec![Instruction::Subtract]);
+        def_two_args("le", "true if a is less than b", vec![Instruction::Less]);
+        def_two_args("leq", "true if a is less than or equal to b", vec![Instruction::LessOrEqual]);
+        def_two_args("ge", "true if a is greater than b", vec![Instruction::Greater]);
+        def_two_args("geq", "true if a is greater than or equal to b", vec![Instruction::GreaterOrEqual]);
+        def_two_args("eq", "true if a and b are equal", vec![Instruction::Equal]);
+        def_two_args("is_same", "true if a and b are the same object", vec![Instruction::IsSame]);
+        def_two_args("aeq", "true if a and b are almost equal", vec![Instruction::AlmostEqual]);
+        def_two_args("neq", "true if a and b are not equal", vec![Instruction::Equal, Instruction::Not]);
+        def_two_args("naeq", "true if a and b are not almost equal", vec![Instruction::AlmostEqual, Instruction::Not]);
+        def_two_args("mult", "a multiplied by b", vec![Instruction::Multiply]);
+        def_two_args("div", "a divided by b", vec![Instruction::Divide]);
+        def_two_args("pow", "a raised to the power of b", vec![Instruction::Power]);
+        def_two_args("atan2", "the angle in radians of the point (b, a)", vec![Instruction::Atan2]);
+        def_two_args("modulo", "the remainder of the division of a by b", vec![Instruction::Modulo]);
+        def_two_args("idiv", "the integer division of a by b, rounded down", vec![Instruction::Divide, Instruction::Floor]);
+        def_two_args("fmod", "the remainder of the division of a by b, with the sign of b", vec![Instruction::FloorModulo]);
+        def_two_args("join_paths", "joins two file paths", vec![Instruction::JoinPaths]);
+        def_two_args("write_text", "replaces the content of the file a by the text b", vec![Instruction::WriteTextFileSync]);
+        def_two_args("from_unit", "converts the number b in the unit a to SI units", vec![Instruction::FromUnit]);
+        def_two_args("to_unit", "converts the number b in SI units to the unit a", vec![Instruction::ToUnit]);
+        def_two_args("char_at", "the character at index a of the string b", vec![Instruction::CharAt]);
+        def_two_args("find", "the position of the string a in the string b, or -1", vec![Instruction::Find]);
+        def_two_args("buf_push", "appends the string value of a to the buffer b and returns the buffer", vec![Instruction::BufPush]);
+        def_two_args("send", "sends the value b on the channel a and returns the channel", vec![Instruction::Send]);
+        def_two_args("connect", "opens a socket to the host a on the port b", vec![Instruction::Connect]);
+        def_two_args("sock_write", "writes the text b to the socket a", vec![Instruction::SockWrite]);
+        def_two_args("contains", "true if the string a is in the string b", vec![
             Instruction::Find,
             Instruction::PushNum(0.0),
             Instruction::GreaterOrEqual,
         ]);
 
+        let variadic_func = vec![
+            FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
+            FunctionArg { name: "b".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: true },
+        ];
+
+        let mut def_variadic = |name: &str, doc: &str, instructions: Vec<Instruction>| {
+            stdlib.functions.push(StdlibFunction {
+                instructions,
+                name: name.to_owned(),
+                args: variadic_func.clone(),
+                native: None,
+                doc: doc.to_owned(),
+            });
+        };
+
+        def_variadic("max", "the largest of the numbers", vec![Instruction::Max]);
+        def_variadic("min", "the smallest of the numbers", vec![Instruction::Min]);
+        def_variadic("sum", "the sum of the numbers", vec![Instruction::Add]);
+
         let three_args_func = vec![
-            FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0 },
-            FunctionArg { name: "b".to_owned(), is_func: false, func_arity: 0 },
-            FunctionArg { name: "c".to_owned(), is_func: false, func_arity: 0 },
+            FunctionArg { name: "a".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
+            FunctionArg { name: "b".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
+            FunctionArg { name: "c".to_owned(), is_func: false, func_arity: 0, type_name: None, is_variadic: false },
         ];
 
-        let mut def_three_args = |name: &str, instruction: Instruction| {
+        let mut def_three_args = |name: &str, doc: &str, instruction: Instruction| {
             stdlib.functions.push(StdlibFunction {
                 instructions: vec![instruction],
                 name: name.to_owned(),
                 args: three_args_func.clone(),
+                native: None,
+                doc: doc.to_owned(),
             });
         };
 
-        def_three_args("replace", Instruction::Replace);
-        def_three_args("substr", Instruction::SubStr);
+        def_three_args("replace", "replaces the instances of a by b in the string c", Instruction::Replace);
+        def_three_args("substr", "the characters of the string c from the index a to the index b", Instruction::SubStr);
 
         let iterator_args = vec![
-            FunctionArg{is_func: false, func_arity:0, name:"array".to_owned()},
-            FunctionArg{is_func: true,  func_arity:1, name:"iterator".to_owned()},
+            FunctionArg{is_func: false, func_arity:0, name:"gen".to_owned(), type_name: None, is_variadic: false},
+            FunctionArg{is_func: true,  func_arity:1, name:"iterator".to_owned(), type_name: None, is_variadic: false},
         ];
 
-        let mut def_iterator = |name: &str, instructions: Vec<Instruction>| {
+        let mut def_iterator = |name: &str, doc: &str, instructions: Vec<Instruction>| {
             stdlib.functions.push(StdlibFunction {
                 instructions,
                 name: name.to_owned(),
                 args: iterator_args.clone(),
+                native: None,
+                doc: doc.to_owned(),
+            });
+        };
+
+        // with the generator and the function on the stack, calls the function with each
+        // value of the generator until it returns null, the value is checked for null on
+        // a copy that sits below the function
+        def_iterator("iter", "calls the iterator with each value of the generator", vec![
+            Instruction::Over,             // gen f gen
+            Instruction::Resume,           // gen f v
+            Instruction::Swap,             // gen v f
+            Instruction::Over,             // gen v f v
+            Instruction::IsNull,           // gen v f done
+            Instruction::JumpIfTrue(7),
+            Instruction::Pop,              // gen v f
+            Instruction::Swap,             // gen f v
+            Instruction::Over,             // gen f v f
+            Instruction::Call(1),          // gen f result
+            Instruction::Pop,              // gen f
+            Instruction::Jump(-11),
+            Instruction::Pop,
+            Instruction::Pop,
+            Instruction::Pop,
+            Instruction::Pop,
+            Instruction::PushVoid,
+        ]);
+
+        let server_args = vec![
+            FunctionArg{is_func: false, func_arity:0, name:"port".to_owned(), type_name: None, is_variadic: false},
+            FunctionArg{is_func: true,  func_arity:1, name:"handler".to_owned(), type_name: None, is_variadic: false},
+        ];
+
+        let mut def_server = |name: &str, doc: &str, instructions: Vec<Instruction>| {
+            stdlib.functions.push(StdlibFunction {
+                instructions,
+                name: name.to_owned(),
+                args: server_args.clone(),
+                native: None,
+                doc: doc.to_owned(),
+            });
+        };
+
+        def_server("listen", "calls the handler with the socket of each connection to a local port", vec![Instruction::Listen]);
+        def_server("serve", "a small http server that calls the handler with each request on a local port", vec![Instruction::Serve]);
+
+        stdlib.functions.push(StdlibFunction {
+            instructions: vec![Instruction::Watch],
+            name: "watch".to_owned(),
+            args: vec![
+                FunctionArg{is_func: false, func_arity:0, name:"path".to_owned(), type_name: None, is_variadic: false},
+                FunctionArg{is_func: true,  func_arity:1, name:"handler".to_owned(), type_name: None, is_variadic: false},
+            ],
+            native: None,
+            doc: "waits for a file or a directory to change and calls the handler with its path, until it returns false".to_owned(),
+        });
+
+        stdlib.functions.push(StdlibFunction {
+            instructions: vec![Instruction::EachLine],
+            name: "each_line".to_owned(),
+            args: vec![
+                FunctionArg{is_func: false, func_arity:0, name:"path".to_owned(), type_name: None, is_variadic: false},
+                FunctionArg{is_func: true,  func_arity:1, name:"handler".to_owned(), type_name: None, is_variadic: false},
+            ],
+            native: None,
+            doc: "calls the handler with each line of a text file, without reading it all at once, until it returns false".to_owned(),
+        });
+
+        let mut def_native = |name: &str, doc: &str, args: &[&str], native: NativeFunction| {
+            stdlib.functions.push(StdlibFunction {
+                instructions: vec![],
+                name: name.to_owned(),
+                args: args.iter().map(|arg| {
+                    FunctionArg { name: arg.to_string(), is_func: false, func_arity: 0, type_name: None, is_variadic: false }
+                }).collect(),
+                native: Some(native),
+                doc: doc.to_owned(),
             });
         };
 
-        def_iterator("iter", vec![]); // not implemented, used in parsing tests
+        def_native("reverse", "a string with its characters in reverse order", &["a"], natives::reverse);
+        def_native("count", "the number of non overlapping occurences of the string a in the string b", &["a", "b"], natives::count);
+        def_native("clamp", "limits x to the [lo, hi] range", &["x", "lo", "hi"], natives::clamp);
+        def_native("lerp", "interpolates linearly from a to b, t going from 0 to 1", &["a", "b", "t"], natives::lerp);
+        def_native("map_range", "maps x from the [a1, b1] range to the [a2, b2] range", &["x", "a1", "b1", "a2", "b2"], natives::map_range);
+        def_native("wrap", "wraps x around the [lo, hi) range", &["x", "lo", "hi"], natives::wrap);
+        def_native("round_to", "x rounded to the nearest multiple of step", &["x", "step"], natives::round_to);
+        def_native("ceil_to", "x rounded up to a multiple of step", &["x", "step"], natives::ceil_to);
+        def_native("floor_to", "x rounded down to a multiple of step", &["x", "step"], natives::floor_to);
+        def_native("round_half_even", "a number rounded to the nearest integer, ties to the even one", &["x"], natives::round_half_even);
+        def_native("to_fraction", "the closest fraction to x with a denominator of at most max_den, as a string", &["x", "max_den"], natives::to_fraction);
+        def_native("fmt_money", "the amount written with the symbol and decimals of the currency", &["amount", "currency"], natives::fmt_money);
+        def_native("gcd", "the greatest common divisor of two integers", &["a", "b"], natives::gcd);
+        def_native("lcm", "the least common multiple of two integers", &["a", "b"], natives::lcm);
+        def_native("factorial", "the factorial of an integer", &["n"], natives::factorial);
+        def_native("comb", "the number of combinations of k elements among n", &["n", "k"], natives::comb);
+        def_native("perm", "the number of permutations of k elements among n", &["n", "k"], natives::perm);
+        def_native("stats_new", "creates an accumulator of statistics", &[], natives::stats_new);
+        def_native("stats_push", "adds a value to the statistics and returns them", &["value", "stats"], natives::stats_push);
+        def_native("stats_count", "the number of values added to the statistics", &["stats"], natives::stats_count);
+        def_native("stats_mean", "the mean of the values added to the statistics", &["stats"], natives::stats_mean);
+        def_native("stats_var", "the sample variance of the values added to the statistics", &["stats"], natives::stats_var);
+        def_native("stats_std", "the sample standard deviation of the values added to the statistics", &["stats"], natives::stats_std);
+        def_native("stats_min", "the smallest value added to the statistics", &["stats"], natives::stats_min);
+        def_native("stats_max", "the largest value added to the statistics", &["stats"], natives::stats_max);
+        def_native("req_method", "the method of an http request", &["req"], natives::req_method);
+        def_native("req_path", "the path of an http request", &["req"], natives::req_path);
+        def_native("req_header", "the value of a header of an http request, or null", &["req", "name"], natives::req_header);
+        def_native("req_body", "the body of an http request", &["req"], natives::req_body);
+        def_native("store", "saves a value to a file", &["path", "value"], natives::store);
+        def_native("load", "reads back a value saved with store, or null", &["path"], natives::load);
+        def_native("file_size", "the size of a file in bytes, or null when it doesn't exist", &["path"], natives::file_size);
+        def_native("file_modified", "when a file was last modified, in seconds since 1970, or null", &["path"], natives::file_modified);
+        def_native("is_file", "true if the path is an existing file", &["path"], natives::is_file);
+        def_native("is_dir", "true if the path is an existing directory", &["path"], natives::is_dir);
+        def_native("is_readonly", "true if the file can't be written, or null when it doesn't exist", &["path"], natives::is_readonly);
+        def_native("platform", "the operating system, like linux, macos or windows", &[], natives::platform);
+        def_native("arch", "the processor architecture, like x86_64 or aarch64", &[], natives::arch);
+        def_native("os_family", "unix or windows", &[], natives::os_family);
+        def_native("hostname", "the name of the computer, or null", &[], natives::hostname);
+        def_native("cpu_count", "the number of processors the script can use", &[], natives::cpu_count);
+        def_native("nope_version", "the version of nope running the script", &[], natives::nope_version);
+        def_native("home_dir", "the home directory of the user, or null", &[], natives::home_dir);
+        def_native("config_dir", "the directory where an application keeps its settings on this platform", &["app"], natives::config_dir);
+        def_native("cache_dir", "the directory where an application keeps its cached files on this platform", &["app"], natives::cache_dir);
+        def_native("parse_args", "parses the arguments of the script with a spec of its options, one on each line", &["spec"], natives::parse_args);
+        def_native("opt", "the value of an option found by parse_args", &["name"], natives::opt);
+        def_native("arg", "the argument of the script at a position, or null", &["idx"], natives::arg);
+        def_native("on_signal", "calls the handler when the script receives INT, TERM or HUP, before it stops", &["signal", "handler"], natives::on_signal);
+        def_native("proc_spawn", "starts a command and returns its process", &["command"], natives::proc_spawn);
+        def_native("proc_spawn_in", "starts a command in a directory, with more environment variables like 'NAME=value'", &["command", "cwd", "env"], natives::proc_spawn_in);
+        def_native("proc_write", "writes the text to the input of a process", &["proc", "text"], natives::proc_write);
+        def_native("proc_read_line", "the next line of the output of a process, or null at the end", &["proc"], natives::proc_read_line);
+        def_native("proc_wait", "closes the input of a process, waits for it to finish and returns its exit code", &["proc"], natives::proc_wait);
+        def_native("proc_kill", "stops a process", &["proc"], natives::proc_kill);
+        def_native("zip_create", "zips the content of a directory, or a file, and returns the number of entries", &["path", "source"], natives::zip_create);
+        def_native("zip_list", "the names of the entries of a zip, a line for each", &["path"], natives::zip_list);
+        def_native("zip_extract", "extracts a zip in a directory and returns the number of files", &["path", "dest"], natives::zip_extract);
+        def_native("temp_file", "the path of a new empty temporary file, removed when the script ends", &["prefix"], natives::temp_file);
+        def_native("temp_dir", "the path of a new temporary directory, removed with its content when the script ends", &["prefix"], natives::temp_dir);
+        def_native("append_text", "adds the text at the end of a file, creating it if needed", &["path", "text"], natives::append_text);
+        def_native("term_clear", "clears the terminal", &[], natives::term_clear);
+        def_native("term_move", "moves the cursor of the terminal to the column x and line y", &["x", "y"], natives::term_move);
+        def_native("term_getch", "waits for a key press and returns it", &[], natives::term_getch);
+        def_native("confirm", "asks a yes or no question and returns true for yes", &["question"], natives::confirm);
+        def_native("choose", "asks to pick one of the lines of the options and returns it, or null", &["question", "options"], natives::choose);
+        def_native("term_width", "the width of the terminal in characters", &[], natives::term_width);
+        def_native("term_height", "the height of the terminal in characters", &[], natives::term_height);
+        def_native("progress_new", "shows a progress bar for a number of steps", &["total"], natives::progress_new);
+        def_native("progress_tick", "advances a progress bar by one step", &["progress"], natives::progress_tick);
+        def_native("spinner", "shows a message next to a spinner, or removes it when null", &["message"], natives::spinner);
+        def_native("log_debug", "logs a message at the debug level", &["message"], natives::log_debug);
+        def_native("log_info", "logs a message at the info level", &["message"], natives::log_info);
+        def_native("log_warn", "logs a message at the warn level", &["message"], natives::log_warn);
+        def_native("log_error", "logs a message at the error level", &["message"], natives::log_error);
+        def_native("set_log_level", "sets the minimum level of the logged messages", &["level"], natives::set_log_level);
+        def_native("undef", "removes a global variable or function", &["name"], natives::undef);
+        def_native("assert", "stops with an error showing the message when the value is falsy", &["value", "message"], natives::assert);
+        def_native("assert_snapshot", "compares a value to the one saved under the name by a previous run", &["name", "value"], natives::assert_snapshot);
+        def_native("help", "prints the arguments and description of a stdlib function", &["name"], natives::help);
+        def_native("builtins", "the names of all the stdlib functions, one per line", &[], natives::builtins);
+        def_native("apropos", "prints the stdlib functions with the text in their name or description", &["text"], natives::apropos);
+        def_native("units", "the known units, a line for each kind of measure", &[], natives::units);
+        def_native("to_human_bytes", "a number of bytes as a short string with a binary prefix, like 1.4Gi", &["n"], natives::to_human_bytes_native);
+        def_native("from_human_bytes", "the number of bytes of a string like 1.4Gi or 356 KiB", &["text"], natives::from_human_bytes_native);
+        def_native("hex_to_rgb", "a color like '#ff8800' as 'rgb(255, 136, 0)'", &["color"], natives::hex_to_rgb);
+        def_native("rgb_to_hex", "the color with the red, green and blue from 0 to 255 as '#rrggbb'", &["r", "g", "b"], natives::rgb_to_hex);
+        def_native("hsl_to_rgb", "the color with the hue in degrees, the saturation and the lightness from 0 to 1 as '#rrggbb'", &["h", "s", "l"], natives::hsl_to_rgb);
+        def_native("mix", "the color between two colors, from the first at 0 to the second at 1", &["c1", "c2", "t"], natives::mix);
+        def_native("lighten", "adds the amount to the lightness of the color, from 0 to 1, darkens it when negative", &["color", "amount"], natives::lighten);
+        def_native("svg_new", "a new empty drawing of a width and height, saved as an svg file by svg_save", &["w", "h"], natives::svg_new);
+        def_native("svg_line", "draws a line from x1, y1 to x2, y2 and returns the drawing", &["svg", "x1", "y1", "x2", "y2"], natives::svg_line);
+        def_native("svg_circle", "draws a circle of radius r centered on x, y and returns the drawing", &["svg", "x", "y", "r"], natives::svg_circle);
+        def_native("svg_path", "draws the svg path data, like 'M 0 0 L 10 20 Z', and returns the drawing", &["svg", "d"], natives::svg_path);
+        def_native("svg_style", "sets the stroke and fill colors, or null for none, and the stroke width of the next shapes", &["svg", "stroke", "fill", "width"], natives::svg_style);
+        def_native("svg_forward", "moves the turtle of the drawing forward, drawing a line", &["svg", "distance"], natives::svg_forward);
+        def_native("svg_turn", "turns the turtle of the drawing clockwise by the degrees", &["svg", "degrees"], natives::svg_turn);
+        def_native("svg_save", "writes the drawing to an svg file", &["svg", "path"], natives::svg_save);
+        def_native("convert", "converts the number x from a unit to another", &["x", "from", "to"], natives::convert);
+        def_native("set_float_digits", "rounds the printed numbers to significant digits, or not when null", &["digits"], natives::set_float_digits);
+        def_native("set_decimal_comma", "prints the numbers with a decimal comma when true", &["enabled"], natives::set_decimal_comma);
+        def_native("set_display_unit", "also shows the numbers echoed by the repl in a unit, or not when null", &["unit"], natives::set_display_unit);
+
+        // The namespaced names like `math.sin` or `fs.read` are aliases of the flat names
+        let mut aliases: Vec<(String, &str)> = vec![];
+        for name in [
+            "abs", "acos", "acosh", "asin", "asinh", "atan", "atan2", "atanh", "cbrt", "ceil", "clamp",
+            "ceil_to", "comb", "cos", "cosh", "exp", "expm1", "factorial", "floor", "floor_to", "fmod", "fround", "gcd", "idiv",
+            "inv", "lcm", "lerp", "ln", "ln1p", "log10", "log2", "map_range", "max", "min", "modulo",
+            "perm", "pow", "round", "round_half_even", "round_to", "sign", "sin", "sinh", "sqrt", "sum", "tan", "tanh", "trunc", "wrap",
+        ] {
+            aliases.push((format!("math.{}", name), name));
+        }
+        for name in ["char_at", "contains", "count", "find", "len", "lower", "replace", "reverse", "substr", "trim", "upper"] {
+            aliases.push((format!("str.{}", name), name));
+        }
+        for (member, name) in [("read", "read_text"), ("write", "write_text"), ("append", "append_text"), ("temp_file", "temp_file"), ("temp_dir", "temp_dir"),
+                               ("size", "file_size"), ("modified", "file_modified"), ("is_file", "is_file"), ("is_dir", "is_dir"),
+                               ("is_readonly", "is_readonly"), ("join", "join_paths"), ("store", "store"), ("load", "load"),
+                               ("each_line", "each_line"), ("watch", "watch")] {
+            aliases.push((format!("fs.{}", member), name));
+        }
+        for (member, name) in [("new", "svg_new"), ("line", "svg_line"), ("circle", "svg_circle"), ("path", "svg_path"),
+                               ("style", "svg_style"), ("forward", "svg_forward"), ("turn", "svg_turn"), ("save", "svg_save")] {
+            aliases.push((format!("svg.{}", member), name));
+        }
+        for (alias, name) in aliases {
+            let mut function = stdlib.functions.iter().find(|function| function.name == name).expect("unknown stdlib function").clone();
+            function.name = alias;
+            stdlib.functions.push(function);
+        }
 
-        for function in stdlib.functions.iter() {
-            stdlib.functions_map.insert(function.name.to_owned(), function.clone());
+        for (idx, function) in stdlib.functions.iter().enumerate() {
+            stdlib.functions_map.insert(function.name.to_owned(), idx);
         }
 
         return stdlib;
@@ -225,14 +504,69 @@ impl Stdlib {
         }
     }
 
-    pub fn get_function_instructions(&self, name: &str) -> Option<&Vec<Instruction>> {
-        if let Some(function) = self.functions_map.get(name) {
-            return Some(&function.instructions);
+    pub fn add_definition_to_env(&self, env: &mut Env, name: &str) {
+        if let Some(&idx) = self.functions_map.get(name) {
+            let func = &self.functions[idx];
+            env.push_func_entry(func.name.to_owned(), true, true, func.args.clone());
+        }
+    }
+
+    // The names of the stdlib functions in alphabetical order
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.iter().map(|function| function.name.as_str()).collect();
+        names.sort_unstable();
+        return names;
+    }
+
+    // The functions whose name or description contain the text, ignoring the case
+    pub fn search(&self, text: &str) -> Vec<&StdlibFunction> {
+        let text = text.to_lowercase();
+        let mut found: Vec<&StdlibFunction> = self.functions.iter().filter(|function| {
+            function.name.contains(&text) || function.doc.to_lowercase().contains(&text)
+        }).collect();
+        found.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        return found;
+    }
+
+    // The usage of a stdlib function as shown by help, like
+    //
+    //     clamp x lo hi
+    //       3 arguments, limits x to the [lo, hi] range
+    pub fn describe(&self, name: &str) -> Option<String> {
+        let function = &self.functions[*self.functions_map.get(name)?];
+        let mut usage = function.name.to_owned();
+        for arg in function.args.iter() {
+            usage.push(' ');
+            usage.push_str(&arg.name);
+            if arg.is_variadic {
+                usage.push_str("...");
+            }
+        }
+        let arity = match function.args.len() {
+            count if function.is_variadic() => format!("{} or more arguments", count),
+            1 => "1 argument".to_owned(),
+            count => format!("{} arguments", count),
+        };
+        return Some(format!("{}\n  {}, {}", usage, arity, function.doc));
+    }
+
+    pub fn get_function_call(&self, name: &str) -> Option<StdlibCall<'_>> {
+        let idx = *self.functions_map.get(name)?;
+        let function = &self.functions[idx];
+        if function.native.is_some() {
+            return Some(StdlibCall::Native(idx));
+        } else if function.is_variadic() {
+            return Some(StdlibCall::Fold(&function.instructions));
         } else {
-            return None;
+            return Some(StdlibCall::Inline(&function.instructions));
         }
     }
 
+    pub fn get_native(&self, idx: usize) -> (usize, NativeFunction) {
+        let function = &self.functions[idx];
+        return (function.args.len(), function.native.expect("not a native function"));
+    }
+
     pub fn make_env(&self) -> Env {
         let mut env = Env::new();
         self.add_definitions_to_env(&mut env);
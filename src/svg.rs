@@ -0,0 +1,81 @@
+// How the next shapes of a drawing are painted, the colors are '#rrggbb' or none
+#[derive(Debug, Clone)]
+pub struct Style {
+    pub stroke: String,
+    pub fill: String,
+    pub width: f64,
+}
+
+impl Style {
+    pub fn new() -> Style {
+        return Style { stroke: "#000000".to_owned(), fill: "none".to_owned(), width: 1.0 };
+    }
+
+    fn attributes(&self) -> String {
+        return format!("stroke=\"{}\" fill=\"{}\" stroke-width=\"{}\"", self.stroke, self.fill, num(self.width));
+    }
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        return Style::new();
+    }
+}
+
+// The coordinates are rounded to the hundredth, which is plenty for a picture and
+// keeps the files small
+fn num(x: f64) -> String {
+    let rounded = (x * 100.0).round() / 100.0;
+    return format!("{}", if rounded == 0.0 { 0.0 } else { rounded });
+}
+
+pub fn line(x1: f64, y1: f64, x2: f64, y2: f64, style: &Style) -> String {
+    return format!("<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {}/>", num(x1), num(y1), num(x2), num(y2), style.attributes());
+}
+
+pub fn circle(cx: f64, cy: f64, r: f64, style: &Style) -> String {
+    return format!("<circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>", num(cx), num(cy), num(r), style.attributes());
+}
+
+// The path data is the d attribute of an svg path, like 'M 0 0 L 10 20 Z'
+pub fn path(data: &str, style: &Style) -> Result<String, String> {
+    let valid = data.chars().all(|c| c.is_ascii_digit() || c.is_whitespace() || ",.-+eE".contains(c) || "MmLlHhVvCcSsQqTtAaZz".contains(c));
+    if !valid || data.trim().is_empty() {
+        return Err(format!("'{}' is not path data like 'M 0 0 L 10 20'", data));
+    }
+    return Ok(format!("<path d=\"{}\" {}/>", data.trim(), style.attributes()));
+}
+
+pub fn document(width: f64, height: f64, shapes: &[String]) -> String {
+    let mut text = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+        num(width), num(height),
+    );
+    for shape in shapes {
+        text.push_str("  ");
+        text.push_str(shape);
+        text.push('\n');
+    }
+    text.push_str("</svg>\n");
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let style = Style { stroke: "#ff8800".to_owned(), ..Style::new() };
+        let shapes = vec![line(0.0, 0.0, 10.0, 1.0 / 3.0, &style), circle(5.0, 5.0, 2.5, &Style::new()), path("M 0 0 L 4 4 Z", &style).unwrap()];
+        assert_eq!(document(20.0, 10.0, &shapes), "\
+<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"20\" height=\"10\" viewBox=\"0 0 20 10\">
+  <line x1=\"0\" y1=\"0\" x2=\"10\" y2=\"0.33\" stroke=\"#ff8800\" fill=\"none\" stroke-width=\"1\"/>
+  <circle cx=\"5\" cy=\"5\" r=\"2.5\" stroke=\"#000000\" fill=\"none\" stroke-width=\"1\"/>
+  <path d=\"M 0 0 L 4 4 Z\" stroke=\"#ff8800\" fill=\"none\" stroke-width=\"1\"/>
+</svg>
+");
+        assert!(path("M 0 0\"/><script>", &style).is_err());
+        assert!(path(" ", &style).is_err());
+    }
+}
@@ -10,14 +10,18 @@
 " Quit when a syntax file was already loaded.
 if exists('b:current_syntax') | finish|  endif
 
-syntax keyword letsetvar let set var do
+syntax keyword letsetvar let set var const do inc dec memo lazy spawn test
 syntax keyword cond if else
-syntax keyword repeat while break break_as continue loop
+syntax keyword repeat while break break_as continue loop return yield
 
 syntax keyword stdlib random rand100 flip_coin d4 d6 d8 d10 d12 d20 d100
-syntax keyword stdlib to_num print echo neg return not to_bool floor ceil abs acos acosh decr incr sin sinh asin asinh cos cosh tan tanh atan atanh inv log2 log10 ln1p ln exp expm1 sqrt cbrt round fround trunc sign to_str upper lower trim shh bitstr read_text is_even is_odd
-syntax keyword stdlib add sub le leq ge geq eq aeq neq naeq max min mult div pow atan2 modulo join_paths write_text from_unit to_unit
-syntax keyword stdlib replace contains[] len substr char_at find char_at is_void is_null is_bool is_num is_int is_str
+syntax keyword stdlib to_num print echo neg not to_bool floor ceil abs acos acosh decr incr sin sinh asin asinh cos cosh tan tanh atan atanh inv log2 log10 ln1p ln exp expm1 sqrt cbrt round fround trunc sign to_str upper lower trim shh bitstr read_text is_even is_odd
+syntax keyword stdlib add sub le leq ge geq eq aeq neq naeq max min mult div pow atan2 modulo idiv fmod clamp lerp map_range wrap gcd lcm factorial comb perm join_paths write_text from_unit to_unit
+syntax keyword stdlib store load clip_get clip_set term_clear term_move term_getch term_width term_height progress_new progress_tick spinner log_debug log_info log_warn log_error set_log_level undef set_display_unit assert assert_snapshot help builtins apropos
+syntax keyword stdlib force next await sleep chan_new send recv
+syntax keyword stdlib connect listen sock_read_line sock_write sock_close serve req_method req_path req_header req_body
+syntax keyword stdlib stats_new stats_push stats_count stats_mean stats_var stats_std stats_min stats_max
+syntax keyword stdlib replace contains[] len substr char_at find char_at is_void is_null is_bool is_num is_int is_str is_same is_fn type_of
 
 syntax match comment "\v#.*$"
 
@@ -0,0 +1,265 @@
+use std::env;
+use std::io::{self, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// The signals that scripts can handle with on_signal, a flag is set when one
+// is received until the vm handles it
+pub const SIGNAL_NAMES: [&str; 3] = ["INT", "TERM", "HUP"];
+static RECEIVED: [AtomicBool; 3] = [AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false)];
+#[cfg(unix)]
+const SIGNAL_NUMBERS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+pub fn is_tty() -> bool {
+    return io::stdout().is_terminal();
+}
+
+// Writes an escape sequence to the terminal, nothing is written when the
+// output is redirected to a file or a pipe
+pub fn write_control(sequence: &str) {
+    if is_tty() {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(sequence.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+// Moves the cursor to the column x and line y, starting at 0. The terminals count
+// from 1 and don't go further than u16, NaN goes to 0.
+pub fn move_sequence(x: f64, y: f64) -> String {
+    let position = |n: f64| n.max(0.0).min(u16::MAX as f64 - 1.0) as u32 + 1;
+    return format!("\x1b[{};{}H", position(y), position(x));
+}
+
+// Replaces the current line of stderr, used for the progress bars and spinners
+// that are only shown when stderr is a terminal
+pub fn write_status(line: &str, done: bool) {
+    let mut stderr = io::stderr();
+    if stderr.is_terminal() {
+        let _ = write!(stderr, "\r\x1b[2K{}{}", line, if done { "\n" } else { "" });
+        let _ = stderr.flush();
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    if let Some(idx) = SIGNAL_NUMBERS.iter().position(|number| *number == signal) {
+        RECEIVED[idx].store(true, Ordering::Relaxed);
+    }
+}
+
+// The index of a signal in SIGNAL_NAMES, from its name with or without SIG
+pub fn signal_index(name: &str) -> Option<usize> {
+    let name = name.trim().to_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    return SIGNAL_NAMES.iter().position(|signal| *signal == name);
+}
+
+// The signal sets a flag instead of killing the process, the vm checks it
+// between instructions
+#[cfg(unix)]
+pub fn catch_signal(idx: usize) {
+    let handler: extern "C" fn(libc::c_int) = on_signal;
+    unsafe { libc::signal(SIGNAL_NUMBERS[idx], handler as libc::sighandler_t) };
+}
+
+#[cfg(not(unix))]
+pub fn catch_signal(_idx: usize) {}
+
+// Ctrl-C stops the program with an error, or calls the handler given to on_signal
+pub fn catch_interrupts() {
+    catch_signal(0);
+}
+
+// True once after each time the signal was received
+pub fn take_signal(idx: usize) -> bool {
+    return RECEIVED[idx].load(Ordering::Relaxed) && RECEIVED[idx].swap(false, Ordering::Relaxed);
+}
+
+pub fn is_signal_pending(idx: usize) -> bool {
+    return RECEIVED[idx].load(Ordering::Relaxed);
+}
+
+// True once after each Ctrl-C
+pub fn take_interrupt() -> bool {
+    return take_signal(0);
+}
+
+// Asks a yes or no question, any other answer than y or yes is a no
+pub fn ask_yes_no(question: &str) -> bool {
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "{} ", question);
+    let _ = stdout.flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    return matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+}
+
+#[cfg(unix)]
+fn window_size() -> Option<(usize, usize)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if res == 0 && size.ws_col > 0 && size.ws_row > 0 {
+        return Some((size.ws_col as usize, size.ws_row as usize));
+    }
+    return None;
+}
+
+#[cfg(not(unix))]
+fn window_size() -> Option<(usize, usize)> {
+    return None;
+}
+
+// The width and height of the terminal in characters, from $COLUMNS and
+// $LINES or 80x24 when it can't be queried
+pub fn size() -> (usize, usize) {
+    if let Some(size) = window_size() {
+        return size;
+    }
+    let from_env = |name: &str, default: usize| {
+        env::var(name).ok().and_then(|val| val.parse::<usize>().ok()).unwrap_or(default)
+    };
+    return (from_env("COLUMNS", 80), from_env("LINES", 24));
+}
+
+fn read_byte() -> Option<u8> {
+    let mut byte = [0u8; 1];
+    match io::stdin().read(&mut byte) {
+        Ok(1) => Some(byte[0]),
+        _ => None,
+    }
+}
+
+fn read_char() -> Option<String> {
+    let first = read_byte()?;
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        bytes.push(read_byte()?);
+    }
+    return Some(String::from_utf8_lossy(&bytes).into_owned());
+}
+
+// Reads a single key without waiting for enter, or null at the end of the input
+#[cfg(unix)]
+pub fn read_key() -> Option<String> {
+    if !io::stdin().is_terminal() {
+        return read_char();
+    }
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+        return read_char();
+    }
+    let mut raw = original;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) };
+    let mut key = read_char();
+    // the arrow keys are sent as escape sequences, a lone escape is not followed by anything
+    if key.as_deref() == Some("\x1b") {
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 1;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) };
+        let name = match (read_byte(), read_byte()) {
+            (Some(b'['), Some(b'A')) => "up",
+            (Some(b'['), Some(b'B')) => "down",
+            (Some(b'['), Some(b'C')) => "right",
+            (Some(b'['), Some(b'D')) => "left",
+            _ => "escape",
+        };
+        key = Some(name.to_owned());
+    }
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &original) };
+    return key;
+}
+
+#[cfg(not(unix))]
+pub fn read_key() -> Option<String> {
+    return read_char();
+}
+
+// The option picked by an answer, its number starting at 1 or its text
+fn parse_choice(answer: &str, options: &[String]) -> Option<usize> {
+    let answer = answer.trim();
+    if let Ok(number) = answer.parse::<usize>() {
+        return (1..=options.len()).contains(&number).then(|| number - 1);
+    }
+    return options.iter().position(|option| option.eq_ignore_ascii_case(answer));
+}
+
+fn draw_menu(options: &[String], selected: usize) {
+    let mut stdout = io::stdout();
+    for (idx, option) in options.iter().enumerate() {
+        if idx == selected {
+            let _ = writeln!(stdout, "\r\x1b[2K\x1b[1m> {}\x1b[0m", option);
+        } else {
+            let _ = writeln!(stdout, "\r\x1b[2K  {}", option);
+        }
+    }
+    let _ = stdout.flush();
+}
+
+// Asks to pick one of the options, with the arrow keys and enter in a terminal, or
+// by typing its number otherwise. None when the input ends or on escape.
+pub fn choose(question: &str, options: &[String]) -> Option<usize> {
+    let mut stdout = io::stdout();
+    let _ = writeln!(stdout, "{}", question);
+    if is_tty() && io::stdin().is_terminal() {
+        let mut selected = 0;
+        draw_menu(options, selected);
+        loop {
+            match read_key()?.as_str() {
+                "up" | "k" => selected = (selected + options.len() - 1) % options.len(),
+                "down" | "j" => selected = (selected + 1) % options.len(),
+                "\n" | "\r" => return Some(selected),
+                "escape" | "q" => return None,
+                _ => continue,
+            }
+            // back to the first option to draw the menu again
+            let _ = write!(stdout, "\x1b[{}A", options.len());
+            draw_menu(options, selected);
+        }
+    }
+    for (idx, option) in options.iter().enumerate() {
+        let _ = writeln!(stdout, "  {}) {}", idx + 1, option);
+    }
+    loop {
+        let _ = write!(stdout, "? ");
+        let _ = stdout.flush();
+        let mut answer = String::new();
+        match io::stdin().read_line(&mut answer) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => if let Some(idx) = parse_choice(&answer, options) {
+                return Some(idx);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_sequence() {
+        for (x, y, sequence) in [(0.0, 0.0, "\x1b[1;1H"), (4.0, 2.5, "\x1b[3;5H"), (-3.0, f64::NAN, "\x1b[1;1H"),
+                                 (0.0, 1e30, "\x1b[65535;1H"), (f64::INFINITY, 0.0, "\x1b[1;65535H")] {
+            assert_eq!(move_sequence(x, y), sequence, "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        let options: Vec<String> = ["red", "green", "blue"].iter().map(|option| option.to_string()).collect();
+        for (answer, choice) in [("1", Some(0)), (" 3\n", Some(2)), ("Green", Some(1)), ("0", None), ("4", None), ("", None), ("pink", None)] {
+            assert_eq!(parse_choice(answer, &options), choice, "{}", answer);
+        }
+    }
+}
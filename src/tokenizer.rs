@@ -1,4 +1,8 @@
+use std::ops::Range;
+
 use crate::consts::EPSILON;
+use crate::vm::json_string;
+use crate::units::is_known_unit;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum TokenValue {
@@ -14,6 +18,7 @@ pub enum TokenValue {
     Pipe,
     PipeLeft,
     Comma,
+    Semicolon,
     Equal,
     Eof,
     Swp, // Significant whitespace, after `]`
@@ -48,6 +53,8 @@ pub struct Tokenizer {
     pub line: usize, // line of character at 'index', starts at 1
     pub col: usize,  // collumn of character at 'index', starts at 1
     pub tokens: Vec<Token>, // resulting tokens
+    pub spans: Vec<(usize, usize)>, // index in chars and length of the text of each token
+    token_start: usize, // index in chars of the text of the tokens being tokenized
     pub state: TokenizerState,
 }
 
@@ -62,17 +69,18 @@ fn is_wp(c:char) -> bool {
 fn is_separator(c:char) -> bool {
     return c == '.' || c == ':' || c == '[' || c == ']' || c == '!' 
         || c == '|' || c == '"' || c == '\'' || c == '#' || c == ','
-        || c == '(' || c == ')';
+        || c == '(' || c == ')' || c == ';' || c == '{' || c == '}';
 }
 
 fn is_tildestr_separator(c:char) -> bool {
-    return c == ':' || c == '[' || c == ']' || c == ',' || c == '(' || c == ')';
+    return c == ':' || c == '[' || c == ']' || c == ',' || c == '(' || c == ')' || c == ';'
+        || c == '{' || c == '}';
 }
 
 fn is_num_separator(c:char) -> bool {
     return c == ':' || c == '[' || c == ']' || c == '!' || c == '|' 
         || c == '"' || c == '\'' || c == '#' || c == ','
-        || c == ')' || c == '(';
+        || c == ')' || c == '(' || c == ';' || c == '{' || c == '}';
 }
 
 fn is_namechar(c:char) -> bool {
@@ -103,9 +111,9 @@ fn is_operator(c:char) -> bool {
         || c == '?'; 
 }
 
-const OPERATORS: [&str; 30] = [
+const OPERATORS: [&str; 32] = [
      "==", "!=", "<=", ">=", "+-=", "!+-=",
-     "**", "&&", "||", "??", "*:",
+     "**", "&&", "||", "??", "*:", "//", "%%",
      "~<<", "~>>>", "~&", "~|", "~!", "~^", "~>>", 
      "~+", "~-", "~*", "~/",
      "<", ">", "+", "-", "*", "/", "!", "%", 
@@ -121,6 +129,8 @@ impl Tokenizer {
             chars: source.chars().collect(),
             source,
             tokens: Vec::new(),
+            spans: Vec::new(),
+            token_start: 0,
             state: TokenizerState::Wip,
         };
     }
@@ -143,6 +153,90 @@ impl Tokenizer {
         );
     }
 
+    // Each token as a json object {"value", "kind", "line", "col", "len"} where value is
+    // the text of the token in the source and len its length in characters
+    pub fn tokens_to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (idx, (token, (start, len))) in self.tokens.iter().zip(self.spans.iter()).enumerate() {
+            let debug = format!("{:?}", token.value);
+            let kind = debug.split('(').next().unwrap_or_default();
+            let text: String = self.chars[*start..start+len].iter().collect();
+            if idx > 0 {
+                json.push_str(",\n ");
+            }
+            json.push_str(&format!(
+                "{{\"value\": {}, \"kind\": {}, \"line\": {}, \"col\": {}, \"len\": {}}}",
+                json_string(&text), json_string(kind), token.line, token.col, len,
+            ));
+        }
+        json.push(']');
+        return json;
+    }
+
+    // Records the spans of the tokens pushed since the last call, they all start at
+    // token_start, or on the same line for the `(` that follows a name
+    fn measure_tokens(&mut self) {
+        let end = self.nextindex.min(self.chars.len());
+        let first = self.spans.len();
+        for idx in first..self.tokens.len() {
+            let start = self.token_start + self.tokens[idx].col.saturating_sub(self.tokens[first].col);
+            let next_start = match self.tokens.get(idx + 1) {
+                Some(next) => self.token_start + next.col.saturating_sub(self.tokens[first].col),
+                None => end,
+            };
+            self.spans.push((start.min(end), next_start.min(end).saturating_sub(start)));
+        }
+        self.token_start = end;
+    }
+
+    // Replaces the characters in `range` by `new_text` and tokenizes the result again,
+    // keeping the tokens that end before the edit. The token right before the edit is
+    // tokenized again as it can be extended by the new text.
+    pub fn retokenize(&mut self, range: Range<usize>, new_text: &str) {
+        let end = range.end.min(self.chars.len());
+        let start = range.start.min(end);
+        self.chars.splice(start..end, new_text.chars());
+        self.source = self.chars.iter().collect();
+
+        let mut kept = self.spans.iter().take_while(|(token_start, len)| token_start + len < start).count();
+        if kept > 0 && matches!(self.tokens.get(kept), Some(Token { value: TokenValue::NameLeftP, .. })) {
+            kept -= 1; // the name and its `(` are tokenized together
+        }
+        self.tokens.truncate(kept);
+        self.spans.truncate(kept);
+        self.state = TokenizerState::Wip;
+        match (self.tokens.last(), self.spans.last()) {
+            (Some(token), Some((token_start, len))) => {
+                // the tokenizer continues after the last character of the last kept token
+                let resume = token_start + len;
+                let (mut line, mut col) = (token.line, token.col);
+                for c in &self.chars[*token_start..resume-1] {
+                    if *c == '\n' {
+                        line += 1;
+                        col = 1;
+                    } else {
+                        col += 1;
+                    }
+                }
+                self.line = line;
+                self.col = col;
+                self.index = resume - 1;
+                self.nextindex = resume;
+                self.token_start = resume;
+            },
+            _ => {
+                self.tokens.clear();
+                self.spans.clear();
+                self.line = 1;
+                self.col = 1;
+                self.index = 0;
+                self.nextindex = 0;
+                self.token_start = 0;
+            },
+        }
+        self.tokenize();
+    }
+
     fn nextc(&mut self) -> char {
         if self.nextindex == 0 {
             self.line = 1;
@@ -181,6 +275,47 @@ impl Tokenizer {
         }
     }
 
+    // The number of characters that continue the unit of a number into a compound unit,
+    // like the `/h` of `30km/h`. A `/` that doesn't make a known unit is a division, so
+    // `6km/x` divides by x
+    fn compound_unit_len(&self, unit: &[char]) -> Option<usize> {
+        let separator = self.peek1();
+        if separator != '/' && separator != '·' {
+            return None;
+        }
+        let next: Vec<char> = self.chars[self.nextindex + 1..].iter().copied().take_while(|c| is_unit(*c)).collect();
+        if next.is_empty() || !next[0].is_alphabetic() {
+            return None;
+        }
+        let compound: String = unit.iter().chain(std::iter::once(&separator)).chain(next.iter()).collect();
+        if !is_known_unit(&compound) {
+            return None;
+        }
+        return Some(next.len() + 1);
+    }
+
+    // A `%` right after a number is a percentage unless an operand follows it on the same
+    // line, `20%` and `20% of 50` are 0.2 but `20%3` and `7% 2` are modulos
+    fn percent_ends_number(&self) -> bool {
+        let mut idx = self.nextindex + 1;
+        if self.chars.get(idx) == Some(&'%') {
+            return false;
+        }
+        while self.chars.get(idx).is_some_and(|c| *c == ' ' || *c == '\t') {
+            idx += 1;
+        }
+        let afterc = self.chars.get(idx).copied().unwrap_or('\0');
+        if is_eof(afterc) {
+            return true;
+        }
+        let word: String = self.chars[idx..].iter().take_while(|c| is_namechar(**c)).collect();
+        if word == "of" {
+            return true;
+        }
+        return !(is_namechar(afterc) || afterc == '(' || afterc == '[' || afterc == '\'' || afterc == '"'
+            || afterc == '-' || afterc == '~');
+    }
+
     fn match_and_push_operator(&mut self) -> bool {
         if self.index >= self.chars.len() {
             return false;
@@ -226,16 +361,20 @@ impl Tokenizer {
 
     pub fn tokenize(&mut self) {
         self.tokenize_raw();
+        self.measure_tokens();
         // FIXME there ought to be a better way to do this
         let mut newtokens: Vec<Token> = vec![];
-        for ref token in self.tokens.iter() {
+        let mut newspans: Vec<(usize, usize)> = vec![];
+        for (token, span) in self.tokens.iter().zip(self.spans.iter()) {
             if matches!(token, Token { value: TokenValue::Comment(..), ..}) {
                 continue
             } else {
-                newtokens.push((*token).to_owned());
+                newtokens.push(token.to_owned());
+                newspans.push(*span);
             }
         }
         self.tokens = newtokens;
+        self.spans = newspans;
     }
 
     fn tokenize_raw(&mut self) {
@@ -244,6 +383,7 @@ impl Tokenizer {
                 return;
             }
 
+            self.measure_tokens();
             let cur = self.nextc();
 
             if cur == '\0' {
@@ -285,6 +425,8 @@ impl Tokenizer {
                 self.push_token(TokenValue::Pipe);
             } else if cur == ',' {
                 self.push_token(TokenValue::Comma);
+            } else if cur == ';' {
+                self.push_token(TokenValue::Semicolon);
             } else if cur == '#' {
                 // here we parse comments
                 let line = self.line;
@@ -337,7 +479,9 @@ impl Tokenizer {
                             break;
                         }
                     }
-                    if !error {
+                    if !error && num.is_empty() {
+                        self.state = TokenizerState::Error("This binary number has no digits".to_owned());
+                    } else if !error {
                         let numstr: String = num.iter().collect();
                         let val = usize::from_str_radix(&numstr, 2).unwrap() as f64;
                         self.tokens.push(Token { line, col, value: TokenValue::Number(val, None) });
@@ -368,7 +512,9 @@ impl Tokenizer {
                             break;
                         }
                     }
-                    if !error {
+                    if !error && num.is_empty() {
+                        self.state = TokenizerState::Error("This hexadecimal number has no digits".to_owned());
+                    } else if !error {
                         let numstr: String = num.iter().collect();
                         let val = usize::from_str_radix(&numstr, 16).unwrap() as f64;
                         self.tokens.push(Token { line, col, value: TokenValue::Number(val, None) });
@@ -386,7 +532,10 @@ impl Tokenizer {
 
                         let nextc = self.peek1();
 
-                        if is_eof(nextc) || is_wp(nextc) || is_operator(nextc) || is_num_separator(nextc) {
+                        if nextc == '%' && self.percent_ends_number() {
+                            unit.push(self.nextc());
+                            break;
+                        } else if is_eof(nextc) || is_wp(nextc) || is_operator(nextc) || is_num_separator(nextc) {
                             break;
                         } else if !has_exp && (nextc == 'e') || (nextc == 'E') {
                             has_exp = true;
@@ -410,6 +559,10 @@ impl Tokenizer {
                                 let nextu = self.peek1();
                                 if is_unit(nextu) {
                                     unit.push(self.nextc());
+                                } else if let Some(len) = self.compound_unit_len(&unit) {
+                                    for _ in 0..len {
+                                        unit.push(self.nextc());
+                                    }
                                 } else {
                                     break;
                                 }
@@ -578,6 +731,22 @@ mod tests {
         assert_eq!(program.state, TokenizerState::Done);
     }
     
+    #[test]
+    fn test_parse_semicolon() {
+        let mut program = Tokenizer::new(String::from("foo;1;'a';"));
+        program.tokenize();
+        assert_eq!(program.tokens, vec![
+            Token{line:1, col:1, value: TokenValue::Name("foo".to_owned())},
+            Token{line:1, col:4, value: TokenValue::Semicolon},
+            Token{line:1, col:5, value: TokenValue::Number(1.0, None)},
+            Token{line:1, col:6, value: TokenValue::Semicolon},
+            Token{line:1, col:7, value: TokenValue::String("a".to_owned())},
+            Token{line:1, col:10, value: TokenValue::Semicolon},
+            Token{line:1, col:10, value: TokenValue::Eof},
+        ]);
+        assert_eq!(program.state, TokenizerState::Done);
+    }
+
     #[test]
     fn test_parse_lb() {
         let mut program = Tokenizer::new(String::from("["));
@@ -908,11 +1077,17 @@ mod tests {
         program.tokenize();
         assert_eq!(program.tokens, vec![]);
         assert_eq!(program.state, TokenizerState::Error("Too many dots '.' in number".to_owned()));
+        let mut program = Tokenizer::new(String::from("0b_ 1"));
+        program.tokenize();
+        assert_eq!(program.state, TokenizerState::Error("This binary number has no digits".to_owned()));
+        let mut program = Tokenizer::new(String::from("0x__"));
+        program.tokenize();
+        assert_eq!(program.state, TokenizerState::Error("This hexadecimal number has no digits".to_owned()));
     }
 
     #[test]
     fn test_parse_num_123xyz() {
-        let mut program = Tokenizer::new(String::from("123;"));
+        let mut program = Tokenizer::new(String::from("123$"));
         program.tokenize();
         assert_eq!(program.tokens, vec![]);
         assert_eq!(program.state, TokenizerState::Error("This number contains unexpected characters".to_owned()));
@@ -932,6 +1107,49 @@ mod tests {
         assert_eq!(program.state, TokenizerState::Done);
     }
 
+    #[test]
+    fn test_parse_num_percent() {
+        let mut program = Tokenizer::new(String::from("(20%) 5%%3 7%1 7% 2"));
+        program.tokenize();
+        assert_eq!(
+            program.tokens,
+            vec![
+                Token{line:1, col:1, value: TokenValue::LeftP},
+                Token{line:1, col:2, value: TokenValue::Number(20.0, Some("%".to_owned()))},
+                Token{line:1, col:5, value: TokenValue::RightP},
+                Token{line:1, col:7, value: TokenValue::Number(5.0, None)},
+                Token{line:1, col:8, value: TokenValue::Operator("%%".to_owned())},
+                Token{line:1, col:10, value: TokenValue::Number(3.0, None)},
+                Token{line:1, col:12, value: TokenValue::Number(7.0, None)},
+                Token{line:1, col:13, value: TokenValue::Operator("%".to_owned())},
+                Token{line:1, col:14, value: TokenValue::Number(1.0, None)},
+                Token{line:1, col:16, value: TokenValue::Number(7.0, None)},
+                Token{line:1, col:17, value: TokenValue::Operator("%".to_owned())},
+                Token{line:1, col:19, value: TokenValue::Number(2.0, None)},
+                Token{line:1, col:19, value: TokenValue::Eof},
+            ],
+        );
+        assert_eq!(program.state, TokenizerState::Done);
+    }
+
+    #[test]
+    fn test_parse_num_compound_unit() {
+        let mut program = Tokenizer::new(String::from("30km/h 2N·m 6km/x"));
+        program.tokenize();
+        assert_eq!(
+            program.tokens,
+            vec![
+                Token{line:1, col:1, value: TokenValue::Number(30.0, Some("km/h".to_owned()))},
+                Token{line:1, col:8, value: TokenValue::Number(2.0, Some("N·m".to_owned()))},
+                Token{line:1, col:13, value: TokenValue::Number(6.0, Some("km".to_owned()))},
+                Token{line:1, col:16, value: TokenValue::Operator("/".to_owned())},
+                Token{line:1, col:17, value: TokenValue::Name("x".to_owned())},
+                Token{line:1, col:17, value: TokenValue::Eof},
+            ],
+        );
+        assert_eq!(program.state, TokenizerState::Done);
+    }
+
     #[test]
     fn test_parse_num_123m3() {
         let mut program = Tokenizer::new(String::from("123m3"));
@@ -1309,4 +1527,20 @@ mod tests {
             assert_eq!(program.state, TokenizerState::Done);
         }
     }
+
+    #[test]
+    fn test_tokens_to_json() {
+        let mut program = Tokenizer::new(String::from("a == 'b\nc'\n# x\nf(2cm)"));
+        program.tokenize();
+        assert_eq!(program.tokens_to_json(), concat!(
+            "[{\"value\": \"a\", \"kind\": \"Name\", \"line\": 1, \"col\": 1, \"len\": 1},\n ",
+            "{\"value\": \"==\", \"kind\": \"Operator\", \"line\": 1, \"col\": 3, \"len\": 2},\n ",
+            "{\"value\": \"'b\\nc'\", \"kind\": \"String\", \"line\": 1, \"col\": 6, \"len\": 5},\n ",
+            "{\"value\": \"f\", \"kind\": \"Name\", \"line\": 4, \"col\": 1, \"len\": 1},\n ",
+            "{\"value\": \"(\", \"kind\": \"NameLeftP\", \"line\": 4, \"col\": 2, \"len\": 1},\n ",
+            "{\"value\": \"2cm\", \"kind\": \"Number\", \"line\": 4, \"col\": 3, \"len\": 3},\n ",
+            "{\"value\": \")\", \"kind\": \"RightP\", \"line\": 4, \"col\": 6, \"len\": 1},\n ",
+            "{\"value\": \"\", \"kind\": \"Eof\", \"line\": 4, \"col\": 6, \"len\": 0}]",
+        ));
+    }
 }
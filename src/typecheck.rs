@@ -0,0 +1,205 @@
+use crate::parser::{Parser, AstNode, BinaryOperator, UnaryOperator};
+use crate::penv::FunctionArg;
+
+// What is known about a variable while walking its scope
+struct Binding {
+    name: String,
+    type_name: Option<String>,
+    args: Option<Vec<FunctionArg>>,
+}
+
+// The --typecheck pass, it compares the type annotations of the variables and function
+// arguments to the type of the expressions assigned to them whenever the type can be
+// known without running the code. This is flow-insensitive and best-effort: an expression
+// of unknown type is always accepted. Returns the ast node and message of each mismatch.
+pub fn check_types(parser: &Parser) -> Vec<(usize, String)> {
+    let mut checker = TypeChecker { parser, scope: vec![], errors: vec![] };
+    if !parser.ast.is_empty() {
+        checker.check(parser.ast.len() - 1);
+    }
+    return checker.errors;
+}
+
+struct TypeChecker<'a> {
+    parser: &'a Parser,
+    scope: Vec<Binding>,
+    errors: Vec<(usize, String)>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn binding(&self, name: &str) -> Option<&Binding> {
+        return self.scope.iter().rev().find(|binding| binding.name == name);
+    }
+
+    fn function_args(&self, name: &str) -> Option<Vec<FunctionArg>> {
+        if let Some(binding) = self.binding(name) {
+            return binding.args.clone();
+        }
+        // functions defined by a previous REPL submission or the prelude
+        return self.parser.env.get_entry(&name.to_owned())
+            .filter(|entry| entry.is_func)
+            .map(|entry| entry.func_args);
+    }
+
+    fn bind(&mut self, name: &str, value_idx: usize) {
+        let args = match &self.parser.ast[value_idx] {
+            AstNode::FunctionDef(_, args, _) => Some(args.clone()),
+            AstNode::Memo(_, def_idx) => match &self.parser.ast[*def_idx] {
+                AstNode::FunctionDef(_, args, _) => Some(args.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        self.scope.push(Binding {
+            name: name.to_owned(),
+            type_name: self.parser.let_types.get(&value_idx).cloned(),
+            args,
+        });
+    }
+
+    fn expect(&mut self, node_idx: usize, expected: &str, what: &str) {
+        if let Some(found) = self.type_of(node_idx) {
+            if found != expected {
+                let message = format!("ERROR: {} should be a {} but this is a {}", what, expected, found);
+                self.errors.push((node_idx, message));
+            }
+        }
+    }
+
+    // The type of the value of an expression, when it can be known without running it
+    fn type_of(&self, node_idx: usize) -> Option<String> {
+        let name = match &self.parser.ast[node_idx] {
+            AstNode::Number(..) => "num",
+            AstNode::String(..) => "str",
+            AstNode::Boolean(..) => "bool",
+            AstNode::Null(..) => "null",
+            AstNode::Void(..) => "void",
+            AstNode::FunctionDef(..) | AstNode::Memo(..) => "fn",
+            AstNode::Lazy(..) => "lazy",
+            AstNode::Spawn(..) => "task",
+            AstNode::UnaryOperator(_, UnaryOperator::Not, _) => "bool",
+            AstNode::UnaryOperator(..) => "num",
+            AstNode::ChainedComparison(..) => "bool",
+            AstNode::BinaryOperator(_, op, left, right) => match op {
+                BinaryOperator::Equal | BinaryOperator::NotEqual |
+                BinaryOperator::Less | BinaryOperator::LessOrEqual |
+                BinaryOperator::Greater | BinaryOperator::GreaterOrEqual |
+                BinaryOperator::AlmostEqual | BinaryOperator::NotAlmostEqual => "bool",
+                BinaryOperator::Add => {
+                    let (left, right) = (self.type_of(*left)?, self.type_of(*right)?);
+                    if left == "num" && right == "num" { "num" } else { return None; }
+                },
+                BinaryOperator::And | BinaryOperator::Or | BinaryOperator::NullishOr |
+                BinaryOperator::Repeat => return None,
+                _ => "num",
+            },
+            AstNode::Do(_, _, last) => return self.type_of(*last),
+            AstNode::IfElse(_, _, then_idx, else_idx) => {
+                let then_type = self.type_of(*then_idx)?;
+                return if self.type_of(*else_idx)? == then_type { Some(then_type) } else { None };
+            },
+            AstNode::LocalLet(_, _, _, next_idx) | AstNode::GlobalLet(_, _, _, next_idx) => {
+                return self.type_of(*next_idx);
+            },
+            AstNode::GlobalValueReference(_, name) | AstNode::LocalValueReference(_, name) => {
+                return self.binding(name)?.type_name.clone();
+            },
+            _ => return None,
+        };
+        return Some(name.to_owned());
+    }
+
+    fn check(&mut self, node_idx: usize) {
+        if node_idx >= self.parser.ast.len() {
+            return;
+        }
+        match &self.parser.ast[node_idx] {
+            AstNode::LocalLet(_, name, value_idx, next_idx) | AstNode::GlobalLet(_, name, value_idx, next_idx) => {
+                self.check(*value_idx);
+                if let Some(expected) = self.parser.let_types.get(value_idx) {
+                    self.expect(*value_idx, expected, &format!("'{}'", name));
+                }
+                let scope_len = self.scope.len();
+                self.bind(name, *value_idx);
+                self.check(*next_idx);
+                if matches!(self.parser.ast[node_idx], AstNode::LocalLet(..)) {
+                    self.scope.truncate(scope_len);
+                }
+            },
+            AstNode::LocalSet(_, target_idx, value_idx) | AstNode::GlobalSet(_, target_idx, value_idx) => {
+                self.check(*value_idx);
+                if let AstNode::GlobalValueReference(_, name) | AstNode::LocalValueReference(_, name) = &self.parser.ast[*target_idx] {
+                    if let Some(expected) = self.binding(name).and_then(|binding| binding.type_name.clone()) {
+                        self.expect(*value_idx, &expected, &format!("'{}'", name));
+                    }
+                }
+            },
+            AstNode::FunctionCall(_, name, args) => {
+                for arg_idx in args {
+                    self.check(*arg_idx);
+                }
+                if let Some(params) = self.function_args(name) {
+                    for (arg_idx, param) in args.iter().zip(params.iter()) {
+                        if let Some(expected) = &param.type_name {
+                            self.expect(*arg_idx, expected, &format!("the argument '{}' of '{}'", param.name, name));
+                        }
+                    }
+                }
+            },
+            AstNode::FunctionDef(_, args, body_idx) => {
+                let scope_len = self.scope.len();
+                for arg in args {
+                    self.scope.push(Binding {
+                        name: arg.name.clone(),
+                        type_name: arg.type_name.clone(),
+                        args: None,
+                    });
+                }
+                self.check(*body_idx);
+                self.scope.truncate(scope_len);
+            },
+            node => {
+                for child_idx in node.children() {
+                    self.check(child_idx);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NopeConfig;
+
+    fn type_errors(code: &str) -> Vec<String> {
+        let mut parser = Parser::new(NopeConfig::new(), code.to_owned());
+        parser.parse();
+        assert!(!parser.failed(), "{}", code);
+        return check_types(&parser).into_iter().map(|(_, message)| message).collect();
+    }
+
+    #[test]
+    fn test_check_types() {
+        assert_eq!(type_errors("let x:num = 1 + 2\nlet y:bool = x > 1\nlet z:str = to_str(x)"), Vec::<String>::new());
+        assert_eq!(type_errors("let x:num = 'three'"), vec!["ERROR: 'x' should be a num but this is a str"]);
+        assert_eq!(type_errors("var x:str = 'a'\nset x = 3"), vec!["ERROR: 'x' should be a str but this is a num"]);
+        assert_eq!(
+            type_errors("let f = |a:num b| a\nlet n:str = 'x'\nf(n, 2)"),
+            vec!["ERROR: the argument 'a' of 'f' should be a num but this is a str"],
+        );
+        // the arguments are only known inside of the function
+        assert_eq!(type_errors("let f = |a:num| a\nlet a = 'x'\nf(3)"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_typecheck_config() {
+        let config = NopeConfig { typecheck: true, ..NopeConfig::new() };
+        let mut parser = Parser::new(config, "let x:num = true".to_owned());
+        parser.parse();
+        assert!(parser.failed());
+        let mut parser = Parser::new(NopeConfig::new(), "let x:num = true".to_owned());
+        parser.parse();
+        assert!(!parser.failed());
+    }
+}
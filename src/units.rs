@@ -1,119 +1,322 @@
 use std::f64;
 
-pub fn convert_si_to_unit(num:f64, unit:&str) -> Option<f64> {
-    match convert_unit_to_si(1.0, unit) {
-        Some(factor) => { 
-            if unit == "F" {
-                Some((num - 273.15) * (9.0/5.0) + 32.0)
-            } else if unit == "C" {
-                Some(num - 273.15)
-            } else {
-                Some(num / factor)
+// The powers of the length, mass, time, temperature and data that a measure is made of,
+// a speed is a length divided by a time
+type Dimension = [i32; 5];
+
+const NUMBER: Dimension = [0, 0, 0, 0, 0];
+
+// A measure, its dimension and its units with their factor
+type UnitGroup = (&'static str, Dimension, &'static [(&'static str, f64)]);
+
+// The units known by convert_unit_to_si, grouped by what they measure, with the number of
+// SI units in one of them. The temperatures are not just a factor, F and C are converted
+// by convert_unit_to_si and convert_si_to_unit themselves.
+pub const UNIT_GROUPS: [UnitGroup; 17] = [
+    ("length", [1, 0, 0, 0, 0], &[
+        ("in", 0.0254), ("km", 1000.0), ("m", 1.0), ("dm", 0.1), ("cm", 0.01), ("mm", 0.001),
+        ("um", 0.000001), ("nm", 0.000000001), ("mile", 1609.344), ("miles", 1609.344), ("ft", 0.3048),
+        ("yd", 0.9144), ("nmi", 1852.0),
+    ]),
+    ("area", [2, 0, 0, 0, 0], &[
+        ("m2", 1.0), ("dm2", 0.01), ("cm2", 0.0001), ("mm2", 0.000001), ("a", 100.0), ("ha", 10000.0),
+        ("km2", 1000000.0), ("mile2", 2589988.110336), ("yd2", 0.83612736), ("sqyd", 0.83612736),
+        ("ft2", 0.09290304), ("sqft", 0.09290304), ("in2", 0.00064516), ("sqin", 0.00064516),
+        ("belgium", 30688000000.0), ("footballfield", 6000.0),
+    ]),
+    ("volume", [3, 0, 0, 0, 0], &[
+        ("m3", 1.0), ("l", 0.001), ("dm3", 0.001), ("dl", 0.0001), ("cl", 0.00001), ("ml", 0.000001),
+        ("cm3", 0.000001), ("barrel", 0.158987294928), ("cuft", 0.028316846592), ("ft3", 0.028316846592),
+        ("gal", 0.003785411784), ("pint", 0.000473176473), ("cuin", 0.000016387064), ("in3", 0.000016387064),
+        ("cuyd", 0.764554857984), ("yd3", 0.764554857984),
+    ]),
+    ("mass", [0, 1, 0, 0, 0], &[
+        ("GT", 1000000000000.0), ("MT", 1000000000.0), ("kT", 1000000.0), ("T", 1000.0), ("kg", 1.0),
+        ("g", 0.001), ("mg", 0.000001), ("ug", 0.000000001), ("ng", 0.000000000001), ("lb", 0.45359237),
+        ("oz", 0.028349523125),
+    ]),
+    ("time", [0, 0, 1, 0, 0], &[
+        ("d", 86400.0), ("h", 3600.0), ("min", 60.0), ("s", 1.0), ("ms", 0.001), ("us", 0.000001),
+        ("ns", 0.000000001), ("moon", 2551442.976),
+    ]),
+    ("speed", [1, 0, -1, 0, 0], &[("m/s", 1.0), ("km/h", 1.0 / 3.6), ("kmh", 1.0 / 3.6), ("mph", 0.44704)]),
+    ("force", [1, 1, -2, 0, 0], &[("N", 1.0), ("kN", 1000.0)]),
+    ("pressure", [-1, 1, -2, 0, 0], &[("Pa", 1.0), ("bar", 100000.0), ("psi", 6894.757293168361)]),
+    ("power", [2, 1, -3, 0, 0], &[("W", 1.0), ("kW", 1000.0)]),
+    ("energy", [2, 1, -2, 0, 0], &[
+        ("J", 1.0), ("kJ", 1000.0), ("Wh", 3600.0), ("kWh", 3600000.0), ("cal", 4.184), ("kcal", 4184.0),
+    ]),
+    ("temperature", [0, 0, 0, 1, 0], &[("F", 5.0 / 9.0), ("C", 1.0), ("K", 1.0)]),
+    ("angle", NUMBER, &[("deg", f64::consts::PI / 180.0), ("rad", 1.0)]),
+    ("data", [0, 0, 0, 0, 1], &[
+        ("TiB", 1099511627776.0), ("TB", 1099511627776.0), ("GiB", 1073741824.0), ("GB", 1073741824.0),
+        ("MiB", 1048576.0), ("MB", 1048576.0), ("KiB", 1024.0), ("KB", 1024.0),
+    ]),
+    ("binary", NUMBER, &[("Ti", 1099511627776.0), ("Gi", 1073741824.0), ("Mi", 1048576.0), ("ki", 1024.0)]),
+    ("count", NUMBER, &[
+        ("million", 1000000.0), ("billion", 1000000000.0), ("trillion", 1000000000000.0),
+        ("quadrillon", 1000000000000000.0), ("milli", 0.001), ("thousandth", 0.001), ("micro", 0.000001),
+        ("nano", 0.000000001), ("pico", 0.000000000001),
+    ]),
+    ("ratio", NUMBER, &[("%", 0.01), ("percent", 0.01)]),
+    ("constant", NUMBER, &[("pi", f64::consts::PI), ("tau", f64::consts::TAU), ("phi", 1.618033988749894)]),
+];
+
+pub fn unit_names() -> impl Iterator<Item = &'static str> {
+    return UNIT_GROUPS.iter().flat_map(|(_, _, units)| units.iter().map(|(name, _)| *name));
+}
+
+// What a unit of the table measures, like "length"
+fn unit_measure(unit: &str) -> Option<&'static str> {
+    return UNIT_GROUPS.iter()
+        .find(|(_, _, units)| units.iter().any(|(name, _)| *name == unit))
+        .map(|(measure, _, _)| *measure);
+}
+
+fn table_unit(unit: &str) -> Option<(f64, Dimension)> {
+    for (_, dimension, units) in UNIT_GROUPS.iter() {
+        if let Some((_, factor)) = units.iter().find(|(name, _)| *name == unit) {
+            return Some((*factor, *dimension));
+        }
+    }
+    return None;
+}
+
+// A unit of the table raised to the power of the digit after it, like s2. F and C can't
+// be part of a compound unit as they are not just a factor
+fn unit_power(unit: &str) -> Option<(f64, Dimension)> {
+    let name = unit.trim_end_matches(|c: char| c.is_ascii_digit());
+    if name == "F" || name == "C" {
+        return None;
+    } else if let Some(found) = table_unit(unit) {
+        return Some(found);
+    }
+    let power: i32 = unit[name.len()..].parse().ok()?;
+    if power > 9 {
+        return None;
+    }
+    let (factor, dimension) = table_unit(name)?;
+    return Some((factor.powi(power), dimension.map(|exponent| exponent * power)));
+}
+
+// The factor and dimension of a unit of the table, or of a compound unit like km/h, m/s2
+// or N·m: units multiplied with `·`, dividing everything after a `/`, and raised to a power
+// with a digit after their name
+fn resolve_unit(unit: &str) -> Option<(f64, Dimension)> {
+    if let Some(found) = table_unit(unit) {
+        return Some(found);
+    }
+    let (numerator, denominator) = match unit.split_once('/') {
+        Some((_, "")) => return None,
+        Some(parts) => parts,
+        None => (unit, ""),
+    };
+    let mut factor = 1.0;
+    let mut dimension = NUMBER;
+    if numerator.is_empty() {
+        return None;
+    }
+    for (part, sign) in [(numerator, 1), (denominator, -1)] {
+        if part.is_empty() {
+            continue;
+        }
+        for name in part.split(['·', '/']) {
+            let (name_factor, name_dimension) = unit_power(name)?;
+            factor *= name_factor.powi(sign);
+            for (exponent, name_exponent) in dimension.iter_mut().zip(name_dimension) {
+                *exponent += sign * name_exponent;
             }
-        },
-        None => None,
-    }
-}
-
-pub fn convert_unit_to_si(mut num:f64, unit:&str) -> Option<f64> {
-    match unit {
-        "pi" => {num *= f64::consts::PI},
-        "tau" => {num *= f64::consts::PI*2.0},
-        "phi" => {num *= 1.618033988749894},
-        "GT" => {num *= 1000000000000.0},
-        "MT" => {num *= 1000000000.0},
-        "kT" => {num *= 1000000.0},
-        "T" => {num *= 1000.0},
-        "kg" => {},
-        "g" => {num *= 0.001},
-        "mg" => {num *= 0.000001},
-        "ug" => {num *= 0.000000001},
-        "ng" => {num *= 0.000000000001},
-        "Ti" => {num *= 1024.0 * 1024.0 * 1024.0 * 1024.0},
-        "Gi" => {num *= 1024.0 * 1024.0 * 1024.0},
-        "Mi" => {num *= 1024.0 * 1024.0},
-        "ki" => {num *= 1024.0},
-        "d" => {num *= 60.0 * 60.0 * 24.0},
-        "h" => {num *= 60.0 * 60.0},
-        "min" => {num *= 60.0},
-        "s" => {},
-        "ms" => {num *= 0.001},
-        "us" => {num *= 0.000001},
-        "ns" => {num *= 0.000000001},
-        "moon" => { num *= 2551442.976 },
-        "deg" => {num *= std::f64::consts::PI / 180.0},
-        "rad" => {},
-        "in" => {num *= 0.024},
-        "km" => {num *= 1000.0},
-        "m" => {},
-        "dm" => {num *= 0.1},
-        "cm" => {num *= 0.01},
-        "mm" => {num *= 0.001},
-        "um" => {num *= 0.000001},
-        "nm" => {num *= 0.000000001},
-        "lb" => {num *= 0.453592},
-        "oz" => {num *= 0.0283495},
-        "mile" => {num *= 1609.34},
-        "miles" => {num *= 1609.34},
-        "ft" => {num *= 0.3048},
-        "yd" => {num *= 0.9144},
-        "F" => {num = ((num - 32.0) * 5.0 / 9.0) + 273.15},
-        "C" => {num += 273.15},
-        "K" => {},
-        "m3" => {},
-        "l" => {num *= 0.0001},
-        "dm3" => {num *= 0.0001},
-        "dl" => {num *= 0.00001},
-        "cl" => {num *= 0.000001},
-        "ml" => {num *= 0.0000001},
-        "cm3" => {num *= 0.0000001},
-        "barrel" => {num *= 0.158987294928},
-        "cuft" => {num *= 0.028},
-        "ft3" => {num *= 0.028},
-        "gal" => {num *= 0.003785411784},
-        "pint" => {num *= 0.000473176473},
-        "cuin" => {num *= 0.000016387064},
-        "in3" => {num *= 0.000016387064},
-        "cuyd" => {num *= 0.7645549},
-        "yd3" => {num *= 0.7645549},
-        "m2" => {},
-        "dm2" => {num *= 0.01},
-        "cm2" => {num *= 0.0001},
-        "mm2" => {num *= 0.000001},
-        "a" => {num *= 100.0},
-        "ha" => {num *= 100000.0},
-        "km2" => {num *= 1000000.0},
-        "mile2" => {num *= 2589975.23456},
-        "yd2" => {num *= 0.836127},
-        "sqyd" => {num *= 0.836127},
-        "ft2" => {num *= 0.092903},
-        "sqft" => {num *= 0.092903},
-        "in2" => {num *= 0.00064516},
-        "sqin" => {num *= 0.00064516},
-        "belgium" => {num *= 30688000000.0},
-        "footballfield" => {num *= 6000.0},
-        // bytes
-        "TiB" => {num *= 1024.0 * 1024.0 * 1024.0 * 1024.0},
-        "TB" => {num *= 1024.0 * 1024.0 * 1024.0 * 1024.0},
-        "GiB" => {num *= 1024.0 * 1024.0 * 1024.0},
-        "GB" => {num *= 1024.0 * 1024.0 * 1024.0},
-        "MiB" => {num *= 1024.0 * 1024.0},
-        "MB" => {num *= 1024.0 * 1024.0},
-        "KiB" => {num *= 1024.0},
-        "KB" => {num *= 1024.0},
-        "million" => {num *= 1000.0 * 1000.0 },
-        "billion" => {num *= 1000.0 * 1000.0 * 1000.0},
-        "trillion" => {num *= 1000.0 * 1000.0 * 1000.0 * 1000.0},
-        "quadrillon" => {num *= 1000.0 * 1000.0 * 1000.0 * 1000.0 * 1000.0},
-        "milli" => {num *= 0.001 },
-        "thousandth" => {num *= 0.001 },
-        "micro" => {num *= 0.000001},
-        "nano" => {num *= 0.000000001},
-        "pico" => {num *= 0.000000000001},
+        }
+    }
+    return Some((factor, dimension));
+}
 
+// True if the text is a unit of the table or a compound unit made of them
+pub fn is_known_unit(unit: &str) -> bool {
+    return resolve_unit(unit).is_some();
+}
+
+// The number of characters to insert, remove or replace to go from a to b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    return previous[b.len()];
+}
+
+// The known unit that an unknown one was most likely meant to be: the same letters in
+// another case, or else the closest one by a typo or two for the longer names
+pub fn closest_unit(unit: &str) -> Option<&'static str> {
+    if let Some(name) = unit_names().find(|name| name.eq_ignore_ascii_case(unit)) {
+        return Some(name);
+    }
+    let max_distance = if unit.chars().count() <= 3 { 1 } else { 2 };
+    return unit_names()
+        .map(|name| (edit_distance(unit, name), name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name);
+}
+
+// The error message of an unknown unit, with the unit it is close to
+pub fn unknown_unit_message(unit: &str) -> String {
+    return match closest_unit(unit) {
+        Some(name) => format!("unknown unit '{}', did you mean '{}'?", unit, name),
+        None => format!("unknown unit '{}'", unit),
+    };
+}
+
+// A number of bytes with the largest binary prefix below it, like 1.4Gi or 356ki, with a
+// decimal under 10 of a prefix
+pub fn to_human_bytes(bytes: f64) -> String {
+    let sign = if bytes < 0.0 { "-" } else { "" };
+    let mut size = bytes.abs();
+    let mut prefix = 0;
+    let prefixes = ["", "ki", "Mi", "Gi", "Ti"];
+    while size >= 1024.0 && prefix < prefixes.len() - 1 {
+        size /= 1024.0;
+        prefix += 1;
+    }
+    let mut rounded = if size < 10.0 { (size * 10.0).round() / 10.0 } else { size.round() };
+    if rounded >= 1024.0 && prefix < prefixes.len() - 1 {
+        rounded = 1.0;
+        prefix += 1;
+    }
+    return format!("{}{}{}", sign, rounded, prefixes[prefix]);
+}
+
+// The number of bytes of a size like 1.4Gi, 356 KiB or 512
+pub fn from_human_bytes(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let split = text.find(|c: char| c.is_alphabetic()).unwrap_or(text.len());
+    let num: f64 = text[..split].trim().parse().ok()?;
+    let unit = &text[split..];
+    if unit.is_empty() {
+        return Some(num);
+    } else if !matches!(unit_measure(unit), Some("data") | Some("binary")) {
+        return None;
+    }
+    return convert_unit_to_si(num, unit);
+}
+
+fn unknown_unit(unit: &str) -> String {
+    let names: Vec<&str> = unit_names().collect();
+    return format!("{}, the known units are {}", unknown_unit_message(unit), names.join(", "));
+}
+
+// Converts a number from a unit to another, like 30 C to F. The units must measure the
+// same thing, unless one of them is just a number like `million` or `%`
+pub fn convert_units(num: f64, from: &str, to: &str) -> Result<f64, String> {
+    let (_, from_dimension) = resolve_unit(from).ok_or_else(|| unknown_unit(from))?;
+    let (_, to_dimension) = resolve_unit(to).ok_or_else(|| unknown_unit(to))?;
+    let numbers = ["count", "ratio", "constant"];
+    match (unit_measure(from), unit_measure(to)) {
+        (Some(from_measure), Some(to_measure)) => {
+            if from_measure != to_measure && !numbers.contains(&from_measure) && !numbers.contains(&to_measure) {
+                return Err(format!("cannot convert '{}' to '{}', a {} is not a {}", from, to, from_measure, to_measure));
+            }
+        },
         _ => {
-            return None;
+            if from_dimension != to_dimension && from_dimension != NUMBER && to_dimension != NUMBER {
+                return Err(format!("cannot convert '{}' to '{}', they don't measure the same thing", from, to));
+            }
+        },
+    }
+    let si = convert_unit_to_si(num, from).ok_or_else(|| unknown_unit(from))?;
+    return convert_si_to_unit(si, to).ok_or_else(|| unknown_unit(to));
+}
+
+pub fn convert_si_to_unit(num: f64, unit: &str) -> Option<f64> {
+    return match unit {
+        "F" => Some((num - 273.15) * (9.0 / 5.0) + 32.0),
+        "C" => Some(num - 273.15),
+        "%" | "percent" => Some(num * 100.0),
+        _ => Some(num / resolve_unit(unit)?.0),
+    };
+}
+
+pub fn convert_unit_to_si(num: f64, unit: &str) -> Option<f64> {
+    return match unit {
+        "F" => Some(((num - 32.0) * 5.0 / 9.0) + 273.15),
+        "C" => Some(num + 273.15),
+        "%" | "percent" => Some(num / 100.0),
+        _ => Some(num * resolve_unit(unit)?.0),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f64, expected: f64, what: &str) {
+        assert!((value - expected).abs() <= 1e-6 * expected.abs().max(1.0), "{}: {} instead of {}", what, value, expected);
+    }
+
+    #[test]
+    fn test_convert_units_both_ways() {
+        for (num, from, expected, to) in [
+            (1.0, "in", 2.54, "cm"), (1.0, "ft", 12.0, "in"), (1.0, "mile", 5280.0, "ft"), (1.0, "yd", 3.0, "ft"),
+            (1.0, "nmi", 1852.0, "m"), (100.0, "km/h", 62.137119, "mph"), (1.0, "mph", 1.609344, "kmh"),
+            (36.0, "kmh", 10.0, "m/s"), (1.0, "bar", 14.503774, "psi"), (1.0, "kWh", 3600000.0, "J"),
+            (1.0, "kWh", 860.420650, "kcal"), (1.0, "cal", 4.184, "J"), (1.0, "Wh", 3.6, "kJ"), (1.0, "kW", 1000.0, "W"),
+            (1.0, "lb", 16.0, "oz"), (1.0, "gal", 8.0, "pint"), (1.0, "l", 1000.0, "ml"), (1.0, "dm3", 1.0, "l"),
+            (1.0, "ha", 100.0, "a"), (1.0, "ha", 10000.0, "m2"), (1.0, "ft3", 1728.0, "in3"), (1.0, "yd3", 27.0, "ft3"),
+            (1.0, "mile2", 3097600.0, "yd2"), (1.0, "sqft", 144.0, "sqin"), (212.0, "F", 100.0, "C"), (-40.0, "F", -40.0, "C"),
+            (0.0, "C", 273.15, "K"), (1.0, "d", 24.0, "h"), (180.0, "deg", f64::consts::PI, "rad"), (1.0, "GiB", 1024.0, "MiB"),
+            (50.0, "%", 50.0, "percent"), (0.5, "million", 50000000.0, "%"), (3.0, "million", 3000000.0, "kg"),
+        ] {
+            let what = format!("{} {} to {}", num, from, to);
+            assert_close(convert_units(num, from, to).unwrap(), expected, &what);
+            assert_close(convert_units(expected, to, from).unwrap(), num, &what);
+        }
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        for (bytes, text) in [(0.0, "0"), (512.0, "512"), (1024.0, "1ki"), (364544.0, "356ki"), (1503238553.6, "1.4Gi"),
+                              (1048575.0, "1Mi"), (10.0 * 1099511627776.0, "10Ti"), (5e15, "4547Ti"), (-2048.0, "-2ki")] {
+            assert_eq!(to_human_bytes(bytes), text);
+        }
+        for (text, bytes) in [("512", 512.0), ("1ki", 1024.0), ("1.5 Mi", 1572864.0), ("2GiB", 2147483648.0), (" 3KB ", 3072.0)] {
+            assert_eq!(from_human_bytes(text), Some(bytes), "{}", text);
+        }
+        for text in ["", "Gi", "2km", "1.4.2Gi", "abc"] {
+            assert_eq!(from_human_bytes(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_compound_units() {
+        for (num, from, expected, to) in [
+            (36.0, "km/h", 10.0, "m/s"), (1.0, "g/cm3", 1000.0, "kg/m3"), (9.81, "m/s2", 35.316, "km/h/s"),
+            (1.0, "N·m", 1.0, "J"), (1.0, "kWh", 3600000.0, "N·m"), (1.0, "N/m2", 1.0, "Pa"), (1.0, "kg·m/s2", 1.0, "N"),
+            (60.0, "mile/h", 1.0, "mile/min"), (1.0, "km3", 1000000000.0, "m3"),
+        ] {
+            let what = format!("{} {} to {}", num, from, to);
+            assert_close(convert_units(num, from, to).unwrap(), expected, &what);
+            assert_close(convert_units(expected, to, from).unwrap(), num, &what);
+        }
+        for unit in ["km/", "/h", "km/x", "C/s", "m·", "s99", "m//s"] {
+            assert!(!is_known_unit(unit), "{}", unit);
+        }
+        assert_eq!(convert_units(1.0, "m/s2", "km/h"), Err("cannot convert 'm/s2' to 'km/h', they don't measure the same thing".to_owned()));
+    }
+
+    #[test]
+    fn test_convert_units_errors() {
+        assert_eq!(convert_units(1.0, "kg", "m"), Err("cannot convert 'kg' to 'm', a mass is not a length".to_owned()));
+        assert!(convert_units(1.0, "parsec", "m").unwrap_err().starts_with("unknown unit 'parsec', the known units are in, km, m"));
+        assert!(convert_units(1.0, "m", "Km").unwrap_err().starts_with("unknown unit 'Km', did you mean 'km'?"));
+        let names: Vec<&str> = unit_names().collect();
+        for (idx, name) in names.iter().enumerate() {
+            assert!(!names[..idx].contains(name), "{} is defined twice", name);
         }
     }
-    return Some(num);
 }
@@ -1,6 +1,13 @@
 use rand::Rng;
-use std::time::SystemTime;
-use std::path::Path;
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::mem;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
 use crate::{
     consts::EPSILON,
     parser::{
@@ -15,13 +22,22 @@ use crate::{
     },
     penv::{
         Env,
+        EnvEntry,
     },
-    stdlib::Stdlib,
+    stdlib::{Stdlib, StdlibCall},
     config::NopeConfig,
+    sandbox::{check_fs_access, check_net_access},
+    http::{read_request, format_response},
+    clipboard::{get_clipboard, set_clipboard},
+    terminal,
+    cli,
+    natives::floor_mod,
     chunk::{
         Value,
         Chunk,
+        Function,
         Instruction,
+        MemoKey,
         GlobalsTable,
         LocalsTable,
         LoopsTable,
@@ -30,6 +46,7 @@ use crate::{
         Gc,
         GcRef,
     },
+    objects::{StringBuffer, RunningStats, Thunk, Generator, Task, Channel, Socket, LineReader, FileWatch, HttpRequest, ProgressBar, Drawing},
 };
 
 use colored::*;
@@ -38,42 +55,326 @@ use colored::*;
 pub enum InterpretResult {
     Ok,
     CompileError,
-    _RuntimeError,
+    RuntimeError,
+}
+
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    function: Option<usize>,
+    ip: usize,
+    base: usize,
+    on_return: Option<ReturnAction>,
+}
+
+impl CallFrame {
+    pub fn trace(&self, gc: &mut Gc) {
+        if let Some(action) = &self.on_return {
+            action.trace(gc);
+        }
+    }
+}
+
+// What to do with the result of a call besides returning it
+#[derive(Debug, Clone)]
+enum ReturnAction {
+    Memo(Vec<MemoKey>), // cache it in the called memo function
+    Force(GcRef<Thunk>), // keep it in the forced thunk
+    Generator(GcRef<Generator>), // the resumed generator is done
+    Task(GcRef<Task>), // the resumed task is done
+    Listen(GcRef<Socket>, GcRef<Socket>), // the connection was handled, the server stops on false
+    Serve(GcRef<Socket>, GcRef<HttpRequest>), // the result is the response to the request
+    EachLine(GcRef<LineReader>), // the line was handled, the file is closed on false
+    Watch(GcRef<FileWatch>), // the change was handled, the watch stops on false
+    Signal(usize), // the signal was handled, the program stops
+}
+
+impl ReturnAction {
+    fn trace(&self, gc: &mut Gc) {
+        match self {
+            ReturnAction::Memo(_) => {},
+            ReturnAction::Force(thunk_ref) => gc.mark_object(*thunk_ref),
+            ReturnAction::Generator(gen_ref) => gc.mark_object(*gen_ref),
+            ReturnAction::Task(task_ref) => gc.mark_object(*task_ref),
+            ReturnAction::Listen(listener_ref, socket_ref) => {
+                gc.mark_object(*listener_ref);
+                gc.mark_object(*socket_ref);
+            },
+            ReturnAction::Serve(listener_ref, request_ref) => {
+                gc.mark_object(*listener_ref);
+                gc.mark_object(*request_ref);
+            },
+            ReturnAction::EachLine(lines_ref) => gc.mark_object(*lines_ref),
+            ReturnAction::Watch(watch_ref) => gc.mark_object(*watch_ref),
+            ReturnAction::Signal(_) => {},
+        }
+    }
+}
+
+// The global functions defined again by a submission with another number of
+// arguments than in the environment it was parsed with
+fn redefined_functions(parser: &Parser, previous_env: &Env) -> Vec<(String, usize)> {
+    let mut redefined = vec![];
+    for node in &parser.ast {
+        if let AstNode::GlobalLet(_, name, value_idx, _) = node {
+            let def_idx = match parser.ast[*value_idx] {
+                AstNode::Memo(_, def_idx) => def_idx,
+                _ => *value_idx,
+            };
+            if let AstNode::FunctionDef(_, args, _) = &parser.ast[def_idx] {
+                if let Some(previous) = previous_env.get_entry(name) {
+                    if previous.is_global && previous.is_func && previous.func_args.len() != args.len() {
+                        redefined.push((name.to_owned(), args.len()));
+                    }
+                }
+            }
+        }
+    }
+    return redefined;
+}
+
+fn calls_with_other_arity(ast: &[AstNode], node_idx: usize, name: &str, arity: usize) -> bool {
+    if let AstNode::FunctionCall(_, callee, args) = &ast[node_idx] {
+        if callee == name && args.len() != arity {
+            return true;
+        }
+    }
+    return ast[node_idx].children().into_iter().any(|child_idx| calls_with_other_arity(ast, child_idx, name, arity));
+}
+
+fn now_ms() -> f64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64() * 1000.0).unwrap_or(0.0);
+}
+
+const WATCH_POLL_MS: u64 = 100;
+
+// The latest modification time under a path and how many files and directories
+// are there, so that a removed file is a change too. None when the path doesn't exist.
+fn watched_state(path: &Path) -> Option<(SystemTime, usize)> {
+    return tree_state(path, std::fs::metadata(path).ok()?);
+}
+
+// The links inside the watched directory are not followed, a link to a parent
+// directory would never end
+fn tree_state(path: &Path, metadata: std::fs::Metadata) -> Option<(SystemTime, usize)> {
+    let mut latest = metadata.modified().ok()?;
+    let mut count = 1;
+    if metadata.is_dir() {
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                let entry_state = std::fs::symlink_metadata(entry.path()).ok().and_then(|metadata| tree_state(&entry.path(), metadata));
+                if let Some((modified, entry_count)) = entry_state {
+                    latest = latest.max(modified);
+                    count += entry_count;
+                }
+            }
+        }
+    }
+    return Some((latest, count));
+}
+
+fn sleep_ms(ms: f64) {
+    if ms > 0.0 {
+        thread::sleep(Duration::from_secs_f64(ms / 1000.0));
+    }
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    return json;
 }
 
 pub struct Vm {
     parsers: Vec<Parser>,
     config: NopeConfig,
-    gc: Gc,
+    pub gc: Gc,
     stdlib: Stdlib,
     globals: GlobalsTable,
     locals: LocalsTable,
     loops: LoopsTable,
     chunk: Chunk,
+    functions: Vec<Function>,
+    static_functions: Vec<(String, usize)>,
+    frames: Vec<CallFrame>,
+    function: Option<usize>,
+    base: usize,
     stack: Vec<Value>,
     ip: usize,
+    tracing: bool,
     rng: rand::rngs::ThreadRng,
+    last_result: Value,
+    kept_results: usize, // how many REPL results were bound to `_1`, `_2`...
+    tasks: Vec<GcRef<Task>>, // the unfinished tasks, in the order they are scheduled
+    tests: Vec<TestBlock>, // the test blocks compiled with --test, not yet run
+    step_limit: Option<usize>, // instructions of a submission before asking to keep running
+    steps: usize,
+    temp_paths: Vec<PathBuf>, // made by temp_file and temp_dir, removed when the vm is dropped
+    signal_handlers: [Option<usize>; terminal::SIGNAL_NAMES.len()], // the functions given to on_signal
+    handling_signal: bool,
+    parsed_args: Option<cli::ParsedArgs>, // the options found by parse_args
+}
+
+impl Drop for Vm {
+    fn drop(&mut self) {
+        self.remove_temp_paths();
+    }
+}
+
+// A `test 'name' expr` block, its expression is compiled as a function
+// that is called by run_tests after the program
+struct TestBlock {
+    name: String,
+    function: usize,
+    parser: usize,
+    node: usize,
 }
 
 impl Vm {
     pub fn new (config: NopeConfig) -> Vm {
+        let mut gc = Gc::new();
+        gc.set_memory_limit(config.memory_limit);
+        gc.set_log(config.gc_log);
+        let tracing = config.trace;
         return Vm {
             parsers: vec![],
-            gc: Gc::new(),
+            gc,
             globals: GlobalsTable::new(),
             locals: LocalsTable::new(),
             loops: LoopsTable::new(),
             stdlib: Stdlib::new(),
             config,
             chunk: Chunk::new(),
+            functions: vec![],
+            static_functions: vec![],
+            frames: vec![],
+            function: None,
+            base: 0,
             stack: vec![],
             ip: 0,
+            tracing,
             rng: rand::thread_rng(),
+            last_result: Value::Void,
+            kept_results: 0,
+            tasks: vec![],
+            tests: vec![],
+            step_limit: None,
+            steps: 0,
+            temp_paths: vec![],
+            signal_handlers: [None; terminal::SIGNAL_NAMES.len()],
+            handling_signal: false,
+            parsed_args: None,
         };
     }
 
     fn print_trace(&self) {
-        println!("{:<4} {:<24} {:?}", self.ip, format!("{:?}", self.chunk.code[self.ip]), self.stack);
+        let chunk = self.current_chunk();
+        let (line, col) = chunk.positions.get(self.ip).copied().unwrap_or((0, 0));
+        let function = match self.function {
+            Some(idx) => self.functions[idx].name.as_str(),
+            None => "",
+        };
+        println!(
+            "{:>4}:{:<4} {:<10} {:<4} {:<24} {:?}",
+            line, col, function, self.ip, format!("{:?}", chunk.code[self.ip]), self.stack
+        );
+    }
+
+    fn current_line(&self) -> usize {
+        return self.current_chunk().positions.get(self.ip).map(|(line, _)| *line).unwrap_or(0);
+    }
+
+    // The chunk of the function being executed, or the main chunk
+    fn current_chunk(&self) -> &Chunk {
+        match self.function {
+            Some(idx) => &self.functions[idx].chunk,
+            None => &self.chunk,
+        }
+    }
+
+    pub fn config_mut(&mut self) -> &mut NopeConfig {
+        return &mut self.config;
+    }
+
+    pub fn config(&self) -> &NopeConfig {
+        return &self.config;
+    }
+
+    pub fn track_temp_path(&mut self, path: PathBuf) {
+        self.temp_paths.push(path);
+    }
+
+    // Done when the vm is dropped, and before exiting the process from a native
+    pub fn remove_temp_paths(&mut self) {
+        for path in self.temp_paths.drain(..) {
+            if path.is_dir() {
+                let _ = std::fs::remove_dir_all(&path);
+            } else {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+    }
+
+    pub fn parsed_args(&self) -> Option<&cli::ParsedArgs> {
+        return self.parsed_args.as_ref();
+    }
+
+    pub fn set_parsed_args(&mut self, parsed: cli::ParsedArgs) {
+        self.parsed_args = Some(parsed);
+    }
+
+    // The handler is a function taking the name of the signal, or null to go back
+    // to the default behavior
+    pub fn set_signal_handler(&mut self, name: &str, handler: Value) -> Result<(), String> {
+        let idx = terminal::signal_index(name).ok_or_else(|| {
+            format!("on_signal expects one of {}, got '{}'", terminal::SIGNAL_NAMES.join(", "), name)
+        })?;
+        self.signal_handlers[idx] = match handler {
+            Value::Null => None,
+            Value::Function(function) if self.functions[function].arity == 1 && !self.functions[function].generator => Some(function),
+            _ => return Err(format!("on_signal expects a function taking one argument, got {}", self.value_to_repr(&handler))),
+        };
+        if self.signal_handlers[idx].is_some() {
+            terminal::catch_signal(idx);
+        }
+        return Ok(());
+    }
+
+    // A signal received since the last instruction, with the handler to call.
+    // Ctrl-C is always caught, the other signals only once they have a handler.
+    fn take_signal(&self) -> Option<(usize, Option<usize>)> {
+        for (idx, handler) in self.signal_handlers.iter().enumerate() {
+            if (idx == 0 || handler.is_some()) && terminal::take_signal(idx) {
+                return Some((idx, *handler));
+            }
+        }
+        return None;
+    }
+
+    fn is_signal_pending(&self) -> bool {
+        return self.signal_handlers.iter().enumerate()
+            .any(|(idx, handler)| (idx == 0 || handler.is_some()) && terminal::is_signal_pending(idx));
+    }
+
+    // The REPL asks to keep running a submission that executed that many instructions,
+    // so an accidental infinite loop doesn't end the session
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    pub fn stdlib(&self) -> &Stdlib {
+        return &self.stdlib;
     }
 
     pub fn get_copy_of_last_env(&self) -> Option<Env> {
@@ -97,21 +398,83 @@ impl Vm {
     }
 
     fn get_at_depth(&mut self, depth: usize) -> Value {
-        self.stack[depth]
+        self.stack[self.base + depth]
     }
 
     fn set_at_depth(&mut self, depth: usize, value: Value) {
-        self.stack[depth] = value;
+        self.stack[self.base + depth] = value;
     }
 
-    fn intern(&mut self, name: String) -> GcRef<String> {
+    pub fn intern(&mut self, name: String) -> GcRef<String> {
     //    self.mark_and_sweep();
         self.gc.intern(name)
     }
 
-    fn value_to_str(&self, val: &Value) -> String {
+    fn collect_garbage(&mut self) {
+        self.gc.mark_value(self.last_result);
+        for frame in &self.frames {
+            if let Some(action) = &frame.on_return {
+                action.trace(&mut self.gc);
+            }
+        }
+        for task_ref in &self.tasks {
+            self.gc.mark_object(*task_ref);
+        }
+        for value in &self.stack {
+            self.gc.mark_value(*value);
+        }
+        for value in self.globals.values() {
+            self.gc.mark_value(*value);
+        }
+        for value in &self.chunk.constants {
+            self.gc.mark_value(*value);
+        }
+        for function in &self.functions {
+            for value in &function.chunk.constants {
+                self.gc.mark_value(*value);
+            }
+            for value in function.memo.iter().flat_map(|cache| cache.values()) {
+                self.gc.mark_value(*value);
+            }
+        }
+        self.gc.collect_garbage();
+    }
+
+    // Numbers are shown with all their digits, or rounded to the configured number of
+    // significant digits like printf's %g, and with the configured decimal separator
+    pub fn num_to_str(&self, num: f64) -> String {
+        let text = match self.config.float_digits {
+            Some(digits) if num.is_finite() && num != 0.0 => {
+                let exponent = num.abs().log10().floor() as i32;
+                if exponent < -4 || exponent >= digits as i32 {
+                    let text = format!("{:.*e}", digits - 1, num);
+                    match text.split_once('e') {
+                        Some((mantissa, exponent)) if mantissa.contains('.') => {
+                            format!("{}e{}", mantissa.trim_end_matches('0').trim_end_matches('.'), exponent)
+                        },
+                        _ => text,
+                    }
+                } else {
+                    let decimals = (digits as i32 - 1 - exponent).max(0) as usize;
+                    let text = format!("{:.*}", decimals, num);
+                    if text.contains('.') {
+                        text.trim_end_matches('0').trim_end_matches('.').to_owned()
+                    } else {
+                        text
+                    }
+                }
+            },
+            _ => format!("{}", num),
+        };
+        if self.config.decimal_comma {
+            return text.replace('.', ",");
+        }
+        return text;
+    }
+
+    pub fn value_to_str(&self, val: &Value) -> String {
         match val {
-            Value::Num(num) =>  format!("{}", num),
+            Value::Num(num) => self.num_to_str(*num),
             Value::Null => "null".to_string(),
             Value::Void => "_".to_string(),
             Value::Boolean(val) => {
@@ -125,12 +488,31 @@ impl Vm {
                 let val = self.gc.deref(*str_ref);
                 val.to_string() 
             },
+            Value::Buffer(buf_ref) => {
+                self.gc.deref(*buf_ref).text.to_owned()
+            },
+            Value::Stats(stats_ref) => self.stats_to_str(*stats_ref),
+            Value::Lazy(thunk_ref) => match self.gc.deref(*thunk_ref).value {
+                Some(value) => self.value_to_str(&value),
+                None => "<lazy>".to_owned(),
+            },
+            Value::Generator(gen_ref) => self.generator_to_str(*gen_ref),
+            Value::Task(_) => "<task>".to_owned(),
+            Value::Channel(_) => "<channel>".to_owned(),
+            Value::Socket(_) => "<socket>".to_owned(),
+            Value::Lines(_) => "<lines>".to_owned(),
+            Value::Watch(watch_ref) => format!("<watch {}>", self.gc.deref(*watch_ref).path),
+            Value::Process(proc_ref) => format!("<process {}>", self.gc.deref(*proc_ref).child.id()),
+            Value::Request(request_ref) => self.request_to_str(*request_ref),
+            Value::Progress(progress_ref) => self.progress_to_str(*progress_ref),
+            Value::Drawing(drawing_ref) => self.drawing_to_str(*drawing_ref),
+            Value::Function(idx) => self.function_to_str(*idx),
         }
     }
 
-    fn value_to_repr(&self, val: &Value) -> String {
+    pub fn value_to_repr(&self, val: &Value) -> String {
         match val {
-            Value::Num(num) =>  format!("{}", num),
+            Value::Num(num) => self.num_to_str(*num),
             Value::Null => "null".to_string(),
             Value::Void => "_".to_string(),
             Value::Boolean(val) => {
@@ -144,7 +526,488 @@ impl Vm {
                 let val = self.gc.deref(*str_ref);
                 format!("\"{}\"", val.replace('\"', "\\\""))
             },
+            Value::Buffer(buf_ref) => {
+                let val = &self.gc.deref(*buf_ref).text;
+                format!("buf(\"{}\")", val.replace('\"', "\\\""))
+            },
+            Value::Stats(stats_ref) => self.stats_to_str(*stats_ref),
+            Value::Lazy(thunk_ref) => match self.gc.deref(*thunk_ref).value {
+                Some(value) => format!("lazy({})", self.value_to_repr(&value)),
+                None => "<lazy>".to_owned(),
+            },
+            Value::Generator(gen_ref) => self.generator_to_str(*gen_ref),
+            Value::Task(_) => "<task>".to_owned(),
+            Value::Channel(_) => "<channel>".to_owned(),
+            Value::Socket(_) => "<socket>".to_owned(),
+            Value::Lines(_) => "<lines>".to_owned(),
+            Value::Watch(watch_ref) => format!("<watch {}>", self.gc.deref(*watch_ref).path),
+            Value::Process(proc_ref) => format!("<process {}>", self.gc.deref(*proc_ref).child.id()),
+            Value::Request(request_ref) => self.request_to_str(*request_ref),
+            Value::Progress(progress_ref) => self.progress_to_str(*progress_ref),
+            Value::Drawing(drawing_ref) => self.drawing_to_str(*drawing_ref),
+            Value::Function(idx) => self.function_to_str(*idx),
+        }
+    }
+
+    pub fn value_to_json(&self, val: &Value) -> String {
+        match val {
+            Value::Num(num) if num.is_finite() => format!("{}", num),
+            Value::Num(_) | Value::Null | Value::Void | Value::Generator(_) | Value::Task(_) | Value::Channel(_) |
+            Value::Socket(_) | Value::Lines(_) | Value::Watch(_) | Value::Process(_) | Value::Request(_) | Value::Drawing(_) => "null".to_string(),
+            Value::Progress(progress_ref) => self.value_to_json(&Value::Num(self.gc.deref(*progress_ref).current)),
+            Value::Boolean(val) => val.to_string(),
+            Value::String(_) | Value::Buffer(_) | Value::Function(_) => json_string(&self.value_to_str(val)),
+            Value::Lazy(thunk_ref) => match self.gc.deref(*thunk_ref).value {
+                Some(value) => self.value_to_json(&value),
+                None => "null".to_owned(),
+            },
+            Value::Stats(stats_ref) => {
+                let stats = self.gc.deref(*stats_ref);
+                format!(
+                    "{{\"count\": {}, \"mean\": {}, \"std\": {}}}",
+                    stats.count,
+                    self.value_to_json(&Value::Num(stats.mean)),
+                    self.value_to_json(&Value::Num(stats.variance().sqrt())),
+                )
+            },
+        }
+    }
+
+    // The value of the last expression of the last interpreted program
+    pub fn last_result(&self) -> Value {
+        return self.last_result;
+    }
+
+    // Binds the result of the last REPL submission to the next of `_1`, `_2`... and
+    // to `_last`, void results are not kept
+    pub fn keep_result(&mut self) {
+        if matches!(self.last_result, Value::Void) {
+            return;
+        }
+        self.kept_results += 1;
+        for name in [format!("_{}", self.kept_results), "_last".to_owned()] {
+            let slot = self.globals.resolve(&name);
+            self.globals.set(slot, self.last_result);
+            if let Some(parser) = self.parsers.last_mut() {
+                parser.env.push_value_entry(name, true, true);
+            }
+        }
+    }
+
+    fn runtime_error(&mut self, message: &str) -> InterpretResult {
+        println!("{} {}", "runtime error:".red(), message);
+        // the tasks that were running lost their frames, they finish with null
+        for task_ref in mem::take(&mut self.tasks) {
+            let task = self.gc.deref_mut(task_ref);
+            if task.running {
+                task.running = false;
+                task.result = Some(Value::Null);
+            } else {
+                self.tasks.push(task_ref);
+            }
+        }
+        // so did the generators, they can't be resumed anymore
+        for frame in mem::take(&mut self.frames) {
+            if let Some(ReturnAction::Generator(gen_ref)) = frame.on_return {
+                let generator = self.gc.deref_mut(gen_ref);
+                generator.running = false;
+                generator.done = true;
+            }
+        }
+        self.stack.clear();
+        self.frames.clear();
+        self.function = None;
+        self.base = 0;
+        self.ip = self.chunk.code.len();
+        return InterpretResult::RuntimeError;
+    }
+
+    // Operands are truncated to int32. The result wraps around on overflow,
+    // unless checked int arithmetic is enabled, which makes it an error.
+    fn i32_arithmetic(
+        &self,
+        a: Value,
+        b: Value,
+        symbol: &str,
+        checked: fn(i32, i32) -> Option<i32>,
+        wrapping: fn(i32, i32) -> i32,
+    ) -> Result<f64, String> {
+        if !self.config.checked_int {
+            return Ok(wrapping(a.num_equiv() as i32, b.num_equiv() as i32) as f64);
+        }
+        let to_i32 = |value: &Value| {
+            let num = value.num_equiv().trunc();
+            if num >= i32::MIN as f64 && num <= i32::MAX as f64 {
+                Some(num as i32)
+            } else {
+                None
+            }
+        };
+        let (int_a, int_b) = match (to_i32(&a), to_i32(&b)) {
+            (Some(int_a), Some(int_b)) => (int_a, int_b),
+            (None, _) => return Err(format!("operand {} of {} is not an int32", self.value_to_repr(&a), symbol)),
+            (_, None) => return Err(format!("operand {} of {} is not an int32", self.value_to_repr(&b), symbol)),
+        };
+        return match checked(int_a, int_b) {
+            Some(result) => Ok(result as f64),
+            None => Err(format!("int32 overflow in {} {} {}", self.value_to_repr(&a), symbol, self.value_to_repr(&b))),
+        };
+    }
+
+    fn stats_to_str(&self, stats_ref: GcRef<RunningStats>) -> String {
+        let stats = self.gc.deref(stats_ref);
+        format!("stats(count: {}, mean: {}, std: {})", stats.count, stats.mean, stats.variance().sqrt())
+    }
+
+    fn function_to_str(&self, idx: usize) -> String {
+        let function = &self.functions[idx];
+        if function.name.is_empty() {
+            format!("<function/{}>", function.arity)
+        } else {
+            format!("<function {}/{}>", function.name, function.arity)
+        }
+    }
+
+    fn request_to_str(&self, request_ref: GcRef<HttpRequest>) -> String {
+        let request = &self.gc.deref(request_ref).request;
+        return format!("<request {} {}>", request.method, request.path);
+    }
+
+    fn progress_to_str(&self, progress_ref: GcRef<ProgressBar>) -> String {
+        let progress = self.gc.deref(progress_ref);
+        return format!("<progress {}/{}>", progress.current, progress.total);
+    }
+
+    fn drawing_to_str(&self, drawing_ref: GcRef<Drawing>) -> String {
+        let drawing = self.gc.deref(drawing_ref);
+        return format!("<svg {}x{}, {} shapes>", drawing.width, drawing.height, drawing.shapes.len());
+    }
+
+    fn generator_to_str(&self, gen_ref: GcRef<Generator>) -> String {
+        let function = &self.functions[self.gc.deref(gen_ref).function];
+        if function.name.is_empty() {
+            "<generator>".to_owned()
+        } else {
+            format!("<generator {}>", function.name)
+        }
+    }
+
+    // The next task to run, the one that can be resumed the soonest
+    fn next_task(&self) -> Option<GcRef<Task>> {
+        let mut next: Option<(GcRef<Task>, f64)> = None;
+        for task_ref in &self.tasks {
+            let task = self.gc.deref(*task_ref);
+            if task.running || next.is_some_and(|(_, wake_at)| wake_at <= task.wake_at) {
+                continue;
+            }
+            if task.receiving.is_some_and(|chan_ref| self.gc.deref(chan_ref).queue.is_empty()) {
+                continue;
+            }
+            next = Some((*task_ref, task.wake_at));
+        }
+        return next.map(|(task_ref, _)| task_ref);
+    }
+
+    // Runs the task until it pauses or finishes, then execution continues at return_ip
+    fn resume_task(&mut self, task_ref: GcRef<Task>, return_ip: usize) -> Result<(), String> {
+        let task = self.gc.deref(task_ref);
+        if self.frames.len() + task.paused_frames.len() >= self.config.max_call_depth {
+            return Err(format!("maximum call depth of {} exceeded", self.config.max_call_depth));
+        }
+        sleep_ms(task.wake_at - now_ms());
+        self.frames.push(CallFrame {
+            function: self.function,
+            ip: return_ip,
+            base: self.base,
+            on_return: Some(ReturnAction::Task(task_ref)),
+        });
+        let base = self.stack.len();
+        let task = self.gc.deref_mut(task_ref);
+        if task.started {
+            self.stack.append(&mut task.paused_stack);
+            for mut frame in task.paused_frames.drain(..) {
+                frame.base += base;
+                self.frames.push(frame);
+            }
+            self.function = task.paused_function;
+            self.ip = task.paused_ip;
+            self.base = task.paused_base + base;
+        } else {
+            self.function = Some(task.function);
+            self.ip = 0;
+            self.base = base;
+        }
+        task.started = true;
+        task.running = true;
+        task.receiving = None;
+        // the other tasks get their turn before this one runs again
+        self.tasks.retain(|other| *other != task_ref);
+        self.tasks.push(task_ref);
+        return Ok(());
+    }
+
+    // Pauses the innermost running task until wake_at, and goes back to the frame
+    // that resumed it. The paused instruction is executed again when the task resumes.
+    fn pause_task(&mut self, wake_at: f64) -> Option<GcRef<Task>> {
+        let task_frame_idx = self.frames.iter().rposition(|frame| matches!(frame.on_return, Some(ReturnAction::Task(_))))?;
+        let task_base = self.frames.get(task_frame_idx + 1).map_or(self.base, |frame| frame.base);
+        let mut paused_frames = self.frames.split_off(task_frame_idx + 1);
+        for frame in paused_frames.iter_mut() {
+            frame.base -= task_base;
+        }
+        let task_frame = self.frames.pop().unwrap();
+        let task_ref = match task_frame.on_return {
+            Some(ReturnAction::Task(task_ref)) => task_ref,
+            _ => return None,
+        };
+        let paused_stack = self.stack.split_off(task_base);
+        let task = self.gc.deref_mut(task_ref);
+        task.running = false;
+        task.wake_at = wake_at;
+        task.paused_function = self.function;
+        task.paused_ip = self.ip - 1;
+        task.paused_base = self.base - task_base;
+        task.paused_frames = paused_frames;
+        task.paused_stack = paused_stack;
+        self.function = task_frame.function;
+        self.ip = task_frame.ip;
+        self.base = task_frame.base;
+        return Some(task_ref);
+    }
+
+    // The function on top of the stack, which handles each connection, line or
+    // change of `listen`, `serve`, `each_line` and `watch`
+    fn loop_handler(&mut self, name: &str) -> Result<usize, String> {
+        let handler = self.top();
+        return match handler {
+            Value::Function(idx) if self.functions[idx].arity == 1 && !self.functions[idx].generator => Ok(idx),
+            _ => Err(format!("{} expects a function taking one argument, got {}", name, self.value_to_repr(&handler))),
+        };
+    }
+
+    // Calls the handler with arg like a function called by the current instruction,
+    // except that the call returns to that same instruction. When the handler returns,
+    // on_return consumes its result and the instruction runs again, with the handler
+    // still on the stack, and decides whether to call it once more or to finish.
+    fn call_handler(&mut self, handler_idx: usize, arg: Value, on_return: ReturnAction) -> Result<(), String> {
+        if self.frames.len() >= self.config.max_call_depth {
+            return Err(format!("maximum call depth of {} exceeded", self.config.max_call_depth));
+        }
+        self.frames.push(CallFrame {
+            function: self.function,
+            ip: self.ip - 1,
+            base: self.base,
+            on_return: Some(on_return),
+        });
+        self.function = Some(handler_idx);
+        self.ip = 0;
+        self.base = self.stack.len();
+        self.push(arg);
+        return Ok(());
+    }
+
+    // Implements `listen` and `serve`, with the port and the handler on the stack.
+    // The port is replaced by the listening socket, and the handler is called with
+    // each connection.
+    fn handle_next_connection(&mut self, name: &str, http: bool) -> Result<(), String> {
+        let handler_idx = self.loop_handler(name)?;
+        let target = self.stack[self.stack.len() - 2];
+        let listener_ref = match target {
+            Value::Socket(socket_ref) => socket_ref,
+            _ => {
+                let address = format!("127.0.0.1:{}", self.value_to_str(&target));
+                check_net_access(&self.config, &address)?;
+                match TcpListener::bind(&address) {
+                    Ok(listener) => {
+                        let listener_ref = self.gc.alloc(Socket::Listener(listener));
+                        let len = self.stack.len();
+                        self.stack[len - 2] = Value::Socket(listener_ref);
+                        listener_ref
+                    },
+                    Err(e) => {
+                        self.stack.truncate(self.stack.len() - 2);
+                        let ref_err = self.intern(e.to_string());
+                        self.push(Value::String(ref_err));
+                        return Ok(());
+                    }
+                }
+            },
+        };
+        let accepted = match self.gc.deref(listener_ref) {
+            Socket::Listener(listener) => listener.accept(),
+            _ => {
+                // the handler returned false and closed the listener
+                self.stack.truncate(self.stack.len() - 2);
+                self.push(Value::Void);
+                return Ok(());
+            },
+        };
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                self.stack.truncate(self.stack.len() - 2);
+                let ref_err = self.intern(e.to_string());
+                self.push(Value::String(ref_err));
+                return Ok(());
+            },
+        };
+        let (arg, on_return) = if http {
+            let mut reader = BufReader::new(stream);
+            match read_request(&mut reader) {
+                Ok(request) => {
+                    let request_ref = self.gc.alloc(HttpRequest { request, stream: Some(reader.into_inner()) });
+                    (Value::Request(request_ref), ReturnAction::Serve(listener_ref, request_ref))
+                },
+                Err(message) => {
+                    // a malformed request doesn't stop the server
+                    let _ = reader.get_mut().write_all(format_response(400, &message).as_bytes());
+                    self.ip -= 1;
+                    return Ok(());
+                },
+            }
+        } else {
+            let socket_ref = self.gc.alloc(Socket::Stream(BufReader::new(stream)));
+            (Value::Socket(socket_ref), ReturnAction::Listen(listener_ref, socket_ref))
+        };
+        return self.call_handler(handler_idx, arg, on_return);
+    }
+
+    // Implements `each_line`, with the path and the handler on the stack. The path
+    // is replaced by the opened file, and the handler is called with each line so
+    // that the file is never read all at once.
+    fn handle_next_line(&mut self) -> Result<(), String> {
+        let handler_idx = self.loop_handler("each_line")?;
+        let target = self.stack[self.stack.len() - 2];
+        let lines_ref = match target {
+            Value::Lines(lines_ref) => lines_ref,
+            _ => {
+                let path = self.value_to_str(&target);
+                check_fs_access(&self.config, &path)?;
+                match std::fs::File::open(Path::new(&path)) {
+                    Ok(file) => {
+                        let lines_ref = self.gc.alloc(LineReader { reader: Some(BufReader::new(file)) });
+                        let len = self.stack.len();
+                        self.stack[len - 2] = Value::Lines(lines_ref);
+                        lines_ref
+                    },
+                    Err(e) => {
+                        self.stack.truncate(self.stack.len() - 2);
+                        let ref_err = self.intern(e.to_string());
+                        self.push(Value::String(ref_err));
+                        return Ok(());
+                    }
+                }
+            },
+        };
+        let mut line = String::new();
+        let read = match self.gc.deref_mut(lines_ref).reader.as_mut() {
+            Some(reader) => reader.read_line(&mut line),
+            // the handler returned false
+            None => Ok(0),
+        };
+        match read {
+            Ok(0) => {
+                self.gc.deref_mut(lines_ref).reader = None;
+                self.stack.truncate(self.stack.len() - 2);
+                self.push(Value::Void);
+                return Ok(());
+            },
+            Ok(_) => {},
+            Err(e) => {
+                self.gc.deref_mut(lines_ref).reader = None;
+                self.stack.truncate(self.stack.len() - 2);
+                let ref_err = self.intern(e.to_string());
+                self.push(Value::String(ref_err));
+                return Ok(());
+            },
+        }
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let line = line.strip_suffix('\r').unwrap_or(line).to_owned();
+        let ref_line = self.intern(line);
+        return self.call_handler(handler_idx, Value::String(ref_line), ReturnAction::EachLine(lines_ref));
+    }
+
+    // Implements `watch`, with the path and the handler on the stack. The path is
+    // replaced by a FileWatch, and the handler is called with the watched path after
+    // each change.
+    fn handle_next_change(&mut self) -> Result<(), String> {
+        let handler_idx = self.loop_handler("watch")?;
+        let target = self.stack[self.stack.len() - 2];
+        let watch_ref = match target {
+            Value::Watch(watch_ref) => watch_ref,
+            _ => {
+                let path = self.value_to_str(&target);
+                check_fs_access(&self.config, &path)?;
+                let seen = watched_state(Path::new(&path));
+                let watch_ref = self.gc.alloc(FileWatch { path, seen, stopped: false });
+                let len = self.stack.len();
+                self.stack[len - 2] = Value::Watch(watch_ref);
+                watch_ref
+            },
+        };
+        if self.gc.deref(watch_ref).stopped {
+            self.stack.truncate(self.stack.len() - 2);
+            self.push(Value::Void);
+            return Ok(());
         }
+        let path = self.gc.deref(watch_ref).path.to_owned();
+        let seen = self.gc.deref(watch_ref).seen;
+        let mut state = watched_state(Path::new(&path));
+        while state == seen {
+            if self.is_signal_pending() {
+                // executed again once the signal was handled
+                self.ip -= 1;
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(WATCH_POLL_MS));
+            state = watched_state(Path::new(&path));
+        }
+        // an editor or a build often writes several times in a row, the handler
+        // is called once things stopped changing
+        loop {
+            thread::sleep(Duration::from_millis(WATCH_POLL_MS));
+            let next_state = watched_state(Path::new(&path));
+            if next_state == state {
+                break;
+            }
+            state = next_state;
+        }
+        self.gc.deref_mut(watch_ref).seen = state;
+        let ref_path = self.intern(path);
+        return self.call_handler(handler_idx, Value::String(ref_path), ReturnAction::Watch(watch_ref));
+    }
+
+    fn memo_key(&self, args: &[Value]) -> Result<Vec<MemoKey>, String> {
+        return args.iter().map(|arg| {
+            match arg {
+                Value::Null => Ok(MemoKey::Null),
+                Value::Void => Ok(MemoKey::Void),
+                Value::Boolean(val) => Ok(MemoKey::Boolean(*val)),
+                // 0 and -0 are the same argument
+                Value::Num(num) => Ok(MemoKey::Num((num + 0.0).to_bits())),
+                Value::String(str_ref) => Ok(MemoKey::String(self.gc.deref(*str_ref).to_owned())),
+                Value::Function(idx) => Ok(MemoKey::Function(*idx)),
+                _ => Err(format!("memo functions can't take {} as argument", self.value_to_repr(arg))),
+            }
+        }).collect();
+    }
+
+    // The innermost function calls being executed, the deepest first
+    fn deepest_calls(&self, count: usize) -> String {
+        let mut calls = vec![(self.function, self.ip)];
+        calls.extend(self.frames.iter().rev().map(|frame| (frame.function, frame.ip)));
+        return calls.iter()
+            .take(count)
+            .map(|(function, ip)| {
+                let (name, chunk) = match function {
+                    Some(idx) => (self.function_to_str(*idx), &self.functions[*idx].chunk),
+                    None => ("<main>".to_owned(), &self.chunk),
+                };
+                let line = chunk.positions.get(ip.saturating_sub(1)).map(|(line, _)| *line).unwrap_or(0);
+                format!("    in {} at line {}", name, line)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
     }
 
     fn print_val(&self, val: &Value) {
@@ -156,6 +1019,14 @@ impl Vm {
             Value::Void => {
                 println!();
             }
+            Value::Num(num) if self.config.display_unit.is_some() => {
+                let unit = self.config.display_unit.as_deref().unwrap_or_default();
+                let converted = convert_si_to_unit(*num, unit).unwrap_or(f64::NAN);
+                let in_unit = format!("= {} {}", self.value_to_repr(&Value::Num(converted)), unit);
+                println!();
+                println!("   {}  {}", self.value_to_repr(val).blue(), in_unit.dimmed());
+                println!();
+            }
             _ => {
                 println!();
                 println!("   {}", self.value_to_repr(val).blue());
@@ -164,6 +1035,27 @@ impl Vm {
         };
     }
 
+    // Evaluates the prelude file if there is one, its globals are then
+    // available to the code interpreted afterwards
+    pub fn load_prelude(&mut self) -> InterpretResult {
+        let path = match &self.config.prelude {
+            Some(path) => path.to_owned(),
+            None => return InterpretResult::Ok,
+        };
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(_) => return InterpretResult::Ok,
+        };
+        let echo_result = self.config.echo_result;
+        self.config.echo_result = false;
+        let result = self.interpret(source);
+        self.config.echo_result = echo_result;
+        if !matches!(result, InterpretResult::Ok) {
+            eprintln!("{} could not load the prelude {}", "warning:".yellow(), path);
+        }
+        return result;
+    }
+
     pub fn interpret(&mut self, code: String) -> InterpretResult {
         if self.config.debug {
             println!("create parser...");
@@ -175,7 +1067,8 @@ impl Vm {
             self.stdlib.make_env()
         };
 
-        let mut parser = Parser::new_with_env(self.config, env, code);
+        let previous_env = env.clone();
+        let mut parser = Parser::new_with_env(self.config.clone(), env, code);
 
         parser.parse();
 
@@ -190,21 +1083,43 @@ impl Vm {
             println!("compile...");
         }
 
+        let code_len = self.chunk.code.len();
         if !self.compile(&parser) {
             println!("compilation error");
             self.chunk.pretty_print();
+            self.chunk.code.truncate(code_len);
+            self.chunk.ast_map.truncate(code_len);
+            self.tests.retain(|test| test.parser < self.parsers.len());
             return InterpretResult::CompileError
         }
 
+        let redefined = redefined_functions(&parser, &previous_env);
         self.parsers.push(parser);
+        for (name, arity) in redefined {
+            for caller in self.callers_with_other_arity(&name, arity) {
+                eprintln!("{} '{}' now takes {} arguments, define '{}' again to update its calls to '{}'",
+                    "warning:".yellow(), name, arity, caller, name);
+            }
+        }
 
         if self.config.debug || self.config.trace {
+            for (idx, function) in self.functions.iter().enumerate() {
+                println!("function {} {}/{}:", idx, function.name, function.arity);
+                function.chunk.pretty_print();
+            }
             self.chunk.pretty_print();
             println!("run...\n");
         }
         
         let now = SystemTime::now();
+        self.last_result = Value::Void;
+        self.steps = 0;
+        self.handling_signal = false;
+        terminal::take_interrupt();
         let res = self.run();
+        if matches!(res, InterpretResult::RuntimeError) {
+            self.forget_unexecuted_definitions(&previous_env);
+        }
 
         if self.config.debug {
             match now.elapsed() {
@@ -221,31 +1136,128 @@ impl Vm {
         return res;
     }
 
-    fn compile_node(&mut self, ast: &Parser, node_idx: usize) -> bool {
-        match &ast.ast[node_idx] {
-            AstNode::Number(_, num) => {
-                self.chunk.write(node_idx, Instruction::PushNum(*num));
-            },
-            AstNode::Null(_) => {
-                self.chunk.write(node_idx, Instruction::PushNull);
-            },
-            AstNode::Void(_) => {
-                self.chunk.write(node_idx, Instruction::PushVoid);
-            },
-            AstNode::Boolean(_, val) => {
-                self.chunk.write(node_idx, Instruction::PushBool(*val));
-            },
-            AstNode::String(_, val) => {
-                let str_ref = self.gc.intern(val.to_owned()); //FIXME should be self.intern ?
-                self.chunk.write_constant(node_idx, Value::String(str_ref));
-            },
-            AstNode::Do(_, expr1, expr2) => {
-                if !self.compile_node(ast, *expr1) {
-                    println!("error compiling first expression of Do");
-                    return false;
-                }
-                self.chunk.write(node_idx, Instruction::Pop);
-                if !self.compile_node(ast, *expr2) {
+    // Removes a global from the globals and from the environment of the next
+    // submissions, a stdlib function it replaced is available again
+    pub fn undefine_global(&mut self, name: &str) -> Result<(), String> {
+        let slot = self.globals.slot(name);
+        let defined = slot.is_some_and(|slot| self.globals.get(slot).is_some());
+        let in_stdlib = self.stdlib.get_function_call(name).is_some();
+        let parser = match self.parsers.last_mut() {
+            Some(parser) if defined || !in_stdlib => parser,
+            _ => return Err(format!("'{}' is not a global variable", name)),
+        };
+        if !parser.env.remove_global_entry(name) && !defined {
+            return Err(format!("'{}' is not a global variable", name));
+        }
+        if in_stdlib {
+            self.stdlib.add_definition_to_env(&mut parser.env, name);
+        }
+        if in_stdlib {
+            self.globals.remove(name);
+        } else if let Some(slot) = slot {
+            self.globals.unset(slot);
+        }
+        return Ok(());
+    }
+
+    // The global functions defined by the submissions so far, with the latest definition
+    // of each, that call `name` with another number of arguments than `arity`
+    fn callers_with_other_arity(&self, name: &str, arity: usize) -> Vec<String> {
+        let mut callers: Vec<(String, bool)> = vec![];
+        for parser in &self.parsers {
+            for node in &parser.ast {
+                if let AstNode::GlobalLet(_, caller, value_idx, _) = node {
+                    if caller == name || !matches!(parser.ast[*value_idx], AstNode::FunctionDef(..) | AstNode::Memo(..)) {
+                        continue;
+                    }
+                    let stale = calls_with_other_arity(&parser.ast, *value_idx, name, arity);
+                    callers.retain(|(other, _)| other != caller);
+                    callers.push((caller.to_owned(), stale));
+                }
+            }
+        }
+        return callers.into_iter().filter(|(_, stale)| *stale).map(|(caller, _)| caller).collect();
+    }
+
+    fn global_matches_entry(&self, entry: &EnvEntry) -> bool {
+        let value = self.globals.slot(&entry.name).and_then(|slot| self.globals.get(slot));
+        return match (entry.is_func, value) {
+            (true, Some(Value::Function(idx))) => self.functions[idx].arity == entry.func_args.len(),
+            (false, Some(value)) => !matches!(value, Value::Function(_)),
+            _ => false,
+        };
+    }
+
+    // After a runtime error, the definitions that were not reached keep their previous
+    // entry in the environment, so the next submissions are parsed against what is defined
+    fn forget_unexecuted_definitions(&mut self, previous_env: &Env) {
+        let mut restored = vec![];
+        if let Some(parser) = self.parsers.last() {
+            for entry in parser.env.global_entries() {
+                let previous = match previous_env.get_entry(&entry.name) {
+                    Some(previous) if previous.is_global && previous != entry => previous,
+                    _ => continue,
+                };
+                if !self.global_matches_entry(&entry) && self.global_matches_entry(&previous) {
+                    restored.push(previous);
+                }
+            }
+        }
+        if let Some(parser) = self.parsers.last_mut() {
+            for entry in restored {
+                parser.env.set_global_entry(entry);
+            }
+        }
+    }
+
+    // A panic of the interpreter is a bug, but it shouldn't end the REPL
+    // session: it is reported as a runtime error and the globals are kept.
+    pub fn interpret_catching_panics(&mut self, code: String) -> InterpretResult {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.interpret(code))) {
+            Ok(result) => result,
+            Err(payload) => self.recover_from_panic(payload),
+        }
+    }
+
+    fn recover_from_panic(&mut self, payload: Box<dyn Any + Send>) -> InterpretResult {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.to_owned()
+        } else {
+            "unknown panic".to_owned()
+        };
+        // the panic may have happened in the middle of a compilation
+        self.locals = LocalsTable::new();
+        self.loops = LoopsTable::new();
+        return self.runtime_error(&format!("internal error: {}", message));
+    }
+
+    fn compile_node(&mut self, ast: &Parser, node_idx: usize) -> bool {
+        match &ast.ast[node_idx] {
+            AstNode::Number(_, num) => {
+                self.chunk.write(node_idx, Instruction::PushNum(*num));
+            },
+            AstNode::Null(_) => {
+                self.chunk.write(node_idx, Instruction::PushNull);
+            },
+            AstNode::Void(_) => {
+                self.chunk.write(node_idx, Instruction::PushVoid);
+            },
+            AstNode::Boolean(_, val) => {
+                self.chunk.write(node_idx, Instruction::PushBool(*val));
+            },
+            AstNode::String(_, val) => {
+                let str_ref = self.gc.intern(val.to_owned()); //FIXME should be self.intern ?
+                self.chunk.write_constant(node_idx, Value::String(str_ref));
+            },
+            AstNode::Do(_, expr1, expr2) => {
+                if !self.compile_node(ast, *expr1) {
+                    println!("error compiling first expression of Do");
+                    return false;
+                }
+                self.chunk.write(node_idx, Instruction::Pop);
+                if !self.compile_node(ast, *expr2) {
                     println!("error compiling second expression of Do");
                     return false;
                 }
@@ -263,15 +1275,12 @@ impl Vm {
                 }
             },
             AstNode::GlobalLet(_, name, value_expr_node_idx, next_expr_node_idx) => {
-                let name_ref = self.gc.intern(name.to_owned());
-                let name_cst_idx = self.chunk.write_constant(node_idx, Value::String(name_ref));
-                self.locals.push_anonymous();
-                if !self.compile_node(ast, *value_expr_node_idx) {
+                let slot = self.globals.resolve(name);
+                if !self.compile_value(ast, *value_expr_node_idx, name) {
                     println!("error compiling expression value for global variable {}", name);
                     return false;
                 }
-                self.chunk.write(node_idx, Instruction::DefineGlobal(name_cst_idx));
-                self.locals.pop();
+                self.chunk.write(node_idx, Instruction::DefineGlobal(slot));
                 if !self.compile_node(ast, *next_expr_node_idx) {
                     println!("error compile continuation expression for global variable {}", name);
                     return false;
@@ -280,31 +1289,44 @@ impl Vm {
             AstNode::GlobalSet(_, value_target_idx, value_expr_node_idx) => {
                 let name = match ast.get_ast_node(*value_target_idx) {
                     AstNode::GlobalValueReference(_, name) => name,
-                    _ => panic!("attempting to global set a non global var"),
+                    _ => {
+                        println!("error compiling set, the target is not a global variable");
+                        return false;
+                    },
                 };
-                let name_ref = self.gc.intern(name.to_owned());
-                let name_cst_idx = self.chunk.write_constant(node_idx, Value::String(name_ref));
+                let slot = self.globals.resolve(&name);
                 if !self.compile_node(ast, *value_expr_node_idx) {
                     println!("error compiling expression value for global variable {}", name);
                     return false;
                 }
-                self.chunk.write(node_idx, Instruction::SetGlobal(name_cst_idx));
+                self.chunk.write(node_idx, Instruction::SetGlobal(slot));
             },
             AstNode::GlobalValueReference(_, var_name) => {
-                let name_ref = self.gc.intern(var_name.to_owned());
-                let name_cst_idx = self.chunk.add_constant(Value::String(name_ref));
-                self.chunk.write(node_idx, Instruction::GetGlobal(name_cst_idx));
+                let slot = self.globals.resolve(var_name);
+                self.chunk.write(node_idx, Instruction::GetGlobal(slot));
             },
             AstNode::LocalLet(_, name, value_expr_node_idx, next_expr_node_idx) => {
-                if !self.compile_node(ast, *value_expr_node_idx) {
+                if !self.compile_value(ast, *value_expr_node_idx, name) {
                     println!("error compiling expression value for global variable {}", name);
                     return false;
                 }
                 self.locals.add_local(name.to_owned());
+                // local functions can't be redefined, so nested functions
+                // can call them without capturing the local variable
+                let static_function = match (&ast.ast[*value_expr_node_idx], self.chunk.code.last()) {
+                    (AstNode::FunctionDef(..) | AstNode::Memo(..), Some(Instruction::PushFunction(idx))) => Some(*idx),
+                    _ => None,
+                };
+                if let Some(idx) = static_function {
+                    self.static_functions.push((name.to_owned(), idx));
+                }
                 if !self.compile_node(ast, *next_expr_node_idx) {
                     println!("error compile continuation expression for global variable {}", name);
                     return false;
                 }
+                if static_function.is_some() {
+                    self.static_functions.pop();
+                }
                 self.locals.pop();
                 self.chunk.write(node_idx, Instruction::Swap);
                 self.chunk.write(node_idx, Instruction::Pop);
@@ -312,18 +1334,99 @@ impl Vm {
             AstNode::LocalSet(_, value_target_idx, value_expr_node_idx) => {
                 let name = match ast.get_ast_node(*value_target_idx) {
                     AstNode::LocalValueReference(_, name) => name,
-                    _ => panic!("attempting to local set a non local var"),
+                    _ => {
+                        println!("error compiling set, the target is not a local variable");
+                        return false;
+                    },
+                };
+                let depth = match self.locals.find_local_depth(&name) {
+                    Some(depth) => depth,
+                    None => {
+                        println!("error compiling set of {}, closures can't modify variables of the enclosing function", name);
+                        return false;
+                    }
                 };
-                let depth = self.locals.get_local_depth(&name);
                 if !self.compile_node(ast, *value_expr_node_idx) {
                     println!("error compiling expression value for local variable {}", name);
                     return false;
                 }
                 self.chunk.write(node_idx, Instruction::SetInStack(depth));
             },
-            AstNode::LocalValueReference(_, var_name) => {
-                let depth = self.locals.get_local_depth(var_name);
+            AstNode::LocalIncr(_, value_target_idx, decrement) => {
+                let name = match ast.get_ast_node(*value_target_idx) {
+                    AstNode::LocalValueReference(_, name) => name,
+                    _ => {
+                        println!("error compiling increment, the target is not a local variable");
+                        return false;
+                    },
+                };
+                let depth = match self.locals.find_local_depth(&name) {
+                    Some(depth) => depth,
+                    None => {
+                        println!("error compiling increment of {}, closures can't modify variables of the enclosing function", name);
+                        return false;
+                    }
+                };
                 self.chunk.write(node_idx, Instruction::LoadFromStack(depth));
+                self.chunk.write(node_idx, if *decrement { Instruction::Decr } else { Instruction::Incr });
+                self.chunk.write(node_idx, Instruction::SetInStack(depth));
+            },
+            AstNode::GlobalIncr(_, value_target_idx, decrement) => {
+                let name = match ast.get_ast_node(*value_target_idx) {
+                    AstNode::GlobalValueReference(_, name) => name,
+                    _ => {
+                        println!("error compiling increment, the target is not a global variable");
+                        return false;
+                    },
+                };
+                let slot = self.globals.resolve(&name);
+                self.chunk.write(node_idx, Instruction::GetGlobal(slot));
+                self.chunk.write(node_idx, if *decrement { Instruction::Decr } else { Instruction::Incr });
+                self.chunk.write(node_idx, Instruction::SetGlobal(slot));
+            },
+            AstNode::LocalValueReference(_, var_name) => {
+                match self.locals.find_local_depth(var_name) {
+                    Some(depth) => {
+                        self.chunk.write(node_idx, Instruction::LoadFromStack(depth));
+                    },
+                    None => {
+                        println!("error compiling reference to {}, closures can't capture variables of the enclosing function", var_name);
+                        return false;
+                    }
+                }
+            },
+            AstNode::FunctionDef(..) | AstNode::Memo(..) => {
+                return self.compile_value(ast, node_idx, "");
+            },
+            AstNode::Lazy(_, expr_node_idx) => {
+                if !self.compile_function_body(ast, node_idx, "", &[], *expr_node_idx) {
+                    println!("error compiling lazy expression");
+                    return false;
+                }
+                self.chunk.write(node_idx, Instruction::Lazy);
+            },
+            AstNode::Spawn(_, expr_node_idx) => {
+                if !self.compile_function_body(ast, node_idx, "", &[], *expr_node_idx) {
+                    println!("error compiling spawned expression");
+                    return false;
+                }
+                self.chunk.write(node_idx, Instruction::Spawn);
+            },
+            AstNode::Test(_, name, expr_node_idx) => {
+                if self.config.run_tests {
+                    let function = self.functions.len();
+                    if !self.compile_function_body(ast, node_idx, "", &[], *expr_node_idx) {
+                        println!("error compiling test");
+                        return false;
+                    }
+                    // the parser is kept once compiled, after the ones of the previous submissions
+                    let test = TestBlock { name: name.to_owned(), function, parser: self.parsers.len(), node: node_idx };
+                    self.tests.push(test);
+                    let last = self.chunk.code.len() - 1;
+                    self.chunk.rewrite(last, Instruction::PushVoid);
+                } else {
+                    self.chunk.write(node_idx, Instruction::PushVoid);
+                }
             },
             AstNode::IfElse(_, cond_expr_node_idx, val_expr_node_idx, else_expr_node_idx) => {
                 if !self.compile_node(ast, *cond_expr_node_idx) {
@@ -437,18 +1540,36 @@ impl Vm {
 
                 let lcount = self.locals.get_locals_count();
 
-                let var_to_pop = lcount - cloop.locals_count;
-                for _ in 0..var_to_pop {
-                    self.chunk.write(node_idx, Instruction::Pop);
-                }
+                // the break value can use the locals of the loop body,
+                // they are dropped from under it afterwards
                 if !self.compile_node(ast, *expr_node_idx) {
                     println!("error compiling break value");
                     return false;
                 }
+                let var_to_pop = lcount - cloop.locals_count;
+                for _ in 0..var_to_pop {
+                    self.chunk.write(node_idx, Instruction::Swap);
+                    self.chunk.write(node_idx, Instruction::Pop);
+                }
                 self.chunk.write(node_idx, Instruction::Jump(
                     cloop.break_ip as i64 - (self.chunk.last_instr_idx() + 1) as i64
                 ));
             },
+            AstNode::Yield(_, expr_node_idx) => {
+                if !self.compile_node(ast, *expr_node_idx) {
+                    println!("error compiling yield value");
+                    return false;
+                }
+                self.chunk.write(node_idx, Instruction::Yield);
+            },
+            AstNode::Return(_, expr_node_idx) => {
+                // the function's frame, locals included, is dropped by the Return
+                if !self.compile_node(ast, *expr_node_idx) {
+                    println!("error compiling return value");
+                    return false;
+                }
+                self.chunk.write(node_idx, Instruction::Return);
+            },
             AstNode::FunctionCall(_, name, args) => {
                 for arg in args {
                     if !self.compile_node(ast, *arg) {
@@ -456,12 +1577,37 @@ impl Vm {
                         return false;
                     }
                 }
-                match self.stdlib.get_function_instructions(name) {
-                    Some(instructions) => {
+                if let Some(depth) = self.locals.find_local_depth(name) {
+                    self.chunk.write(node_idx, Instruction::LoadFromStack(depth));
+                    self.chunk.write(node_idx, Instruction::Call(args.len()));
+                    return true;
+                }
+                if let Some((_, idx)) = self.static_functions.iter().rev().find(|(fname, _)| fname == name) {
+                    self.chunk.write(node_idx, Instruction::PushFunction(*idx));
+                    self.chunk.write(node_idx, Instruction::Call(args.len()));
+                    return true;
+                }
+                if let Some(slot) = self.globals.slot(name) {
+                    self.chunk.write(node_idx, Instruction::GetGlobal(slot));
+                    self.chunk.write(node_idx, Instruction::Call(args.len()));
+                    return true;
+                }
+                match self.stdlib.get_function_call(name) {
+                    Some(StdlibCall::Inline(instructions)) => {
                         for instruction in instructions {
                             self.chunk.write(node_idx, *instruction);
                         }
                     },
+                    Some(StdlibCall::Fold(instructions)) => {
+                        for _ in 1..args.len() {
+                            for instruction in instructions {
+                                self.chunk.write(node_idx, *instruction);
+                            }
+                        }
+                    },
+                    Some(StdlibCall::Native(idx)) => {
+                        self.chunk.write(node_idx, Instruction::CallNative(idx));
+                    },
                     None => {
                         println!("error compiling function {}, not implemented", name);
                         return false;
@@ -553,7 +1699,7 @@ impl Vm {
                     println!("error compiling count of *:");
                     return false;
                 }
-                self.chunk.write(node_idx, Instruction::Num);
+                self.chunk.write(node_idx, Instruction::Floor);
                 self.chunk.write(node_idx, Instruction::PushNum(0.0));
                 self.chunk.write(node_idx, Instruction::Max);
 
@@ -630,42 +1776,22 @@ impl Vm {
                     println!("error compiling right arm of binary operator");
                     return false;
                 }
-                match op {
-                    BinaryOperator::Equal          => { self.chunk.write(node_idx, Instruction::Equal); },
-                    BinaryOperator::NotEqual       => { 
-                        self.chunk.write(node_idx, Instruction::Equal);
-                        self.chunk.write(node_idx, Instruction::Not);
-                    },
-                    BinaryOperator::Less           => { self.chunk.write(node_idx, Instruction::Less);},
-                    BinaryOperator::LessOrEqual    => { self.chunk.write(node_idx, Instruction::LessOrEqual);},
-                    BinaryOperator::Greater        => { self.chunk.write(node_idx, Instruction::Greater);},
-                    BinaryOperator::GreaterOrEqual => { self.chunk.write(node_idx, Instruction::GreaterOrEqual);},
-                    BinaryOperator::AlmostEqual    => { self.chunk.write(node_idx, Instruction::AlmostEqual);},
-                    BinaryOperator::NotAlmostEqual => {
-                        self.chunk.write(node_idx, Instruction::AlmostEqual);
-                        self.chunk.write(node_idx, Instruction::Not);
-                    },
-                    BinaryOperator::Add            => { self.chunk.write(node_idx, Instruction::Add);},
-                    BinaryOperator::Subtract       => { self.chunk.write(node_idx, Instruction::Subtract);},
-                    BinaryOperator::Multiply       => { self.chunk.write(node_idx, Instruction::Multiply);},
-                    BinaryOperator::Divide         => { self.chunk.write(node_idx, Instruction::Divide);},
-                    BinaryOperator::Modulo         => { self.chunk.write(node_idx, Instruction::Modulo);},
-                    BinaryOperator::Power          => { self.chunk.write(node_idx, Instruction::Power);},
-                    BinaryOperator::BitwiseAnd     => { self.chunk.write(node_idx, Instruction::BitwiseAnd);},
-                    BinaryOperator::BitwiseOr      => { self.chunk.write(node_idx, Instruction::BitwiseOr);},
-                    BinaryOperator::BitwiseXor     => { self.chunk.write(node_idx, Instruction::BitwiseXor);},
-                    BinaryOperator::BitwiseLeftShift      => { self.chunk.write(node_idx, Instruction::BitwiseLeftShift);},
-                    BinaryOperator::BitwiseRightShift     => { self.chunk.write(node_idx, Instruction::BitwiseRightShift);},
-                    BinaryOperator::BitwiseZeroRightShift => { self.chunk.write(node_idx, Instruction::BitwiseZeroRightShift);},
-                    BinaryOperator::I32Add         => { self.chunk.write(node_idx, Instruction::I32Add);},
-                    BinaryOperator::I32Subtract    => { self.chunk.write(node_idx, Instruction::I32Subtract);},
-                    BinaryOperator::I32Multiply    => { self.chunk.write(node_idx, Instruction::I32Multiply);},
-                    BinaryOperator::I32Divide      => { self.chunk.write(node_idx, Instruction::I32Divide);},
-                    BinaryOperator::And            => { panic!("BinaryOperator::And case should have be handled elsewhere") },
-                    BinaryOperator::Or             => { panic!("BinaryOperator::Or case should have be handled elsewhere") },
-                    BinaryOperator::NullishOr      => { panic!("BinaryOperator::NullishOr case should have be handled elsewhere") },
-                    BinaryOperator::Repeat         => { panic!("BinaryOperator::Repeat case should have be handled elsewhere") },
+                self.write_binary_operator(node_idx, *op);
+            },
+            AstNode::ChainedComparison(..) => {
+                let mut jumps_to_end = Vec::new();
+                if !self.compile_comparison_chain(ast, node_idx, &mut jumps_to_end) {
+                    return false;
                 }
+                let jmp_to_end_target_idx = self.chunk.last_instr_idx() + 1;
+                for jmp_to_end_idx in jumps_to_end {
+                    self.chunk.rewrite(jmp_to_end_idx, Instruction::JumpIfFalse(
+                        jmp_to_end_target_idx as i64 - jmp_to_end_idx as i64
+                    ));
+                }
+                // drop the last compared operand, keep the result
+                self.chunk.write(node_idx, Instruction::Swap);
+                self.chunk.write(node_idx, Instruction::Pop);
             },
             _ => {
                 return false;
@@ -674,6 +1800,208 @@ impl Vm {
         return true;
     }
 
+    fn write_binary_operator(&mut self, node_idx: usize, op: BinaryOperator) {
+        match op {
+            BinaryOperator::Equal          => { self.chunk.write(node_idx, Instruction::Equal); },
+            BinaryOperator::NotEqual       => { 
+                self.chunk.write(node_idx, Instruction::Equal);
+                self.chunk.write(node_idx, Instruction::Not);
+            },
+            BinaryOperator::Less           => { self.chunk.write(node_idx, Instruction::Less);},
+            BinaryOperator::LessOrEqual    => { self.chunk.write(node_idx, Instruction::LessOrEqual);},
+            BinaryOperator::Greater        => { self.chunk.write(node_idx, Instruction::Greater);},
+            BinaryOperator::GreaterOrEqual => { self.chunk.write(node_idx, Instruction::GreaterOrEqual);},
+            BinaryOperator::AlmostEqual    => { self.chunk.write(node_idx, Instruction::AlmostEqual);},
+            BinaryOperator::NotAlmostEqual => {
+                self.chunk.write(node_idx, Instruction::AlmostEqual);
+                self.chunk.write(node_idx, Instruction::Not);
+            },
+            BinaryOperator::Add            => { self.chunk.write(node_idx, Instruction::Add);},
+            BinaryOperator::Subtract       => { self.chunk.write(node_idx, Instruction::Subtract);},
+            BinaryOperator::Multiply       => { self.chunk.write(node_idx, Instruction::Multiply);},
+            BinaryOperator::Divide         => { self.chunk.write(node_idx, Instruction::Divide);},
+            BinaryOperator::Modulo         => { self.chunk.write(node_idx, Instruction::Modulo);},
+            BinaryOperator::FloorDivide    => {
+                self.chunk.write(node_idx, Instruction::Divide);
+                self.chunk.write(node_idx, Instruction::Floor);
+            },
+            BinaryOperator::FloorModulo    => { self.chunk.write(node_idx, Instruction::FloorModulo);},
+            BinaryOperator::Power          => { self.chunk.write(node_idx, Instruction::Power);},
+            BinaryOperator::BitwiseAnd     => { self.chunk.write(node_idx, Instruction::BitwiseAnd);},
+            BinaryOperator::BitwiseOr      => { self.chunk.write(node_idx, Instruction::BitwiseOr);},
+            BinaryOperator::BitwiseXor     => { self.chunk.write(node_idx, Instruction::BitwiseXor);},
+            BinaryOperator::BitwiseLeftShift      => { self.chunk.write(node_idx, Instruction::BitwiseLeftShift);},
+            BinaryOperator::BitwiseRightShift     => { self.chunk.write(node_idx, Instruction::BitwiseRightShift);},
+            BinaryOperator::BitwiseZeroRightShift => { self.chunk.write(node_idx, Instruction::BitwiseZeroRightShift);},
+            BinaryOperator::I32Add         => { self.chunk.write(node_idx, Instruction::I32Add);},
+            BinaryOperator::I32Subtract    => { self.chunk.write(node_idx, Instruction::I32Subtract);},
+            BinaryOperator::I32Multiply    => { self.chunk.write(node_idx, Instruction::I32Multiply);},
+            BinaryOperator::I32Divide      => { self.chunk.write(node_idx, Instruction::I32Divide);},
+            BinaryOperator::And            => { panic!("BinaryOperator::And case should have be handled elsewhere") },
+            BinaryOperator::Or             => { panic!("BinaryOperator::Or case should have be handled elsewhere") },
+            BinaryOperator::NullishOr      => { panic!("BinaryOperator::NullishOr case should have be handled elsewhere") },
+            BinaryOperator::Repeat         => { panic!("BinaryOperator::Repeat case should have be handled elsewhere") },
+        }
+    }
+
+    // Compiles a < b < c as a < b && b < c, evaluating b only once.
+    // Leaves the last operand and the result of the last comparison
+    // on the stack. Failed comparisons jump to the end of the chain.
+    fn compile_comparison_chain(&mut self, ast: &Parser, node_idx: usize, jumps_to_end: &mut Vec<usize>) -> bool {
+        match &ast.ast[node_idx] {
+            AstNode::ChainedComparison(_, op, lexpr_node_idx, rexpr_node_idx) => {
+                if !self.compile_comparison_chain(ast, *lexpr_node_idx, jumps_to_end) {
+                    return false;
+                }
+                self.chunk.write(node_idx, Instruction::JumpIfFalse(0));
+                jumps_to_end.push(self.chunk.last_instr_idx());
+                self.chunk.write(node_idx, Instruction::Pop);
+
+                self.locals.push_anonymous();
+                if !self.compile_node(ast, *rexpr_node_idx) {
+                    println!("error compiling right arm of chained comparison");
+                    return false;
+                }
+                self.locals.pop();
+                self.chunk.write(node_idx, Instruction::Swap);
+                self.chunk.write(node_idx, Instruction::Over);
+                self.write_binary_operator(node_idx, *op);
+            },
+            AstNode::BinaryOperator(_, op, lexpr_node_idx, rexpr_node_idx) => {
+                if !self.compile_node(ast, *lexpr_node_idx) {
+                    println!("error compiling left arm of chained comparison");
+                    return false;
+                }
+                if !self.compile_node(ast, *rexpr_node_idx) {
+                    println!("error compiling right arm of chained comparison");
+                    return false;
+                }
+                self.chunk.write(node_idx, Instruction::Swap);
+                self.chunk.write(node_idx, Instruction::Over);
+                self.write_binary_operator(node_idx, *op);
+            },
+            _ => panic!("attempting to compile a non comparison in a comparison chain"),
+        }
+        return true;
+    }
+
+    fn compile_value(&mut self, ast: &Parser, node_idx: usize, name: &str) -> bool {
+        match &ast.ast[node_idx] {
+            AstNode::FunctionDef(..) => {
+                return self.compile_function(ast, node_idx, name);
+            },
+            AstNode::Memo(_, def_node_idx) => {
+                let func_idx = self.functions.len();
+                if !self.compile_function(ast, *def_node_idx, name) {
+                    return false;
+                }
+                self.functions[func_idx].memo = Some(HashMap::new());
+                return true;
+            },
+            _ => {
+                return self.compile_node(ast, node_idx);
+            },
+        }
+    }
+
+    // Compiles the function body in its own chunk, and pushes the
+    // function value in the current chunk.
+    fn compile_function(&mut self, ast: &Parser, node_idx: usize, name: &str) -> bool {
+        let (args, body_node_idx) = match &ast.ast[node_idx] {
+            AstNode::FunctionDef(_, args, body_node_idx) => (args, *body_node_idx),
+            _ => panic!("attempting to compile a non function as a function"),
+        };
+        let arg_names: Vec<&str> = args.iter().map(|arg| arg.name.as_str()).collect();
+        return self.compile_function_body(ast, node_idx, name, &arg_names, body_node_idx);
+    }
+
+    fn compile_function_body(
+        &mut self,
+        ast: &Parser,
+        node_idx: usize,
+        name: &str,
+        args: &[&str],
+        body_node_idx: usize,
+    ) -> bool {
+        let func_idx = self.functions.len();
+        self.functions.push(Function {
+            name: name.to_owned(),
+            arity: args.len(),
+            chunk: Chunk::new(),
+            memo: None,
+            generator: false,
+        });
+
+        let mut chunk = Chunk::new();
+        let mut locals = LocalsTable::new();
+        let mut loops = LoopsTable::new();
+        std::mem::swap(&mut self.chunk, &mut chunk);
+        std::mem::swap(&mut self.locals, &mut locals);
+        std::mem::swap(&mut self.loops, &mut loops);
+
+        for arg in args {
+            self.locals.add_local((*arg).to_owned());
+        }
+        // the function can call itself without capturing anything
+        if !name.is_empty() {
+            self.static_functions.push((name.to_owned(), func_idx));
+        }
+
+        let success = self.compile_node(ast, body_node_idx);
+        self.chunk.write(node_idx, Instruction::Return);
+        self.chunk.resolve_positions(ast);
+
+        if !name.is_empty() {
+            self.static_functions.pop();
+        }
+
+        std::mem::swap(&mut self.chunk, &mut chunk);
+        std::mem::swap(&mut self.locals, &mut locals);
+        std::mem::swap(&mut self.loops, &mut loops);
+        self.functions[func_idx].generator = chunk.code.contains(&Instruction::Yield);
+        self.functions[func_idx].chunk = chunk;
+
+        if !success {
+            println!("error compiling body of function {}", name);
+            return false;
+        }
+
+        self.chunk.write(node_idx, Instruction::PushFunction(func_idx));
+        return true;
+    }
+
+    // Calls the functions of the test blocks one after the other, a test passes when
+    // it returns a truthy value without error. Returns the number of failed tests.
+    pub fn run_tests(&mut self) -> usize {
+        let tests = mem::take(&mut self.tests);
+        let mut failed = 0;
+        for test in tests.iter() {
+            self.ip = self.chunk.code.len();
+            self.chunk.write(test.node, Instruction::PushFunction(test.function));
+            self.chunk.write(test.node, Instruction::Call(0));
+            self.chunk.write(test.node, Instruction::PopResult);
+            self.chunk.write(test.node, Instruction::Return);
+            self.chunk.resolve_positions(&self.parsers[test.parser]);
+            self.last_result = Value::Void;
+            let passed = match self.run() {
+                InterpretResult::Ok if self.last_result.is_truthy() => true,
+                InterpretResult::Ok => {
+                    println!("{} returned {}", "test failed:".red(), self.value_to_repr(&self.last_result));
+                    false
+                },
+                _ => false,
+            };
+            if passed {
+                println!("{} {}", "ok".green(), test.name);
+            } else {
+                println!("{} {}", "FAILED".red(), test.name);
+                failed += 1;
+            }
+        }
+        println!("{} passed, {} failed", tests.len() - failed, failed);
+        return failed;
+    }
+
     pub fn compile(&mut self, parser:&Parser) -> bool {
         let ast: &Vec<AstNode> = &parser.ast;
         if !ast.is_empty() {
@@ -683,30 +2011,224 @@ impl Vm {
             if self.config.echo_result && !self.chunk.is_last_instruction_echo_or_print() {
                 self.chunk.write(self.chunk.ast_map[self.chunk.ast_map.len()-1], Instruction::Echo);
             }
-            self.chunk.write(0, Instruction::Pop);
+            self.chunk.write(0, Instruction::PopResult);
             self.chunk.write(self.chunk.ast_map[self.chunk.ast_map.len()-1], Instruction::Return);
         } else {
             self.chunk.write(0, Instruction::Return);
         }
+        self.chunk.resolve_positions(parser);
         return true;
     }
 
     pub fn run(&mut self) -> InterpretResult {
         loop {
-            if self.config.trace {
+            if let Some(line) = self.config.trace_from {
+                if !self.tracing && self.current_line() >= line {
+                    self.tracing = true;
+                }
+            }
+            if self.tracing {
                 self.print_trace();
             }
+            match self.take_signal() {
+                Some((idx, Some(handler))) if !self.handling_signal && self.frames.len() < self.config.max_call_depth => {
+                    // the handler runs before the next instruction, then the program stops
+                    self.handling_signal = true;
+                    self.frames.push(CallFrame {
+                        function: self.function,
+                        ip: self.ip,
+                        base: self.base,
+                        on_return: Some(ReturnAction::Signal(idx)),
+                    });
+                    self.function = Some(handler);
+                    self.ip = 0;
+                    self.base = self.stack.len();
+                    let ref_name = self.intern(terminal::SIGNAL_NAMES[idx].to_owned());
+                    self.push(Value::String(ref_name));
+                    continue;
+                },
+                Some((0, _)) => return self.runtime_error("interrupted"),
+                Some((idx, _)) => return self.runtime_error(&format!("stopped by SIG{}", terminal::SIGNAL_NAMES[idx])),
+                None => {},
+            }
+            if let Some(limit) = self.step_limit {
+                self.steps += 1;
+                if self.steps >= limit {
+                    let question = format!("still running after {} instructions, keep running? (y/n)", limit);
+                    if !terminal::ask_yes_no(&question) {
+                        return self.runtime_error("stopped after too many instructions");
+                    }
+                    self.steps = 0;
+                }
+            }
             // println!("ip:{}", self.ip);
-            let instr = self.chunk.code[self.ip];
+            if self.gc.should_gc() {
+                self.collect_garbage();
+                if self.gc.over_limit() {
+                    let limit = self.gc.memory_limit().unwrap_or(0);
+                    return self.runtime_error(&format!("memory limit of {} bytes exceeded", limit));
+                }
+            }
+            let instr = self.current_chunk().code[self.ip];
             self.ip += 1;
             match instr {
                 Instruction::Return => {
                     //println!("{:?}", self.pop());
-                    return InterpretResult::Ok;
+                    match self.frames.pop() {
+                        Some(frame) => {
+                            let mut result = self.pop();
+                            let resumes_instruction = matches!(
+                                frame.on_return,
+                                Some(ReturnAction::Task(_)) | Some(ReturnAction::Listen(..)) | Some(ReturnAction::Serve(..)) |
+                                Some(ReturnAction::EachLine(_)) | Some(ReturnAction::Watch(_)),
+                            );
+                            match (frame.on_return, self.function) {
+                                (Some(ReturnAction::Memo(key)), Some(idx)) => {
+                                    if let Some(cache) = self.functions[idx].memo.as_mut() {
+                                        cache.insert(key, result);
+                                    }
+                                },
+                                (Some(ReturnAction::Force(thunk_ref)), _) => {
+                                    self.gc.deref_mut(thunk_ref).value = Some(result);
+                                },
+                                (Some(ReturnAction::Generator(gen_ref)), _) => {
+                                    let generator = self.gc.deref_mut(gen_ref);
+                                    generator.running = false;
+                                    generator.done = true;
+                                    // a finished generator produces null
+                                    result = Value::Null;
+                                },
+                                (Some(ReturnAction::Task(task_ref)), _) => {
+                                    let task = self.gc.deref_mut(task_ref);
+                                    task.running = false;
+                                    task.result = Some(result);
+                                    self.tasks.retain(|other| *other != task_ref);
+                                },
+                                (Some(ReturnAction::Serve(_, request_ref)), _) => {
+                                    let response = match result {
+                                        Value::Null => format_response(404, "not found"),
+                                        _ => format_response(200, &self.value_to_str(&result)),
+                                    };
+                                    if let Some(mut stream) = self.gc.deref_mut(request_ref).stream.take() {
+                                        let _ = stream.write_all(response.as_bytes());
+                                    }
+                                },
+                                (Some(ReturnAction::Listen(listener_ref, socket_ref)), _) => {
+                                    *self.gc.deref_mut(socket_ref) = Socket::Closed;
+                                    if result == Value::Boolean(false) {
+                                        *self.gc.deref_mut(listener_ref) = Socket::Closed;
+                                    }
+                                },
+                                (Some(ReturnAction::EachLine(lines_ref)), _) if result == Value::Boolean(false) => {
+                                    self.gc.deref_mut(lines_ref).reader = None;
+                                },
+                                (Some(ReturnAction::Watch(watch_ref)), _) if result == Value::Boolean(false) => {
+                                    self.gc.deref_mut(watch_ref).stopped = true;
+                                },
+                                (Some(ReturnAction::Signal(idx)), _) => {
+                                    self.handling_signal = false;
+                                    if idx == 0 {
+                                        return self.runtime_error("interrupted");
+                                    }
+                                    return self.runtime_error(&format!("stopped by SIG{}", terminal::SIGNAL_NAMES[idx]));
+                                },
+                                _ => {},
+                            }
+                            self.stack.truncate(self.base);
+                            self.function = frame.function;
+                            self.ip = frame.ip;
+                            self.base = frame.base;
+                            // the instruction that resumed the task or called the handler is executed
+                            // again, see call_handler
+                            if !resumes_instruction {
+                                self.push(result);
+                            }
+                        },
+                        None => {
+                            return InterpretResult::Ok;
+                        },
+                    }
+                },
+                Instruction::TraceOn => {
+                    self.tracing = true;
+                    self.push(Value::Void);
+                },
+                Instruction::TraceOff => {
+                    self.tracing = false;
+                    self.push(Value::Void);
+                },
+                Instruction::PushFunction(idx) => {
+                    self.push(Value::Function(idx));
+                },
+                Instruction::Call(argc) => {
+                    let callee = self.pop();
+                    match callee {
+                        Value::Function(_) if self.frames.len() >= self.config.max_call_depth => {
+                            let message = format!(
+                                "maximum call depth of {} exceeded, deepest calls:\n{}",
+                                self.config.max_call_depth, self.deepest_calls(5),
+                            );
+                            return self.runtime_error(&message);
+                        },
+                        Value::Function(idx) if self.functions[idx].arity == argc && self.functions[idx].generator => {
+                            // the call only captures the arguments, the body runs on `next`
+                            let args = self.stack.split_off(self.stack.len() - argc);
+                            let gen_ref = self.gc.alloc(Generator {
+                                function: idx,
+                                ip: 0,
+                                stack: args,
+                                started: false,
+                                running: false,
+                                done: false,
+                            });
+                            self.push(Value::Generator(gen_ref));
+                        },
+                        Value::Function(idx) if self.functions[idx].arity == argc => {
+                            let args_start = self.stack.len() - argc;
+                            let memo_key = if self.functions[idx].memo.is_some() {
+                                let key = match self.memo_key(&self.stack[args_start..]) {
+                                    Ok(key) => key,
+                                    Err(message) => return self.runtime_error(&message),
+                                };
+                                let cache = self.functions[idx].memo.as_ref().unwrap();
+                                if let Some(result) = cache.get(&key).copied() {
+                                    self.stack.truncate(args_start);
+                                    self.push(result);
+                                    continue;
+                                }
+                                Some(ReturnAction::Memo(key))
+                            } else {
+                                None
+                            };
+                            self.frames.push(CallFrame {
+                                function: self.function,
+                                ip: self.ip,
+                                base: self.base,
+                                on_return: memo_key,
+                            });
+                            self.function = Some(idx);
+                            self.ip = 0;
+                            self.base = args_start;
+                        },
+                        Value::Function(idx) => {
+                            let message = format!(
+                                "{} expects {} arguments, got {}",
+                                self.function_to_str(idx), self.functions[idx].arity, argc,
+                            );
+                            return self.runtime_error(&message);
+                        },
+                        _ => {
+                            let message = format!("{} is not a function", self.value_to_repr(&callee));
+                            return self.runtime_error(&message);
+                        },
+                    }
                 },
                 Instruction::Pop => {
                     self.pop();
                 },
+                Instruction::PopResult => {
+                    self.last_result = self.pop();
+                },
                 Instruction::Silence => {
                     self.pop();
                     self.push(Value::Void);
@@ -718,7 +2240,7 @@ impl Vm {
                     self.echo_val(&self.stack[self.stack.len() - 1]);
                 },
                 Instruction::Constant(cst_idx) => {
-                    let cst = self.chunk.read_constant(cst_idx);
+                    let cst = self.current_chunk().read_constant(cst_idx);
                     self.push(cst);
                 },
                 Instruction::PushNum(num)  => {
@@ -753,6 +2275,15 @@ impl Vm {
                     let v = self.pop();
                     self.push(Value::Boolean(matches!(v, Value::String(_))));
                 },
+                Instruction::IsFn => {
+                    let v = self.pop();
+                    self.push(Value::Boolean(matches!(v, Value::Function(_))));
+                },
+                Instruction::TypeOf => {
+                    let name = self.pop().type_name();
+                    let s = self.intern(name.to_owned());
+                    self.push(Value::String(s));
+                },
                 Instruction::IsNaN => {
                     match self.pop() {
                         Value::Num(v) => self.push(Value::Boolean(v.is_nan())),
@@ -765,28 +2296,22 @@ impl Vm {
                         _ => self.push(Value::Boolean(false)),
                     }
                 },
-                Instruction::DefineGlobal(cst_idx)  => {
-                    let global_name = self.chunk.read_constant_string(cst_idx);
+                Instruction::DefineGlobal(slot)  => {
                     let value = self.pop();
-                    self.globals.insert(global_name, value);
-                    self.pop();
+                    self.globals.set(slot, value);
                 },
-                Instruction::GetGlobal(cst_idx) => {
-                    let global_name = self.chunk.read_constant_string(cst_idx);
-                    match self.globals.get(&global_name) {
-                        Some(&value) => self.push(value),
+                Instruction::GetGlobal(slot) => {
+                    match self.globals.get(slot) {
+                        Some(value) => self.push(value),
                         None => {
-                            let global_name = self.gc.deref(global_name);
-                            panic!("Undefined global {}", global_name);
+                            let message = format!("undefined global {}", self.globals.name(slot));
+                            return self.runtime_error(&message);
                         }
                     }
                 },
-                Instruction::SetGlobal(cst_idx) => {
-                    let global_name = self.chunk.read_constant_string(cst_idx);
-                    let value = self.pop();
-                    self.globals.insert(global_name, value);
-                    self.pop();
-                    self.push(value);
+                Instruction::SetGlobal(slot) => {
+                    let value = self.top();
+                    self.globals.set(slot, value);
                 },
                 Instruction::LoadFromStack(depth) => {
                     let value = self.get_at_depth(depth);
@@ -849,6 +2374,10 @@ impl Vm {
                             let str_val = self.gc.deref(ref_val);
                             self.push(Value::Num(str_val.chars().count() as f64));
                         }
+                        Value::Buffer(ref_val) => {
+                            let buf = self.gc.deref(ref_val);
+                            self.push(Value::Num(buf.text.chars().count() as f64));
+                        }
                         _ => {
                             self.push(Value::Num(0.0));
                         }
@@ -942,6 +2471,10 @@ impl Vm {
                     self.push(val1);
                     self.push(val2);
                 },
+                Instruction::Over => {
+                    let val = self.stack[self.stack.len() - 2];
+                    self.push(val);
+                },
                 Instruction::Negate => {
                     let val = self.pop();
                     match &val {
@@ -1140,6 +2673,9 @@ impl Vm {
                 Instruction::ReadTextFileSync=> {
                     let val = self.pop();
                     let str_val = self.value_to_str(&val);
+                    if let Err(message) = check_fs_access(&self.config, &str_val) {
+                        return self.runtime_error(&message);
+                    }
                     let txt = std::fs::read_to_string(Path::new(&str_val));
                     match txt {
                         Ok(txt_str) => {
@@ -1157,6 +2693,9 @@ impl Vm {
                     let str_text = self.value_to_str(&text);
                     let path = self.pop();
                     let str_path = self.value_to_str(&path);
+                    if let Err(message) = check_fs_access(&self.config, &str_path) {
+                        return self.runtime_error(&message);
+                    }
                     let res = std::fs::write(Path::new(&str_path), str_text);
                     match res {
                         Ok(_) => {
@@ -1215,11 +2754,20 @@ impl Vm {
                         (Value::Void, Value::Void) => {
                             self.push(Value::Boolean(true));
                         },
-                        _ => {
-                            self.push(Value::Boolean(false));
+                        (Value::String(ref_b), Value::String(ref_a)) => {
+                            let same = self.gc.deref(ref_a) == self.gc.deref(ref_b);
+                            self.push(Value::Boolean(same));
+                        },
+                        // the other values are references, equal when they are the same object
+                        (val_b, val_a) => {
+                            self.push(Value::Boolean(val_a == val_b));
                         },
                     }
                 },
+                Instruction::IsSame => {
+                    let ops = (self.pop(), self.pop());
+                    self.push(Value::Boolean(ops.0 == ops.1));
+                },
                 Instruction::Greater => {
                     let ops = (self.pop(), self.pop());
                     match ops {
@@ -1373,6 +2921,14 @@ impl Vm {
                         },
                     }
                 },
+                Instruction::FloorModulo => {
+                    let ops = (self.pop(), self.pop());
+                    let (a, b) = match ops {
+                        (Value::Num(val_b), Value::Num(val_a)) => (val_a, val_b),
+                        (b, a) => (a.num_equiv(), b.num_equiv()),
+                    };
+                    self.push(Value::Num(floor_mod(a, b)));
+                },
                 Instruction::Min => {
                     let ops = (self.pop(), self.pop());
                     match ops {
@@ -1409,31 +2965,47 @@ impl Vm {
                 },
                 Instruction::BitwiseLeftShift => {
                     let (b, a) = (self.pop(), self.pop());
-                    self.push(Value::Num(((a.num_equiv() as i32) << (b.num_equiv() as i32)) as f64));
+                    self.push(Value::Num((a.num_equiv() as i32).wrapping_shl(b.num_equiv() as i32 as u32) as f64));
                 },
                 Instruction::BitwiseRightShift => {
                     let (b, a) = (self.pop(), self.pop());
-                    self.push(Value::Num(((a.num_equiv() as i32) >> (b.num_equiv() as i32)) as f64));
+                    self.push(Value::Num((a.num_equiv() as i32).wrapping_shr(b.num_equiv() as i32 as u32) as f64));
                 },
                 Instruction::BitwiseZeroRightShift => {
                     let (b, a) = (self.pop(), self.pop());
-                    self.push(Value::Num(((a.num_equiv() as i32 as u32) >> (b.num_equiv() as i32 as u32)) as i32 as f64));
+                    self.push(Value::Num((a.num_equiv() as i32 as u32).wrapping_shr(b.num_equiv() as i32 as u32) as i32 as f64));
                 },
                 Instruction::I32Add => {
                     let (b, a) = (self.pop(), self.pop());
-                    self.push(Value::Num(((a.num_equiv() as i32) + (b.num_equiv() as i32)) as f64));
+                    match self.i32_arithmetic(a, b, "~+", i32::checked_add, i32::wrapping_add) {
+                        Ok(result) => self.push(Value::Num(result)),
+                        Err(message) => return self.runtime_error(&message),
+                    }
                 },
                 Instruction::I32Subtract => {
                     let (b, a) = (self.pop(), self.pop());
-                    self.push(Value::Num(((a.num_equiv() as i32) - (b.num_equiv() as i32)) as f64));
+                    match self.i32_arithmetic(a, b, "~-", i32::checked_sub, i32::wrapping_sub) {
+                        Ok(result) => self.push(Value::Num(result)),
+                        Err(message) => return self.runtime_error(&message),
+                    }
                 },
                 Instruction::I32Multiply => {
                     let (b, a) = (self.pop(), self.pop());
-                    self.push(Value::Num(((a.num_equiv() as i32) * (b.num_equiv() as i32)) as f64));
+                    match self.i32_arithmetic(a, b, "~*", i32::checked_mul, i32::wrapping_mul) {
+                        Ok(result) => self.push(Value::Num(result)),
+                        Err(message) => return self.runtime_error(&message),
+                    }
                 },
                 Instruction::I32Divide => {
                     let (b, a) = (self.pop(), self.pop());
-                    self.push(Value::Num(((a.num_equiv() as i32) / (b.num_equiv() as i32)) as f64));
+                    if b.num_equiv() as i32 == 0 {
+                        let message = format!("int32 division by zero in {} ~/ {}", self.value_to_repr(&a), self.value_to_repr(&b));
+                        return self.runtime_error(&message);
+                    }
+                    match self.i32_arithmetic(a, b, "~/", i32::checked_div, i32::wrapping_div) {
+                        Ok(result) => self.push(Value::Num(result)),
+                        Err(message) => return self.runtime_error(&message),
+                    }
                 },
                 Instruction::Bitstr => {
                     let val = self.pop().num_equiv() as i32;
@@ -1576,8 +3148,1869 @@ impl Vm {
                     let val: f64 = self.rng.gen();
                     self.push(Value::Num(val));
                 },
+                Instruction::Lazy => {
+                    if let Value::Function(idx) = self.pop() {
+                        let thunk_ref = self.gc.alloc(Thunk { function: idx, value: None });
+                        self.push(Value::Lazy(thunk_ref));
+                    }
+                },
+                Instruction::Force => {
+                    let val = self.pop();
+                    match val {
+                        Value::Lazy(thunk_ref) => {
+                            let thunk = self.gc.deref(thunk_ref);
+                            if let Some(value) = thunk.value {
+                                self.push(value);
+                            } else if self.frames.len() >= self.config.max_call_depth {
+                                let message = format!("maximum call depth of {} exceeded", self.config.max_call_depth);
+                                return self.runtime_error(&message);
+                            } else {
+                                let function = thunk.function;
+                                self.frames.push(CallFrame {
+                                    function: self.function,
+                                    ip: self.ip,
+                                    base: self.base,
+                                    on_return: Some(ReturnAction::Force(thunk_ref)),
+                                });
+                                self.function = Some(function);
+                                self.ip = 0;
+                                self.base = self.stack.len();
+                            }
+                        },
+                        _ => self.push(val),
+                    }
+                },
+                Instruction::Yield => {
+                    let val = self.pop();
+                    let frame = match self.frames.pop() {
+                        Some(frame) => frame,
+                        None => return self.runtime_error("yield outside of a generator"),
+                    };
+                    let gen_ref = match frame.on_return {
+                        Some(ReturnAction::Generator(gen_ref)) => gen_ref,
+                        _ => return self.runtime_error("yield outside of a generator"),
+                    };
+                    let saved_stack = self.stack.split_off(self.base);
+                    let generator = self.gc.deref_mut(gen_ref);
+                    generator.stack = saved_stack;
+                    generator.ip = self.ip;
+                    generator.running = false;
+                    self.function = frame.function;
+                    self.ip = frame.ip;
+                    self.base = frame.base;
+                    self.push(val);
+                },
+                Instruction::Resume => {
+                    let val = self.pop();
+                    let gen_ref = match val {
+                        Value::Generator(gen_ref) => gen_ref,
+                        _ => {
+                            let message = format!("{} is not a generator", self.value_to_repr(&val));
+                            return self.runtime_error(&message);
+                        },
+                    };
+                    let generator = self.gc.deref(gen_ref);
+                    if generator.done {
+                        self.push(Value::Null);
+                    } else if generator.running {
+                        return self.runtime_error("the generator is already running");
+                    } else if self.frames.len() >= self.config.max_call_depth {
+                        let message = format!("maximum call depth of {} exceeded", self.config.max_call_depth);
+                        return self.runtime_error(&message);
+                    } else {
+                        let (function, ip, started) = (generator.function, generator.ip, generator.started);
+                        self.frames.push(CallFrame {
+                            function: self.function,
+                            ip: self.ip,
+                            base: self.base,
+                            on_return: Some(ReturnAction::Generator(gen_ref)),
+                        });
+                        let generator = self.gc.deref_mut(gen_ref);
+                        let mut saved_stack = std::mem::take(&mut generator.stack);
+                        generator.started = true;
+                        generator.running = true;
+                        self.function = Some(function);
+                        self.ip = ip;
+                        self.base = self.stack.len();
+                        self.stack.append(&mut saved_stack);
+                        // the paused `yield` expression evaluates to void
+                        if started {
+                            self.push(Value::Void);
+                        }
+                    }
+                },
+                Instruction::Spawn => {
+                    if let Value::Function(idx) = self.pop() {
+                        let task_ref = self.gc.alloc(Task {
+                            function: idx,
+                            started: false,
+                            running: false,
+                            wake_at: 0.0,
+                            result: None,
+                            receiving: None,
+                            paused_function: None,
+                            paused_ip: 0,
+                            paused_base: 0,
+                            paused_frames: vec![],
+                            paused_stack: vec![],
+                        });
+                        self.tasks.push(task_ref);
+                        self.push(Value::Task(task_ref));
+                    }
+                },
+                Instruction::Await => {
+                    let val = self.top();
+                    let task_ref = match val {
+                        Value::Task(task_ref) => task_ref,
+                        _ => {
+                            let message = format!("{} is not a task", self.value_to_repr(&val));
+                            return self.runtime_error(&message);
+                        },
+                    };
+                    if let Some(result) = self.gc.deref(task_ref).result {
+                        self.pop();
+                        self.push(result);
+                        continue;
+                    }
+                    // other tasks may run while waiting, Await is executed again after each of them
+                    let next = match self.next_task() {
+                        Some(next) => next,
+                        None => return self.runtime_error("the awaited task can't finish, the tasks are waiting for each other"),
+                    };
+                    if let Err(message) = self.resume_task(next, self.ip - 1) {
+                        return self.runtime_error(&message);
+                    }
+                },
+                Instruction::Deadline => {
+                    let ms = self.pop().num_equiv();
+                    self.push(Value::Num(now_ms() + ms));
+                },
+                Instruction::Sleep => {
+                    let deadline = self.top().num_equiv();
+                    let now = now_ms();
+                    if now >= deadline || deadline.is_nan() {
+                        self.pop();
+                        self.push(Value::Void);
+                    } else if self.pause_task(deadline).is_some() {
+                        // the sleeping task resumes once its deadline has passed
+                    } else {
+                        match self.next_task() {
+                            Some(next) if self.gc.deref(next).wake_at < deadline => {
+                                if let Err(message) = self.resume_task(next, self.ip - 1) {
+                                    return self.runtime_error(&message);
+                                }
+                            },
+                            _ => {
+                                // sleeps in small steps to notice Ctrl-C
+                                sleep_ms(f64::min(deadline - now, 100.0));
+                                self.ip -= 1;
+                            },
+                        }
+                    }
+                },
+                Instruction::ChanNew => {
+                    let chan_ref = self.gc.alloc(Channel::default());
+                    self.push(Value::Channel(chan_ref));
+                },
+                Instruction::Send => {
+                    let val = self.pop();
+                    let chan = self.pop();
+                    match chan {
+                        Value::Channel(chan_ref) => {
+                            self.gc.deref_mut(chan_ref).queue.push_back(val);
+                            self.gc.resize(chan_ref);
+                            self.push(chan);
+                        },
+                        _ => {
+                            let repr = self.value_to_repr(&chan);
+                            return self.runtime_error(&format!("send expects a channel, got {}", repr));
+                        }
+                    }
+                },
+                Instruction::Recv => {
+                    let chan = self.top();
+                    let chan_ref = match chan {
+                        Value::Channel(chan_ref) => chan_ref,
+                        _ => {
+                            let repr = self.value_to_repr(&chan);
+                            return self.runtime_error(&format!("recv expects a channel, got {}", repr));
+                        }
+                    };
+                    if let Some(val) = self.gc.deref_mut(chan_ref).queue.pop_front() {
+                        self.pop();
+                        self.push(val);
+                    } else if let Some(task_ref) = self.pause_task(now_ms()) {
+                        // Recv is executed again once a value was sent to the channel
+                        self.gc.deref_mut(task_ref).receiving = Some(chan_ref);
+                    } else {
+                        match self.next_task() {
+                            Some(next) => {
+                                if let Err(message) = self.resume_task(next, self.ip - 1) {
+                                    return self.runtime_error(&message);
+                                }
+                            },
+                            None => return self.runtime_error("recv on an empty channel would wait forever"),
+                        }
+                    }
+                },
+                Instruction::Connect => {
+                    let port = self.pop();
+                    let host = self.pop();
+                    let address = format!("{}:{}", self.value_to_str(&host), self.value_to_str(&port));
+                    if let Err(message) = check_net_access(&self.config, &address) {
+                        return self.runtime_error(&message);
+                    }
+                    match TcpStream::connect(&address) {
+                        Ok(stream) => {
+                            let socket_ref = self.gc.alloc(Socket::Stream(BufReader::new(stream)));
+                            self.push(Value::Socket(socket_ref));
+                        },
+                        Err(e) => {
+                            let ref_err = self.intern(e.to_string());
+                            self.push(Value::String(ref_err));
+                        }
+                    }
+                },
+                Instruction::Listen => {
+                    if let Err(message) = self.handle_next_connection("listen", false) {
+                        return self.runtime_error(&message);
+                    }
+                },
+                Instruction::Serve => {
+                    if let Err(message) = self.handle_next_connection("serve", true) {
+                        return self.runtime_error(&message);
+                    }
+                },
+                Instruction::EachLine => {
+                    if let Err(message) = self.handle_next_line() {
+                        return self.runtime_error(&message);
+                    }
+                },
+                Instruction::Watch => {
+                    if let Err(message) = self.handle_next_change() {
+                        return self.runtime_error(&message);
+                    }
+                },
+                Instruction::SockReadLine => {
+                    let sock = self.pop();
+                    let mut line = String::new();
+                    let read = match sock {
+                        Value::Socket(socket_ref) => match self.gc.deref_mut(socket_ref) {
+                            Socket::Stream(reader) => reader.read_line(&mut line),
+                            _ => Ok(0),
+                        },
+                        _ => {
+                            let repr = self.value_to_repr(&sock);
+                            return self.runtime_error(&format!("sock_read_line expects a socket, got {}", repr));
+                        }
+                    };
+                    match read {
+                        Ok(0) => self.push(Value::Null),
+                        Ok(_) => {
+                            let line = line.strip_suffix('\n').unwrap_or(&line);
+                            let line = line.strip_suffix('\r').unwrap_or(line).to_owned();
+                            let ref_line = self.intern(line);
+                            self.push(Value::String(ref_line));
+                        },
+                        Err(e) => {
+                            let ref_err = self.intern(e.to_string());
+                            self.push(Value::String(ref_err));
+                        }
+                    }
+                },
+                Instruction::SockWrite => {
+                    let text = self.pop();
+                    let str_text = self.value_to_str(&text);
+                    let sock = self.pop();
+                    let written = match sock {
+                        Value::Socket(socket_ref) => match self.gc.deref_mut(socket_ref) {
+                            Socket::Stream(reader) => reader.get_mut().write_all(str_text.as_bytes()),
+                            _ => Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "the socket is closed")),
+                        },
+                        _ => {
+                            let repr = self.value_to_repr(&sock);
+                            return self.runtime_error(&format!("sock_write expects a socket, got {}", repr));
+                        }
+                    };
+                    match written {
+                        Ok(_) => self.push(sock),
+                        Err(e) => {
+                            let ref_err = self.intern(e.to_string());
+                            self.push(Value::String(ref_err));
+                        }
+                    }
+                },
+                Instruction::SockClose => {
+                    let sock = self.pop();
+                    match sock {
+                        Value::Socket(socket_ref) => {
+                            *self.gc.deref_mut(socket_ref) = Socket::Closed;
+                            self.push(Value::Void);
+                        },
+                        _ => {
+                            let repr = self.value_to_repr(&sock);
+                            return self.runtime_error(&format!("sock_close expects a socket, got {}", repr));
+                        }
+                    }
+                },
+                Instruction::ClipGet => {
+                    match get_clipboard(&self.config) {
+                        Ok(text) => {
+                            let ref_text = self.intern(text);
+                            self.push(Value::String(ref_text));
+                        },
+                        Err(message) => return self.runtime_error(&message),
+                    }
+                },
+                Instruction::ClipSet => {
+                    let val = self.top();
+                    let text = self.value_to_str(&val);
+                    if let Err(message) = set_clipboard(&self.config, &text) {
+                        return self.runtime_error(&message);
+                    }
+                },
+                Instruction::BufNew => {
+                    let ref_buf = self.gc.alloc(StringBuffer::default());
+                    self.push(Value::Buffer(ref_buf));
+                },
+                Instruction::BufPush => {
+                    let buf = self.pop();
+                    let val = self.pop();
+                    match buf {
+                        Value::Buffer(ref_buf) => {
+                            let str_val = self.value_to_str(&val);
+                            self.gc.deref_mut(ref_buf).text.push_str(&str_val);
+                            self.gc.resize(ref_buf);
+                            self.push(buf);
+                        },
+                        _ => {
+                            let repr = self.value_to_repr(&buf);
+                            return self.runtime_error(&format!("buf_push expects a buffer, got {}", repr));
+                        }
+                    }
+                },
+                Instruction::BufStr => {
+                    let val = self.pop();
+                    match val {
+                        Value::String(_) => {
+                            self.push(val);
+                        },
+                        _ => {
+                            let str_val = self.value_to_str(&val);
+                            let ref_val = self.intern(str_val);
+                            self.push(Value::String(ref_val));
+                        }
+                    }
+                },
+                Instruction::CallNative(idx) => {
+                    let (arity, native) = self.stdlib.get_native(idx);
+                    let args = self.stack.split_off(self.stack.len() - arity);
+                    match native(self, &args) {
+                        Ok(value) => self.push(value),
+                        Err(message) => return self.runtime_error(&message),
+                    }
+                },
+                Instruction::GcStats => {
+                    let stats = self.gc.stats();
+                    let ref_stats = self.intern(stats);
+                    self.push(Value::String(ref_stats));
+                },
             }
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &str) -> Vm {
+        let mut vm = Vm::new(NopeConfig::new());
+        vm.interpret(code.to_owned());
+        return vm;
+    }
+
+    fn global_str(vm: &Vm, name: &str) -> Option<String> {
+        let slot = vm.globals.slot(name)?;
+        return vm.globals.get(slot).map(|value| vm.value_to_str(&value));
+    }
+
+    #[test]
+    fn test_globals_redefinition_between_submissions() {
+        let mut vm = run("let x = 3");
+        vm.interpret("var y = x + 1".to_owned());
+        vm.interpret("let x = 'foo' + y".to_owned());
+        vm.interpret("set y = y * 10".to_owned());
+        assert_eq!(global_str(&vm, "x"), Some("foo4".to_owned()));
+        assert_eq!(global_str(&vm, "y"), Some("40".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_load_prelude() {
+        let path = std::env::temp_dir().join(format!("nope_test_prelude_{}.nope", std::process::id()));
+        std::fs::write(&path, "let mile = 1.609\nlet double = |x| x * 2").unwrap();
+        let mut vm = Vm::new(NopeConfig {
+            prelude: Some(path.to_string_lossy().into_owned()),
+            echo_result: true,
+            ..NopeConfig::new()
+        });
+        assert!(matches!(vm.load_prelude(), InterpretResult::Ok));
+        assert!(vm.config.echo_result);
+        vm.interpret("let x = double(mile)".to_owned());
+        assert_eq!(global_str(&vm, "x"), Some("3.218".to_owned()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_prelude_is_ignored() {
+        let mut vm = Vm::new(NopeConfig {
+            prelude: Some("/does/not/exist/prelude.nope".to_owned()),
+            ..NopeConfig::new()
+        });
+        assert!(matches!(vm.load_prelude(), InterpretResult::Ok));
+        vm.interpret("let x = 1".to_owned());
+        assert_eq!(global_str(&vm, "x"), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn test_undefined_global_is_a_runtime_error() {
+        let mut vm = Vm::new(NopeConfig::new());
+        let slot = vm.globals.resolve("x");
+        vm.chunk.write(0, Instruction::GetGlobal(slot));
+        vm.chunk.write(0, Instruction::Return);
+        assert!(matches!(vm.run(), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_function_calls() {
+        let vm = run("
+let add = |a b| a + b
+let fact = |n| if n <= 1, 1 else n * fact(n - 1)
+let twice = |f:1 x| f(f(x))
+let a = add(1, 2)
+let b = fact(10)
+let c = twice(|x| x * 3, 2)
+");
+        assert_eq!(global_str(&vm, "a"), Some("3".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("3628800".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("18".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+        assert_eq!(vm.frames.len(), 0);
+    }
+
+    #[test]
+    fn test_functions_have_their_own_chunk() {
+        let vm = run("
+let greet = |name| 'hello ' + name
+let x = greet 'bob'
+");
+        assert_eq!(vm.functions.len(), 1);
+        assert_eq!(vm.functions[0].name, "greet");
+        assert_eq!(vm.functions[0].arity, 1);
+        assert_eq!(vm.functions[0].chunk.constants.len(), 1);
+        assert_eq!(vm.functions[0].chunk.code.last(), Some(&Instruction::Return));
+        assert_eq!(global_str(&vm, "x"), Some("hello bob".to_owned()));
+    }
+
+    #[test]
+    fn test_local_functions() {
+        let vm = run("
+let x = (
+    let sq = |x| x * x
+    let sum_sq = |n| if n == 0, 0 else sq(n) + sum_sq(n - 1)
+    var total = 0
+    var i = 0
+    while i < 3 (
+        set total = total + sum_sq(i)
+        set i = i + 1
+    )
+    total
+)
+");
+        assert_eq!(global_str(&vm, "x"), Some("6".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_functions_between_submissions() {
+        let mut vm = run("let double = |x| x * 2");
+        vm.interpret("let quad = |x| double(double(x))".to_owned());
+        vm.interpret("let y = quad 3".to_owned());
+        assert_eq!(global_str(&vm, "y"), Some("12".to_owned()));
+    }
+
+    #[test]
+    fn test_closures_are_a_compile_error() {
+        let mut vm = Vm::new(NopeConfig::new());
+        let res = vm.interpret("let x = (let k = 3, let addk = |x| x + k, addk 1)".to_owned());
+        assert!(matches!(res, InterpretResult::CompileError));
+        assert_eq!(vm.chunk.code.len(), 0);
+        vm.interpret("let y = 2".to_owned());
+        assert_eq!(global_str(&vm, "y"), Some("2".to_owned()));
+    }
+
+    #[test]
+    fn test_instruction_positions() {
+        let vm = run("let x = 1\nlet add_one = |a| a + 1\nlet y = add_one(x)");
+        assert_eq!(vm.chunk.positions.len(), vm.chunk.code.len());
+        assert_eq!(vm.chunk.positions[0], (1, 9));
+        assert!(vm.chunk.positions.contains(&(3, 9)));
+        let add_one = &vm.functions[0].chunk;
+        assert_eq!(add_one.positions.len(), add_one.code.len());
+        assert!(add_one.positions.iter().all(|(line, _)| *line == 2));
+    }
+
+    #[test]
+    fn test_trace_on_off() {
+        let mut vm = run("trace_off");
+        assert!(!vm.tracing);
+        vm.interpret("let x = (\n    trace_on\n    trace_off\n    trace_on\n    3\n)".to_owned());
+        assert!(vm.tracing);
+        vm.interpret("trace_off".to_owned());
+        assert!(!vm.tracing);
+    }
+
+    #[test]
+    fn test_native_functions() {
+        let vm = run("
+let r = reverse <- 'abc' + 'def'
+let c = count('ab', 'abcabcab')
+let e = count('', 'abc')
+");
+        assert_eq!(global_str(&vm, "r"), Some("fedcba".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("3".to_owned()));
+        assert_eq!(global_str(&vm, "e"), Some("0".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_range_functions() {
+        let vm = run("
+let a = clamp(5, 0, 3)
+let b = clamp(-1, 0, 3)
+let c = clamp 2 0 3
+let d = lerp(0, 10, 0.25)
+let e = lerp(10, 20, 2)
+let f = map_range(5, 0, 10, 100, 200)
+let g = map_range(1, 0, 2, 10, 0)
+let h = wrap(370, 0, 360)
+let i = wrap(-10, 0, 360)
+let j = wrap(5, 1, 4)
+");
+        assert_eq!(global_str(&vm, "a"), Some("3".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("2.5".to_owned()));
+        assert_eq!(global_str(&vm, "e"), Some("30".to_owned()));
+        assert_eq!(global_str(&vm, "f"), Some("150".to_owned()));
+        assert_eq!(global_str(&vm, "g"), Some("5".to_owned()));
+        assert_eq!(global_str(&vm, "h"), Some("10".to_owned()));
+        assert_eq!(global_str(&vm, "i"), Some("350".to_owned()));
+        assert_eq!(global_str(&vm, "j"), Some("2".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_convert() {
+        let vm = run("
+let a = convert 30 'C' 'F'
+let b = convert 2 'km' 'm'
+let c = convert 86 'F' 'C'
+");
+        for (name, value) in [("a", "86"), ("b", "2000"), ("c", "30")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = run("");
+        assert!(matches!(vm.interpret("convert 1 'm' 'furlong'".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("convert 1 'parsec' 'm'".to_owned()), InterpretResult::RuntimeError));
+        for unit in crate::units::unit_names() {
+            assert!(crate::units::convert_unit_to_si(1.0, unit).is_some(), "{}", unit);
+        }
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        let vm = run("
+let a = to_human_bytes 1.4Gi
+let b = to_human_bytes 356ki
+let c = from_human_bytes '1.5Mi'
+let d = from_human_bytes <- to_human_bytes 2Ti
+");
+        for (name, value) in [("a", "1.4Gi"), ("b", "356ki"), ("c", "1572864"), ("d", "2199023255552")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = run("");
+        assert!(matches!(vm.interpret("from_human_bytes '3km'".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_units() {
+        let vm = run("let names = units");
+        let names = global_str(&vm, "names").unwrap();
+        assert!(names.starts_with("length: in km m "), "{}", names);
+        assert!(names.contains("\ntemperature: F C K\n"), "{}", names);
+        assert_eq!(names.lines().count(), crate::units::UNIT_GROUPS.len());
+    }
+
+    #[test]
+    fn test_to_fraction() {
+        let vm = run("
+let a = to_fraction 0.375 16
+let b = to_fraction 0.37 16
+let c = to_fraction 3.14159265 1000
+let d = to_fraction 0.333 10
+let e = to_fraction -1.375 8
+let f = to_fraction 2 8
+let g = to_fraction 0.01 8
+let h = to_fraction 0.99 8
+let i = to_fraction (0.1 + 0.2) 100
+");
+        for (name, value) in [("a", "3/8"), ("b", "3/8"), ("c", "355/113"), ("d", "1/3"), ("e", "-11/8"),
+                              ("f", "2"), ("g", "0"), ("h", "1"), ("i", "3/10")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = run("");
+        assert!(matches!(vm.interpret("to_fraction 0.5 0".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("to_fraction 0.5 1.5".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_fmt_money() {
+        let vm = run("
+let a = fmt_money 1234.5 'USD'
+let b = fmt_money 1234.5 'EUR'
+let c = fmt_money 1234.5 'JPY'
+let d = fmt_money 1.005 'usd'
+let e = fmt_money -1234567.891 'CHF'
+let f = fmt_money 999.999 'GBP'
+let g = fmt_money -0.001 'USD'
+let h = fmt_money 0.5 'SEK'
+let i = fmt_money 12 'BRL'
+");
+        for (name, value) in [("a", "$1,234.50"), ("b", "1.234,50 €"), ("c", "¥1,235"), ("d", "$1.01"),
+                              ("e", "-CHF 1'234'567.89"), ("f", "£1,000.00"), ("g", "$0.00"), ("h", "0,50 kr"),
+                              ("i", "R$ 12,00")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = run("");
+        assert!(matches!(vm.interpret("fmt_money 1 'XYZ'".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_percentages() {
+        let vm = run("
+let a = 20% of 50
+let b = 50 * 20%
+let c = 3 + 10% of 200 * 2
+let d = 20%3
+let e = 2.5percent of 1000
+let f = (15%)
+let g = 7% 2
+let h = (7%)
+let i = 7% -2
+let j = 50% * 3
+");
+        for (name, value) in [("a", "10"), ("b", "10"), ("c", "43"), ("d", "2"), ("e", "25"), ("f", "0.15"), ("g", "1"), ("h", "0.07"),
+                              ("i", "1"), ("j", "1.5")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_rounding_functions() {
+        let vm = run("
+let a = round_to 12.37 0.05
+let b = round_to 1234 100
+let c = floor_to 0.3 0.1
+let d = ceil_to 0.7 0.1
+let e = ceil_to 1.01 0.25
+let f = floor_to -1.01 0.25
+let g = round_to (0.1 + 0.2) 0.1
+let h = round_half_even 2.5
+let i = round_half_even 3.5
+let j = round_half_even -0.5
+");
+        for (name, value) in [("a", "12.35"), ("b", "1200"), ("c", "0.3"), ("d", "0.7"), ("e", "1.25"),
+                              ("f", "-1.25"), ("g", "0.3"), ("h", "2"), ("i", "4"), ("j", "-0")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = run("");
+        assert!(matches!(vm.interpret("round_to 1 0".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("floor_to 1 -2".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_integer_functions() {
+        let vm = run("
+let a = gcd(12, 18)
+let b = gcd(-4, 6)
+let c = lcm(4, 6)
+let d = lcm(0, 3)
+let e = factorial 5
+let f = factorial 20
+let g = comb(52, 5)
+let h = comb(3, 5)
+let i = perm(5, 2)
+let j = perm(5, 0)
+let k = comb(60, 30) == 118264581564861424
+");
+        assert_eq!(global_str(&vm, "a"), Some("6".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("12".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "e"), Some("120".to_owned()));
+        assert_eq!(global_str(&vm, "f"), Some("2432902008176640000".to_owned()));
+        assert_eq!(global_str(&vm, "g"), Some("2598960".to_owned()));
+        assert_eq!(global_str(&vm, "h"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "i"), Some("20".to_owned()));
+        assert_eq!(global_str(&vm, "j"), Some("1".to_owned()));
+        assert_eq!(global_str(&vm, "k"), Some("true".to_owned()));
+    }
+
+    #[test]
+    fn test_integer_functions_errors() {
+        for code in ["factorial 21", "factorial 2.5", "factorial -1", "comb(68, 34)", "gcd('a', 2)", "lcm(1, Inf)", "perm(100, 50)",
+            "comb(61, 30)", "perm(1037, 6)", "lcm(3, 9007199254740991)"] {
+            let mut vm = Vm::new(NopeConfig::new());
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_running_stats() {
+        let mut vm = run("
+let s = stats_new
+let e = stats_new
+stats_push(2, s)
+stats_push(4, s)
+stats_push(4, s)
+stats_push(4, s)
+stats_push(5, s)
+stats_push(5, s)
+stats_push(7, s)
+stats_push(9, s)
+let count = stats_count s
+let mean = stats_mean s
+let var = stats_var s
+let min = stats_min s
+let max = stats_max s
+let empty_mean = stats_mean e
+let single_std = stats_std <- stats_push(1, e)
+");
+        assert_eq!(global_str(&vm, "count"), Some("8".to_owned()));
+        assert_eq!(global_str(&vm, "mean"), Some("5".to_owned()));
+        assert_eq!(global_str(&vm, "var"), Some(format!("{}", 32.0 / 7.0)));
+        assert_eq!(global_str(&vm, "min"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "max"), Some("9".to_owned()));
+        assert_eq!(global_str(&vm, "empty_mean"), Some("NaN".to_owned()));
+        assert_eq!(global_str(&vm, "single_std"), Some("NaN".to_owned()));
+        vm.collect_garbage();
+        assert_eq!(global_str(&vm, "s"), Some(format!("stats(count: 8, mean: 5, std: {})", (32.0f64 / 7.0).sqrt())));
+        assert!(matches!(vm.interpret("stats_mean 3".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_temporary_strings_memory_is_bounded() {
+        let vm = run("
+var i = 0, while i < 200000 (
+    let s = to_str i
+    set i = i + 1
+)
+");
+        assert!(vm.gc.peak_bytes_allocated() < 2 * 1024 * 1024);
+        assert!(vm.gc.interned_count() < 50000);
+    }
+
+    #[test]
+    fn test_substr_in_loop_memory_is_bounded() {
+        let vm = run("
+var text = 'abcdefghijklmnopqrstuvwxyz'
+var i = 0, while i < 100000 (
+    set text = substr(1, 27, text + char_at(i % 26, 'abcdefghijklmnopqrstuvwxyz'))
+    set i = i + 1
+)
+");
+        assert!(vm.gc.peak_bytes_allocated() < 2 * 1024 * 1024);
+        assert_eq!(global_str(&vm, "text"), Some("efghijklmnopqrstuvwxyzabcd".to_owned()));
+    }
+
+    #[test]
+    fn test_live_strings_survive_collection() {
+        let mut vm = run("
+var kept = 'hello' + ' world'
+var i = 0, while i < 100000 (
+    let s = to_str i
+    set i = i + 1
+)
+");
+        vm.collect_garbage();
+        assert_eq!(global_str(&vm, "kept"), Some("hello world".to_owned()));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let vm = run("
+let a = true && false
+let b = 1 && 'yes'
+let c = false || 3
+let d = 'x' || 3
+let e = null ?? 4
+let f = 0 ?? 4
+let g = 1 || 2 && 0
+");
+        assert_eq!(global_str(&vm, "a"), Some("false".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("yes".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("3".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("x".to_owned()));
+        assert_eq!(global_str(&vm, "e"), Some("4".to_owned()));
+        assert_eq!(global_str(&vm, "f"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "g"), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        let vm = run("
+let buf = buf_new
+let a = false && buf_push('a', buf)
+let o = true || buf_push('o', buf)
+let n = 0 ?? buf_push('n', buf)
+let r = true && buf_push('r', buf)
+let s = buf_str(buf)
+");
+        assert_eq!(global_str(&vm, "s"), Some("r".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_repeat_operator() {
+        let vm = run("
+let a = 3 *: 2
+let b = 2 *: 'ab'
+let c = 2 *: 3 *: 1
+let d = 0 *: 2
+let e = -2 *: 2
+let f = 2.7 *: 1
+let g = 0.5 *: 1
+let h = null *: 1
+");
+        assert_eq!(global_str(&vm, "a"), Some("6".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("abab".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("6".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("_".to_owned()));
+        assert_eq!(global_str(&vm, "e"), Some("_".to_owned()));
+        assert_eq!(global_str(&vm, "f"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "g"), Some("_".to_owned()));
+        assert_eq!(global_str(&vm, "h"), Some("_".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_repeat_operator_evaluates_each_time() {
+        let vm = run("
+let buf = buf_new
+let r = 3 *: buf_push('x', buf)
+let none = 0 *: buf_push('y', buf)
+let s = buf_str(buf)
+");
+        assert_eq!(global_str(&vm, "s"), Some("xxx".to_owned()));
+    }
+
+    #[test]
+    fn test_i32_arithmetic_wraps() {
+        let vm = run("
+let a = 2147483647 ~+ 1
+let b = -2147483648 ~- 1
+let c = 65536 ~* 65536
+let d = -2147483648 ~/ -1
+let e = 10 ~/ 4
+let f = 1 ~<< 33
+let g = -1 ~>>> 28
+");
+        assert_eq!(global_str(&vm, "a"), Some("-2147483648".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("2147483647".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("-2147483648".to_owned()));
+        assert_eq!(global_str(&vm, "e"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "f"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "g"), Some("15".to_owned()));
+    }
+
+    #[test]
+    fn test_i32_division_by_zero_is_a_runtime_error() {
+        for code in ["1 ~/ 0", "1 ~/ 0.5", "1 ~/ null"] {
+            let mut vm = Vm::new(NopeConfig::new());
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+            assert_eq!(vm.stack.len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_checked_i32_arithmetic() {
+        let config = NopeConfig { checked_int: true, ..NopeConfig::new() };
+        for code in ["2147483647 ~+ 1", "-2147483648 ~- 1", "65536 ~* 65536", "-2147483648 ~/ -1", "3e10 ~+ 1", "'a' ~+ 1"] {
+            let mut vm = Vm::new(config.clone());
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+        }
+        let mut vm = Vm::new(config);
+        assert!(matches!(vm.interpret("let x = 2147483646 ~+ 1.5".to_owned()), InterpretResult::Ok));
+        assert_eq!(global_str(&vm, "x"), Some("2147483647".to_owned()));
+    }
+
+    #[test]
+    fn test_floor_division_and_modulo() {
+        let vm = run("
+let a = 7 // 2
+let b = -7 // 2
+let c = 7 // -2
+let d = 7 %% 3
+let e = -7 %% 3
+let f = 7 %% -3
+let g = -7.5 %% 2
+let h = -6 %% 3
+let i = 1 %% 0
+let j = idiv(-7, 2)
+let k = fmod(-7, 3)
+let l = -7 % 3
+");
+        assert_eq!(global_str(&vm, "a"), Some("3".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("-4".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("-4".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("1".to_owned()));
+        assert_eq!(global_str(&vm, "e"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "f"), Some("-2".to_owned()));
+        assert_eq!(global_str(&vm, "g"), Some("0.5".to_owned()));
+        assert_eq!(global_str(&vm, "h"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "i"), Some("NaN".to_owned()));
+        assert_eq!(global_str(&vm, "j"), Some("-4".to_owned()));
+        assert_eq!(global_str(&vm, "k"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "l"), Some("-1".to_owned()));
+    }
+
+    #[test]
+    fn test_chained_comparisons() {
+        let vm = run("
+let a = 1 < 2 < 3
+let b = 1 < 3 < 2
+let c = 1 <= 1 < 2 >= 2
+let d = 5 > 4 > 3 > 2 > 3
+let e = (1 < 2) < 3
+let f = 1 + 1 < 3 < 2 * 2
+");
+        assert_eq!(global_str(&vm, "a"), Some("true".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("false".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("true".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("false".to_owned()));
+        assert_eq!(global_str(&vm, "e"), Some("true".to_owned()));
+        assert_eq!(global_str(&vm, "f"), Some("true".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_chained_comparisons_evaluate_once() {
+        let vm = run("
+var calls = 0
+let mid = |x| (
+    inc calls
+    x
+)
+let a = 1 < mid(2) < 3
+let b = 3 < 2 < mid(3)
+let c = 1 < mid(5) < 3
+");
+        assert_eq!(global_str(&vm, "calls"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "a"), Some("true".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("false".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("false".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_inc_dec() {
+        let vm = run("
+var g = 1
+inc g
+inc g
+dec g
+let f = |n| (
+    var i = n
+    inc i
+    inc i
+    i
+)
+let l = f(5)
+var k = 0
+while k < 3 (
+    inc k
+)
+let r = inc k
+");
+        assert_eq!(global_str(&vm, "g"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "l"), Some("7".to_owned()));
+        assert_eq!(global_str(&vm, "k"), Some("4".to_owned()));
+        assert_eq!(global_str(&vm, "r"), Some("4".to_owned()));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_inc_compiles_in_place() {
+        let vm = run("var g = 1, inc g");
+        let slot = vm.globals.slot("g").unwrap();
+        assert!(vm.chunk.code.windows(3).any(|code| code == [
+            Instruction::GetGlobal(slot),
+            Instruction::Incr,
+            Instruction::SetGlobal(slot),
+        ]));
+    }
+
+    #[test]
+    fn test_last_result() {
+        let mut vm = run("let x = 3\nx * 2");
+        assert_eq!(vm.last_result(), Value::Num(6.0));
+        vm.interpret("x > 2".to_owned());
+        assert_eq!(vm.last_result(), Value::Boolean(true));
+        vm.interpret("".to_owned());
+        assert_eq!(vm.last_result(), Value::Void);
+    }
+
+    #[test]
+    fn test_value_to_json() {
+        for (code, json) in [
+            ("3.5", "3.5"),
+            ("1 / 0", "null"),
+            ("null", "null"),
+            ("_", "null"),
+            ("3 > 2", "true"),
+            ("'say \"hi\"\\n'", "\"say \\\"hi\\\"\\n\""),
+            ("buf_push('a', buf_new())", "\"a\""),
+            ("stats_push(2, stats_new())", "{\"count\": 1, \"mean\": 2, \"std\": null}"),
+        ] {
+            let vm = run(code);
+            assert_eq!(vm.value_to_json(&vm.last_result()), json, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_semicolon_separated_expressions() {
+        let vm = run("let x = 3; var y = x * 2; set y = y + 1; y");
+        assert_eq!(global_str(&vm, "y"), Some("7".to_owned()));
+        assert_eq!(vm.last_result(), Value::Num(7.0));
+    }
+
+    #[test]
+    fn test_recover_from_panic() {
+        let mut vm = run("let x = 3");
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| vm.pop())).unwrap_err();
+        assert!(matches!(vm.recover_from_panic(payload), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret_catching_panics("x + 1".to_owned()), InterpretResult::Ok));
+        assert_eq!(vm.last_result(), Value::Num(4.0));
+    }
+
+    #[test]
+    fn test_code_blocks() {
+        let vm = run("let f = |x| {\n    let y = x * 2\n    y + 1\n}\nlet a = f(4)\nlet y = 10\nlet b = { let y = 1; y + 1 } + y");
+        assert_eq!(global_str(&vm, "a"), Some("9".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("12".to_owned()));
+        let vm = run("var i = 0\nwhile i < 3 { inc i; i }\nlet c = if i == 3 { 'three' } else 'other'");
+        assert_eq!(global_str(&vm, "c"), Some("three".to_owned()));
+    }
+
+    #[test]
+    fn test_return() {
+        let vm = run("let sign = |x| {\n    if x > 0, return 'positive'\n    if x < 0, return 'negative'\n    'zero'\n}\nlet a = sign(3) + sign(-2) + sign(0)");
+        assert_eq!(global_str(&vm, "a"), Some("positivenegativezero".to_owned()));
+        let vm = run("let find = |n| {\n    var i = 0\n    loop (\n        let sq = i * i\n        if sq > n, return i\n        inc i\n    )\n}\nlet b = find(50) + find(3)");
+        assert_eq!(global_str(&vm, "b"), Some("10".to_owned()));
+        let vm = run("let fact = |n| do if n <= 1, return 1, n * fact(n - 1)\nlet c = fact(5)");
+        assert_eq!(global_str(&vm, "c"), Some("120".to_owned()));
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let mut vm = Vm::new(NopeConfig { max_call_depth: 50, ..NopeConfig::new() });
+        let result = vm.interpret("let f = |n| if n > 0, 1 + f(n - 1) else 0\nlet depth = f(40)".to_owned());
+        assert!(matches!(result, InterpretResult::Ok));
+        assert_eq!(global_str(&vm, "depth"), Some("40".to_owned()));
+        assert!(matches!(vm.interpret("let forever = |n| forever(n + 1)\nforever(0)".to_owned()), InterpretResult::RuntimeError));
+        assert_eq!(vm.frames.len(), 0);
+        assert!(matches!(vm.interpret("f(10)".to_owned()), InterpretResult::Ok));
+    }
+
+    #[test]
+    fn test_memo() {
+        let vm = run("var calls = 0\nlet fib = memo |n| do set calls = calls + 1, if n < 2, n else fib(n - 1) + fib(n - 2)\nlet a = fib(30)\nlet b = fib(30) + fib(-0)");
+        assert_eq!(global_str(&vm, "a"), Some("832040".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("832040".to_owned()));
+        assert_eq!(global_str(&vm, "calls"), Some("31".to_owned()));
+        let vm = run("var calls = 0\nlet size = memo |s| do set calls = calls + 1, len(s)\nlet c = size('ab') + size('ab') + size('abc')");
+        assert_eq!(global_str(&vm, "c"), Some("7".to_owned()));
+        assert_eq!(global_str(&vm, "calls"), Some("2".to_owned()));
+        let mut vm = run("let f = memo |b| 1");
+        assert!(matches!(vm.interpret("f(buf_new())".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_lazy_force() {
+        let vm = run("var calls = 0\nlet answer = lazy do set calls = calls + 1, 6 * 7\nlet before = calls\nlet a = force(answer) + force(answer)\nlet b = force(lazy force(answer) + 1)\nlet c = force('not lazy')");
+        assert_eq!(global_str(&vm, "before"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "a"), Some("84".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("43".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("not lazy".to_owned()));
+        assert_eq!(global_str(&vm, "calls"), Some("1".to_owned()));
+        let vm = run("let text = lazy 'a' + 'b'\nlet s = to_str(text)\nforce(text)\nlet t = to_str(text)");
+        assert_eq!(global_str(&vm, "s"), Some("<lazy>".to_owned()));
+        assert_eq!(global_str(&vm, "t"), Some("ab".to_owned()));
+    }
+
+    #[test]
+    fn test_generators() {
+        let vm = run("let count = |n| {\n    var i = 0\n    while i < n (\n        yield i\n        inc i\n    )\n}\nlet g = count(2)\nlet a = next(g)\nlet b = next(g)\nlet c = next(g)\nlet d = next(g)\nlet s = to_str(g)");
+        assert_eq!(global_str(&vm, "a"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("1".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("null".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("null".to_owned()));
+        assert_eq!(global_str(&vm, "s"), Some("<generator count>".to_owned()));
+        let vm = run("var calls = 0\nlet g = |x| { set calls = calls + 1; yield x; return 'ignored' }\nlet gen = g(5)\nlet before = calls\nlet a = next(gen) + next(g(1))\nlet b = next(gen)");
+        assert_eq!(global_str(&vm, "before"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "a"), Some("6".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("null".to_owned()));
+        let mut vm = run("var gen = null\nlet g = |x| yield next(gen)\nset gen = g(1)");
+        assert!(matches!(vm.interpret("next(gen)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("let after = next(gen)".to_owned()), InterpretResult::Ok));
+        assert_eq!(global_str(&vm, "after"), Some("null".to_owned()));
+        assert!(matches!(vm.interpret("next(3)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("iter(3, |v| v)".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_iter_generators() {
+        let vm = run("
+let squares = |n| {
+    var i = 1
+    while i <= n (
+        yield i * i
+        inc i
+    )
+}
+let b = buf_new()
+let res = iter(squares(4), |sq| buf_push(sq + ';', b))
+let seen = buf_str(b)
+let voids = |n| { yield void; yield n }
+var total = 0
+iter(voids(3), |v| set total = total + 1)
+var nested = 0
+iter(squares(2), |a| iter(squares(3), |b| set nested = nested + b))
+");
+        for (name, value) in [("seen", "1;4;9;16;"), ("res", "_"), ("total", "2"), ("nested", "28")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_tasks() {
+        let vm = run("var log = buf_new()\nlet work = |name ms| {\n    var i = 0\n    while i < 2 (\n        buf_push(name + i, log)\n        sleep(ms)\n        inc i\n    )\n    name\n}\nlet a = spawn work('a', 5)\nlet b = spawn work('b', 1)\nlet before = buf_str(log)\nlet c = spawn await(a) + await(b)\nlet result = await(c)\nlet order = buf_str(log)");
+        assert_eq!(global_str(&vm, "before"), Some("".to_owned()));
+        assert_eq!(global_str(&vm, "result"), Some("ab".to_owned()));
+        assert_eq!(global_str(&vm, "order"), Some("a0b0b1a1".to_owned()));
+        let mut vm = run("var me = null\nset me = spawn await(me)");
+        assert!(matches!(vm.interpret("await(me)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("await(3)".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_channels() {
+        let vm = run("let ch = chan_new()\nlet produce = |n| {\n    var i = 1\n    while i <= n (\n        send(ch, i)\n        inc i\n    )\n    send(ch, null)\n}\nlet sum = |c| {\n    var total = 0\n    var v = recv(c)\n    while v != null (\n        set total = total + v\n        set v = recv(c)\n    )\n    total\n}\nlet consumer = spawn sum(ch)\nlet producer = spawn produce(4)\nlet total = await(consumer)\nlet direct = recv(send(chan_new(), 'x'))");
+        assert_eq!(global_str(&vm, "total"), Some("10".to_owned()));
+        assert_eq!(global_str(&vm, "direct"), Some("x".to_owned()));
+        let mut vm = run("let ch = chan_new()\nlet t = spawn recv(ch)");
+        assert!(matches!(vm.interpret("await(t)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("recv(ch)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("send(3, 4)".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_sockets() {
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let echo = std::thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+            stream.write_all(format!("echo {}", line).as_bytes()).unwrap();
+        });
+        let vm = run(&format!("let s = connect('127.0.0.1', {})\nsock_write(s, 'hello\\n')\nlet a = sock_read_line(s)\nlet b = sock_read_line(s)", port));
+        echo.join().unwrap();
+        assert_eq!(global_str(&vm, "a"), Some("echo hello".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("null".to_owned()));
+
+        // listen blocks until its handler returns false, so it gets a port that was free a moment ago
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let client = std::thread::spawn(move || {
+            let mut replies = vec![];
+            for name in ["a", "b"] {
+                let mut tries = 0;
+                let mut stream = loop {
+                    match std::net::TcpStream::connect(("127.0.0.1", port)) {
+                        Ok(stream) => break stream,
+                        Err(err) if tries == 500 => panic!("could not connect to the listener: {}", err),
+                        Err(_) => tries += 1,
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                };
+                stream.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+                stream.write_all(format!("{}\n", name).as_bytes()).unwrap();
+                let mut line = String::new();
+                BufReader::new(stream).read_line(&mut line).unwrap();
+                replies.push(line);
+            }
+            replies
+        });
+        let vm = run(&format!("var served = 0\nlet res = listen({}, |sock| {{\n    sock_write(sock, upper(sock_read_line(sock)) + '\\n')\n    set served = served + 1\n    served < 2\n}})", port));
+        assert_eq!(client.join().unwrap(), vec!["A\n".to_owned(), "B\n".to_owned()]);
+        assert_eq!(global_str(&vm, "served"), Some("2".to_owned()));
+        assert_eq!(global_str(&vm, "res"), Some("_".to_owned()));
+
+        let mut vm = Vm::new(NopeConfig { no_net: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("connect('127.0.0.1', 80)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("listen(47879, |s| false)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("serve(47879, |req| 'hi')".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_store_load() {
+        let path = std::env::temp_dir().join(format!("nope_store_{}.json", std::process::id()));
+        let path = path.to_string_lossy().replace('\\', "/");
+        let _ = std::fs::remove_file(&path);
+        let vm = run(&format!("let missing = load('{0}')\nstore('{0}', 'say \"hi\"\\n\\ttab')\nlet text = load('{0}')\nstore('{0}', 4.5)\nlet num = load('{0}') * 2\nstore('{0}', false)\nlet bool = load('{0}') == false", path));
+        assert_eq!(global_str(&vm, "missing"), Some("null".to_owned()));
+        assert_eq!(global_str(&vm, "text"), Some("say \"hi\"\n\ttab".to_owned()));
+        assert_eq!(global_str(&vm, "num"), Some("9".to_owned()));
+        assert_eq!(global_str(&vm, "bool"), Some("true".to_owned()));
+        let mut vm = run("let f = |x| x");
+        assert!(matches!(vm.interpret(format!("store('{}', stats_new())", path)), InterpretResult::RuntimeError));
+        std::fs::write(&path, "[1, 2]").unwrap();
+        assert!(matches!(vm.interpret(format!("load('{}')", path)), InterpretResult::RuntimeError));
+        std::fs::remove_file(&path).unwrap();
+        let mut vm = Vm::new(NopeConfig { no_fs: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret(format!("store('{}', 1)", path)), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_file_metadata() {
+        let vm = run("
+let f = temp_file('nope_test_')
+write_text(f, 'hello')
+let d = temp_dir('nope_test_')
+let size = file_size f
+let recent = file_modified(f) > 1600000000
+let file = is_file f
+let not_file = is_file d
+let dir = is_dir d
+let not_dir = is_dir f
+let readonly = is_readonly f
+let missing = file_size(d + '/missing')
+let missing_dir = is_dir(d + '/missing')
+let missing_time = file_modified(d + '/missing')
+");
+        for (name, value) in [("size", "5"), ("recent", "true"), ("file", "true"), ("not_file", "false"), ("dir", "true"),
+                              ("not_dir", "false"), ("readonly", "false"),
+                              ("missing", "null"), ("missing_dir", "false"), ("missing_time", "null")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = Vm::new(NopeConfig { no_fs: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("file_size('x')".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_term_move() {
+        let mut vm = run("");
+        for code in ["term_move(0, 1e30)", "term_move(1 / 0, -1 / 0)", "term_move(0 / 0, 3)"] {
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::Ok), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_choose() {
+        // the tests have no input, choosing needs at least an option
+        let mut vm = run("");
+        assert!(matches!(vm.interpret("choose('Pick one', '\\n ')".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = ["-v", "data.txt", "--out", "x.txt"].iter().map(|arg| arg.to_string()).collect();
+        let mut vm = Vm::new(NopeConfig { script_args: args, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("let raw = arg 1".to_owned()), InterpretResult::Ok));
+        assert!(matches!(vm.interpret("opt 'out'".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("
+parse_args '
+    -v, --verbose  Print more
+    --out=result.txt  Where to write
+    --limit=  Stop after that many lines
+    <input>  The file to read
+'
+let verbose = opt 'verbose'
+let out = opt '--out'
+let limit = opt 'limit'
+let input = arg 0
+let missing = arg 1
+".to_owned()), InterpretResult::Ok));
+        for (name, value) in [("raw", "data.txt"), ("verbose", "true"), ("out", "x.txt"), ("limit", "null"), ("input", "data.txt"), ("missing", "null")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        for code in ["opt 'quiet'", "arg(-1)", "parse_args '--verbose'", "parse_args 'verbose'"] {
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_colors() {
+        let vm = run("
+let rgb = hex_to_rgb '#ff8800'
+let short = hex_to_rgb 'f80'
+let hex = rgb_to_hex(255, 136, 0)
+let clamped = rgb_to_hex(300, -4, 12.6)
+let red = hsl_to_rgb(360, 1, 0.5)
+let teal = hsl_to_rgb(180, 1, 0.25)
+let gray = mix('#000000', 'rgb(255, 255, 255)', 0.5)
+let first = mix('#102030', '#ffffff', 0)
+let light = lighten('#ff0000', 0.25)
+let dark = lighten('#ff0000', -0.5)
+");
+        for (name, value) in [("rgb", "rgb(255, 136, 0)"), ("short", "rgb(255, 136, 0)"), ("hex", "#ff8800"), ("clamped", "#ff000d"),
+                              ("red", "#ff0000"), ("teal", "#008080"), ("gray", "#808080"), ("first", "#102030"),
+                              ("light", "#ff8080"), ("dark", "#000000")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = run("");
+        for code in ["hex_to_rgb 'pink'", "mix('#fff', '#12345', 0.5)", "lighten('rgb(1, 2)', 0.1)"] {
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_svg() {
+        let vm = run("
+let pic = svg_new(40, 20)
+svg_line(pic, 0, 0, 40, 20)
+svg.style(pic, '#f80', null, 2)
+svg.circle(pic, 20, 10, 5)
+svg_path(pic, 'M 0 20 L 40 20')
+svg_turn(pic, 90)
+svg_forward(pic, 10)
+let shown = '' + pic
+let kind = type_of pic
+let file = temp_dir('nope_test_') + '/pic.svg'
+let saved = svg_save(pic, file)
+let text = read_text file
+let has_circle = contains('<circle cx=\"20\" cy=\"10\" r=\"5\" stroke=\"#ff8800\" fill=\"none\" stroke-width=\"2\"/>', text)
+let has_turtle = contains('<line x1=\"20\" y1=\"10\" x2=\"30\" y2=\"10\"', text)
+let closed = contains('</svg>', text)
+");
+        for (name, value) in [("shown", "<svg 40x20, 4 shapes>"), ("kind", "svg"), ("saved", "_"), ("has_circle", "true"),
+                              ("has_turtle", "true"), ("closed", "true")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = run("let pic = svg_new(10, 10)");
+        for code in ["svg_new(0, 10)", "svg_line('pic', 0, 0, 1, 1)", "svg_circle(pic, 0, 0, -1)", "svg_path(pic, 'M 0 0 <')",
+                     "svg_style(pic, 'pink', null, 1)", "svg_forward(pic, 0 / 0)"] {
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+        }
+        let mut vm = Vm::new(NopeConfig { no_fs: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("svg_save(svg_new(10, 10), 'pic.svg')".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_platform() {
+        let vm = run("
+let os = platform()
+let cpu = arch()
+let family = os_family()
+let host = hostname()
+let cpus = cpu_count() >= 1
+let version = nope_version()
+");
+        for (name, value) in [("os", std::env::consts::OS), ("cpu", std::env::consts::ARCH), ("family", std::env::consts::FAMILY),
+                              ("cpus", "true"), ("version", env!("CARGO_PKG_VERSION"))] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        assert!(global_str(&vm, "host").is_some_and(|host| !host.is_empty()));
+    }
+
+    #[test]
+    fn test_user_dirs() {
+        let vm = run("let home = home_dir()\nlet config = config_dir 'myapp'\nlet cache = cache_dir 'myapp'");
+        let home = global_str(&vm, "home").unwrap();
+        assert_eq!(Some(home.clone()), dirs::home_dir().map(|dir| dir.to_string_lossy().into_owned()));
+        for name in ["config", "cache"] {
+            let dir = global_str(&vm, name).unwrap();
+            assert!(Path::new(&dir).ends_with("myapp"), "{}", dir);
+        }
+        let mut vm = run("");
+        for code in ["config_dir '../x'", "cache_dir ''", "config_dir 'a/b'"] {
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_on_signal() {
+        // HUP is only caught once a handler is set, so the other tests don't see it
+        let mut vm = run("var got = 0\nvar after = false\non_signal('SIGHUP', |name| set got = name)");
+        unsafe { libc::raise(libc::SIGHUP) };
+        assert!(matches!(vm.interpret("set after = true".to_owned()), InterpretResult::RuntimeError));
+        assert_eq!(global_str(&vm, "got"), Some("HUP".to_owned()));
+        assert_eq!(global_str(&vm, "after"), Some("false".to_owned()));
+        for code in ["on_signal('KILL', |name| name)", "on_signal('HUP', 3)", "on_signal('HUP', |a b| a)"] {
+            assert!(!matches!(vm.interpret(code.to_owned()), InterpretResult::Ok), "{}", code);
+        }
+        assert!(matches!(vm.interpret("on_signal('hup', null)".to_owned()), InterpretResult::Ok));
+        assert!(matches!(vm.interpret("set after = true".to_owned()), InterpretResult::Ok));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_processes() {
+        let vm = run("
+let p = proc_spawn 'cat'
+proc_write(p, 'hello\\nworld\\n')
+let first = proc_read_line p
+let second = proc_read_line p
+let code = proc_wait p
+let after = proc_read_line p
+let failed = proc_wait <- proc_spawn \"sh -c 'exit 3'\"
+let words = proc_read_line <- proc_spawn \"echo 'a  b' \\\"c d\\\"\"
+let s = proc_spawn 'sleep 10'
+proc_kill s
+let killed = proc_wait s
+let kind = type_of s
+");
+        for (name, value) in [("first", "hello"), ("second", "world"), ("code", "0"), ("after", "null"), ("failed", "3"),
+                              ("words", "a  b c d"), ("killed", "null"), ("kind", "process")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let vm = run(&format!("
+let here = proc_read_line <- proc_spawn_in('pwd', '{}', null)
+let greeting = proc_read_line <- proc_spawn_in(\"sh -c 'echo $A $B'\", '', \"A=hello B='big world'\")
+", dir.display()));
+        assert_eq!(global_str(&vm, "here"), Some(dir.display().to_string()));
+        assert_eq!(global_str(&vm, "greeting"), Some("hello big world".to_owned()));
+        let mut vm = run("");
+        for code in ["proc_spawn ''", "proc_spawn 'nope_missing_command'", "proc_spawn \"echo 'a\"", "proc_wait 3",
+                     "proc_spawn_in('ls', null, 'A')", "proc_spawn_in('ls', 'nope_missing_dir', null)"] {
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+        }
+        let mut vm = Vm::new(NopeConfig { no_exec: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("proc_spawn 'ls'".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("proc_spawn_in('ls', null, null)".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_processes_stopped_with_the_vm() {
+        let vm = run("let s = proc_spawn 'sleep 30'");
+        let slot = vm.globals.slot("s").unwrap();
+        let pid = match vm.globals.get(slot) {
+            Some(Value::Process(proc_ref)) => vm.gc.deref(proc_ref).child.id(),
+            _ => panic!("s is not a process"),
+        };
+        drop(vm);
+        // the process was waited for, so there is no process left with that id
+        let alive = std::process::Command::new("kill").args(["-0", &pid.to_string()]).status().unwrap();
+        assert!(!alive.success());
+    }
+
+    #[test]
+    fn test_clipboard_no_exec() {
+        for config in [NopeConfig { no_exec: true, ..NopeConfig::new() }, NopeConfig { no_fs: true, ..NopeConfig::new() }] {
+            let mut vm = Vm::new(config);
+            for code in ["clip_get()", "clip_set 'secret'"] {
+                assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+            }
+        }
+    }
+
+    #[test]
+    fn test_zip() {
+        let vm = run("
+let src = temp_dir 'nope_test_'
+write_text(src + '/a.txt', 'hello')
+write_text(temp_dir('nope_test_') + '/b.txt', 'other')
+let dest = temp_dir 'nope_test_'
+let zip = dest + '/out.zip'
+let created = zip_create(zip, src)
+let names = zip_list zip
+let extracted = zip_extract(zip, dest + '/out')
+let text = read_text(dest + '/out/a.txt')
+let single = zip_create(dest + '/single.zip', src + '/a.txt')
+");
+        for (name, value) in [("created", "1"), ("names", "a.txt"), ("extracted", "1"), ("text", "hello"), ("single", "1")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        let mut vm = run("let f = temp_file 'nope_test_'");
+        assert!(matches!(vm.interpret("zip_list f".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("zip_create(f + '.zip', f + '.missing')".to_owned()), InterpretResult::RuntimeError));
+        let mut vm = Vm::new(NopeConfig { no_fs: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("zip_list('x.zip')".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_zip_create_sandbox_links() {
+        let root = std::env::temp_dir().join(format!("nope_zip_sandbox_{}", std::process::id()));
+        let allowed = root.join("allowed");
+        std::fs::create_dir_all(allowed.join("src")).unwrap();
+        std::fs::write(root.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(root.join("secret.txt"), allowed.join("src").join("h")).unwrap();
+        let allowed = allowed.to_string_lossy().replace('\\', "/");
+        let mut vm = Vm::new(NopeConfig { allowed_paths: vec![allowed.clone()], ..NopeConfig::new() });
+        assert!(matches!(vm.interpret(format!("read_text('{}/src/h')", allowed)), InterpretResult::RuntimeError));
+        let code = format!("let created = zip_create('{0}/o.zip', '{0}/src')\nlet names = zip_list('{0}/o.zip')", allowed);
+        assert!(matches!(vm.interpret(code), InterpretResult::Ok));
+        assert_eq!(global_str(&vm, "created"), Some("0".to_owned()));
+        assert_eq!(global_str(&vm, "names"), Some("".to_owned()));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_temp_paths() {
+        let vm = run("let f = temp_file('nope_test_')\nlet d = temp_dir('nope_test_')\nwrite_text(d + '/inner.txt', 'hi')");
+        let file = global_str(&vm, "f").unwrap();
+        let dir = global_str(&vm, "d").unwrap();
+        assert_ne!(file, dir);
+        assert!(Path::new(&file).is_file());
+        assert!(Path::new(&dir).join("inner.txt").is_file());
+        assert!(Path::new(&file).file_name().unwrap().to_string_lossy().starts_with("nope_test_"));
+        drop(vm);
+        assert!(!Path::new(&file).exists());
+        assert!(!Path::new(&dir).exists());
+        let mut vm = run("");
+        assert!(matches!(vm.interpret("temp_file('../x')".to_owned()), InterpretResult::RuntimeError));
+        let mut vm = Vm::new(NopeConfig { no_fs: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("temp_dir('x')".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_append_text() {
+        let path = std::env::temp_dir().join(format!("nope_append_{}.txt", std::process::id()));
+        let path = path.to_string_lossy().replace('\\', "/");
+        let _ = std::fs::remove_file(&path);
+        let vm = run(&format!("let a = append_text('{0}', 'one\n')\nappend_text('{0}', 2)\nlet text = read_text('{0}')", path));
+        assert_eq!(global_str(&vm, "a"), Some("_".to_owned()));
+        assert_eq!(global_str(&vm, "text"), Some("one\n2".to_owned()));
+        std::fs::remove_file(&path).unwrap();
+        let vm = run(&format!("let err = append_text('{}/missing/file.txt', 'x')", path));
+        assert!(global_str(&vm, "err").is_some_and(|err| err != "_"));
+        let mut vm = Vm::new(NopeConfig { no_fs: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret(format!("append_text('{}', 'x')", path)), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_watch() {
+        let dir = std::env::temp_dir().join(format!("nope_watch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_string_lossy().replace('\\', "/");
+        let file = dir.join("watched.txt");
+        let writer = thread::spawn(move || {
+            for idx in 0..2 {
+                thread::sleep(Duration::from_millis(300));
+                std::fs::write(&file, format!("{}", idx)).unwrap();
+            }
+        });
+        let vm = run(&format!("
+var changes = 0
+var changed = 0
+let done = watch('{}', |path| (
+    set changes = changes + 1
+    set changed = path
+    changes < 2
+))
+", dir_str));
+        writer.join().unwrap();
+        for (name, value) in [("changes", "2"), ("changed", dir_str.as_str()), ("done", "_")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut vm = Vm::new(NopeConfig { no_fs: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("watch('.', |path| false)".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_watched_state_skips_links() {
+        let dir = std::env::temp_dir().join(format!("nope_watch_links_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub").join("a.txt"), "a").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub").join("loop")).unwrap();
+        assert_eq!(watched_state(&dir).map(|(_, count)| count), Some(4));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_each_line() {
+        let path = std::env::temp_dir().join(format!("nope_each_line_{}.txt", std::process::id()));
+        let path = path.to_string_lossy().replace('\\', "/");
+        std::fs::write(&path, "a\r\nbb\n\nccc").unwrap();
+        let vm = run(&format!("
+var lines = 0
+let b = buf_new()
+let done = each_line('{0}', |line| (
+    set lines = lines + 1
+    buf_push(line + ';', b)
+))
+var first = 0
+each_line('{0}', |line| (
+    set first = line
+    false
+))
+let missing = each_line('{0}.missing', |line| line)
+", path));
+        for (name, value) in [("lines", "4"), ("b", "a;bb;;ccc;"), ("done", "_"), ("first", "a")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+        assert!(global_str(&vm, "missing").is_some_and(|err| err != "_"));
+        let mut vm = run("");
+        assert!(matches!(vm.interpret(format!("each_line('{}', 3)", path)), InterpretResult::CompileError));
+        std::fs::remove_file(&path).unwrap();
+        let mut vm = Vm::new(NopeConfig { no_fs: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret(format!("each_line('{}', |l| l)", path)), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_progress() {
+        let vm = run("let p = progress_new(3)\nprogress_tick(progress_tick(p))\nlet s = to_str(p)\nspinner('working')\nspinner(null)");
+        assert_eq!(global_str(&vm, "s"), Some("<progress 2/3>".to_owned()));
+        let mut vm = run("let p = progress_new(3)");
+        assert!(matches!(vm.interpret("progress_new(-1)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("progress_tick(3)".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_logging() {
+        let vm = run("set_log_level('off')\nlet x = log_error(3)\nlet s = log_info('hi')");
+        assert_eq!(global_str(&vm, "x"), Some("3".to_owned()));
+        assert_eq!(global_str(&vm, "s"), Some("hi".to_owned()));
+        assert_eq!(vm.config().log_level, 4);
+        let mut vm = run("let x = 1");
+        assert!(matches!(vm.interpret("set_log_level('loud')".to_owned()), InterpretResult::RuntimeError));
+    }
+
+    #[test]
+    fn test_equality() {
+        let vm = run("let a = 'foo' == to_str('foo')\nlet b = ~foo != 'bar'\nlet c = chan_new()\nlet d = c == c\nlet e = c == chan_new()\nlet f = is_same(c, c)\nlet g = is_same(c, chan_new())\nlet h = is_same(1, 1) && !is_same(1, true)");
+        for (name, expected) in [("a", "true"), ("b", "true"), ("d", "true"), ("e", "false"), ("f", "true"), ("g", "false"), ("h", "true")] {
+            assert_eq!(global_str(&vm, name), Some(expected.to_owned()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_type_of() {
+        let vm = run("let a = type_of(null)\nlet b = type_of(3)\nlet c = type_of('x')\nlet d = type_of(true)\nlet e = type_of(chan_new())\nlet f = type_of(|x| x)\nlet g = is_fn(|x| x) && !is_fn(3)");
+        for (name, expected) in [("a", "null"), ("b", "num"), ("c", "str"), ("d", "bool"), ("e", "channel"), ("f", "fn"), ("g", "true")] {
+            assert_eq!(global_str(&vm, name), Some(expected.to_owned()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_type_annotations_of_objects() {
+        let mut vm = Vm::new(NopeConfig { typecheck: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret("
+let d:svg = svg_new(10, 10)
+let p:progress = progress_new 3
+let names = type_of(d) + ' ' + type_of(p)
+let on_line = |reader:lines path:str| reader
+let on_change = |w:watch| w
+".to_owned()), InterpretResult::Ok));
+        assert_eq!(global_str(&vm, "names"), Some("svg progress".to_owned()));
+        #[cfg(unix)]
+        assert!(matches!(vm.interpret("let proc:process = proc_spawn 'cat'\nproc_kill proc".to_owned()), InterpretResult::Ok));
+        assert!(matches!(vm.interpret("let bad:nothing = 3".to_owned()), InterpretResult::CompileError));
+    }
+
+    #[test]
+    fn test_loop_body_locals() {
+        let vm = run("var k = 0\nlet r = loop {\n    let y = k * 10\n    if y > 20, break_as y\n    inc k\n}");
+        assert_eq!(global_str(&vm, "r"), Some("30".to_owned()));
+        let vm = run("var s = 0\nvar i = 0\nwhile i < 10 {\n    let a = i\n    inc i\n    if a % 2 == 0, continue\n    let b = a * 2\n    set s = s + b\n}");
+        assert_eq!(global_str(&vm, "s"), Some("50".to_owned()));
+        let vm = run("var t = 0\nvar p = 0\nwhile p < 3 {\n    let a = p\n    var q = 0\n    while q < 2 {\n        let b = a + q\n        if b > 3, break_as b\n        set t = t + b\n        inc q\n    }\n    inc p\n}");
+        assert_eq!(global_str(&vm, "t"), Some("9".to_owned()));
+        let vm = run("let x = 1\nlet y = if true { let x = 2; x * 10 } else 0\nlet z = x");
+        assert_eq!(global_str(&vm, "y"), Some("20".to_owned()));
+        assert_eq!(global_str(&vm, "z"), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn test_redefine_function_arity() {
+        let mut vm = run("let f = |a| a * 2");
+        vm.interpret("let f = |a b| a + b".to_owned());
+        vm.interpret("let x = f(1, 2)".to_owned());
+        assert_eq!(global_str(&vm, "x"), Some("3".to_owned()));
+        let env = vm.get_copy_of_last_env().unwrap();
+        assert_eq!(env.global_entries().iter().filter(|entry| entry.name == "f").count(), 1);
+
+        // a definition after a runtime error didn't happen
+        let mut vm = run("let f = |a| a * 2");
+        assert!(matches!(vm.interpret("progress_new(-1)\nlet f = |a b| a + b".to_owned()), InterpretResult::RuntimeError));
+        vm.interpret("let x = f 5".to_owned());
+        assert_eq!(global_str(&vm, "x"), Some("10".to_owned()));
+
+        let mut vm = run("let f = |a| a\nlet g = |n| f(n)\nlet h = |n| n");
+        vm.interpret("let f = |a b| a + b".to_owned());
+        assert_eq!(vm.callers_with_other_arity("f", 2), vec!["g".to_owned()]);
+        vm.interpret("let g = |n| f(n, 1)".to_owned());
+        assert!(vm.callers_with_other_arity("f", 2).is_empty());
+    }
+
+    #[test]
+    fn test_undef() {
+        let mut vm = run("const x = 3\nlet f = |a| a");
+        assert!(matches!(vm.interpret("undef('x')".to_owned()), InterpretResult::Ok));
+        assert_eq!(global_str(&vm, "x"), None);
+        assert!(matches!(vm.interpret("let y = x".to_owned()), InterpretResult::CompileError));
+        vm.interpret("var x = 'again'".to_owned());
+        assert_eq!(global_str(&vm, "x"), Some("again".to_owned()));
+
+        vm.interpret("undef('f')".to_owned());
+        assert!(matches!(vm.interpret("let y = f(2)".to_owned()), InterpretResult::CompileError));
+        for code in ["undef('sqrt')", "undef('missing')", "undef('f')"] {
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::RuntimeError), "{}", code);
+        }
+        vm.interpret("let y = sqrt(16)".to_owned());
+        assert_eq!(global_str(&vm, "y"), Some("4".to_owned()));
+
+        let mut vm = run("let sqrt = |a| a\nlet max = |a b| a\nlet before = sqrt(16)");
+        assert_eq!(global_str(&vm, "before"), Some("16".to_owned()));
+        for code in ["undef('sqrt')", "undef('max')", "let after = sqrt(16) + max(1, 2)"] {
+            assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::Ok), "{}", code);
+        }
+        assert_eq!(global_str(&vm, "after"), Some("6".to_owned()));
+        vm.interpret("let sqrt = |a| a * 10\nlet again = sqrt(2)".to_owned());
+        assert_eq!(global_str(&vm, "again"), Some("20".to_owned()));
+    }
+
+    #[test]
+    fn test_keep_result() {
+        let mut vm = run("3 * 2");
+        vm.keep_result();
+        vm.interpret("let x = 1".to_owned());
+        vm.keep_result();
+        vm.interpret("_1 + 1".to_owned());
+        vm.keep_result();
+        vm.interpret("let y = _1 + _2 + _last".to_owned());
+        assert_eq!(global_str(&vm, "_2"), Some("7".to_owned()));
+        assert_eq!(global_str(&vm, "y"), Some("20".to_owned()));
+    }
+
+    #[test]
+    fn test_display_unit() {
+        let mut vm = run("set_display_unit('cm')");
+        assert_eq!(vm.config().display_unit, Some("cm".to_owned()));
+        assert!(matches!(vm.interpret("set_display_unit('furlong')".to_owned()), InterpretResult::RuntimeError));
+        assert_eq!(vm.config().display_unit, Some("cm".to_owned()));
+        vm.interpret("set_display_unit(null)".to_owned());
+        assert_eq!(vm.config().display_unit, None);
+    }
+
+    #[test]
+    fn test_variadic_calls() {
+        let vm = run("
+let a = max 1 7 3 4
+let b = min(4, 2, 8)
+let c = sum 1 2 3 4
+let d = max 1 2
+let e = sum (max 1 2) 10 min 5 3 4
+let f = max 1 2 + 1
+let g = math.sum 1 2
+let h = sum (min 3 1) 2
+let i = sum 1 2
+print i
+");
+        for (name, value) in [("a", "7"), ("b", "2"), ("c", "10"), ("d", "2"), ("e", "15"), ("f", "3"), ("g", "3"), ("h", "3"), ("i", "3")] {
+            assert_eq!(global_str(&vm, name), Some(value.to_owned()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_float_digits() {
+        use std::f64::consts::PI;
+        let mut vm = run("");
+        assert_eq!(vm.num_to_str(0.1 + 0.2), "0.30000000000000004");
+        vm.interpret("set_float_digits(4)".to_owned());
+        for (num, text) in [(0.1 + 0.2, "0.3"), (PI, "3.142"), (1234.56, "1235"), (12346.0, "1.235e4"),
+                            (0.000012345, "1.234e-5"), (-2.5, "-2.5"), (0.0, "0"), (f64::NAN, "NaN")] {
+            assert_eq!(vm.num_to_str(num), text);
+        }
+        vm.interpret("set_decimal_comma(true)".to_owned());
+        vm.interpret("let x = to_str(3.14159)".to_owned());
+        assert_eq!(global_str(&vm, "x"), Some("3,142".to_owned()));
+        assert!(matches!(vm.interpret("set_float_digits(0)".to_owned()), InterpretResult::RuntimeError));
+        vm.interpret("set_float_digits(null)".to_owned());
+        assert_eq!(vm.num_to_str(PI), "3,141592653589793");
+    }
+
+    #[test]
+    fn test_help() {
+        let mut vm = run("");
+        assert!(matches!(vm.interpret("help('clamp')".to_owned()), InterpretResult::Ok));
+        assert!(matches!(vm.interpret("help('nope')".to_owned()), InterpretResult::RuntimeError));
+        assert_eq!(vm.stdlib().describe("clamp"), Some("clamp x lo hi\n  3 arguments, limits x to the [lo, hi] range".to_owned()));
+        assert_eq!(vm.stdlib().describe("sqrt"), Some("sqrt a\n  1 argument, the square root of a number".to_owned()));
+        assert_eq!(vm.stdlib().describe("max"), Some("max a b...\n  2 or more arguments, the largest of the numbers".to_owned()));
+        assert_eq!(vm.stdlib().describe("unknown"), None);
+    }
+
+    #[test]
+    fn test_builtins() {
+        let vm = run("let names = builtins\nlet has_clamp = contains('\nclamp\n', names)");
+        assert_eq!(global_str(&vm, "has_clamp"), Some("true".to_owned()));
+        assert!(global_str(&vm, "names").unwrap().starts_with("abs\nacos\n"));
+        let found: Vec<&str> = vm.stdlib().search("HYPERBOLIC SINE").iter().map(|function| function.name.as_str()).collect();
+        assert_eq!(found, vec!["asinh", "math.asinh", "math.sinh", "sinh"]);
+        assert!(vm.stdlib().search("stdlib").iter().any(|function| function.name == "builtins"));
+    }
+
+    #[test]
+    fn test_namespaced_functions() {
+        let vm = run("let a = math.sqrt 16\nlet b = str.upper('abc')\nlet c = fs.join 'a' 'b'\nlet d = sqrt 16");
+        assert_eq!(global_str(&vm, "a"), Some("4".to_owned()));
+        assert_eq!(global_str(&vm, "b"), Some("ABC".to_owned()));
+        assert_eq!(global_str(&vm, "c"), Some("a/b".to_owned()));
+        assert_eq!(global_str(&vm, "d"), Some("4".to_owned()));
+    }
+
+    #[test]
+    fn test_run_tests() {
+        let code = "var runs = 0\ntest 'ok' { inc runs; runs == 1 }\ntest 'false' false\ntest 'assert' assert(false, 'no')\nlet x = 3";
+        let mut vm = run(code);
+        assert_eq!(vm.run_tests(), 0);
+        assert_eq!(global_str(&vm, "runs"), Some("0".to_owned()));
+
+        let mut vm = Vm::new(NopeConfig { run_tests: true, ..NopeConfig::new() });
+        assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::Ok));
+        assert_eq!(global_str(&vm, "runs"), Some("0".to_owned()));
+        assert_eq!(vm.run_tests(), 2);
+        assert_eq!(global_str(&vm, "runs"), Some("1".to_owned()));
+        assert_eq!(vm.run_tests(), 0);
+    }
+
+    #[test]
+    fn test_assert_snapshot() {
+        let dir = std::env::temp_dir().join(format!("nope_snapshots_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = NopeConfig { snapshot_dir: Some(dir.to_string_lossy().into_owned()), ..NopeConfig::new() };
+        let mut vm = Vm::new(config);
+        let code = "let a = assert_snapshot('text', 'one\\ntwo')\nlet b = assert_snapshot('num', 12)";
+        assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::Ok));
+        assert_eq!(std::fs::read_to_string(dir.join("text.snap")).unwrap(), "one\ntwo");
+        assert_eq!(std::fs::read_to_string(dir.join("num.snap")).unwrap(), "12");
+        assert!(matches!(vm.interpret(code.to_owned()), InterpretResult::Ok));
+        assert_eq!(global_str(&vm, "a"), Some("true".to_owned()));
+        assert!(matches!(vm.interpret("assert_snapshot('text', 'one\\nthree')".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("assert_snapshot('num', 13)".to_owned()), InterpretResult::RuntimeError));
+        assert!(matches!(vm.interpret("assert_snapshot('../up', 1)".to_owned()), InterpretResult::RuntimeError));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}